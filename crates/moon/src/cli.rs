@@ -25,8 +25,10 @@ pub mod clean;
 pub mod coverage;
 pub mod deps;
 pub mod doc;
+pub mod explain;
 pub mod external;
 pub mod fmt;
+pub mod gen;
 pub mod generate_test_driver;
 pub mod info;
 pub mod mooncake_adapter;
@@ -50,8 +52,10 @@ pub use clean::*;
 pub use coverage::*;
 pub use deps::*;
 pub use doc::*;
+pub use explain::*;
 pub use external::*;
 pub use fmt::*;
+pub use gen::*;
 pub use generate_test_driver::*;
 pub use info::*;
 use moonbuild::upgrade::UpgradeSubcommand;
@@ -69,15 +73,24 @@ pub use upgrade::*;
 pub use version::*;
 
 use anyhow::bail;
+use colored::Colorize;
 use moonutil::{
     cli::UniversalFlags,
     common::{
-        read_module_desc_file_in_dir, BuildPackageFlags, DiagnosticLevel, LinkCoreFlags, MooncOpt,
-        OutputFormat, SurfaceTarget, TargetBackend, MOONBITLANG_CORE, MOON_MOD_JSON,
+        all_targets_of_module, lower_surface_targets, CompilerFlagsOptions, DiagnosticLevel,
+        MooncOpt, SurfaceTarget, TargetBackend,
     },
+    compiler_flags::OptLevel as CCOptLevel,
+    cond_expr::OptLevel,
     mooncakes::{LoginSubcommand, PackageSubcommand, PublishSubcommand, RegisterSubcommand},
 };
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::rr_build::BuildProfile;
+use crate::run::{
+    JsRuntime, RuntimeConfig, StopPolicy, StopSignal, WasmGcRunner, DEFAULT_STOP_GRACE,
+};
 
 #[derive(Debug, clap::Parser)]
 #[clap(
@@ -101,7 +114,7 @@ pub enum MoonBuildSubcommands {
     Build(BuildSubcommand),
     Check(CheckSubcommand),
     Run(RunSubcommand),
-    Test(TestSubcommand),
+    Test(Box<TestSubcommand>),
     #[clap(hide = true)]
     GenerateTestDriver(GenerateTestDriverSubcommand),
     Clean(CleanSubcommand),
@@ -126,6 +139,8 @@ pub enum MoonBuildSubcommands {
 
     // Misc
     Coverage(CoverageSubcommand),
+    Explain(ExplainSubcommand),
+    Gen(GenSubcommand),
     GenerateBuildMatrix(GenerateBuildMatrix),
     #[clap(hide = true)]
     Query(QuerySubcommand),
@@ -160,6 +175,14 @@ pub struct BuildFlags {
     #[clap(long, conflicts_with = "debug")]
     pub release: bool,
 
+    /// The optimization level of the C compiler for the native and LLVM backends, instead of the one of the debug or release mode. `0` also turns off the optimizations of moonc (only with Rupes Recta)
+    #[clap(long, value_name = "LEVEL")]
+    pub opt_level: Option<NativeOptLevel>,
+
+    /// Optimize the output of the wasm and wasm-gc backends with `wasm-opt` of Binaryen, at the given level, `s` if not given (only with Rupes Recta)
+    #[clap(long, value_name = "LEVEL", num_args = 0..=1, require_equals = true, default_missing_value = "s")]
+    pub wasm_opt: Option<WasmOptLevel>,
+
     /// Enable stripping debug information
     #[clap(long, conflicts_with = "no_strip")]
     pub strip: bool,
@@ -223,6 +246,10 @@ pub struct BuildFlags {
     /// Render no-location diagnostics starting from a certain level
     #[clap(long, value_name = "MIN_LEVEL", default_value = "error")]
     pub render_no_loc: DiagnosticLevel,
+
+    /// Do not show the progress of the build on terminals
+    #[clap(long)]
+    pub no_progress: bool,
 }
 
 impl Default for BuildFlags {
@@ -233,6 +260,8 @@ impl Default for BuildFlags {
             no_std: false,
             debug: false,
             release: false,
+            opt_level: None,
+            wasm_opt: None,
             strip: false,
             no_strip: false,
             target: None,
@@ -248,6 +277,7 @@ impl Default for BuildFlags {
             enable_value_tracing: false,
             jobs: None,
             render_no_loc: DiagnosticLevel::Error,
+            no_progress: false,
         }
     }
 }
@@ -261,85 +291,285 @@ impl BuildFlags {
             (true, true) => panic!("both std and no_std flags are set"),
         }
     }
+}
 
-    pub fn strip(&self) -> bool {
-        if self.strip {
-            true
-        } else if self.no_strip {
-            false
-        } else {
-            !self.debug
+/// An optimization level of `--opt-level`, as in `-O<LEVEL>` of C compilers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum NativeOptLevel {
+    #[value(name = "0")]
+    O0,
+    #[value(name = "1")]
+    O1,
+    #[value(name = "2")]
+    O2,
+    #[value(name = "3")]
+    O3,
+    #[value(name = "s")]
+    Os,
+    #[value(name = "z")]
+    Oz,
+}
+
+impl NativeOptLevel {
+    pub fn to_cc_opt_level(self) -> CCOptLevel {
+        match self {
+            Self::O0 => CCOptLevel::None,
+            Self::O1 => CCOptLevel::Basic,
+            Self::O2 => CCOptLevel::Speed,
+            Self::O3 => CCOptLevel::Aggressive,
+            Self::Os => CCOptLevel::Size,
+            Self::Oz => CCOptLevel::MinSize,
         }
     }
 }
 
-pub fn get_compiler_flags(src_dir: &Path, build_flags: &BuildFlags) -> anyhow::Result<MooncOpt> {
-    // read moon.mod.json
-    if !moonutil::common::check_moon_mod_exists(src_dir) {
-        bail!("could not find `{}`", MOON_MOD_JSON);
+/// An optimization level of `--wasm-opt`, as in `-O<LEVEL>` of `wasm-opt`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum WasmOptLevel {
+    #[value(name = "1")]
+    O1,
+    #[value(name = "2")]
+    O2,
+    #[value(name = "3")]
+    O3,
+    #[value(name = "4")]
+    O4,
+    #[value(name = "s")]
+    Os,
+    #[value(name = "z")]
+    Oz,
+}
+
+impl WasmOptLevel {
+    pub fn to_flag(self) -> &'static str {
+        match self {
+            Self::O1 => "-O1",
+            Self::O2 => "-O2",
+            Self::O3 => "-O3",
+            Self::O4 => "-O4",
+            Self::Os => "-Os",
+            Self::Oz => "-Oz",
+        }
     }
-    let moon_mod = read_module_desc_file_in_dir(src_dir)?;
-    let extra_build_opt = moon_mod.compile_flags.unwrap_or_default();
-    let extra_link_opt = moon_mod.link_flags.unwrap_or_default();
-
-    let output_format = if build_flags.output_wat {
-        OutputFormat::Wat
-    } else {
-        OutputFormat::Wasm
-    };
+}
+
+/// Flags selecting the runtimes to execute MoonBit programs
+#[derive(Debug, clap::Parser, Clone, Default)]
+pub struct RuntimeFlags {
+    /// The runtime to execute the WasmGC backend output (only with Rupes Recta)
+    #[clap(long)]
+    pub wasm_gc_runner: Option<WasmGcRunner>,
+
+    /// The JavaScript runtime to execute the JS backend output (only with Rupes Recta)
+    #[clap(long)]
+    pub js_runtime: Option<JsRuntime>,
+
+    /// Extra flag passed to `node`, can be specified multiple times (only with Rupes Recta)
+    #[clap(long = "node-flag", value_name = "FLAG", allow_hyphen_values = true)]
+    pub node_flags: Vec<String>,
 
-    let target_backend = build_flags
-        .target_backend
-        .or(moon_mod.preferred_target) // if we have specified in module config
-        .unwrap_or_default();
+    /// Run native executables built for the given target triple under qemu, if its architecture differs from the host (only with Rupes Recta)
+    #[clap(long, value_name = "TRIPLE")]
+    pub cross_run: Option<String>,
 
-    if target_backend == TargetBackend::Js && output_format == OutputFormat::Wat {
-        bail!("--output-wat is not supported for --target js");
+    /// Load environment variables of the program from a dotenv file of `KEY=VALUE` lines. The variables already set in the environment win over the file (only with Rupes Recta)
+    #[clap(long, value_name = "FILE")]
+    pub env_file: Option<PathBuf>,
+
+    /// Let the variables of `--env-file` override the ones already set in the environment (only with Rupes Recta)
+    #[clap(long, requires = "env_file")]
+    pub env_file_override: bool,
+
+    /// How a program is asked to exit when it times out or is restarted by `--watch`, before it's killed. Windows has no signals, so `term` and `int` send CTRL-BREAK there instead (only with Rupes Recta)
+    #[clap(long, value_name = "SIGNAL")]
+    pub kill_signal: Option<StopSignal>,
+
+    /// The milliseconds a program gets to exit after being asked to, before it's killed. Defaults to 2000 (only with Rupes Recta)
+    #[clap(long, value_name = "MS")]
+    pub kill_grace_ms: Option<u64>,
+}
+
+impl RuntimeFlags {
+    /// Bail if any runtime flag is specified, for the legacy build engine
+    /// which doesn't support them.
+    pub fn check_legacy_unsupported(&self) -> anyhow::Result<()> {
+        if self.wasm_gc_runner.is_some() {
+            bail!("`--wasm-gc-runner` is only supported with `-Z rupes_recta`");
+        }
+        if self.js_runtime.is_some() {
+            bail!("`--js-runtime` is only supported with `-Z rupes_recta`");
+        }
+        if !self.node_flags.is_empty() {
+            bail!("`--node-flag` is only supported with `-Z rupes_recta`");
+        }
+        if self.cross_run.is_some() {
+            bail!("`--cross-run` is only supported with `-Z rupes_recta`");
+        }
+        if self.env_file.is_some() {
+            bail!("`--env-file` is only supported with `-Z rupes_recta`");
+        }
+        if self.kill_signal.is_some() {
+            bail!("`--kill-signal` is only supported with `-Z rupes_recta`");
+        }
+        if self.kill_grace_ms.is_some() {
+            bail!("`--kill-grace-ms` is only supported with `-Z rupes_recta`");
+        }
+        Ok(())
     }
 
-    let output_format = match target_backend {
-        TargetBackend::Js => OutputFormat::Js,
-        TargetBackend::Native => OutputFormat::Native,
-        TargetBackend::LLVM => OutputFormat::LLVM,
-        _ => output_format,
-    };
+    pub fn runtime_config(&self) -> anyhow::Result<RuntimeConfig> {
+        let js_runtime = self.js_runtime.unwrap_or_default();
+        if !self.node_flags.is_empty() && js_runtime != JsRuntime::Node {
+            bail!("`--node-flag` can only be used with `--js-runtime node`");
+        }
+        Ok(RuntimeConfig {
+            wasm_gc_runner: self.wasm_gc_runner.unwrap_or_default(),
+            js_runtime,
+            node_flags: self.node_flags.clone(),
+            cross_target: self.cross_run.clone(),
+            envs: match &self.env_file {
+                Some(path) => crate::run::dotenv::load_env_file(path, self.env_file_override)?,
+                None => vec![],
+            },
+            stop: StopPolicy {
+                signal: self.kill_signal.unwrap_or_default(),
+                grace: self
+                    .kill_grace_ms
+                    .map_or(DEFAULT_STOP_GRACE, Duration::from_millis),
+            },
+            ..Default::default()
+        })
+    }
+}
 
-    let debug_flag = build_flags.debug;
-    let enable_coverage = build_flags.enable_coverage;
-    let source_map = debug_flag && target_backend.supports_source_map();
-
-    let build_opt = BuildPackageFlags {
-        debug_flag,
-        strip_flag: build_flags.strip(),
-        source_map,
-        enable_coverage,
-        deny_warn: false,
-        target_backend,
-        warn_list: build_flags.warn_list.clone(),
-        alert_list: build_flags.alert_list.clone(),
-        enable_value_tracing: build_flags.enable_value_tracing,
-    };
+pub fn get_compiler_flags(src_dir: &Path, build_flags: &BuildFlags) -> anyhow::Result<MooncOpt> {
+    if build_flags.opt_level.is_some() {
+        bail!("`--opt-level` is only supported with `-Z rupes_recta`");
+    }
+    if build_flags.wasm_opt.is_some() {
+        bail!("`--wasm-opt` is only supported with `-Z rupes_recta`");
+    }
+    // The legacy build defaults to the release profile, like most commands
+    // of Rupes Recta
+    let profile = BuildProfile::from_flags(build_flags, OptLevel::Release);
+    let moonc_opt = MooncOpt::for_module(
+        src_dir,
+        &CompilerFlagsOptions {
+            target_backend: build_flags.target_backend,
+            debug: profile.opt_level == OptLevel::Debug,
+            strip: Some(!profile.debug_symbols),
+            output_wat: build_flags.output_wat,
+            enable_coverage: build_flags.enable_coverage,
+            enable_value_tracing: build_flags.enable_value_tracing,
+            warn_list: build_flags.warn_list.clone(),
+            alert_list: build_flags.alert_list.clone(),
+            no_std: !build_flags.std(),
+            no_render: build_flags.no_render,
+            native_opt_level: None,
+        },
+    )?;
+    // also checks the preferred target of the module
+    check_legacy_target(moonc_opt.build_opt.target_backend)?;
+    Ok(moonc_opt)
+}
 
-    let link_opt = LinkCoreFlags {
-        debug_flag,
-        source_map,
-        output_format,
-        target_backend,
+/// Bail if the legacy build can't run the output of `backend`. The
+/// experimental `wasi` target is only supported with Rupes Recta, which finds
+/// `wasmtime` along with the other runtimes.
+pub(crate) fn check_legacy_target(backend: TargetBackend) -> anyhow::Result<()> {
+    if backend == TargetBackend::Wasi {
+        bail!("the `wasi` target is only supported with `-Z rupes_recta,wasi`");
+    }
+    Ok(())
+}
+
+/// Run `f` once for each module of the workspace containing the source
+/// directory, see [`moonutil::dirs::workspace_modules`], returning the highest
+/// exit code. Errors of a module stop the run.
+pub(crate) fn run_in_workspace(
+    cli: &UniversalFlags,
+    mut f: impl FnMut(&UniversalFlags) -> anyhow::Result<i32>,
+) -> anyhow::Result<i32> {
+    let dir = match &cli.source_tgt_dir.source_dir {
+        Some(dir) => dunce::canonicalize(dir)?,
+        None => std::env::current_dir()?,
     };
+    let modules = moonutil::dirs::workspace_modules(&dir)
+        .ok_or_else(|| moonutil::dirs::PackageDirsError::NotInProject(dir.clone()))?;
+    if modules.len() > 1
+        && (cli.source_tgt_dir.target_dir.is_some()
+            || std::env::var_os(moonutil::dirs::MOON_TARGET_DIR).is_some())
+    {
+        bail!(
+            "`--workspace` can't be used with a custom target directory, as the {} modules of the workspace would share it",
+            modules.len()
+        );
+    }
+    let mut code = 0;
+    for module in &modules {
+        if modules.len() > 1 && !cli.quiet {
+            eprintln!("In module {}", module.display());
+        }
+        let mut cli = cli.clone();
+        cli.source_tgt_dir.source_dir = Some(module.clone());
+        code = code.max(f(&cli)?);
+    }
+    Ok(code)
+}
 
-    let nostd = !build_flags.std() || moon_mod.name == MOONBITLANG_CORE;
-    let render =
-        !build_flags.no_render || std::env::var("MOON_NO_RENDER").unwrap_or_default() == "1";
-
-    Ok(MooncOpt {
-        build_opt,
-        link_opt,
-        extra_build_opt,
-        extra_link_opt,
-        nostd,
-        render,
-        single_file: false,
-    })
+/// The backends of `--target` if it includes `all`, i.e. the ones of
+/// [`all_targets_of_module`] and the others given along with it.
+pub(crate) fn expand_target_all(
+    source_dir: &Path,
+    surface_targets: &[SurfaceTarget],
+) -> anyhow::Result<Option<Vec<TargetBackend>>> {
+    if !surface_targets.contains(&SurfaceTarget::All) {
+        return Ok(None);
+    }
+    let others = surface_targets
+        .iter()
+        .copied()
+        .filter(|it| *it != SurfaceTarget::All)
+        .collect::<Vec<_>>();
+    let mut targets = all_targets_of_module(source_dir)?;
+    targets.extend(lower_surface_targets(&others));
+    targets.sort();
+    targets.dedup();
+    Ok(Some(targets))
+}
+
+/// Run `f` for each backend of `--target all` in turn, going on with the next
+/// backend when one fails, then print how each went, e.g. `wasm: ok, js: ok,
+/// native: FAILED`. Returns the highest exit code, counting errors as 1.
+pub(crate) fn run_for_all_targets(
+    cli: &UniversalFlags,
+    targets: &[TargetBackend],
+    mut f: impl FnMut(TargetBackend) -> anyhow::Result<i32>,
+) -> i32 {
+    let mut code = 0;
+    let mut summary = vec![];
+    for &target in targets {
+        let res = f(target).unwrap_or_else(|e| {
+            eprintln!(
+                "{}: failed for target {}: {:?}",
+                "error".red().bold(),
+                target.to_flag(),
+                e
+            );
+            1
+        });
+        code = code.max(res);
+        let status = if res == 0 {
+            "ok".green()
+        } else {
+            "FAILED".red().bold()
+        };
+        summary.push(format!("{}: {status}", target.to_flag()));
+    }
+    if !cli.quiet {
+        println!("{}.", summary.join(", "));
+    }
+    code
 }
 
 #[test]
@@ -17,8 +17,13 @@
 // For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
 
 use anyhow::Context;
-use moonutil::{common::lower_surface_targets, dirs::PackageDirs, mooncakes::sync::AutoSyncFlags};
-use std::path::Path;
+use moonbuild::entry::BenchArgs;
+use moonutil::{
+    common::{lower_surface_targets, TargetBackend},
+    dirs::PackageDirs,
+    mooncakes::sync::AutoSyncFlags,
+};
+use std::path::{Path, PathBuf};
 use tracing::{instrument, Level};
 
 use super::{BuildFlags, UniversalFlags};
@@ -51,6 +56,78 @@ pub struct BenchSubcommand {
     /// Run the benchmarks in a target backend sequentially
     #[clap(long)]
     pub no_parallelize: bool,
+
+    #[clap(flatten)]
+    pub bench_flags: BenchFlags,
+}
+
+/// A format to export benchmark results in. `json` writes the statistics of
+/// each benchmark, along with the backend they ran on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum BenchOutputFormat {
+    Json,
+}
+
+/// How the results of benchmarks are reported
+#[derive(Debug, clap::Args, Clone, Default)]
+pub struct BenchFlags {
+    /// Also export the benchmark results in the given format (only with Rupes Recta)
+    #[clap(long, value_name = "FORMAT")]
+    pub bench_output: Option<BenchOutputFormat>,
+
+    /// The file to export the benchmark results to, `bench-<backend>.json` in the
+    /// target directory by default
+    #[clap(long, value_name = "FILE", requires = "bench_output")]
+    pub bench_output_file: Option<PathBuf>,
+
+    /// Compare the results with a report exported by `--bench-output json`
+    /// (only with Rupes Recta)
+    #[clap(long, value_name = "FILE")]
+    pub baseline: Option<PathBuf>,
+
+    /// Fail if any benchmark is slower than the baseline by more than the given
+    /// percentage, e.g. `10%`
+    #[clap(long, requires = "baseline", value_parser = parse_threshold)]
+    pub threshold: Option<f64>,
+
+    /// The number of iterations to run before measuring each benchmark,
+    /// depending on the backend by default (only with Rupes Recta)
+    #[clap(long, value_name = "ITERS")]
+    pub bench_warmup: Option<u32>,
+
+    /// The minimum time to spend measuring each benchmark in milliseconds,
+    /// depending on the backend by default (only with Rupes Recta)
+    #[clap(long, value_name = "MS")]
+    pub bench_min_time: Option<u64>,
+}
+
+impl BenchFlags {
+    /// Whether the results are needed beyond printing them
+    pub fn is_reporting(&self) -> bool {
+        self.bench_output.is_some() || self.baseline.is_some()
+    }
+
+    /// The parameters of the bench harness for `backend`.
+    pub fn bench_args(&self, backend: TargetBackend) -> BenchArgs {
+        let default = BenchArgs::default_for(backend);
+        BenchArgs {
+            warmup_iters: self.bench_warmup.unwrap_or(default.warmup_iters),
+            min_measure_time_ms: self.bench_min_time.unwrap_or(default.min_measure_time_ms),
+        }
+    }
+}
+
+fn parse_threshold(s: &str) -> Result<f64, String> {
+    let pct = s
+        .strip_suffix('%')
+        .unwrap_or(s)
+        .parse::<f64>()
+        .map_err(|e| e.to_string())?;
+    if pct.is_finite() && pct >= 0. {
+        Ok(pct)
+    } else {
+        Err("the threshold should be a non-negative percentage".into())
+    }
 }
 
 #[instrument(skip_all)]
@@ -65,6 +142,13 @@ pub fn run_bench(cli: UniversalFlags, cmd: BenchSubcommand) -> anyhow::Result<i3
     }
     let surface_targets = cmd.build_flags.target.clone().unwrap();
     let targets = lower_surface_targets(&surface_targets);
+    if targets.len() > 1 && cmd.bench_flags.bench_output_file.is_some() {
+        // Timings of different backends should not be mixed in one file
+        anyhow::bail!("`--bench-output-file` cannot be used with multiple targets");
+    }
+    if targets.len() > 1 && cmd.bench_flags.baseline.is_some() {
+        anyhow::bail!("`--baseline` cannot be used with multiple targets");
+    }
     let display_backend_hint = if targets.len() > 1 { Some(()) } else { None };
 
     let mut ret_value = 0;
@@ -19,9 +19,12 @@
 use anyhow::anyhow;
 use anyhow::Context;
 use colored::Colorize;
+use moonbuild::build::BuildMessage;
 use moonbuild::dry_run;
 use moonbuild::entry;
-use moonbuild::watch::watching;
+use moonbuild::gen::compile_commands::CompileCommand;
+use moonbuild::timings;
+use moonbuild::watch::{watching, WatchIgnore, WatchOpt};
 use moonbuild_rupes_recta::model::BuildPlanNode;
 use moonbuild_rupes_recta::model::TargetKind;
 use mooncake::pkg::sync::auto_sync;
@@ -29,17 +32,20 @@ use moonutil::common::lower_surface_targets;
 use moonutil::common::BuildOpt;
 use moonutil::common::FileLock;
 use moonutil::common::MoonbuildOpt;
+use moonutil::common::MooncOpt;
 use moonutil::common::PrePostBuild;
 use moonutil::common::RunMode;
 use moonutil::common::TargetBackend;
 use moonutil::cond_expr::OptLevel;
 use moonutil::dirs::mk_arch_mode_dir;
 use moonutil::dirs::PackageDirs;
+use moonutil::module::ModuleDB;
 use moonutil::mooncakes::sync::AutoSyncFlags;
 use moonutil::mooncakes::RegistryConfig;
 use n2::trace;
 use std::path::Path;
 use std::path::PathBuf;
+use std::time::Duration;
 use tracing::{instrument, Level};
 
 use crate::rr_build;
@@ -63,6 +69,34 @@ pub struct BuildSubcommand {
     #[clap(long, short)]
     pub watch: bool,
 
+    /// Report the watch events as newline-delimited JSON to stdout
+    #[clap(long, requires = "watch")]
+    pub watch_events_json: bool,
+
+    /// Wait until files stop changing for this many milliseconds before rebuilding in watch mode, 0 to rebuild immediately
+    #[clap(long, value_name = "MS", default_value_t = 200, requires = "watch")]
+    pub watch_debounce_ms: u64,
+
+    /// Don't rebuild in watch mode for changes of the paths matching this glob, relative to the module, on top of the globs in `.moonignore`. Can be given multiple times
+    #[clap(long, value_name = "GLOB", requires = "watch")]
+    pub watch_ignore: Vec<String>,
+
+    /// Report the time spent on each build task, `json` also writes all of them to `moon-timings.json` in the target directory
+    #[clap(long, value_name = "FORMAT", num_args = 0..=1, default_missing_value = "text", conflicts_with = "watch")]
+    pub timings: Option<TimingsFormat>,
+
+    /// The format of the build output, `json` prints newline-delimited JSON messages of the diagnostics, the artifacts and the result to stdout
+    #[clap(long, value_name = "FORMAT", default_value = "human", conflicts_with_all = ["watch", "no_render"])]
+    pub message_format: MessageFormat,
+
+    /// Keep building the packages that don't depend on a failed one, instead of stopping at the first failures
+    #[clap(long)]
+    pub keep_going: bool,
+
+    /// Build all the modules of the workspace, the outermost module containing the current directory and the modules nested in it
+    #[clap(long, conflicts_with = "watch")]
+    pub workspace: bool,
+
     #[clap(long, hide = true)]
     pub install_path: Option<PathBuf>,
 
@@ -78,8 +112,55 @@ pub struct BuildSubcommand {
     pub bin_alias: Option<String>,
 }
 
+/// How `--timings` reports the time spent on build tasks. `text` prints a
+/// summary of the slowest tasks, and `json` also writes a file of all of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum TimingsFormat {
+    Text,
+    Json,
+}
+
+/// The format of the output of `moon build`. See
+/// [`moonbuild::build::BuildMessage`] for the messages of `json`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum MessageFormat {
+    Human,
+    Json,
+}
+
+/// Run the build, recording the timings of its tasks if `--timings` is given.
+fn with_timings<T>(
+    cli: &UniversalFlags,
+    cmd: &BuildSubcommand,
+    target_dir: &Path,
+    backend: TargetBackend,
+    build: impl FnOnce() -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let Some(format) = cmd.timings else {
+        return build();
+    };
+    if cli.trace {
+        anyhow::bail!("`--timings` can't be used with `--trace`");
+    }
+    let trace_path = timings::start(target_dir)?;
+    let res = build();
+    let timings = timings::finish(&trace_path, backend)?;
+    timings::report(
+        &timings,
+        (format == TimingsFormat::Json).then_some(target_dir),
+    )?;
+    res
+}
+
 #[instrument(skip_all)]
 pub fn run_build(cli: &UniversalFlags, cmd: &BuildSubcommand) -> anyhow::Result<i32> {
+    if cmd.workspace {
+        let cmd = BuildSubcommand {
+            workspace: false,
+            ..cmd.clone()
+        };
+        return super::run_in_workspace(cli, |cli| run_build(cli, &cmd));
+    }
     let PackageDirs {
         source_dir,
         target_dir,
@@ -89,6 +170,13 @@ pub fn run_build(cli: &UniversalFlags, cmd: &BuildSubcommand) -> anyhow::Result<
         return run_build_internal(cli, cmd, &source_dir, &target_dir);
     }
     let surface_targets = cmd.build_flags.target.clone().unwrap();
+    if let Some(targets) = super::expand_target_all(&source_dir, &surface_targets)? {
+        return Ok(super::run_for_all_targets(cli, &targets, |t| {
+            let mut cmd = (*cmd).clone();
+            cmd.build_flags.target_backend = Some(t);
+            run_build_internal(cli, &cmd, &source_dir, &target_dir)
+        }));
+    }
     let targets = lower_surface_targets(&surface_targets);
 
     let mut ret_value = 0;
@@ -118,7 +206,7 @@ fn run_build_internal(
             OptLevel::Release,
             RunMode::Build,
         );
-        let (_build_meta, build_graph) = rr_build::plan_build(
+        let (mut build_meta, build_graph) = rr_build::plan_build(
             preconfig,
             &cli.unstable_feature,
             source_dir,
@@ -129,18 +217,30 @@ fn run_build_internal(
         if cli.dry_run {
             rr_build::print_dry_run(
                 &build_graph,
-                _build_meta.artifacts.values(),
+                build_meta.artifacts.values(),
                 source_dir,
                 target_dir,
             );
             Ok(0)
         } else {
-            let result = rr_build::execute_build(
-                &BuildConfig::from_flags(&cmd.build_flags, &cli.unstable_feature),
-                build_graph,
-                target_dir,
-            )?;
-            result.print_info(cli.quiet, "building")?;
+            let message_json = cmd.message_format == MessageFormat::Json;
+            let mut cfg = BuildConfig::from_flags(&cmd.build_flags, &cli.unstable_feature);
+            cfg.message_json = message_json;
+            cfg.keep_going = cmd.keep_going;
+            let result = with_timings(cli, cmd, target_dir, build_meta.target_backend, || {
+                rr_build::execute_build(&cfg, build_graph, target_dir)
+            })?;
+            if result.successful() {
+                build_meta.optimize_wasm()?;
+                run_post_build(&build_meta)?;
+            } else if cmd.keep_going && !message_json {
+                print_failed_packages(&build_meta, &result.error_files);
+            }
+            if message_json {
+                print_build_messages(&build_meta, &result);
+            } else {
+                result.print_info(cli.quiet, "building")?;
+            }
             Ok(result.return_code_for_success())
         }
     } else {
@@ -148,13 +248,186 @@ fn run_build_internal(
     }
 }
 
-#[instrument(skip_all)]
-fn run_build_internal_legacy(
+/// Print the failed packages of a `--keep-going` Rupes Recta build, and the
+/// ones skipped because one of their dependencies failed.
+fn print_failed_packages(build_meta: &rr_build::BuildMeta, error_files: &[PathBuf]) {
+    let pkg_dirs = &build_meta.resolve_output.pkg_dirs;
+    let mut failed = vec![];
+    for file in error_files {
+        // The innermost package containing the file
+        let owner = pkg_dirs
+            .all_packages()
+            .filter(|(_, pkg)| file.starts_with(&pkg.root_path))
+            .max_by_key(|(_, pkg)| pkg.root_path.components().count());
+        if let Some((id, _)) = owner {
+            failed.push(id);
+        }
+    }
+
+    let dep_graph = &build_meta.resolve_output.pkg_rel.dep_graph;
+    let mut skipped = vec![];
+    for (id, pkg) in pkg_dirs.all_packages() {
+        if failed.contains(&id) {
+            continue;
+        }
+        let start = id.build_target(TargetKind::Source);
+        if !dep_graph.contains_node(start) {
+            continue;
+        }
+        let mut dfs = petgraph::visit::Dfs::new(dep_graph, start);
+        while let Some(dep) = dfs.next(dep_graph) {
+            if failed.contains(&dep.package) {
+                skipped.push(pkg.fqn.to_string());
+                break;
+            }
+        }
+    }
+
+    let mut failed = failed
+        .iter()
+        .map(|&id| pkg_dirs.get_package(id).fqn.to_string())
+        .collect::<Vec<_>>();
+    failed.sort();
+    failed.dedup();
+    skipped.sort();
+    moonbuild::build::print_failed_packages(&failed, &skipped);
+}
+
+/// Apply the post-build rules of the linked packages of a Rupes Recta build.
+fn run_post_build(build_meta: &rr_build::BuildMeta) -> anyhow::Result<()> {
+    for (node, artifacts) in &build_meta.artifacts {
+        let BuildPlanNode::MakeExecutable(target) = node else {
+            continue;
+        };
+        let pkg = build_meta
+            .resolve_output
+            .pkg_dirs
+            .get_package(target.package);
+        if let Some(executable) = artifacts.artifacts.first() {
+            moonbuild::build::copy_post_build_artifacts(
+                &pkg.root_path,
+                &pkg.raw.post_build,
+                executable,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Print the artifacts and the result of a Rupes Recta build as
+/// `--message-format json` messages.
+fn print_build_messages(build_meta: &rr_build::BuildMeta, result: &entry::N2RunStats) {
+    if result.successful() {
+        for (node, artifacts) in &build_meta.artifacts {
+            let Some(target) = node.extract_target() else {
+                continue;
+            };
+            let package = build_meta
+                .resolve_output
+                .pkg_dirs
+                .get_package(target.package)
+                .fqn
+                .to_string();
+            let executable = matches!(node, BuildPlanNode::MakeExecutable(_))
+                .then(|| artifacts.artifacts.first().cloned())
+                .flatten();
+            BuildMessage::BuildArtifact {
+                package,
+                backend: build_meta.target_backend.to_flag().to_string(),
+                filenames: artifacts.artifacts.clone(),
+                executable,
+            }
+            .print();
+        }
+    }
+    BuildMessage::BuildFinished {
+        success: result.successful(),
+        n_errors: result.n_errors,
+        n_warnings: result.n_warnings,
+    }
+    .print();
+}
+
+/// Plan the build of `cmd` without running it, and collect the commands
+/// building the native/LLVM sources into a compilation database.
+pub(crate) fn compile_commands(
     cli: &UniversalFlags,
     cmd: &BuildSubcommand,
     source_dir: &Path,
     target_dir: &Path,
-) -> anyhow::Result<i32> {
+) -> anyhow::Result<Vec<CompileCommand>> {
+    let ensure_native = |backend: TargetBackend| {
+        if !matches!(backend, TargetBackend::Native | TargetBackend::LLVM) {
+            anyhow::bail!(
+                "compile commands are only available for the native and LLVM backends, got `{}`",
+                backend.to_flag()
+            );
+        }
+        Ok(())
+    };
+
+    if cli.unstable_feature.rupes_recta {
+        let preconfig = preconfig_compile(
+            &cmd.auto_sync_flags,
+            cli,
+            &cmd.build_flags,
+            target_dir,
+            OptLevel::Release,
+            RunMode::Build,
+        );
+        let (build_meta, build_graph) = rr_build::plan_build(
+            preconfig,
+            &cli.unstable_feature,
+            source_dir,
+            target_dir,
+            Box::new(calc_user_intent),
+        )?;
+        ensure_native(build_meta.target_backend)?;
+        let default_files = rr_build::artifact_files(&build_graph, build_meta.artifacts.values());
+        Ok(moonbuild::gen::compile_commands::compile_commands(
+            &build_graph,
+            &default_files,
+            source_dir,
+        ))
+    } else {
+        let (moonc_opt, moonbuild_opt, module, _lock) =
+            legacy_build_setup(cli, cmd, source_dir, target_dir)?;
+        ensure_native(moonc_opt.build_opt.target_backend)?;
+        let state = moonbuild::build::load_moon_proj(&module, &moonc_opt, &moonbuild_opt)?;
+        Ok(moonbuild::gen::compile_commands::compile_commands(
+            &state.graph,
+            &state.default,
+            source_dir,
+        ))
+    }
+}
+
+/// The package graph of the project for `moon gen build-graph`, along with
+/// whether each package is up to date in the target directory of `cmd`.
+pub(crate) fn build_graph(
+    cli: &UniversalFlags,
+    cmd: &BuildSubcommand,
+    source_dir: &Path,
+    target_dir: &Path,
+) -> anyhow::Result<moonbuild::gen::build_graph::BuildGraph> {
+    if cli.unstable_feature.rupes_recta {
+        anyhow::bail!("`moon gen build-graph` is not supported with `-Z rupes_recta` yet");
+    }
+    let (moonc_opt, _, module, _lock) = legacy_build_setup(cli, cmd, source_dir, target_dir)?;
+    Ok(moonbuild::gen::build_graph::build_graph(
+        &module,
+        moonc_opt.build_opt.target_backend,
+    ))
+}
+
+/// Sync the dependencies and scan the module for a legacy build, holding the
+/// lock of the target directory.
+fn legacy_build_setup(
+    cli: &UniversalFlags,
+    cmd: &BuildSubcommand,
+    source_dir: &Path,
+    target_dir: &Path,
+) -> anyhow::Result<(MooncOpt, MoonbuildOpt, ModuleDB, FileLock)> {
     // Run moon install before build
     let (resolved_env, dir_sync_result) = auto_sync(
         source_dir,
@@ -168,7 +441,7 @@ fn run_build_internal_legacy(
     let mut moonc_opt = super::get_compiler_flags(source_dir, &cmd.build_flags)?;
     moonc_opt.build_opt.deny_warn = cmd.build_flags.deny_warn;
     let target_dir = mk_arch_mode_dir(source_dir, target_dir, &moonc_opt, run_mode)?;
-    let _lock = FileLock::lock(&target_dir)?;
+    let lock = FileLock::lock(&target_dir)?;
     let sort_input = cmd.build_flags.sort_input;
 
     // TODO: remove this once LLVM backend is well supported
@@ -190,6 +463,7 @@ fn run_build_internal_legacy(
         build_opt: Some(BuildOpt {
             install_path: cmd.install_path.clone(),
             filter_package: cmd.package.clone(),
+            keep_going: cmd.keep_going,
         }),
         fmt_opt: None,
         args: vec![],
@@ -199,6 +473,8 @@ fn run_build_internal_legacy(
         use_tcc_run: false,
         dynamic_stub_libs: None,
         render_no_loc: cmd.build_flags.render_no_loc,
+        no_progress: cmd.build_flags.no_progress,
+        content_hash: cli.unstable_feature.content_hash,
     };
 
     let mut module = scan_with_x_build(
@@ -229,6 +505,20 @@ fn run_build_internal_legacy(
         &mut module,
     )?;
 
+    Ok((moonc_opt, moonbuild_opt, module, lock))
+}
+
+#[instrument(skip_all)]
+fn run_build_internal_legacy(
+    cli: &UniversalFlags,
+    cmd: &BuildSubcommand,
+    source_dir: &Path,
+    target_dir: &Path,
+) -> anyhow::Result<i32> {
+    let raw_target_dir = target_dir;
+    let (moonc_opt, moonbuild_opt, module, _lock) =
+        legacy_build_setup(cli, cmd, source_dir, target_dir)?;
+
     if cli.dry_run {
         return dry_run::print_commands(&module, &moonc_opt, &moonbuild_opt);
     }
@@ -246,14 +536,33 @@ fn run_build_internal_legacy(
             &reg_cfg,
             &module,
             raw_target_dir,
+            &WatchOpt {
+                events_json: cmd.watch_events_json,
+                debounce: Duration::from_millis(cmd.watch_debounce_ms),
+                ignore: WatchIgnore::new(&moonbuild_opt.source_dir, &cmd.watch_ignore)?,
+            },
         )
     } else {
-        entry::run_build(&moonc_opt, &moonbuild_opt, &module)
+        with_timings(
+            cli,
+            cmd,
+            &moonbuild_opt.target_dir,
+            moonc_opt.build_opt.target_backend,
+            || match cmd.message_format {
+                MessageFormat::Human => entry::run_build(&moonc_opt, &moonbuild_opt, &module),
+                MessageFormat::Json => {
+                    moonbuild::build::run_build_json(&moonc_opt, &moonbuild_opt, &module)
+                }
+            },
+        )
     };
 
     if trace_flag {
         trace::close();
     }
+    if !cmd.watch && matches!(res, Ok(0)) {
+        moonbuild::build::run_post_build(&moonc_opt, &module)?;
+    }
     if let (Ok(_), true) = (res.as_ref(), cmd.show_artifacts) {
         // can't use HashMap because the order of the packages is not guaranteed
         // can't use IndexMap because moonc cannot handled ordered map
@@ -17,6 +17,7 @@
 // For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
 
 use anyhow::Context;
+use colored::Colorize;
 use moonbuild::dry_run;
 use moonbuild_rupes_recta::model::BuildPlanNode;
 use mooncake::pkg::sync::auto_sync;
@@ -28,9 +29,9 @@ use moonutil::{
     },
     cond_expr::OptLevel,
     dirs::{mk_arch_mode_dir, PackageDirs},
-    mooncakes::{sync::AutoSyncFlags, RegistryConfig},
+    mooncakes::{result::ResolvedEnv, sync::AutoSyncFlags, DirSyncResult, RegistryConfig},
 };
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use tracing::{instrument, Level};
 
 use crate::rr_build::{self, BuildConfig};
@@ -48,39 +49,133 @@ pub struct BundleSubcommand {
     #[clap(long)]
     pub all: bool,
 
+    /// Copy the bundled output into this directory, relative to the current
+    /// directory
+    #[clap(long, value_name = "PATH")]
+    pub output_dir: Option<PathBuf>,
+
     #[clap(flatten)]
     pub auto_sync_flags: AutoSyncFlags,
 }
 
 #[instrument(skip_all)]
-pub fn run_bundle(cli: UniversalFlags, cmd: BundleSubcommand) -> anyhow::Result<i32> {
+pub fn run_bundle(cli: UniversalFlags, mut cmd: BundleSubcommand) -> anyhow::Result<i32> {
+    let PackageDirs {
+        source_dir,
+        target_dir,
+    } = cli.source_tgt_dir.try_into_package_dirs()?;
+
+    // Resolve against where moon is run, not the project root
+    if let Some(output_dir) = &mut cmd.output_dir {
+        *output_dir = std::env::current_dir()?.join(&*output_dir);
+    }
+
+    let Some(targets) = bundle_targets(&cmd) else {
+        return run_bundle_for_target(&cli, &cmd, &source_dir, &target_dir, None);
+    };
+
+    // Dependencies are resolved once and shared by all backends
+    let synced = if cli.unstable_feature.rupes_recta {
+        None
+    } else {
+        Some(auto_sync(
+            &source_dir,
+            &cmd.auto_sync_flags,
+            &RegistryConfig::load(),
+            cli.quiet,
+        )?)
+    };
+
+    if let [t] = targets[..] {
+        let mut cmd = cmd.clone();
+        cmd.build_flags.target_backend = Some(t);
+        return run_bundle_for_target(&cli, &cmd, &source_dir, &target_dir, synced.as_ref())
+            .context(format!("failed to run bundle for target {t:?}"));
+    }
+
+    let mut succeeded = vec![];
+    let mut failed = vec![];
+    for t in targets {
+        let mut cmd = cmd.clone();
+        cmd.build_flags.target_backend = Some(t);
+        // Each backend gets its own subdirectory, e.g. `<out>/wasm-gc`
+        if let Some(output_dir) = &mut cmd.output_dir {
+            output_dir.push(t.to_dir_name());
+        }
+        match run_bundle_for_target(&cli, &cmd, &source_dir, &target_dir, synced.as_ref()) {
+            Ok(0) => succeeded.push(t.to_flag()),
+            Ok(_) => failed.push(t.to_flag()),
+            Err(e) => {
+                eprintln!(
+                    "{}: failed to run bundle for target {t:?}: {e:?}",
+                    "error".red().bold()
+                );
+                failed.push(t.to_flag());
+            }
+        }
+    }
+    if failed.is_empty() {
+        return Ok(0);
+    }
+    if !succeeded.is_empty() {
+        eprintln!("Bundled successfully for: {}", succeeded.join(", "));
+    }
+    anyhow::bail!("failed to bundle for: {}", failed.join(", "))
+}
+
+/// The backends to bundle for, or `None` to use the default one.
+fn bundle_targets(cmd: &BundleSubcommand) -> Option<Vec<TargetBackend>> {
+    let mut surface_targets = if cmd.all {
+        vec![SurfaceTarget::All]
+    } else {
+        cmd.build_flags.target.clone()?
+    };
+    if cmd.all {
+        surface_targets.push(SurfaceTarget::All);
+    }
+    let mut targets = lower_surface_targets(&surface_targets);
+    // this is a workaround for supporting bundle core for native & llvm backend when --target all
+    // should move to `lower_surface_targets` when native backend being stable
+    if cmd.all || cmd.build_flags.target == Some(vec![SurfaceTarget::All]) {
+        targets.push(TargetBackend::Native);
+    }
+    Some(targets)
+}
+
+fn run_bundle_for_target(
+    cli: &UniversalFlags,
+    cmd: &BundleSubcommand,
+    source_dir: &Path,
+    target_dir: &Path,
+    synced: Option<&(ResolvedEnv, DirSyncResult)>,
+) -> anyhow::Result<i32> {
     if cli.unstable_feature.rupes_recta {
-        run_bundle_rr(cli, cmd)
+        run_bundle_rr(cli, cmd, source_dir, target_dir)
     } else {
-        run_bundle_legacy(cli, cmd)
+        run_bundle_internal(cli, cmd, source_dir, target_dir, synced)
     }
 }
 
 #[instrument(skip_all)]
-pub fn run_bundle_rr(cli: UniversalFlags, cmd: BundleSubcommand) -> anyhow::Result<i32> {
-    let PackageDirs {
-        source_dir,
-        target_dir,
-    } = cli.source_tgt_dir.try_into_package_dirs()?;
-
+fn run_bundle_rr(
+    cli: &UniversalFlags,
+    cmd: &BundleSubcommand,
+    source_dir: &Path,
+    target_dir: &Path,
+) -> anyhow::Result<i32> {
     let preconfig = rr_build::preconfig_compile(
         &cmd.auto_sync_flags,
-        &cli,
+        cli,
         &cmd.build_flags,
-        &target_dir,
+        target_dir,
         OptLevel::Release,
         RunMode::Build,
     );
     let (_build_meta, build_graph) = rr_build::plan_build(
         preconfig,
         &cli.unstable_feature,
-        &source_dir,
-        &target_dir,
+        source_dir,
+        target_dir,
         Box::new(|_r, m| {
             Ok(m.iter()
                 .map(|&m| BuildPlanNode::Bundle(m))
@@ -93,74 +188,52 @@ pub fn run_bundle_rr(cli: UniversalFlags, cmd: BundleSubcommand) -> anyhow::Resu
         rr_build::print_dry_run(
             &build_graph,
             _build_meta.artifacts.values(),
-            &source_dir,
-            &target_dir,
+            source_dir,
+            target_dir,
         );
         Ok(0)
     } else {
         let result = rr_build::execute_build(
             &BuildConfig::from_flags(&cmd.build_flags, &cli.unstable_feature),
             build_graph,
-            &target_dir,
+            target_dir,
         )?;
         result.print_info(cli.quiet, "bundling")?;
+        if let (Some(output_dir), true) = (&cmd.output_dir, result.successful()) {
+            moonbuild::bundle::copy_to_output_dir(
+                _build_meta
+                    .artifacts
+                    .values()
+                    .flat_map(|a| a.artifacts.iter().map(|p| p.as_path())),
+                output_dir,
+            )?;
+        }
         Ok(result.return_code_for_success())
     }
 }
 
-#[instrument(skip_all)]
-pub fn run_bundle_legacy(cli: UniversalFlags, cmd: BundleSubcommand) -> anyhow::Result<i32> {
-    let PackageDirs {
-        source_dir,
-        target_dir,
-    } = cli.source_tgt_dir.try_into_package_dirs()?;
-
-    let target = if cmd.all {
-        Some(vec![SurfaceTarget::All])
-    } else {
-        cmd.build_flags.target.clone()
-    };
-
-    if target.is_none() {
-        return run_bundle_internal(&cli, &cmd, &source_dir, &target_dir);
-    }
-
-    let mut surface_targets = target.clone().unwrap();
-    if cmd.all {
-        surface_targets.push(SurfaceTarget::All);
-    }
-    let mut targets = lower_surface_targets(&surface_targets);
-    // this is a workaround for supporting bundle core for native & llvm backend when --target all
-    // should move to `lower_surface_targets` when native backend being stable
-    if cmd.all || cmd.build_flags.target == Some(vec![SurfaceTarget::All]) {
-        targets.push(TargetBackend::Native);
-    }
-
-    let mut ret_value = 0;
-    for t in targets {
-        let mut cmd = cmd.clone();
-        cmd.build_flags.target_backend = Some(t);
-        let x = run_bundle_internal(&cli, &cmd, &source_dir, &target_dir)
-            .context(format!("failed to run bundle for target {t:?}"))?;
-        ret_value = ret_value.max(x);
-    }
-    Ok(ret_value)
-}
-
 #[instrument(level = Level::DEBUG, skip_all)]
 fn run_bundle_internal(
     cli: &UniversalFlags,
     cmd: &BundleSubcommand,
     source_dir: &Path,
     target_dir: &Path,
+    synced: Option<&(ResolvedEnv, DirSyncResult)>,
 ) -> anyhow::Result<i32> {
     // Run moon install before build
-    let (resolved_env, dir_sync_result) = auto_sync(
-        source_dir,
-        &cmd.auto_sync_flags,
-        &RegistryConfig::load(),
-        cli.quiet,
-    )?;
+    let synced_here;
+    let (resolved_env, dir_sync_result) = match synced {
+        Some(synced) => synced,
+        None => {
+            synced_here = auto_sync(
+                source_dir,
+                &cmd.auto_sync_flags,
+                &RegistryConfig::load(),
+                cli.quiet,
+            )?;
+            &synced_here
+        }
+    };
 
     let run_mode = RunMode::Bundle;
     let moonc_opt = super::get_compiler_flags(source_dir, &cmd.build_flags)?;
@@ -190,13 +263,15 @@ fn run_bundle_internal(
         use_tcc_run: false,
         dynamic_stub_libs: None,
         render_no_loc: cmd.build_flags.render_no_loc,
+        no_progress: cmd.build_flags.no_progress,
+        content_hash: cli.unstable_feature.content_hash,
     };
     let module = scan_with_x_build(
         false,
         &moonc_opt,
         &moonbuild_opt,
-        &resolved_env,
-        &dir_sync_result,
+        resolved_env,
+        dir_sync_result,
         &PrePostBuild::PreBuild,
     )?;
 
@@ -204,5 +279,10 @@ fn run_bundle_internal(
         return dry_run::print_commands(&module, &moonc_opt, &moonbuild_opt);
     }
 
-    moonbuild::entry::run_bundle(&module, &moonbuild_opt, &moonc_opt)
+    moonbuild::entry::run_bundle(
+        &module,
+        &moonbuild_opt,
+        &moonc_opt,
+        cmd.output_dir.as_deref(),
+    )
 }
@@ -19,9 +19,9 @@
 use anyhow::{bail, Context};
 use colored::Colorize;
 use moonbuild::dry_run;
-use moonbuild::watch::watching;
-use moonbuild::watcher_is_running;
-use moonbuild::{entry, MOON_PID_NAME};
+use moonbuild::entry;
+use moonbuild::watch::{watching, watching_with, WatchIgnore, WatchOpt};
+use moonbuild::{watcher_is_running, WatchKind, WatcherStatus};
 use moonbuild_rupes_recta::model::{BuildPlanNode, PackageId, TargetKind};
 use mooncake::pkg::sync::auto_sync;
 use moonutil::cli::UniversalFlags;
@@ -35,6 +35,7 @@ use moonutil::mooncakes::sync::AutoSyncFlags;
 use moonutil::mooncakes::RegistryConfig;
 use n2::trace;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tracing::{instrument, Level};
 
 use crate::cli::get_module_for_single_file;
@@ -53,6 +54,14 @@ pub struct CheckSubcommand {
     #[clap(long)]
     pub output_json: bool,
 
+    /// Print diagnostics as newline-delimited JSON in the shape of LSP `Diagnostic`s, with the `path` of their file
+    #[clap(long, conflicts_with_all = ["output_json", "no_render"])]
+    pub diagnostics_json: bool,
+
+    /// Also write the diagnostics as a SARIF 2.1.0 report to the file, with the locations relative to the root of the repository
+    #[clap(long, value_name = "PATH", conflicts_with = "watch")]
+    pub sarif: Option<PathBuf>,
+
     #[clap(flatten)]
     pub auto_sync_flags: AutoSyncFlags,
 
@@ -60,6 +69,18 @@ pub struct CheckSubcommand {
     #[clap(long, short)]
     pub watch: bool,
 
+    /// Report the watch events as newline-delimited JSON to stdout
+    #[clap(long, requires = "watch")]
+    pub watch_events_json: bool,
+
+    /// Wait until files stop changing for this many milliseconds before rebuilding in watch mode, 0 to rebuild immediately
+    #[clap(long, value_name = "MS", default_value_t = 200, requires = "watch")]
+    pub watch_debounce_ms: u64,
+
+    /// Don't rebuild in watch mode for changes of the paths matching this glob, relative to the module, on top of the globs in `.moonignore`. Can be given multiple times
+    #[clap(long, value_name = "GLOB", requires = "watch")]
+    pub watch_ignore: Vec<String>,
+
     /// The package(and it's deps) to check
     #[clap(long, short)]
     pub package_path: Option<PathBuf>,
@@ -76,13 +97,24 @@ pub struct CheckSubcommand {
     #[clap(long)]
     pub explain: bool,
 
+    /// Check all the modules of the workspace, the outermost module containing the current directory and the modules nested in it
+    #[clap(long, conflicts_with_all = ["watch", "package_path"])]
+    pub workspace: bool,
+
     /// Check single file (.mbt or .mbt.md)
-    #[clap(conflicts_with = "watch")]
+    #[clap(conflicts_with_all = ["watch", "workspace"])]
     pub single_file: Option<PathBuf>,
 }
 
 #[instrument(skip_all)]
 pub fn run_check(cli: &UniversalFlags, cmd: &CheckSubcommand) -> anyhow::Result<i32> {
+    if cmd.workspace {
+        let cmd = CheckSubcommand {
+            workspace: false,
+            ..cmd.clone()
+        };
+        return super::run_in_workspace(cli, |cli| run_check(cli, &cmd));
+    }
     let (source_dir, mut target_dir) = if let Some(ref single_file_path) = cmd.single_file {
         let single_file_path = &dunce::canonicalize(single_file_path).unwrap();
         let source_dir = single_file_path.parent().unwrap().to_path_buf();
@@ -173,6 +205,8 @@ fn run_check_for_single_file(cli: &UniversalFlags, cmd: &CheckSubcommand) -> any
             patch_file: None,
             no_mi: cmd.no_mi,
             explain: cmd.explain,
+            diagnostics_json: cmd.diagnostics_json,
+            sarif: cmd.sarif.clone(),
         }),
         build_opt: None,
         sort_input: cmd.build_flags.sort_input,
@@ -188,6 +222,8 @@ fn run_check_for_single_file(cli: &UniversalFlags, cmd: &CheckSubcommand) -> any
         use_tcc_run: false,
         dynamic_stub_libs: None,
         render_no_loc: cmd.build_flags.render_no_loc,
+        no_progress: cmd.build_flags.no_progress,
+        content_hash: cli.unstable_feature.content_hash,
     };
     let moonc_opt = MooncOpt {
         build_opt: moonutil::common::BuildPackageFlags {
@@ -236,57 +272,89 @@ fn run_check_normal_internal(
     target_dir: &Path,
 ) -> anyhow::Result<i32> {
     if cli.unstable_feature.rupes_recta {
-        let mut preconfig = preconfig_compile(
-            &cmd.auto_sync_flags,
-            cli,
-            &cmd.build_flags,
-            target_dir,
-            moonutil::cond_expr::OptLevel::Release,
-            RunMode::Check,
-        );
-        preconfig.moonc_output_json |= cmd.output_json;
-
-        let (_build_meta, build_graph) = rr_build::plan_build(
-            preconfig,
-            &cli.unstable_feature,
-            source_dir,
-            target_dir,
-            Box::new(|r, m| {
-                calc_user_intent(
-                    r,
-                    m,
-                    cmd.package_path.as_deref(),
-                    cmd.no_mi,
-                    cmd.patch_file.as_deref(),
-                )
-            }),
-        )?;
-
-        if cli.dry_run {
-            rr_build::print_dry_run(
-                &build_graph,
-                _build_meta.artifacts.values(),
+        if cmd.watch && !cli.dry_run {
+            // `target_dir` is the dedicated `watch` directory, skip the whole
+            // target directory when looking for changes
+            let ignore_dir = target_dir.parent().unwrap_or(target_dir);
+            return watching_with(
                 source_dir,
                 target_dir,
+                ignore_dir,
+                WatchKind::Check,
+                &WatchOpt {
+                    events_json: cmd.watch_events_json,
+                    debounce: Duration::from_millis(cmd.watch_debounce_ms),
+                    ignore: WatchIgnore::new(source_dir, &cmd.watch_ignore)?,
+                },
+                || run_check_rr(cli, cmd, source_dir, target_dir),
             );
-            Ok(0)
-        } else {
-            // Generate metadata for IDE
-            rr_build::generate_metadata(source_dir, target_dir, &_build_meta)?;
-
-            let mut cfg = BuildConfig::from_flags(&cmd.build_flags, &cli.unstable_feature);
-            cfg.no_render |= cmd.output_json;
-            cfg.patch_file = cmd.patch_file.clone();
-            cfg.explain_errors |= cmd.explain;
-            let result = rr_build::execute_build(&cfg, build_graph, target_dir)?;
-            result.print_info(cli.quiet, "checking")?;
-            Ok(result.return_code_for_success())
         }
+        run_check_rr(cli, cmd, source_dir, target_dir)
     } else {
         run_check_normal_internal_legacy(cli, cmd, source_dir, target_dir)
     }
 }
 
+/// Plan and run the check with Rupes Recta. The module is resolved again on
+/// each call, so that the watch mode picks up changes of the configs.
+fn run_check_rr(
+    cli: &UniversalFlags,
+    cmd: &CheckSubcommand,
+    source_dir: &Path,
+    target_dir: &Path,
+) -> anyhow::Result<i32> {
+    let mut preconfig = preconfig_compile(
+        &cmd.auto_sync_flags,
+        cli,
+        &cmd.build_flags,
+        target_dir,
+        moonutil::cond_expr::OptLevel::Release,
+        RunMode::Check,
+    );
+    preconfig.moonc_output_json |= cmd.output_json;
+
+    let (_build_meta, build_graph) = rr_build::plan_build(
+        preconfig,
+        &cli.unstable_feature,
+        source_dir,
+        target_dir,
+        Box::new(|r, m| {
+            calc_user_intent(
+                r,
+                m,
+                cmd.package_path.as_deref(),
+                cmd.no_mi,
+                cmd.patch_file.as_deref(),
+            )
+        }),
+    )?;
+
+    if cli.dry_run {
+        rr_build::print_dry_run(
+            &build_graph,
+            _build_meta.artifacts.values(),
+            source_dir,
+            target_dir,
+        );
+        Ok(0)
+    } else {
+        // Generate metadata for IDE
+        rr_build::generate_metadata(source_dir, target_dir, &_build_meta)?;
+
+        let mut cfg = BuildConfig::from_flags(&cmd.build_flags, &cli.unstable_feature);
+        cfg.no_render |= cmd.output_json;
+        cfg.patch_file = cmd.patch_file.clone();
+        cfg.explain_errors |= cmd.explain;
+        cfg.diagnostics_json = cmd.diagnostics_json;
+        let result = rr_build::execute_build(&cfg, build_graph, target_dir)?;
+        if let Some(path) = &cmd.sarif {
+            moonutil::sarif::write_sarif(path, &result.outputs, source_dir)?;
+        }
+        result.print_info(cli.quiet, "checking")?;
+        Ok(result.return_code_for_success())
+    }
+}
+
 #[instrument(skip_all)]
 fn run_check_normal_internal_legacy(
     cli: &UniversalFlags,
@@ -331,6 +399,8 @@ fn run_check_normal_internal_legacy(
             patch_file: cmd.patch_file.clone(),
             no_mi: cmd.no_mi,
             explain: cmd.explain,
+            diagnostics_json: cmd.diagnostics_json,
+            sarif: cmd.sarif.clone(),
         }),
         test_opt: None,
         build_opt: None,
@@ -341,6 +411,8 @@ fn run_check_normal_internal_legacy(
         use_tcc_run: false,
         dynamic_stub_libs: None,
         render_no_loc: cmd.build_flags.render_no_loc,
+        no_progress: cmd.build_flags.no_progress,
+        content_hash: cli.unstable_feature.content_hash,
     };
 
     let mut module = scan_with_x_build(
@@ -384,18 +456,29 @@ fn run_check_normal_internal_legacy(
             &reg_cfg,
             &module,
             raw_target_dir,
+            &WatchOpt {
+                events_json: cmd.watch_events_json,
+                debounce: Duration::from_millis(cmd.watch_debounce_ms),
+                ignore: WatchIgnore::new(&moonbuild_opt.source_dir, &cmd.watch_ignore)?,
+            },
         )
     } else {
-        let pid_path = target_dir.join(MOON_PID_NAME);
-        let running = watcher_is_running(&pid_path);
+        let running = watcher_is_running(&target_dir, WatchKind::Check);
+        if let Ok(WatcherStatus::Stale { .. }) = running {
+            let _ = std::fs::remove_file(WatchKind::Check.pid_path(&target_dir));
+        }
 
-        if let Ok(true) = running {
+        if running.is_ok_and(|s| s.is_running()) {
             let output_path = target_dir.join("check.output");
             let output = std::fs::read_to_string(&output_path)
                 .context(format!("failed to open `{}`", output_path.display()))?;
             if !output.trim().is_empty() {
                 println!("{}", output.trim());
             }
+            if let Some(path) = &cmd.sarif {
+                let outputs = output.lines().map(String::from).collect::<Vec<_>>();
+                moonutil::sarif::write_sarif(path, &outputs, source_dir)?;
+            }
             Ok(if output.is_empty() { 0 } else { 1 })
         } else {
             entry::run_check(&moonc_opt, &moonbuild_opt, &module)
@@ -16,31 +16,219 @@
 //
 // For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
 
+use std::path::{Path, PathBuf};
+
 use anyhow::{bail, Context};
+use moonbuild::{remove_legacy_pid_file, watcher_is_running, WatchKind, MOON_PID_NAME};
 use moonutil::{
     cli::UniversalFlags,
-    common::{FileLock, MOON_MOD_JSON},
+    common::{FileLock, TargetBackend, MOON_LOCK, MOON_MOD_JSON, WATCH_MODE_DIR},
 };
 
 /// Remove the target directory
 #[derive(Debug, clap::Parser)]
-pub struct CleanSubcommand {}
+pub struct CleanSubcommand {
+    /// Only remove the artifacts of the given backend
+    #[clap(long, value_name = "BACKEND", value_parser = TargetBackend::str_to_backend)]
+    pub clean_backend: Option<TargetBackend>,
+}
 
-pub fn run_clean(cli: &UniversalFlags) -> anyhow::Result<i32> {
+pub fn run_clean(cli: &UniversalFlags, cmd: CleanSubcommand) -> anyhow::Result<i32> {
     if cli.dry_run {
         bail!("dry-run is not implemented for clean");
     }
 
     let src_tgt = cli.source_tgt_dir.try_into_package_dirs()?;
+    let target_dir = &src_tgt.target_dir;
+    check_removable(&src_tgt.source_dir, target_dir)?;
 
-    let _lock = FileLock::lock(&src_tgt.target_dir)?;
+    let lock = FileLock::lock(target_dir)?;
 
     if !moonutil::common::check_moon_mod_exists(&src_tgt.source_dir) {
         bail!("could not find `{}`", MOON_MOD_JSON);
     }
 
-    if src_tgt.target_dir.is_dir() {
-        std::fs::remove_dir_all(src_tgt.target_dir).context("failed to remove target directory")?;
+    // The watchers of `moon check --watch` run in a directory of their own
+    let watch_dir = target_dir.join(WATCH_MODE_DIR);
+    let mut keep = vec![target_dir.join(MOON_LOCK), watch_dir.join(MOON_LOCK)];
+    for dir in [target_dir, &watch_dir] {
+        keep.extend(running_pid_files(dir)?);
+    }
+
+    let mut freed = Freed::default();
+    match cmd.clean_backend {
+        Some(backend) => {
+            for dir in [target_dir, &watch_dir] {
+                remove_stale_pid_files(dir, &keep, &mut freed)?;
+                let subtree = dir.join(backend.to_dir_name());
+                if subtree.exists() {
+                    let subtree = dunce::canonicalize(&subtree)
+                        .with_context(|| format!("failed to resolve `{}`", subtree.display()))?;
+                    if !subtree.starts_with(target_dir) {
+                        bail!(
+                            "refusing to remove `{}`, which is outside the target directory `{}`",
+                            subtree.display(),
+                            target_dir.display()
+                        );
+                    }
+                    freed.remove(&subtree)?;
+                }
+            }
+        }
+        None => {
+            remove_all_except(target_dir, &keep, &mut freed)?;
+            drop(lock);
+            // Nothing is left but the lock
+            let only_lock = std::fs::read_dir(target_dir)?
+                .flatten()
+                .all(|entry| entry.file_name() == MOON_LOCK);
+            if only_lock {
+                std::fs::remove_dir_all(target_dir).context("failed to remove target directory")?;
+            }
+        }
+    }
+
+    if !cli.quiet {
+        println!(
+            "Removed {} files, {} total",
+            freed.files,
+            human_size(freed.bytes)
+        );
     }
     Ok(0)
 }
+
+/// Refuse to clean a `target_dir` that contains the module, or that was set
+/// to a directory moon has never used as a target directory.
+fn check_removable(source_dir: &Path, target_dir: &Path) -> anyhow::Result<()> {
+    if source_dir.starts_with(target_dir) {
+        bail!(
+            "refusing to remove `{}`, which contains the module at `{}`",
+            target_dir.display(),
+            source_dir.display()
+        );
+    }
+    // Every command using a target directory locks it, leaving the lock behind
+    let is_default = target_dir == source_dir.join("target");
+    let is_empty = std::fs::read_dir(target_dir)?.next().is_none();
+    if !is_default && !is_empty && !target_dir.join(MOON_LOCK).exists() {
+        bail!(
+            "refusing to remove `{}`, which doesn't look like a target directory",
+            target_dir.display()
+        );
+    }
+    Ok(())
+}
+
+/// The PID files of the watchers still running in `dir`, and the legacy PID
+/// file if it can't be told stale.
+fn running_pid_files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut res = vec![];
+    if !dir.is_dir() {
+        return Ok(res);
+    }
+    for kind in WatchKind::ALL {
+        if watcher_is_running(dir, kind).is_ok_and(|status| status.is_running()) {
+            res.push(kind.pid_path(dir));
+        }
+    }
+    remove_legacy_pid_file(dir)?;
+    let legacy = dir.join(MOON_PID_NAME);
+    if legacy.exists() {
+        res.push(legacy);
+    }
+    Ok(res)
+}
+
+/// Remove the PID files of the watchers no longer running in `dir`.
+fn remove_stale_pid_files(dir: &Path, keep: &[PathBuf], freed: &mut Freed) -> anyhow::Result<()> {
+    for kind in WatchKind::ALL {
+        let path = kind.pid_path(dir);
+        if path.exists() && !keep.contains(&path) {
+            freed.remove(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove everything in `dir` but the paths in `keep`.
+fn remove_all_except(dir: &Path, keep: &[PathBuf], freed: &mut Freed) -> anyhow::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if keep.contains(&path) {
+            continue;
+        }
+        if path.is_dir() && keep.iter().any(|it| it.starts_with(&path)) {
+            remove_all_except(&path, keep, freed)?;
+        } else {
+            freed.remove(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// The files removed and their total size.
+#[derive(Default)]
+struct Freed {
+    files: usize,
+    bytes: u64,
+}
+
+impl Freed {
+    fn remove(&mut self, path: &Path) -> anyhow::Result<()> {
+        for entry in walkdir::WalkDir::new(path).into_iter().flatten() {
+            if entry.file_type().is_file() {
+                self.files += 1;
+                self.bytes += entry.metadata().map_or(0, |m| m.len());
+            }
+        }
+        let res = if path.is_dir() {
+            std::fs::remove_dir_all(path)
+        } else {
+            std::fs::remove_file(path)
+        };
+        res.with_context(|| format!("failed to remove `{}`", path.display()))
+    }
+}
+
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024. && unit < UNITS.len() - 1 {
+        size /= 1024.;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes}{}", UNITS[0])
+    } else {
+        format!("{size:.1}{}", UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_human_size() {
+        assert_eq!(human_size(512), "512B");
+        assert_eq!(human_size(1536), "1.5KiB");
+        assert_eq!(human_size(3 << 30), "3.0GiB");
+    }
+
+    #[test]
+    fn test_check_removable() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("module");
+        let custom = dir.path().join("custom");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::create_dir_all(&custom).unwrap();
+        assert!(check_removable(&source, dir.path()).is_err());
+        assert!(check_removable(&source, &custom).is_ok());
+        std::fs::write(custom.join("notes.txt"), "").unwrap();
+        assert!(check_removable(&source, &custom).is_err());
+        std::fs::write(custom.join(MOON_LOCK), "").unwrap();
+        assert!(check_removable(&source, &custom).is_ok());
+    }
+}
@@ -17,6 +17,7 @@
 // For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
 
 use anyhow::bail;
+use moonbuild::doc_http::{ServeOptions, TlsFiles};
 use moonbuild::dry_run::print_commands;
 use moonbuild_rupes_recta::model::BuildPlanNode;
 use mooncake::pkg::sync::auto_sync;
@@ -27,6 +28,7 @@ use moonutil::common::{
 use moonutil::dirs::{mk_arch_mode_dir, PackageDirs};
 use moonutil::mooncakes::sync::AutoSyncFlags;
 use moonutil::mooncakes::RegistryConfig;
+use std::path::PathBuf;
 use tracing::instrument;
 
 use super::pre_build::scan_with_x_build;
@@ -50,10 +52,45 @@ pub struct DocSubcommand {
     #[clap(long, short, default_value = "3000", requires("serve"))]
     pub port: u16,
 
+    /// The PEM certificate chain to serve HTTPS with
+    #[clap(long, value_name = "FILE", requires_all = ["serve", "tls_key"])]
+    pub tls_cert: Option<PathBuf>,
+
+    /// The PEM private key of `--tls-cert`, in PKCS #8
+    #[clap(long, value_name = "FILE", requires_all = ["serve", "tls_cert"])]
+    pub tls_key: Option<PathBuf>,
+
+    /// Reload the pages open in the browser when the documentation is
+    /// regenerated
+    #[clap(long, requires("serve"))]
+    pub live_reload: bool,
+
+    /// Listen on a Unix domain socket instead of a TCP port
+    #[cfg(unix)]
+    #[clap(long, value_name = "PATH", requires("serve"), conflicts_with_all = ["bind", "port"])]
+    pub unix_socket: Option<PathBuf>,
+
     #[clap(flatten)]
     pub auto_sync_flags: AutoSyncFlags,
 }
 
+impl DocSubcommand {
+    fn serve_options(&self) -> ServeOptions {
+        ServeOptions {
+            bind: self.bind.clone(),
+            port: self.port,
+            tls: self
+                .tls_cert
+                .clone()
+                .zip(self.tls_key.clone())
+                .map(|(cert, key)| TlsFiles { cert, key }),
+            live_reload: self.live_reload,
+            #[cfg(unix)]
+            unix_socket: self.unix_socket.clone(),
+        }
+    }
+}
+
 #[instrument(skip_all)]
 pub fn run_doc(cli: UniversalFlags, cmd: DocSubcommand) -> anyhow::Result<i32> {
     if cli.unstable_feature.rupes_recta {
@@ -128,7 +165,7 @@ pub fn run_doc_rr(cli: UniversalFlags, cmd: DocSubcommand) -> anyhow::Result<i32
             .mod_name_from_id(mid)
             .name()
             .to_string();
-        moonbuild::doc_http::start_server(static_dir, &full_name, cmd.bind, cmd.port)?;
+        moonbuild::doc_http::start_server(static_dir, &full_name, cmd.serve_options())?;
     }
 
     Ok(0)
@@ -145,14 +182,13 @@ pub fn run_doc_legacy(cli: UniversalFlags, cmd: DocSubcommand) -> anyhow::Result
     if !static_dir.exists() {
         std::fs::create_dir_all(&static_dir)?;
     }
-    let _lock = FileLock::lock(&static_dir)?;
+    let lock = FileLock::lock(&static_dir)?;
 
     if static_dir.exists() {
         static_dir.rm_rf();
     }
     let serve = cmd.serve;
-    let bind = cmd.bind;
-    let port = cmd.port;
+    let serve_options = cmd.serve_options();
 
     let mod_desc = read_module_desc_file_in_dir(&source_dir)?;
 
@@ -191,6 +227,8 @@ pub fn run_doc_legacy(cli: UniversalFlags, cmd: DocSubcommand) -> anyhow::Result
         use_tcc_run: false,
         dynamic_stub_libs: None,
         render_no_loc: DiagnosticLevel::default(),
+        no_progress: false,
+        content_hash: cli.unstable_feature.content_hash,
     };
 
     let module = scan_with_x_build(
@@ -233,7 +271,9 @@ pub fn run_doc_legacy(cli: UniversalFlags, cmd: DocSubcommand) -> anyhow::Result
     }
 
     if serve {
-        moonbuild::doc_http::start_server(static_dir, &mod_desc.name, bind, port)?;
+        // let another `moon doc` regenerate the docs while serving them
+        drop(lock);
+        moonbuild::doc_http::start_server(static_dir, &mod_desc.name, serve_options)?;
     }
     Ok(0)
 }
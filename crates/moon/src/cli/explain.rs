@@ -0,0 +1,64 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+use anyhow::bail;
+use moonutil::error_code_docs::get_error_code_doc;
+
+/// Print the explanation of a compiler error code
+#[derive(Debug, clap::Parser)]
+pub struct ExplainSubcommand {
+    /// The error code, e.g. `E0001` or `1`
+    pub code: String,
+}
+
+pub fn run_explain(cmd: ExplainSubcommand) -> anyhow::Result<i32> {
+    let Some(code) = normalize_error_code(&cmd.code) else {
+        bail!(
+            "`{}` is not an error code, it should look like `E0001`",
+            cmd.code
+        );
+    };
+    let Some(doc) = get_error_code_doc(&code) else {
+        bail!(
+            "no explanation for error code `E{code}`, check the output of `moon check` for the exact code"
+        );
+    };
+    println!("{}", doc.trim_end());
+    Ok(0)
+}
+
+/// Format the error code like `MooncDiagnostic::formatted_error_code`, i.e.
+/// four digits without the leading `E`.
+fn normalize_error_code(code: &str) -> Option<String> {
+    let digits = code.strip_prefix(['E', 'e']).unwrap_or(code);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = digits.parse().ok()?;
+    Some(format!("{code:04}"))
+}
+
+#[test]
+fn test_normalize_error_code() {
+    assert_eq!(normalize_error_code("E0001").as_deref(), Some("0001"));
+    assert_eq!(normalize_error_code("e4021").as_deref(), Some("4021"));
+    assert_eq!(normalize_error_code("23").as_deref(), Some("0023"));
+    assert_eq!(normalize_error_code("E"), None);
+    assert_eq!(normalize_error_code("W0001"), None);
+    assert_eq!(normalize_error_code("E-1"), None);
+}
@@ -16,20 +16,72 @@
 //
 // For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
 
+//! Extension commands: `moon xyz` runs the `moon-xyz` executable found in
+//! `PATH` with the rest of the arguments, if `xyz` isn't a builtin command.
+//! The extension gets the path of the running `moon` in `$MOON`, to call back
+//! into the same toolchain.
+
 use anyhow::{bail, Context as _};
+use clap::CommandFactory;
 use std::process::{Command, ExitStatus};
 use which::which_global;
 
+use super::MoonBuildCli;
+
+/// The environment variable holding the path of `moon` for extensions.
+pub const MOON_ENV: &str = "MOON";
+
 pub fn run_external(mut args: Vec<String>) -> anyhow::Result<i32> {
     if args.is_empty() {
         bail!("no external subcommand provided");
     };
     let subcmd = args.remove(0);
     let bin = &format!("moon-{subcmd}");
-    let resolved = which_global(bin).context(anyhow::format_err!(
-        "no such subcommand: `{subcmd}`, is `{bin}` a valid executable accessible via your `PATH`?"
-    ))?;
-    Ok(exec(Command::new(resolved).args(args))?.code().unwrap_or(0))
+    let resolved = match which_global(bin) {
+        Ok(resolved) => resolved,
+        Err(_) => {
+            let hint = match similar_builtin(&subcmd) {
+                Some(builtin) => format!("\n\ndid you mean `{builtin}`?"),
+                None => String::new(),
+            };
+            bail!("no such command: `{subcmd}`, and no `{bin}` executable found in `PATH`{hint}");
+        }
+    };
+    let mut cmd = Command::new(&resolved);
+    cmd.args(args);
+    if let Ok(moon) = std::env::current_exe() {
+        cmd.env(MOON_ENV, moon);
+    }
+    let status = exec(&mut cmd).with_context(|| format!("failed to run {}", resolved.display()))?;
+    Ok(status.code().unwrap_or(0))
+}
+
+/// The visible builtin command closest to the mistyped `subcmd`, if any is
+/// close enough to be a typo of it.
+fn similar_builtin(subcmd: &str) -> Option<String> {
+    MoonBuildCli::command()
+        .get_subcommands()
+        .filter(|it| !it.is_hide_set())
+        .map(|it| it.get_name().to_string())
+        .map(|name| (edit_distance(subcmd, &name), name))
+        .filter(|(distance, name)| *distance <= (name.len() / 3).max(1))
+        .min()
+        .map(|(_, name)| name)
+}
+
+/// The Levenshtein distance between `a` and `b`, counted in chars.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = prev[j] + usize::from(ca != *cb);
+            cur.push(substitute.min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
 }
 
 #[cfg(unix)]
@@ -57,3 +109,17 @@ fn exec(cmd: &mut Command) -> anyhow::Result<ExitStatus> {
 
     Ok(cmd.status()?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_similar_builtin() {
+        assert_eq!(edit_distance("tset", "test"), 2);
+        assert_eq!(edit_distance("", "fmt"), 3);
+        assert_eq!(similar_builtin("buld").as_deref(), Some("build"));
+        assert_eq!(similar_builtin("chek").as_deref(), Some("check"));
+        assert_eq!(similar_builtin("deploy"), None);
+    }
+}
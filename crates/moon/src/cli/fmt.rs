@@ -16,16 +16,23 @@
 //
 // For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
 
-use anyhow::Context;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use anyhow::{bail, Context};
 use moonbuild::dry_run;
 use moonbuild_rupes_recta::fmt::FmtConfig;
 use mooncake::pkg::sync::auto_sync;
 use moonutil::{
     common::{
         BlockStyle, DiagnosticLevel, FileLock, FmtOpt, MoonbuildOpt, MooncOpt, PrePostBuild,
-        RunMode,
+        RunMode, DOT_MBT_DOT_MD,
     },
     dirs::{mk_arch_mode_dir, PackageDirs},
+    git::{changed_files_since, is_in_git_repo},
     mooncakes::{sync::AutoSyncFlags, RegistryConfig},
 };
 
@@ -34,12 +41,17 @@ use crate::rr_build::{self, BuildConfig};
 use super::{pre_build::scan_with_x_build, UniversalFlags};
 
 /// Format source code
-#[derive(Debug, clap::Parser)]
+#[derive(Debug, clap::Parser, Clone)]
 pub struct FmtSubcommand {
-    /// Check only and don't change the source code
+    /// Check only and don't change the source code, listing the files that
+    /// aren't formatted. Exits with a non-zero code if there are any
     #[clap(long)]
     check: bool,
 
+    /// Print the differences of the files that aren't formatted
+    #[clap(long, requires = "check")]
+    diff: bool,
+
     /// Sort input files
     #[clap(long)]
     pub sort_input: bool,
@@ -47,17 +59,123 @@ pub struct FmtSubcommand {
     /// Add separator between each segments
     #[clap(long, value_enum, num_args=0..=1, default_missing_value = "true")]
     pub block_style: Option<BlockStyle>,
+
+    /// Format the code read from stdin and write the result to stdout,
+    /// without touching any file
+    #[clap(long, conflicts_with_all = ["check", "sort_input"])]
+    pub stdin: bool,
+
+    /// The path of the file being formatted from stdin. It isn't read, but
+    /// gives the formatter the file name and directory as context
+    #[clap(long, value_name = "PATH", requires = "stdin")]
+    pub stdin_filepath: Option<PathBuf>,
+
+    /// Only format the files changed since the git ref, including uncommitted
+    /// and untracked ones
+    #[clap(long, value_name = "REF", conflicts_with = "stdin")]
+    pub changed_since: Option<String>,
+
+    /// Format all the modules of the workspace, the outermost module
+    /// containing the current directory and the modules nested in it
+    #[clap(long, conflicts_with = "stdin")]
+    pub workspace: bool,
+
     pub args: Vec<String>,
 }
 
 pub fn run_fmt(cli: &UniversalFlags, cmd: FmtSubcommand) -> anyhow::Result<i32> {
-    if cli.unstable_feature.rupes_recta {
+    if cmd.workspace {
+        let cmd = FmtSubcommand {
+            workspace: false,
+            ..cmd
+        };
+        return super::run_in_workspace(cli, |cli| run_fmt(cli, cmd.clone()));
+    }
+    if cmd.stdin {
+        run_fmt_stdin(&cmd)
+    } else if cli.unstable_feature.rupes_recta {
         run_fmt_rr(cli, cmd)
     } else {
         run_fmt_legacy(cli, cmd)
     }
 }
 
+/// Format stdin to stdout. `moonfmt` only works on files, so the code goes
+/// through a temporary one named after `--stdin-filepath`.
+fn run_fmt_stdin(cmd: &FmtSubcommand) -> anyhow::Result<i32> {
+    let mut source = Vec::new();
+    std::io::stdin()
+        .read_to_end(&mut source)
+        .context("failed to read stdin")?;
+
+    let file_name = cmd
+        .stdin_filepath
+        .as_deref()
+        .and_then(Path::file_name)
+        .unwrap_or(OsStr::new("stdin.mbt"));
+    let temp_dir = tempfile::tempdir()?;
+    let input = temp_dir.path().join(file_name);
+    std::fs::write(&input, &source)?;
+    std::fs::create_dir(temp_dir.path().join("out"))?;
+    let output = temp_dir.path().join("out").join(file_name);
+
+    let mut moonfmt = std::process::Command::new("moonfmt");
+    moonfmt
+        .arg(&input)
+        .arg("-o")
+        .arg(&output)
+        .args(&cmd.args)
+        // stdout is for the formatted code only
+        .stdout(std::io::stderr())
+        .stderr(Stdio::inherit());
+    if cmd.block_style.as_ref().is_some_and(BlockStyle::is_line) {
+        moonfmt.arg("-block-style");
+    }
+    if let Some(dir) = cmd
+        .stdin_filepath
+        .as_deref()
+        .and_then(Path::parent)
+        .filter(|dir| dir.is_dir())
+    {
+        moonfmt.current_dir(dir);
+    }
+    let status = moonfmt.status().context("failed to run moonfmt")?;
+    if !status.success() {
+        return Ok(status.code().unwrap_or(1));
+    }
+
+    let formatted = std::fs::read(&output).context("moonfmt produced no output")?;
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(&formatted)?;
+    stdout.flush()?;
+    Ok(0)
+}
+
+/// The canonicalized paths of the MoonBit files changed since `--changed-since`
+fn changed_files(
+    source_dir: &Path,
+    cmd: &FmtSubcommand,
+) -> anyhow::Result<Option<HashSet<PathBuf>>> {
+    let Some(git_ref) = &cmd.changed_since else {
+        return Ok(None);
+    };
+    if !is_in_git_repo(source_dir)? {
+        bail!(
+            "`--changed-since` requires {} to be in a git repository",
+            source_dir.display()
+        );
+    }
+    let files = changed_files_since(source_dir, git_ref)?
+        .into_iter()
+        .filter(|f| {
+            let name = f.to_string_lossy();
+            name.ends_with(".mbt") || name.ends_with(DOT_MBT_DOT_MD)
+        })
+        .filter_map(|f| dunce::canonicalize(f).ok())
+        .collect();
+    Ok(Some(files))
+}
+
 fn run_fmt_rr(cli: &UniversalFlags, cmd: FmtSubcommand) -> anyhow::Result<i32> {
     let PackageDirs {
         source_dir,
@@ -67,9 +185,11 @@ fn run_fmt_rr(cli: &UniversalFlags, cmd: FmtSubcommand) -> anyhow::Result<i32> {
     let resolved = moonbuild_rupes_recta::fmt::resolve_for_fmt(&source_dir)
         .context("Failed to resolve environment")?;
     let fmt_config = FmtConfig {
-        block_style: cmd.block_style.unwrap_or_default().is_line(),
+        block_style: cmd.block_style.as_ref().is_some_and(BlockStyle::is_line),
         check_only: cmd.check,
+        show_diff: cmd.diff,
         extra_args: cmd.args.clone(),
+        only_files: changed_files(&source_dir, &cmd)?,
     };
     let graph =
         moonbuild_rupes_recta::fmt::build_graph_for_fmt(&resolved, &fmt_config, &target_dir)?;
@@ -99,11 +219,15 @@ fn run_fmt_legacy(cli: &UniversalFlags, cmd: FmtSubcommand) -> anyhow::Result<i3
     // Resolve dependencies, but don't download anything
     let (resolved_env, dir_sync_result) = auto_sync(
         &source_dir,
-        &AutoSyncFlags { frozen: true },
+        &AutoSyncFlags {
+            frozen: true,
+            locked: false,
+        },
         &RegistryConfig::load(),
         cli.quiet,
     )?;
 
+    let only_files = changed_files(&source_dir, &cmd)?;
     let moonbuild_opt = MoonbuildOpt {
         source_dir,
         raw_target_dir,
@@ -112,8 +236,10 @@ fn run_fmt_legacy(cli: &UniversalFlags, cmd: FmtSubcommand) -> anyhow::Result<i3
         run_mode,
         fmt_opt: Some(FmtOpt {
             check: cmd.check,
+            diff: cmd.diff,
             block_style: cmd.block_style.unwrap_or_default(),
             extra_args: cmd.args,
+            only_files,
         }),
         build_graph: cli.build_graph,
         test_opt: None,
@@ -128,6 +254,8 @@ fn run_fmt_legacy(cli: &UniversalFlags, cmd: FmtSubcommand) -> anyhow::Result<i3
         use_tcc_run: false,
         dynamic_stub_libs: None,
         render_no_loc: DiagnosticLevel::default(),
+        no_progress: false,
+        content_hash: cli.unstable_feature.content_hash,
     };
 
     let module = scan_with_x_build(
@@ -0,0 +1,178 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Generate files for external tools.
+
+use clap_complete::Shell;
+use colored::Colorize;
+use moonutil::{
+    common::{lower_surface_targets, TargetBackend},
+    dirs::PackageDirs,
+    mooncakes::sync::AutoSyncFlags,
+};
+
+use super::build::MessageFormat;
+use super::{gen_shellcomp, BuildFlags, BuildSubcommand, ShellCompSubCommand, UniversalFlags};
+
+/// Generate files for external tools
+#[derive(Debug, clap::Parser)]
+pub struct GenSubcommand {
+    #[clap(subcommand)]
+    pub cmd: GenSubcommands,
+}
+
+#[derive(Debug, clap::Parser)]
+pub enum GenSubcommands {
+    BuildGraph(BuildGraphSubcommand),
+    CompileCommands(CompileCommandsSubcommand),
+    Completions(CompletionsSubcommand),
+}
+
+/// Print the package dependency graph of the project, and whether each package is up to date
+#[derive(Debug, clap::Parser)]
+pub struct BuildGraphSubcommand {
+    #[clap(flatten)]
+    pub build_flags: BuildFlags,
+
+    #[clap(flatten)]
+    pub auto_sync_flags: AutoSyncFlags,
+
+    /// The format of the graph, `dot` for Graphviz
+    #[clap(long, value_name = "FORMAT", default_value = "dot")]
+    pub format: GraphFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum GraphFormat {
+    Dot,
+    Json,
+}
+
+/// Write a `compile_commands.json` database of the native/LLVM build to the project root
+#[derive(Debug, clap::Parser)]
+pub struct CompileCommandsSubcommand {
+    #[clap(flatten)]
+    pub build_flags: BuildFlags,
+
+    #[clap(flatten)]
+    pub auto_sync_flags: AutoSyncFlags,
+}
+
+/// Print the completion script of a shell to stdout, see `moon shell-completion --help` for where to install it
+#[derive(Debug, clap::Parser)]
+pub struct CompletionsSubcommand {
+    /// The shell to generate completion for
+    #[clap(value_enum, ignore_case = true)]
+    pub shell: Shell,
+}
+
+pub fn run_gen(cli: &UniversalFlags, cmd: GenSubcommand) -> anyhow::Result<i32> {
+    match cmd.cmd {
+        GenSubcommands::BuildGraph(c) => run_gen_build_graph(cli, c),
+        GenSubcommands::CompileCommands(c) => run_gen_compile_commands(cli, c),
+        // The same as `moon shell-completion`, with the shell as argument
+        GenSubcommands::Completions(c) => {
+            gen_shellcomp(cli, ShellCompSubCommand { shell: c.shell })
+        }
+    }
+}
+
+fn run_gen_build_graph(cli: &UniversalFlags, cmd: BuildGraphSubcommand) -> anyhow::Result<i32> {
+    let PackageDirs {
+        source_dir,
+        target_dir,
+    } = cli.source_tgt_dir.try_into_package_dirs()?;
+
+    let mut build_flags = cmd.build_flags;
+    if let Some(targets) = build_flags.target.as_deref() {
+        build_flags.target_backend = match lower_surface_targets(targets)[..] {
+            [t] => Some(t),
+            _ => anyhow::bail!("`moon gen build-graph` takes a single `--target`"),
+        };
+    }
+    let build = build_subcommand(build_flags, cmd.auto_sync_flags);
+
+    let graph = super::build::build_graph(cli, &build, &source_dir, &target_dir)?;
+    for cycle in &graph.cycles {
+        eprintln!(
+            "{}: import cycle between packages: {}",
+            "Warning".yellow(),
+            cycle.join(", ")
+        );
+    }
+    match cmd.format {
+        GraphFormat::Dot => print!("{}", graph.to_dot()),
+        GraphFormat::Json => println!("{}", serde_json::to_string_pretty(&graph)?),
+    }
+    Ok(0)
+}
+
+/// A plain `moon build` with the given flags.
+fn build_subcommand(build_flags: BuildFlags, auto_sync_flags: AutoSyncFlags) -> BuildSubcommand {
+    BuildSubcommand {
+        build_flags,
+        auto_sync_flags,
+        watch: false,
+        watch_events_json: false,
+        watch_debounce_ms: 0,
+        watch_ignore: vec![],
+        timings: None,
+        message_format: MessageFormat::Human,
+        keep_going: false,
+        workspace: false,
+        install_path: None,
+        show_artifacts: false,
+        package: None,
+        bin_alias: None,
+    }
+}
+
+fn run_gen_compile_commands(
+    cli: &UniversalFlags,
+    cmd: CompileCommandsSubcommand,
+) -> anyhow::Result<i32> {
+    let PackageDirs {
+        source_dir,
+        target_dir,
+    } = cli.source_tgt_dir.try_into_package_dirs()?;
+
+    let mut build_flags = cmd.build_flags;
+    build_flags.target_backend = match build_flags.target.as_deref() {
+        None => Some(TargetBackend::Native),
+        Some(targets) => match lower_surface_targets(targets)[..] {
+            [t] => Some(t),
+            _ => anyhow::bail!("`moon gen compile-commands` takes a single `--target`"),
+        },
+    };
+    let build = build_subcommand(build_flags, cmd.auto_sync_flags);
+
+    let commands = super::build::compile_commands(cli, &build, &source_dir, &target_dir)?;
+    if cli.dry_run {
+        println!("{}", serde_json::to_string_pretty(&commands)?);
+        return Ok(0);
+    }
+    let path = moonbuild::gen::compile_commands::write_compile_commands(&commands, &source_dir)?;
+    if !cli.quiet {
+        println!(
+            "Wrote {} compile commands to {}",
+            commands.len(),
+            path.display()
+        );
+    }
+    Ok(0)
+}
@@ -42,6 +42,7 @@ use moonutil::{
 use crate::{
     cli::BuildFlags,
     rr_build::{self, BuildConfig, CalcUserIntentOutput},
+    run::{executable_version, ExecutableSource, RuntimeExecutableCache},
 };
 
 use super::{pre_build::scan_with_x_build, UniversalFlags};
@@ -67,9 +68,18 @@ pub struct InfoSubcommand {
     // (username/hello/lib)
     #[clap(short, long)]
     pub package: Option<String>,
+
+    /// Print the runtime executables the backends run with instead, with
+    /// where each is found and its version
+    #[clap(long)]
+    pub print_runtime: bool,
 }
 
 pub fn run_info(cli: UniversalFlags, cmd: InfoSubcommand) -> anyhow::Result<i32> {
+    if cmd.print_runtime {
+        print_runtime();
+        return Ok(0);
+    }
     if cli.unstable_feature.rupes_recta {
         run_info_rr(cli, cmd)
     } else {
@@ -116,6 +126,71 @@ pub fn run_info_rr(cli: UniversalFlags, cmd: InfoSubcommand) -> anyhow::Result<i
     }
 }
 
+/// Print a table of the executables of [`RuntimeExecutableCache`], as `moon`
+/// finds them to run the output of the backends.
+fn print_runtime() {
+    let cache = RuntimeExecutableCache::default();
+    let mut rows = vec![[
+        "RUNTIME".to_string(),
+        "USED FOR".to_string(),
+        "VERSION".to_string(),
+        "SOURCE".to_string(),
+        "PATH".to_string(),
+    ]];
+    for (name, found) in cache.all_executables() {
+        let (version, source, path) = match found {
+            Ok((_, ExecutableSource::NotFound)) => (
+                "-".to_string(),
+                ExecutableSource::NotFound.to_string(),
+                "-".to_string(),
+            ),
+            Ok((path, source)) => (
+                executable_version(path).map_or_else(|_| "unknown".to_string(), |v| v.to_string()),
+                source.to_string(),
+                path.display().to_string(),
+            ),
+            Err(e) => ("-".to_string(), format!("error: {e}"), "-".to_string()),
+        };
+        rows.push([
+            name.to_string(),
+            runtime_used_for(name).to_string(),
+            version,
+            source,
+            path,
+        ]);
+    }
+    let mut widths = [0; 5];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    for row in &rows {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    }
+}
+
+/// What the runtime executable `name` runs, for [`print_runtime`].
+fn runtime_used_for(name: &str) -> &'static str {
+    match name {
+        "moonrun" => "wasm, wasm-gc",
+        "wasmtime" => "wasi, wasm-gc with `--wasm-gc-runner wasmtime`",
+        "node" => "js",
+        "deno" => "js with `--js-runtime deno`",
+        "bun" => "js with `--js-runtime bun`",
+        "tcc" => "native with `tcc -run`",
+        "lldb" | "gdb" => "native with `--debugger`",
+        "rustica-engine" => "moongres",
+        _ => "",
+    }
+}
+
 fn calc_user_intent(
     resolve_output: &moonbuild_rupes_recta::ResolveOutput,
     main_modules: &[moonutil::mooncakes::ModuleId],
@@ -290,6 +365,8 @@ pub fn run_info_internal(
         use_tcc_run: false,
         dynamic_stub_libs: None,
         render_no_loc: DiagnosticLevel::default(),
+        no_progress: false,
+        content_hash: cli.unstable_feature.content_hash,
     };
 
     let mdb = scan_with_x_build(
@@ -21,6 +21,7 @@ use std::process::Stdio;
 use anyhow::bail;
 use moonutil::{
     cli::UniversalFlags,
+    common::ensure_online,
     mooncake_bin::call_mooncake,
     mooncakes::{
         LoginSubcommand, MooncakeSubcommands, PackageSubcommand, PublishSubcommand,
@@ -78,14 +79,17 @@ pub fn execute_cli_with_inherit_stdin<T: Serialize>(
 }
 
 pub fn login_cli(cli: UniversalFlags, cmd: LoginSubcommand) -> anyhow::Result<i32> {
+    ensure_online("log in")?;
     execute_cli_with_inherit_stdin(cli, MooncakeSubcommands::Login(cmd), &["login"])
 }
 
 pub fn register_cli(cli: UniversalFlags, cmd: RegisterSubcommand) -> anyhow::Result<i32> {
+    ensure_online("register")?;
     execute_cli_with_inherit_stdin(cli, MooncakeSubcommands::Register(cmd), &["register"])
 }
 
 pub fn publish_cli(cli: UniversalFlags, cmd: PublishSubcommand) -> anyhow::Result<i32> {
+    ensure_online("publish")?;
     execute_cli(
         cli,
         MooncakeSubcommands::Publish(cmd),
@@ -16,12 +16,17 @@
 //
 // For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
 
-use std::{fs::File, io::BufReader, path::PathBuf};
+use std::{
+    fs::File,
+    io::{BufReader, IsTerminal},
+    path::PathBuf,
+};
 
-use anyhow::bail;
+use anyhow::{bail, Context};
 use colored::Colorize;
+use moonbuild::new::NewLayout;
 use moonutil::{
-    common::{MOON_MOD_JSON, MOON_PKG_JSON},
+    common::{TargetBackend, MOON_MOD_JSON, MOON_PKG_JSON},
     moon_dir,
     mooncakes::{validate_username, Credentials},
 };
@@ -67,6 +72,29 @@ pub struct NewSubcommand {
     /// The name of the module. Default to the last part of the path.
     #[clap(long)]
     pub name: Option<String>,
+
+    /// The preferred target backend of the module.
+    #[clap(long, value_parser = TargetBackend::str_to_backend)]
+    pub backend: Option<TargetBackend>,
+
+    /// Create a library, without any main package.
+    #[clap(long, conflicts_with = "exec")]
+    pub lib: bool,
+
+    /// Create an executable, with a main package `cmd/main` using the library.
+    /// Without `--lib` or `--exec`, ask interactively, or create an executable
+    /// when not in a terminal.
+    #[clap(long)]
+    pub exec: bool,
+
+    /// Create the module from a template, which is a directory or a git
+    /// repository URL, instead of the built-in one.
+    #[clap(long, conflicts_with_all = ["backend", "lib", "exec"])]
+    pub template: Option<String>,
+
+    /// Create the module from the template even if the path is not empty.
+    #[clap(long, requires = "template")]
+    pub force: bool,
 }
 
 pub fn run_new(_cli: &UniversalFlags, cmd: NewSubcommand) -> anyhow::Result<i32> {
@@ -110,5 +138,42 @@ pub fn run_new(_cli: &UniversalFlags, cmd: NewSubcommand) -> anyhow::Result<i32>
     {
         bail!("Project name {} contains invalid characters. Only alphanumeric characters and underscore are allowed.", project_name);
     }
-    moonbuild::new::moon_new_default(&path, username, project_name)
+    match cmd.template {
+        Some(template) => moonbuild::new::moon_new_from_template(
+            &path,
+            &template,
+            username,
+            project_name,
+            cmd.force,
+        ),
+        None => {
+            let layout = if cmd.lib {
+                NewLayout::Lib
+            } else if cmd.exec {
+                NewLayout::Exec
+            } else {
+                ask_layout()?
+            };
+            moonbuild::new::moon_new_default(&path, username, project_name, cmd.backend, layout)
+        }
+    }
+}
+
+/// Ask whether to create a library or an executable. When not in a terminal,
+/// create an executable, which is the layout before `--lib` was introduced.
+fn ask_layout() -> anyhow::Result<NewLayout> {
+    if !std::io::stdin().is_terminal() {
+        return Ok(NewLayout::Exec);
+    }
+    let choice = dialoguer::Select::new()
+        .with_prompt("Create a library or an executable?")
+        .items(&["library", "executable"])
+        .default(0)
+        .interact()
+        .context("failed to read the layout of the module")?;
+    Ok(if choice == 0 {
+        NewLayout::Lib
+    } else {
+        NewLayout::Exec
+    })
 }
@@ -16,11 +16,14 @@
 //
 // For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use anyhow::{bail, Context};
 use moonbuild::dry_run;
 use moonbuild::entry;
+use moonbuild::watch::{watching_then, WatchIgnore, WatchOpt};
+use moonbuild::WatchKind;
 use moonbuild_rupes_recta::model::BuildPlanNode;
 use moonbuild_rupes_recta::model::BuildTarget;
 use mooncake::pkg::sync::auto_sync;
@@ -41,6 +44,7 @@ use moonutil::dirs::PackageDirs;
 use moonutil::moon_dir::MOON_DIRS;
 use moonutil::mooncakes::sync::AutoSyncFlags;
 use moonutil::mooncakes::RegistryConfig;
+use moonutil::package::MoonPkgJSON;
 use n2::trace;
 use tracing::{instrument, Level};
 
@@ -48,16 +52,20 @@ use crate::rr_build;
 use crate::rr_build::preconfig_compile;
 use crate::rr_build::{BuildConfig, CalcUserIntentOutput};
 use crate::run::default_rt;
+use crate::run::ChildStdin;
 use crate::run::CommandGuard;
+use crate::run::SpawnedCommand;
+use crate::run::{Debugger, RuntimeConfig, RuntimeExecutableCache};
 
 use super::pre_build::scan_with_x_build;
-use super::{BuildFlags, UniversalFlags};
+use super::{BuildFlags, RuntimeFlags, UniversalFlags};
 
 /// Run a main package
 #[derive(Debug, clap::Parser, Clone)]
 pub struct RunSubcommand {
     /// The package or .mbt file to run
-    pub package_or_mbt_file: String,
+    #[clap(required_unless_present = "example")]
+    pub package_or_mbt_file: Option<String>,
 
     #[clap(flatten)]
     pub build_flags: BuildFlags,
@@ -71,10 +79,87 @@ pub struct RunSubcommand {
     /// Only build, do not run the code
     #[clap(long)]
     pub build_only: bool,
+
+    /// Read the standard input of the program from the given file instead of
+    /// inheriting it (only with Rupes Recta)
+    #[clap(long, value_name = "FILE", conflicts_with = "build_only")]
+    pub stdin_file: Option<PathBuf>,
+
+    /// The working directory to run the program in, instead of the current
+    /// one (only with Rupes Recta)
+    #[clap(long, value_name = "DIR", conflicts_with = "build_only")]
+    pub run_dir: Option<PathBuf>,
+
+    /// Run the native executable under the given debugger (only with Rupes
+    /// Recta)
+    #[clap(long, value_name = "DEBUGGER", conflicts_with = "build_only")]
+    pub debugger: Option<Debugger>,
+
+    /// Run the native executable under a sampling profiler, `perf` or
+    /// `samply`, writing the profile next to it (only with Rupes Recta)
+    #[clap(long, conflicts_with_all = ["build_only", "debugger"])]
+    pub profile: bool,
+
+    /// Rebuild when the files change, and restart the program after each
+    /// successful rebuild (only with Rupes Recta)
+    #[clap(long, conflicts_with_all = ["build_only", "debugger", "profile"])]
+    pub watch: bool,
+
+    /// Wait until files stop changing for this many milliseconds before
+    /// rebuilding in watch mode, 0 to rebuild immediately
+    #[clap(long, value_name = "MS", default_value_t = 200, requires = "watch")]
+    pub watch_debounce_ms: u64,
+
+    /// Don't rebuild in watch mode for changes of the paths matching this
+    /// glob, relative to the module, on top of the globs in `.moonignore`. Can
+    /// be given multiple times
+    #[clap(long, value_name = "GLOB", requires = "watch")]
+    pub watch_ignore: Vec<String>,
+
+    /// What to do with the running program when a rebuild in watch mode
+    /// fails: `keep` it running, or `stop` it until a rebuild succeeds
+    #[clap(
+        long,
+        value_name = "POLICY",
+        value_enum,
+        default_value_t = WatchFailure::Keep,
+        requires = "watch"
+    )]
+    pub watch_on_failure: WatchFailure,
+
+    /// Run the example of the given name, i.e. the main package in
+    /// `examples/<name>` of the module. The arguments after it are all passed
+    /// to the program
+    #[clap(long, value_name = "NAME")]
+    pub example: Option<String>,
+
+    #[clap(flatten)]
+    pub runtime_flags: RuntimeFlags,
+}
+
+/// What `moon run --watch` does with the running program when a rebuild fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum WatchFailure {
+    Keep,
+    Stop,
+}
+
+impl RunSubcommand {
+    fn package_or_mbt_file(&self) -> &str {
+        self.package_or_mbt_file
+            .as_deref()
+            .expect("the package is required unless running an example")
+    }
 }
 
+/// The directory of the examples of a module.
+const EXAMPLES_DIR: &str = "examples";
+
 #[instrument(skip_all)]
-pub fn run_run(cli: &UniversalFlags, cmd: RunSubcommand) -> anyhow::Result<i32> {
+pub fn run_run(cli: &UniversalFlags, mut cmd: RunSubcommand) -> anyhow::Result<i32> {
+    if let Some(name) = cmd.example.take() {
+        select_example(cli, &mut cmd, &name)?;
+    }
     if let Some(surface_targets) = &cmd.build_flags.target {
         for st in surface_targets.iter() {
             if *st == SurfaceTarget::All {
@@ -98,10 +183,96 @@ pub fn run_run(cli: &UniversalFlags, cmd: RunSubcommand) -> anyhow::Result<i32>
     }
 }
 
+/// Make `cmd` run the example `name` of the module. Without `--target`, the
+/// example runs on the first backend it supports if it doesn't support the
+/// default one.
+fn select_example(cli: &UniversalFlags, cmd: &mut RunSubcommand, name: &str) -> anyhow::Result<()> {
+    let PackageDirs { source_dir, .. } = cli.source_tgt_dir.try_into_package_dirs()?;
+    let moon_mod = moonutil::common::read_module_desc_file_in_dir(&source_dir)?;
+    // The examples are packages of the module, so they are where its packages are
+    let examples_dir = source_dir
+        .join(moon_mod.source.as_deref().unwrap_or_default())
+        .join(EXAMPLES_DIR);
+    let example_dir = examples_dir.join(name);
+    if !check_moon_pkg_exist(&example_dir) {
+        let available = available_examples(&examples_dir);
+        if available.is_empty() {
+            bail!(
+                "no example `{name}`, there are no examples in {}",
+                examples_dir.display()
+            );
+        }
+        bail!(
+            "no example `{name}`, the available examples are:\n{}",
+            available
+                .iter()
+                .map(|it| format!("  {it}"))
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+    }
+
+    // The positional arguments all go to the program, like with `cargo run
+    // --example`, so the first one isn't taken as the package
+    if let Some(first) = cmd.package_or_mbt_file.take() {
+        cmd.args.insert(0, first);
+    }
+    cmd.package_or_mbt_file = Some(if cli.unstable_feature.rupes_recta {
+        // Rupes Recta resolves the package from the current directory
+        example_dir.display().to_string()
+    } else {
+        example_dir.strip_prefix(&source_dir)?.display().to_string()
+    });
+
+    if cmd.build_flags.target.is_none() {
+        // Read `moon.pkg.json` as is, as `MoonPkg` doesn't keep the order of
+        // the supported targets
+        let pkg_json = example_dir.join(MOON_PKG_JSON);
+        let pkg: MoonPkgJSON = serde_json_lenient::from_str(&std::fs::read_to_string(&pkg_json)?)
+            .context(format!("Failed to parse {}", pkg_json.display()))?;
+        let supported = pkg
+            .supported_targets
+            .unwrap_or_default()
+            .iter()
+            .map(|it| TargetBackend::str_to_backend(it))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let default = moon_mod.preferred_target.unwrap_or_default();
+        if let Some(target) = example_target(&supported, default) {
+            cmd.build_flags.target_backend = Some(target);
+        }
+    }
+    Ok(())
+}
+
+/// The backend to run an example on, given the `supported` targets in its
+/// `moon.pkg.json`: the first one if they don't include the `default`, or
+/// `None` to keep the default.
+fn example_target(supported: &[TargetBackend], default: TargetBackend) -> Option<TargetBackend> {
+    if supported.contains(&default) {
+        None
+    } else {
+        supported.first().copied()
+    }
+}
+
+/// The names of the examples in `examples_dir`, sorted.
+fn available_examples(examples_dir: &Path) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(examples_dir) else {
+        return vec![];
+    };
+    let mut names = entries
+        .flatten()
+        .filter(|it| check_moon_pkg_exist(&it.path()))
+        .map(|it| it.file_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
 #[instrument(level = Level::DEBUG, skip_all)]
 fn run_single_mbt_file(cli: &UniversalFlags, cmd: RunSubcommand) -> anyhow::Result<i32> {
     let current_dir = std::env::current_dir()?;
-    let mbt_file_path = dunce::canonicalize(current_dir.join(cmd.package_or_mbt_file))?;
+    let mbt_file_path = dunce::canonicalize(current_dir.join(cmd.package_or_mbt_file()))?;
     let mbt_file_parent_path = mbt_file_path.parent().unwrap();
 
     if !mbt_file_path.is_file() {
@@ -113,6 +284,7 @@ fn run_single_mbt_file(cli: &UniversalFlags, cmd: RunSubcommand) -> anyhow::Resu
     let target_backend = lower_surface_targets(&cmd.build_flags.target.unwrap_or_default())
         .first()
         .map_or(TargetBackend::default(), |it| *it);
+    super::check_legacy_target(target_backend)?;
     let core_bundle_path = moonutil::moon_dir::core_bundle(target_backend);
 
     let output_artifact_path = mbt_file_parent_path.join("target");
@@ -248,6 +420,7 @@ fn run_single_mbt_file(cli: &UniversalFlags, cmd: RunSubcommand) -> anyhow::Resu
                 TargetBackend::MoonGRES => {
                     println!("rustica-engine run {}", output_wasm_or_js_path.display());
                 }
+                TargetBackend::Wasi => unreachable!("checked by `check_legacy_target`"),
                 TargetBackend::Js => {
                     println!("node {}", output_wasm_or_js_path.display());
                 }
@@ -312,6 +485,7 @@ fn run_single_mbt_file(cli: &UniversalFlags, cmd: RunSubcommand) -> anyhow::Resu
         TargetBackend::MoonGRES => {
             moonbuild::build::run_moongres(&output_wasm_or_js_path, &cmd.args, cli.verbose)
         }
+        TargetBackend::Wasi => unreachable!("checked by `check_legacy_target`"),
         TargetBackend::Js => {
             moonbuild::build::run_js(&output_wasm_or_js_path, &cmd.args, cli.verbose)
         }
@@ -336,6 +510,22 @@ pub fn run_run_internal(cli: &UniversalFlags, cmd: RunSubcommand) -> anyhow::Res
     if cli.unstable_feature.rupes_recta {
         run_run_rr(cli, cmd)
     } else {
+        cmd.runtime_flags.check_legacy_unsupported()?;
+        if cmd.stdin_file.is_some() {
+            bail!("`--stdin-file` is only supported with `-Z rupes_recta`");
+        }
+        if cmd.run_dir.is_some() {
+            bail!("`--run-dir` is only supported with `-Z rupes_recta`");
+        }
+        if cmd.debugger.is_some() {
+            bail!("`--debugger` is only supported with `-Z rupes_recta`");
+        }
+        if cmd.profile {
+            bail!("`--profile` is only supported with `-Z rupes_recta`");
+        }
+        if cmd.watch {
+            bail!("`--watch` is only supported with `-Z rupes_recta`");
+        }
         run_run_internal_legacy(cli, cmd)
     }
 }
@@ -346,8 +536,11 @@ fn run_run_rr(cli: &UniversalFlags, cmd: RunSubcommand) -> Result<i32, anyhow::E
         source_dir,
         target_dir,
     } = cli.source_tgt_dir.try_into_package_dirs()?;
+    if cmd.watch && !cli.dry_run {
+        return run_run_rr_watch(cli, &cmd, &source_dir, &target_dir);
+    }
 
-    let input_path = cmd.package_or_mbt_file;
+    let input_path = cmd.package_or_mbt_file().to_owned();
     let preconfig = preconfig_compile(
         &cmd.auto_sync_flags,
         cli,
@@ -356,13 +549,18 @@ fn run_run_rr(cli: &UniversalFlags, cmd: RunSubcommand) -> Result<i32, anyhow::E
         Release,
         RunMode::Run,
     );
-    let (build_meta, build_graph) = rr_build::plan_build(
+    let (mut build_meta, build_graph) = rr_build::plan_build(
         preconfig,
         &cli.unstable_feature,
         &source_dir,
         &target_dir,
         Box::new(|r, m| calc_user_intent(&input_path, r, m)),
     )?;
+    let runtime_config = RuntimeConfig {
+        debugger: cmd.debugger,
+        profile: cmd.profile,
+        ..cmd.runtime_flags.runtime_config()?
+    };
     if cli.dry_run {
         // Print build commands
         rr_build::print_dry_run(
@@ -372,8 +570,14 @@ fn run_run_rr(cli: &UniversalFlags, cmd: RunSubcommand) -> Result<i32, anyhow::E
             &target_dir,
         );
 
-        let cmd = get_run_cmd(&build_meta)?;
-        rr_build::dry_print_command(cmd.command.as_std());
+        let mut argv = crate::run::command_line_for(
+            build_meta.target_backend,
+            &runtime_config,
+            run_executable(&build_meta),
+            None,
+        )?;
+        argv.extend(cmd.args.iter().map(Into::into));
+        rr_build::dry_print_command(&argv);
 
         Ok(0)
     } else {
@@ -386,15 +590,35 @@ fn run_run_rr(cli: &UniversalFlags, cmd: RunSubcommand) -> Result<i32, anyhow::E
         if !build_result.successful() {
             return Ok(build_result.return_code_for_success());
         }
-
-        let cmd = get_run_cmd(&build_meta)?;
+        build_meta.optimize_wasm()?;
+
+        let mut run_cmd = get_run_cmd(&build_meta, &runtime_config, cmd.run_dir.as_deref())?;
+        // Every backend leaves the command ready for the arguments of the
+        // program, after its own `--` if it has one
+        run_cmd.command.args(&cmd.args);
+        run_cmd.stdin = match cmd.stdin_file {
+            Some(path) => ChildStdin::File(path),
+            None => ChildStdin::Inherit,
+        };
 
         // FIXME: Simplify this part
         let res = default_rt()
             .context("Failed to create runtime")?
-            .block_on(crate::run::run(&mut [], true, cmd.command))
+            .block_on(run_cmd.run_with_timeout(&mut [], None, None))
             .context("failed to run command")?;
 
+        if cmd.profile {
+            let (profiler, _) = RuntimeExecutableCache::default().profiler()?;
+            let path = profiler.output_path(run_executable(&build_meta));
+            if path.exists() {
+                eprintln!(
+                    "Profile written to {}, view it with `{}`",
+                    path.display(),
+                    profiler.view_command(&path)
+                );
+            }
+        }
+
         if let Some(code) = res.code() {
             Ok(code)
         } else {
@@ -403,17 +627,103 @@ fn run_run_rr(cli: &UniversalFlags, cmd: RunSubcommand) -> Result<i32, anyhow::E
     }
 }
 
-#[instrument(level = Level::DEBUG, skip_all)]
-fn get_run_cmd(build_meta: &rr_build::BuildMeta) -> Result<CommandGuard, anyhow::Error> {
+/// Rebuild on each change like `moon build --watch`, and restart the program
+/// after each successful rebuild. The program of the previous build is stopped
+/// before the new one starts, so that they don't fight over e.g. ports.
+fn run_run_rr_watch(
+    cli: &UniversalFlags,
+    cmd: &RunSubcommand,
+    source_dir: &Path,
+    target_dir: &Path,
+) -> anyhow::Result<i32> {
+    let rt = default_rt().context("Failed to create runtime")?;
+    let runtime_config = cmd.runtime_flags.runtime_config()?;
+    let mut running: Option<SpawnedCommand> = None;
+    let stop = |running: &mut Option<SpawnedCommand>| -> anyhow::Result<()> {
+        if let Some(program) = running.take() {
+            rt.block_on(program.terminate())?;
+        }
+        Ok(())
+    };
+    watching_then(
+        source_dir,
+        target_dir,
+        target_dir,
+        WatchKind::Run,
+        &WatchOpt {
+            events_json: false,
+            debounce: Duration::from_millis(cmd.watch_debounce_ms),
+            ignore: WatchIgnore::new(source_dir, &cmd.watch_ignore)?,
+        },
+        || {
+            // The module is resolved again on each rebuild, to pick up changes
+            // of the configs
+            let input_path = cmd.package_or_mbt_file().to_owned();
+            let preconfig = preconfig_compile(
+                &cmd.auto_sync_flags,
+                cli,
+                &cmd.build_flags,
+                target_dir,
+                Release,
+                RunMode::Run,
+            );
+            let (mut build_meta, build_graph) = rr_build::plan_build(
+                preconfig,
+                &cli.unstable_feature,
+                source_dir,
+                target_dir,
+                Box::new(|r, m| calc_user_intent(&input_path, r, m)),
+            )?;
+            let build_result = rr_build::execute_build(
+                &BuildConfig::from_flags(&cmd.build_flags, &cli.unstable_feature),
+                build_graph,
+                target_dir,
+            )?;
+            if !build_result.successful() {
+                return Ok(Err(build_result.return_code_for_success()));
+            }
+            build_meta.optimize_wasm()?;
+            let mut run_cmd = get_run_cmd(&build_meta, &runtime_config, cmd.run_dir.as_deref())?;
+            run_cmd.command.args(&cmd.args);
+            run_cmd.stdin = match &cmd.stdin_file {
+                Some(path) => ChildStdin::File(path.clone()),
+                None => ChildStdin::Inherit,
+            };
+            Ok(Ok(run_cmd))
+        },
+        |built| match built {
+            Some(run_cmd) => {
+                stop(&mut running)?;
+                let _enter = rt.enter();
+                running = Some(run_cmd.spawn()?);
+                Ok(())
+            }
+            None if cmd.watch_on_failure == WatchFailure::Stop => stop(&mut running),
+            None => Ok(()),
+        },
+    )
+}
+
+/// The executable to run, i.e. the only output of the only build node
+fn run_executable(build_meta: &rr_build::BuildMeta) -> &Path {
     let (_, artifact) = build_meta
         .artifacts
         .first()
         .expect("Expected exactly one build node emitted by `calc_user_intent`");
-    let executable = artifact
+    artifact
         .artifacts
         .first()
-        .expect("Expected exactly one executable as the output of the build node");
-    let cmd = crate::run::command_for(build_meta.target_backend, executable, None)?;
+        .expect("Expected exactly one executable as the output of the build node")
+}
+
+#[instrument(level = Level::DEBUG, skip_all)]
+fn get_run_cmd(
+    build_meta: &rr_build::BuildMeta,
+    config: &RuntimeConfig,
+    cwd: Option<&Path>,
+) -> Result<CommandGuard, anyhow::Error> {
+    let executable = run_executable(build_meta);
+    let cmd = crate::run::command_for(build_meta.target_backend, config, executable, None, cwd)?;
     Ok(cmd)
 }
 
@@ -479,10 +789,10 @@ fn calc_user_intent(
 #[instrument(skip_all)]
 fn run_run_internal_legacy(cli: &UniversalFlags, cmd: RunSubcommand) -> anyhow::Result<i32> {
     let moon_pkg_json_exist = std::env::current_dir()?
-        .join(&cmd.package_or_mbt_file)
+        .join(cmd.package_or_mbt_file())
         .parent()
         .is_some_and(|p| p.join(MOON_PKG_JSON).exists());
-    if cmd.package_or_mbt_file.ends_with(".mbt") && !moon_pkg_json_exist {
+    if cmd.package_or_mbt_file().ends_with(".mbt") && !moon_pkg_json_exist {
         return run_single_mbt_file(cli, cmd);
     }
 
@@ -513,9 +823,9 @@ fn run_run_internal_legacy(cli: &UniversalFlags, cmd: RunSubcommand) -> anyhow::
     let sort_input = cmd.build_flags.sort_input;
 
     // run .mbt inside a package should run as a package
-    let package_path = if cmd.package_or_mbt_file.ends_with(".mbt") {
+    let package_path = if cmd.package_or_mbt_file().ends_with(".mbt") {
         // `package_path` based on `source_dir`
-        let full_path = std::env::current_dir()?.join(cmd.package_or_mbt_file);
+        let full_path = std::env::current_dir()?.join(cmd.package_or_mbt_file());
         dunce::canonicalize(&full_path)
             .with_context(|| format!("can't canonicalize {}", full_path.display()))?
             .parent()
@@ -524,7 +834,7 @@ fn run_run_internal_legacy(cli: &UniversalFlags, cmd: RunSubcommand) -> anyhow::
             .display()
             .to_string()
     } else {
-        cmd.package_or_mbt_file
+        cmd.package_or_mbt_file().to_owned()
     };
     let package = source_dir.join(&package_path);
     if !check_moon_pkg_exist(&package) {
@@ -555,6 +865,8 @@ fn run_run_internal_legacy(cli: &UniversalFlags, cmd: RunSubcommand) -> anyhow::
         use_tcc_run: false,
         dynamic_stub_libs: None,
         render_no_loc: cmd.build_flags.render_no_loc,
+        no_progress: cmd.build_flags.no_progress,
+        content_hash: cli.unstable_feature.content_hash,
     };
 
     let mut module = scan_with_x_build(
@@ -595,3 +907,18 @@ fn run_run_internal_legacy(cli: &UniversalFlags, cmd: RunSubcommand) -> anyhow::
     }
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_example_target() {
+        use TargetBackend::*;
+        assert_eq!(example_target(&[], WasmGC), None);
+        assert_eq!(example_target(&[Js, WasmGC], WasmGC), None);
+        // in the declared order, not by name
+        assert_eq!(example_target(&[Native, Js], WasmGC), Some(Native));
+        assert_eq!(example_target(&[Js, Native], WasmGC), Some(Js));
+    }
+}
@@ -22,6 +22,7 @@ use anyhow::Context;
 use colored::Colorize;
 use indexmap::IndexMap;
 use log::warn;
+use moonbuild::benchmark::{self, BenchReport};
 use moonbuild::dry_run;
 use moonbuild::entry;
 use moonbuild_rupes_recta::build_plan::InputDirective;
@@ -49,6 +50,8 @@ use n2::trace;
 use smallvec::SmallVec;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::io::IsTerminal;
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tracing::{instrument, Level};
@@ -56,13 +59,23 @@ use tracing::{instrument, Level};
 use crate::cli::pre_build::scan_with_x_build;
 use crate::rr_build;
 use crate::rr_build::preconfig_compile;
-use crate::rr_build::{BuildConfig, CalcUserIntentOutput};
-use crate::run::perform_promotion;
+use crate::rr_build::{BuildConfig, BuildMeta, BuildProfile, CalcUserIntentOutput};
+use crate::run::remote::RemoteExecutor;
+use crate::run::InFlightTests;
+use crate::run::NativeRunner;
+use crate::run::ReplaceableTestResults;
+use crate::run::RuntimeConfig;
+use crate::run::Shard;
+use crate::run::TapReporter;
+use crate::run::TestCoverage;
 use crate::run::TestFilter;
 use crate::run::TestIndex;
+use crate::run::TestNameFilter;
+use crate::run::TestRunConfig;
+use crate::run::{perform_promotion, PromotionScope};
 
-use super::BenchSubcommand;
-use super::{BuildFlags, UniversalFlags};
+use super::{BenchFlags, BenchOutputFormat, BenchSubcommand};
+use super::{BuildFlags, RuntimeFlags, UniversalFlags};
 
 /// Print test summary statistics in the legacy format
 fn print_test_summary(total: usize, passed: usize, quiet: bool, backend_hint: Option<&str>) {
@@ -92,6 +105,15 @@ fn print_test_summary(total: usize, passed: usize, quiet: bool, backend_hint: Op
     }
 }
 
+/// A machine-readable format to report test results in. `junit` writes JUnit
+/// XML to `--reporter-output` in addition to the console output, while `tap`
+/// streams TAP to stdout instead of the console output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum TestReporter {
+    Junit,
+    Tap,
+}
+
 /// Test the current package
 #[derive(Debug, clap::Parser, Clone)]
 pub struct TestSubcommand {
@@ -114,6 +136,23 @@ pub struct TestSubcommand {
     #[clap(long, conflicts_with = "index")]
     pub doc_index: Option<u32>,
 
+    /// Run only the test with this name in the file, i.e. `test "NAME" { ... }`.
+    /// Only valid when `--file` is also specified (only with Rupes Recta)
+    #[clap(
+        long,
+        value_name = "NAME",
+        conflicts_with_all = [
+            "index",
+            "doc_index",
+            "filter",
+            "filter_regex",
+            "filter_file",
+            "exclude_file",
+            "shard"
+        ]
+    )]
+    pub test_name: Option<String>,
+
     /// Update the test snapshot
     #[clap(short, long)]
     pub update: bool,
@@ -122,6 +161,62 @@ pub struct TestSubcommand {
     #[clap(short, long, default_value = "256", requires("update"))]
     pub limit: u32,
 
+    /// Only update the expect tests and snapshots in these files, leaving the
+    /// others failing (only with Rupes Recta)
+    #[clap(long, value_name = "FILE", num_args(1..), requires("update"))]
+    pub update_file: Option<Vec<PathBuf>>,
+
+    /// Show the diff of each expect test and snapshot update, and ask whether
+    /// to accept it (only with Rupes Recta)
+    #[clap(long, requires("update"))]
+    pub interactive: bool,
+
+    /// Only run the tests whose fully-qualified names, like
+    /// `user/mod/pkg/file_test.mbt::name`, match the glob (only with Rupes Recta)
+    #[clap(long, value_name = "GLOB")]
+    pub filter: Option<String>,
+
+    /// Only run the tests whose fully-qualified names match the regex (only
+    /// with Rupes Recta)
+    #[clap(long, value_name = "REGEX", conflicts_with = "filter")]
+    pub filter_regex: Option<String>,
+
+    /// Only run the tests matching any of the globs listed in the file, one
+    /// per line, ignoring blank lines and `#` comments. Combined with
+    /// `--filter` as a union (only with Rupes Recta)
+    #[clap(long, value_name = "FILE")]
+    pub filter_file: Option<PathBuf>,
+
+    /// Skip the tests matching any of the globs listed in the file, in the
+    /// format of `--filter-file` (only with Rupes Recta)
+    #[clap(long, value_name = "FILE")]
+    pub exclude_file: Option<PathBuf>,
+
+    /// Only run the INDEX-th of COUNT shards of the tests, e.g. `2/5`. Tests
+    /// are sorted by name and dealt to the shards in turn, so when the tests
+    /// don't divide evenly, the first shards get one more (only with Rupes
+    /// Recta)
+    #[clap(long, value_name = "INDEX/COUNT")]
+    pub shard: Option<Shard>,
+
+    /// Print the fully-qualified names of the tests instead of running them
+    /// (only with Rupes Recta)
+    #[clap(long, conflicts_with_all = ["update", "build_only"])]
+    pub list_tests: bool,
+
+    /// Print the tests of `--list-tests` as a JSON array, with their package,
+    /// file, index and line
+    #[clap(long, requires = "list_tests")]
+    pub json: bool,
+
+    /// Collect the line coverage of native or JS tests, writing an lcov report
+    /// to `coverage.lcov` in the target directory. Native tests are measured
+    /// with LLVM source-based coverage, needing clang, `llvm-profdata` and
+    /// `llvm-cov`, and JS tests with the V8 coverage of the generated JS,
+    /// needing node (only with Rupes Recta)
+    #[clap(long, conflicts_with_all = ["enable_coverage", "list_tests"])]
+    pub coverage: bool,
+
     #[clap(flatten)]
     pub auto_sync_flags: AutoSyncFlags,
 
@@ -133,6 +228,12 @@ pub struct TestSubcommand {
     #[clap(long)]
     pub no_parallelize: bool,
 
+    /// Print the output of tests as they run instead of after each test
+    /// executable finishes. Implies `--no-parallelize`, so that the output of
+    /// concurrent tests doesn't interleave
+    #[clap(long)]
+    pub no_capture: bool,
+
     /// Print failure message in JSON format
     #[clap(long)]
     pub test_failure_json: bool,
@@ -141,18 +242,101 @@ pub struct TestSubcommand {
     #[clap(long, requires("package"), conflicts_with = "update")]
     pub patch_file: Option<PathBuf>,
 
-    /// Run doc test
-    #[clap(long = "doc")]
+    /// Only run the doc tests, i.e. the code blocks in doc comments and
+    /// `.mbt.md` files, reporting the ones marked `no-run` as not run (only
+    /// with Rupes Recta)
+    #[clap(long = "doc", conflicts_with = "update")]
     pub doc_test: bool,
 
+    #[clap(flatten)]
+    pub runtime_flags: RuntimeFlags,
+
+    /// Also report the test results in the given format (only with Rupes Recta)
+    #[clap(long)]
+    pub reporter: Option<TestReporter>,
+
+    /// The file to write the report to, `junit.xml` in the target directory by default
+    #[clap(long, value_name = "FILE", requires = "reporter")]
+    pub reporter_output: Option<PathBuf>,
+
+    /// The seed passed to tests that randomize, a random one by default (only
+    /// with Rupes Recta)
+    #[clap(long, value_name = "SEED")]
+    pub test_seed: Option<u64>,
+
+    /// Rerun the failed tests up to the given times, reporting the ones passing
+    /// on a retry as flaky (only with Rupes Recta)
+    #[clap(long, value_name = "N", default_value_t = 0, conflicts_with = "update")]
+    pub retry: u32,
+
+    /// Stop launching tests once a test fails, instead of running all of them
+    /// (only with Rupes Recta)
+    #[clap(long, overrides_with = "no_fail_fast", conflicts_with = "update")]
+    pub fail_fast: bool,
+
+    /// Run all tests regardless of failures, the default. Overrides an earlier
+    /// `--fail-fast`
+    #[clap(long, overrides_with = "fail_fast")]
+    pub no_fail_fast: bool,
+
+    /// Whether `--fail-fast` kills the tests still running when a test fails,
+    /// or lets them finish and reports their results
+    #[clap(
+        long,
+        value_name = "MODE",
+        value_enum,
+        default_value_t,
+        requires = "fail_fast"
+    )]
+    pub fail_fast_in_flight: InFlightTests,
+
+    /// Keep at most this much output of each test, e.g. `10MB`, dropping the
+    /// rest after a note of the truncation (only with Rupes Recta)
+    #[clap(long, value_name = "SIZE", value_parser = parse_byte_size)]
+    pub capture_limit: Option<usize>,
+
+    /// Run native test executables on a remote host, through the agent started by the given command, e.g. `ssh farm moon-remote-agent` (only with Rupes Recta)
+    #[clap(long, value_name = "COMMAND", conflicts_with = "coverage")]
+    pub remote_executor: Option<String>,
+
+    /// Test all the modules of the workspace, the outermost module containing the current directory and the modules nested in it
+    #[clap(long, conflicts_with = "package")]
+    pub workspace: bool,
+
     /// Run test in single file (.mbt or .mbt.md). If in a project, runs only
     /// this file (equivalent to `-p` + `-f`); otherwise, runs in a temporary project.
-    #[clap(conflicts_with_all = ["file", "package"])]
+    #[clap(conflicts_with_all = ["file", "package", "workspace"])]
     pub single_file: Option<PathBuf>,
 }
 
+/// Parse a size in bytes, with an optional unit of `B`, `KB`, `MB` or `GB`,
+/// each 1024 times the previous one.
+fn parse_byte_size(s: &str) -> Result<usize, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let n = number.parse::<usize>().map_err(|e| e.to_string())?;
+    let scale: usize = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "KB" => 1 << 10,
+        "MB" => 1 << 20,
+        "GB" => 1 << 30,
+        _ => return Err(format!("unknown unit `{unit}`, expected B, KB, MB or GB")),
+    };
+    n.checked_mul(scale)
+        .ok_or_else(|| "the size is too large".into())
+}
+
 #[instrument(skip_all)]
 pub fn run_test(cli: UniversalFlags, cmd: TestSubcommand) -> anyhow::Result<i32> {
+    if cmd.workspace {
+        let cmd = TestSubcommand {
+            workspace: false,
+            ..cmd
+        };
+        return super::run_in_workspace(&cli, |cli| run_test(cli.clone(), cmd.clone()));
+    }
+
     // Check if we're running within a project
     let dirs = match cli.source_tgt_dir.try_into_package_dirs() {
         Ok(dirs) => dirs,
@@ -169,34 +353,45 @@ pub fn run_test(cli: UniversalFlags, cmd: TestSubcommand) -> anyhow::Result<i32>
         }
     };
 
-    if cmd.doc_test {
+    if cmd.doc_test && !cli.unstable_feature.rupes_recta {
         eprintln!(
             "{}: --doc flag is deprecated and will be removed in the future, please use `moon test` directly",
             "Warning".yellow(),
         );
     }
 
+    if cmd.no_capture && cmd.build_flags.jobs.is_some_and(|j| j > 1) {
+        warn!("`--no-capture` runs the tests sequentially, `-j` only applies to the build");
+    }
+
     let Some(surface_targets) = &cmd.build_flags.target else {
         return run_test_internal(&cli, &cmd, &dirs.source_dir, &dirs.target_dir, None);
     };
-    let targets = lower_surface_targets(surface_targets);
+    let all_targets = super::expand_target_all(&dirs.source_dir, surface_targets)?;
+    let is_all = all_targets.is_some();
+    let targets = all_targets.unwrap_or_else(|| lower_surface_targets(surface_targets));
     if cmd.update && targets.len() > 1 {
         return Err(anyhow::anyhow!("cannot update test on multiple targets"));
     }
     let display_backend_hint = if targets.len() > 1 { Some(()) } else { None };
-
-    let mut ret_value = 0;
-    for t in targets {
+    let run_for_target = |t| {
         let mut cmd = cmd.clone();
         cmd.build_flags.target_backend = Some(t);
-        let x = run_test_internal(
+        run_test_internal(
             &cli,
             &cmd,
             &dirs.source_dir,
             &dirs.target_dir,
             display_backend_hint,
         )
-        .context(format!("failed to run test for target {t:?}"))?;
+    };
+    if is_all {
+        return Ok(super::run_for_all_targets(&cli, &targets, run_for_target));
+    }
+
+    let mut ret_value = 0;
+    for t in targets {
+        let x = run_for_target(t).context(format!("failed to run test for target {t:?}"))?;
         ret_value = ret_value.max(x);
     }
     Ok(ret_value)
@@ -221,6 +416,7 @@ fn run_test_internal(
 
 #[instrument(level = Level::DEBUG, skip_all)]
 fn run_test_in_single_file(cli: &UniversalFlags, cmd: &TestSubcommand) -> anyhow::Result<i32> {
+    TestLikeSubcommand::from(cmd).check_legacy_unsupported()?;
     let single_file_path = &dunce::canonicalize(cmd.single_file.as_ref().unwrap()).unwrap();
     let source_dir = single_file_path.parent().unwrap().to_path_buf();
     let raw_target_dir = source_dir.join("target");
@@ -240,6 +436,7 @@ fn run_test_in_single_file(cli: &UniversalFlags, cmd: &TestSubcommand) -> anyhow
             .target_backend
             .unwrap_or(TargetBackend::WasmGC)
     };
+    super::check_legacy_target(target_backend)?;
 
     let debug_flag = !cmd.build_flags.release;
 
@@ -268,7 +465,7 @@ fn run_test_in_single_file(cli: &UniversalFlags, cmd: &TestSubcommand) -> anyhow
         run_mode: RunMode::Test,
         quiet: true,
         verbose: cli.verbose,
-        no_parallelize: cmd.no_parallelize,
+        no_parallelize: cmd.no_parallelize || cmd.no_capture,
         build_graph: cli.build_graph,
         fmt_opt: None,
         args: vec![],
@@ -277,6 +474,8 @@ fn run_test_in_single_file(cli: &UniversalFlags, cmd: &TestSubcommand) -> anyhow
         use_tcc_run: false,
         dynamic_stub_libs: None,
         render_no_loc: cmd.build_flags.render_no_loc,
+        no_progress: cmd.build_flags.no_progress,
+        content_hash: cli.unstable_feature.content_hash,
     };
     let moonc_opt = MooncOpt {
         build_opt: moonutil::common::BuildPackageFlags {
@@ -380,6 +579,7 @@ pub fn get_module_for_single_file(
             alert_list: moonc_opt.build_opt.alert_list.clone(),
             targets: None,
             pre_build: None,
+            post_build: vec![],
             patch_file: None,
             no_mi: false,
             install_path: None,
@@ -461,13 +661,95 @@ pub(crate) struct TestLikeSubcommand<'a> {
     pub file: &'a Option<String>,
     pub index: &'a Option<u32>,
     pub doc_index: &'a Option<u32>,
+    pub test_name: Option<&'a str>,
     pub update: bool,
     pub limit: u32,
+    pub update_file: Option<&'a [PathBuf]>,
+    pub interactive: bool,
+    pub filter: Option<&'a str>,
+    pub filter_regex: Option<&'a str>,
+    pub filter_file: Option<&'a Path>,
+    pub exclude_file: Option<&'a Path>,
+    pub shard: Option<Shard>,
+    pub list_tests: bool,
+    pub json: bool,
+    pub coverage: bool,
     pub auto_sync_flags: &'a AutoSyncFlags,
     pub build_only: bool,
     pub no_parallelize: bool,
     pub test_failure_json: bool,
     pub patch_file: &'a Option<PathBuf>,
+    pub runtime_flags: RuntimeFlags,
+    pub reporter: Option<TestReporter>,
+    pub reporter_output: Option<&'a Path>,
+    pub test_seed: Option<u64>,
+    pub retry: u32,
+    pub fail_fast: Option<InFlightTests>,
+    pub capture_limit: Option<usize>,
+    pub doc_only: bool,
+    pub remote_executor: Option<&'a str>,
+    pub bench: Option<&'a BenchFlags>,
+}
+
+impl TestLikeSubcommand<'_> {
+    /// Bail if any flag is specified that the legacy build engine doesn't
+    /// support, like [`RuntimeFlags::check_legacy_unsupported`].
+    fn check_legacy_unsupported(&self) -> anyhow::Result<()> {
+        self.runtime_flags.check_legacy_unsupported()?;
+        if self.reporter.is_some() {
+            bail!("`--reporter` is only supported with `-Z rupes_recta`");
+        }
+        if self.test_seed.is_some() {
+            bail!("`--test-seed` is only supported with `-Z rupes_recta`");
+        }
+        if self.retry > 0 {
+            bail!("`--retry` is only supported with `-Z rupes_recta`");
+        }
+        if self.fail_fast.is_some() {
+            bail!("`--fail-fast` is only supported with `-Z rupes_recta`");
+        }
+        if self.capture_limit.is_some() {
+            bail!("`--capture-limit` is only supported with `-Z rupes_recta`");
+        }
+        if self.remote_executor.is_some() {
+            bail!("`--remote-executor` is only supported with `-Z rupes_recta`");
+        }
+        if self.update_file.is_some() {
+            bail!("`--update-file` is only supported with `-Z rupes_recta`");
+        }
+        if self.interactive {
+            bail!("`--interactive` is only supported with `-Z rupes_recta`");
+        }
+        if self.filter.is_some() || self.filter_regex.is_some() {
+            bail!("`--filter` and `--filter-regex` are only supported with `-Z rupes_recta`");
+        }
+        if self.filter_file.is_some() || self.exclude_file.is_some() {
+            bail!("`--filter-file` and `--exclude-file` are only supported with `-Z rupes_recta`");
+        }
+        if self.list_tests {
+            bail!("`--list-tests` is only supported with `-Z rupes_recta`");
+        }
+        if self.test_name.is_some() {
+            bail!("`--test-name` is only supported with `-Z rupes_recta`");
+        }
+        if self.shard.is_some() {
+            bail!("`--shard` is only supported with `-Z rupes_recta`");
+        }
+        if self.coverage {
+            bail!("`--coverage` is only supported with `-Z rupes_recta`");
+        }
+        if let Some(bench) = self.bench {
+            if bench.is_reporting() {
+                bail!("`--bench-output` and `--baseline` are only supported with `-Z rupes_recta`");
+            }
+            if bench.bench_warmup.is_some() || bench.bench_min_time.is_some() {
+                bail!(
+                    "`--bench-warmup` and `--bench-min-time` are only supported with `-Z rupes_recta`"
+                );
+            }
+        }
+        Ok(())
+    }
 }
 
 impl<'a> From<&'a TestSubcommand> for TestLikeSubcommand<'a> {
@@ -480,13 +762,34 @@ impl<'a> From<&'a TestSubcommand> for TestLikeSubcommand<'a> {
             file: &cmd.file,
             index: &cmd.index,
             doc_index: &cmd.doc_index,
+            test_name: cmd.test_name.as_deref(),
             update: cmd.update,
             limit: cmd.limit,
+            update_file: cmd.update_file.as_deref(),
+            interactive: cmd.interactive,
+            filter: cmd.filter.as_deref(),
+            filter_regex: cmd.filter_regex.as_deref(),
+            filter_file: cmd.filter_file.as_deref(),
+            exclude_file: cmd.exclude_file.as_deref(),
+            shard: cmd.shard,
+            list_tests: cmd.list_tests,
+            json: cmd.json,
+            coverage: cmd.coverage,
             auto_sync_flags: &cmd.auto_sync_flags,
             build_only: cmd.build_only,
-            no_parallelize: cmd.no_parallelize,
+            no_parallelize: cmd.no_parallelize || cmd.no_capture,
             test_failure_json: cmd.test_failure_json,
             patch_file: &cmd.patch_file,
+            runtime_flags: cmd.runtime_flags.clone(),
+            reporter: cmd.reporter,
+            reporter_output: cmd.reporter_output.as_deref(),
+            test_seed: cmd.test_seed,
+            retry: cmd.retry,
+            fail_fast: cmd.fail_fast.then_some(cmd.fail_fast_in_flight),
+            capture_limit: cmd.capture_limit,
+            doc_only: cmd.doc_test,
+            remote_executor: cmd.remote_executor.as_deref(),
+            bench: None,
         }
     }
 }
@@ -500,13 +803,34 @@ impl<'a> From<&'a BenchSubcommand> for TestLikeSubcommand<'a> {
             file: &cmd.file,
             index: &cmd.index,
             doc_index: &None,
+            test_name: None,
             update: false,
             limit: 256, // FIXME: unsure about why this default, shouldn't bench have only 1 run?
+            update_file: None,
+            interactive: false,
+            filter: None,
+            filter_regex: None,
+            filter_file: None,
+            exclude_file: None,
+            shard: None,
+            list_tests: false,
+            json: false,
+            coverage: false,
             auto_sync_flags: &cmd.auto_sync_flags,
             build_only: cmd.build_only,
             no_parallelize: cmd.no_parallelize,
             test_failure_json: false,
             patch_file: &None,
+            runtime_flags: RuntimeFlags::default(),
+            reporter: None,
+            reporter_output: None,
+            test_seed: None,
+            retry: 0,
+            fail_fast: None,
+            capture_limit: None,
+            doc_only: false,
+            remote_executor: None,
+            bench: Some(&cmd.bench_flags),
         }
     }
 }
@@ -529,13 +853,29 @@ pub(crate) fn run_test_or_bench_internal(
     if cmd.file.is_none() && cmd.doc_index.is_some() {
         anyhow::bail!("`--doc-index` must be used with `--file`");
     }
+    if cmd.file.is_none() && cmd.test_name.is_some() {
+        anyhow::bail!("`--test-name` must be used with `--file`");
+    }
     if cmd.explicit_file_filter.is_some() && (cmd.package.is_some() || cmd.file.is_some()) {
         anyhow::bail!("cannot filter package or files when testing a single file in a project");
     }
+    if cmd.interactive && !std::io::stdin().is_terminal() {
+        anyhow::bail!("`--interactive` needs a terminal to ask for each update");
+    }
+    if cmd.reporter == Some(TestReporter::Tap) {
+        // Results of reruns would be reported twice
+        if cmd.update {
+            anyhow::bail!("`--reporter tap` cannot be used with `--update`");
+        }
+        if cmd.reporter_output.is_some() {
+            anyhow::bail!("`--reporter tap` always writes to stdout, not `--reporter-output`");
+        }
+    }
 
     if cli.unstable_feature.rupes_recta {
         run_test_rr(cli, &cmd, source_dir, target_dir, display_backend_hint)
     } else {
+        cmd.check_legacy_unsupported()?;
         run_test_or_bench_internal_legacy(cli, cmd, source_dir, target_dir, display_backend_hint)
     }
 }
@@ -548,7 +888,7 @@ fn run_test_rr(
     target_dir: &Path,
     display_backend_hint: Option<()>, // FIXME: unsure why it's option but as-is for now
 ) -> Result<i32, anyhow::Error> {
-    let preconfig = preconfig_compile(
+    let mut preconfig = preconfig_compile(
         cmd.auto_sync_flags,
         cli,
         cmd.build_flags,
@@ -556,9 +896,17 @@ fn run_test_rr(
         OptLevel::Debug,
         RunMode::Test,
     );
+    preconfig.llvm_coverage = cmd.coverage;
 
-    let mut filter = TestFilter::default();
-    let (build_meta, build_graph) = rr_build::plan_build(
+    let mut filter = TestFilter {
+        doc_only: cmd.doc_only,
+        ..Default::default()
+    };
+    if cmd.update && cmd.build_flags.wasm_opt.is_some() {
+        // The tests rerun after promoting are rebuilt without `wasm-opt`
+        bail!("`--wasm-opt` can't be used with `--update`");
+    }
+    let (mut build_meta, build_graph) = rr_build::plan_build(
         preconfig,
         &cli.unstable_feature,
         source_dir,
@@ -568,6 +916,26 @@ fn run_test_rr(
         }),
     )?;
 
+    let mut names = vec![];
+    if let Some(glob) = cmd.filter {
+        names.push(TestNameFilter::glob(glob)?);
+    }
+    if let Some(regex) = cmd.filter_regex {
+        names.push(TestNameFilter::regex(regex)?);
+    }
+    if let Some(path) = cmd.filter_file {
+        let Some(listed) = TestNameFilter::glob_file(path)? else {
+            bail!("no test patterns found in {}", path.display());
+        };
+        names.push(listed);
+    }
+    filter.name = TestNameFilter::any(names);
+    filter.exclude = cmd
+        .exclude_file
+        .map(TestNameFilter::glob_file)
+        .transpose()?
+        .flatten();
+
     if cli.dry_run {
         rr_build::print_dry_run(
             &build_graph,
@@ -588,21 +956,123 @@ fn run_test_rr(
         if !result.successful() || cmd.build_only {
             return Ok(result.return_code_for_success());
         }
+        build_meta.optimize_wasm()?;
+
+        // Counted before sharding, which leaves the excluded tests out
+        let excluded = if cmd.list_tests || cli.quiet {
+            0
+        } else {
+            crate::run::count_excluded(&build_meta, &filter)?
+        };
+        if let Some(shard) = cmd.shard {
+            filter = crate::run::shard_filter(&build_meta, &filter, shard)?;
+        }
+
+        if cmd.list_tests {
+            let tests = crate::run::list_tests(&build_meta, &filter)?;
+            if cmd.json {
+                println!("{}", serde_json::to_string_pretty(&tests)?);
+            } else {
+                for test in &tests {
+                    println!("{}", test.name);
+                }
+            }
+            return Ok(0);
+        }
+
+        // `tcc -run` is only used for debug builds, same as the legacy behavior,
+        // and it would skip the coverage instrumentation. The remote host
+        // only gets the executable.
+        let native_runner = if cli.unstable_feature.rr_tcc_run
+            && !cmd.coverage
+            && cmd.remote_executor.is_none()
+            && build_meta.opt_level == OptLevel::Debug
+            && build_meta.target_backend == TargetBackend::Native
+        {
+            NativeRunner::Tcc
+        } else {
+            NativeRunner::DirectExecutable
+        };
+        // Bench doesn't take a seed, so keep its command lines unchanged
+        let seed =
+            (cmd.run_mode == RunMode::Test).then(|| cmd.test_seed.unwrap_or_else(rand::random));
+        let runtime = RuntimeConfig {
+            native_runner,
+            remote: cmd.remote_executor.map(RemoteExecutor::parse).transpose()?,
+            ..cmd.runtime_flags.runtime_config()?
+        };
+        let coverage = cmd
+            .coverage
+            .then(|| TestCoverage::new(build_meta.target_backend, &runtime, target_dir))
+            .transpose()?;
+        let run_config = TestRunConfig {
+            runtime,
+            jobs: if cmd.no_parallelize {
+                NonZeroUsize::new(1)
+            } else {
+                cmd.build_flags.jobs.and_then(NonZeroUsize::new)
+            },
+            seed,
+            bench: cmd.bench.map(|b| b.bench_args(build_meta.target_backend)),
+            retries: cmd.retry,
+            fail_fast: cmd.fail_fast,
+            capture_limit: cmd.capture_limit,
+            envs: coverage.iter().map(TestCoverage::env).collect(),
+            ..Default::default()
+        };
 
-        let mut test_result = crate::run::run_tests(&build_meta, target_dir, &filter)?;
+        let mut tap = (cmd.reporter == Some(TestReporter::Tap)).then(TapReporter::start);
+        let mut test_result = match (cmd.test_name, cmd.file.as_deref()) {
+            (Some(name), Some(file)) => {
+                // A file filter always resolves to a single package
+                let Some(pkg) = filter
+                    .filter
+                    .as_ref()
+                    .and_then(|f| f.0.keys().next())
+                    .map(|t| t.package)
+                else {
+                    bail!("no package found to run the test {name:?} in");
+                };
+                crate::run::run_single_test(
+                    &build_meta,
+                    target_dir,
+                    pkg,
+                    file,
+                    name,
+                    &run_config,
+                    tap.as_mut(),
+                )?
+            }
+            _ => {
+                crate::run::run_tests(&build_meta, target_dir, &filter, &run_config, tap.as_mut())?
+            }
+        };
 
         let backend_hint = display_backend_hint
             .and(cmd.build_flags.target_backend)
             .map(|t| t.to_backend_ext());
 
         if cmd.update {
+            let only_files = cmd
+                .update_file
+                .map(|files| {
+                    files
+                        .iter()
+                        .map(|f| {
+                            dunce::canonicalize(f)
+                                .with_context(|| format!("cannot find `{}`", f.display()))
+                        })
+                        .collect::<anyhow::Result<Vec<_>>>()
+                })
+                .transpose()?;
+            let mut scope = PromotionScope::new(only_files, cmd.interactive);
             let mut loop_count = 0;
             let mut last_test_result = None;
             loop {
                 // Promote test results
                 let promotion_source = last_test_result.as_ref().unwrap_or(&test_result);
-                let (rerun_count, rerun_filter) =
-                    perform_promotion(promotion_source).expect("Failed to promote tests");
+                let (rerun_count, rerun_filter) = perform_promotion(promotion_source, &mut scope)
+                    .expect("Failed to promote tests");
                 if rerun_filter.is_empty() {
                     break; // Nothing to promote
                 }
@@ -665,9 +1135,17 @@ fn run_test_rr(
                 // Run the tests
                 let rerun_filter = TestFilter {
                     filter: Some(rerun_filter),
+                    name: filter.name.clone(),
+                    exclude: filter.exclude.clone(),
+                    doc_only: filter.doc_only,
                 };
-                let new_test_result =
-                    crate::run::run_tests(&build_meta, target_dir, &rerun_filter)?;
+                let new_test_result = crate::run::run_tests(
+                    &build_meta,
+                    target_dir,
+                    &rerun_filter,
+                    &run_config,
+                    None,
+                )?;
 
                 // Merge test results
                 test_result.merge(&new_test_result);
@@ -675,11 +1153,66 @@ fn run_test_rr(
             }
         }
 
-        test_result.print_result(&build_meta, cli.verbose);
+        if let Some(coverage) = coverage {
+            let path = target_dir.join("coverage.lcov");
+            coverage.export(&build_meta, &path)?;
+            if !cli.quiet {
+                eprintln!("Coverage written to {}", path.display());
+            }
+        }
+
         let summary = test_result.summary();
-        print_test_summary(summary.total, summary.passed, cli.quiet, backend_hint);
+        if let Some(tap) = tap {
+            tap.finish();
+            print_test_seed(seed, cli.quiet);
+            return Ok(if summary.total == summary.passed {
+                0
+            } else {
+                1
+            });
+        }
 
-        if summary.total == summary.passed {
+        test_result.print_result(&build_meta, cli.verbose);
+        if cmd.doc_only {
+            for t in crate::run::not_run_doc_tests(&build_meta, &filter)? {
+                println!(
+                    "[{}] doc test {}:{} not run (no-run)",
+                    t.package, t.file, t.line
+                );
+            }
+        }
+        if let Some(TestReporter::Junit) = cmd.reporter {
+            let path = cmd
+                .reporter_output
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| target_dir.join("junit.xml"));
+            let skipped = crate::run::not_run_doc_tests(&build_meta, &filter)?;
+            std::fs::write(&path, test_result.to_junit_xml(&build_meta, &skipped))
+                .with_context(|| format!("failed to write JUnit report to {}", path.display()))?;
+        }
+        let mut regressed = false;
+        if let Some(bench) = cmd.bench.filter(|b| b.is_reporting()) {
+            regressed = report_bench_results(bench, &test_result, &build_meta, target_dir)?;
+        }
+        match &filter.name {
+            Some(name) if summary.total == 0 => {
+                println!("0 tests matched the filter `{}`.", name.pattern);
+            }
+            _ => print_test_summary(summary.total, summary.passed, cli.quiet, backend_hint),
+        }
+        if summary.flaky > 0 {
+            println!(
+                "{}: {} of the passed tests are flaky, passing only on a retry.",
+                "Warning".yellow().bold(),
+                summary.flaky
+            );
+        }
+        if excluded > 0 {
+            println!("{excluded} tests were excluded by `--exclude-file`.");
+        }
+        print_test_seed(seed, cli.quiet);
+
+        if summary.total == summary.passed && !regressed {
             Ok(0)
         } else {
             Ok(1)
@@ -687,6 +1220,61 @@ fn run_test_rr(
     }
 }
 
+/// Export the benchmark results to `--bench-output-file` (or a file named
+/// after the backend in the target directory), and compare them with
+/// `--baseline`. Returns whether any benchmark regressed beyond `--threshold`.
+fn report_bench_results(
+    flags: &BenchFlags,
+    test_result: &ReplaceableTestResults,
+    build_meta: &BuildMeta,
+    target_dir: &Path,
+) -> anyhow::Result<bool> {
+    let report = test_result.bench_report(build_meta)?;
+    if let Some(format) = flags.bench_output {
+        let path = flags
+            .bench_output_file
+            .clone()
+            .unwrap_or_else(|| target_dir.join(format!("bench-{}.json", report.backend)));
+        let content = match format {
+            BenchOutputFormat::Json => serde_json::to_string_pretty(&report)?,
+        };
+        std::fs::write(&path, content)
+            .with_context(|| format!("failed to write benchmark results to {}", path.display()))?;
+    }
+    let Some(baseline_path) = &flags.baseline else {
+        return Ok(false);
+    };
+    let baseline = std::fs::read_to_string(baseline_path)
+        .with_context(|| format!("failed to read baseline {}", baseline_path.display()))?;
+    let baseline: BenchReport = serde_json_lenient::from_str(&baseline)
+        .with_context(|| format!("failed to parse baseline {}", baseline_path.display()))?;
+    if baseline.backend != report.backend {
+        bail!(
+            "the baseline is benchmarked on {}, but the current run is on {}",
+            baseline.backend,
+            report.backend
+        );
+    }
+    let changes = benchmark::compare_bench_reports(&baseline, &report);
+    // Without a threshold, only report the changes
+    let threshold = flags.threshold.unwrap_or(f64::INFINITY);
+    let regressed = benchmark::render_bench_comparison(&changes, threshold);
+    if regressed {
+        eprintln!(
+            "{}: some benchmarks are slower than the baseline by more than {threshold}%",
+            "Error".red().bold()
+        );
+    }
+    Ok(regressed)
+}
+
+/// Print the seed of a test run, so that it can be reproduced
+fn print_test_seed(seed: Option<u64>, quiet: bool) {
+    if let Some(seed) = seed.filter(|_| !quiet) {
+        eprintln!("Test seed: {seed}, rerun with `--test-seed {seed}` to reproduce");
+    }
+}
+
 /// The nodes wanted to run a test for a build target
 fn node_from_target(x: BuildTarget) -> [BuildPlanNode; 2] {
     [
@@ -850,6 +1438,12 @@ fn calc_user_intent(
         .packages_for_module(main_module_id)
         .ok_or_else(|| anyhow::anyhow!("Cannot find the local module!"))?;
     let affected_packages = packages.values().copied();
+    // Doc tests are compiled into the blackbox tests, so only build those
+    let test_kinds: &[TargetKind] = if cmd.doc_only {
+        &[TargetKind::BlackboxTest]
+    } else {
+        TargetKind::all_tests()
+    };
 
     let directive = if let Some(file_filter) = cmd.explicit_file_filter {
         apply_explicit_file_filter(resolve_output, out_filter, file_filter)?;
@@ -868,12 +1462,7 @@ fn calc_user_intent(
     } else {
         // No filter, return all packages and all targets
         return Ok(affected_packages
-            .flat_map(move |x| {
-                TargetKind::all_tests()
-                    .iter()
-                    .copied()
-                    .map(move |t| x.build_target(t))
-            })
+            .flat_map(move |x| test_kinds.iter().copied().map(move |t| x.build_target(t)))
             .flat_map(node_from_target)
             .collect::<Vec<_>>()
             .into());
@@ -884,6 +1473,7 @@ fn calc_user_intent(
         filt.0
             .keys()
             .copied()
+            .filter(|t| test_kinds.contains(&t.kind))
             .flat_map(node_from_target)
             .collect::<Vec<_>>()
     } else {
@@ -917,16 +1507,11 @@ pub(crate) fn run_test_or_bench_internal_legacy(
     let mut moonc_opt = super::get_compiler_flags(source_dir, &build_flags)?;
     // release is 'false' by default, so we will run test at debug mode(to gain more detailed stack trace info), unless `--release` is specified
     // however, other command like build, check, run, etc, will run at release mode by default
-    moonc_opt.build_opt.debug_flag = !cmd.build_flags.release;
+    let profile = BuildProfile::from_flags(cmd.build_flags, OptLevel::Debug);
+    moonc_opt.build_opt.debug_flag = profile.opt_level == OptLevel::Debug;
     moonc_opt.build_opt.enable_value_tracing = cmd.build_flags.enable_value_tracing;
-    moonc_opt.build_opt.strip_flag = if cmd.build_flags.strip {
-        true
-    } else if cmd.build_flags.no_strip {
-        false
-    } else {
-        cmd.build_flags.release
-    };
-    moonc_opt.link_opt.debug_flag = !cmd.build_flags.release;
+    moonc_opt.build_opt.strip_flag = !profile.debug_symbols;
+    moonc_opt.link_opt.debug_flag = profile.opt_level == OptLevel::Debug;
 
     // TODO: remove this once LLVM backend is well supported
     if moonc_opt.build_opt.target_backend == TargetBackend::LLVM {
@@ -1013,6 +1598,8 @@ pub(crate) fn run_test_or_bench_internal_legacy(
         use_tcc_run: false,
         dynamic_stub_libs: None,
         render_no_loc: cmd.build_flags.render_no_loc,
+        no_progress: cmd.build_flags.no_progress,
+        content_hash: cli.unstable_feature.content_hash,
     };
 
     let mut module = scan_with_x_build(
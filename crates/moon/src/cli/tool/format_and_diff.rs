@@ -33,6 +33,11 @@ pub struct FormatAndDiffSubcommand {
     #[clap(long)]
     block_style: bool,
 
+    /// Print the difference, instead of only the source path, if the code
+    /// isn't formatted
+    #[clap(long)]
+    diff: bool,
+
     pub args: Vec<String>,
 }
 
@@ -59,11 +64,20 @@ pub fn run_format_and_diff(cmd: FormatAndDiffSubcommand) -> anyhow::Result<i32>
     if exit_code == 0 {
         return Ok(0);
     }
+    if !cmd.diff {
+        println!("{}", cmd.old.display());
+        return Ok(1);
+    }
+    let color = if moonutil::common::no_color() {
+        "--color=never"
+    } else {
+        "--color=always"
+    };
     let mut execution = std::process::Command::new("git")
         .args([
             "--no-pager",
             "diff",
-            "--color=always",
+            color,
             "--no-index",
             cmd.old.to_str().unwrap(),
             cmd.new.to_str().unwrap(),
@@ -85,9 +99,12 @@ pub fn run_format_and_diff(cmd: FormatAndDiffSubcommand) -> anyhow::Result<i32>
     let status = execution.wait()?;
     match status.code() {
         Some(0) => Ok(0),
+        // `git diff --no-index` exits with 1 if there are differences
+        Some(1) => Ok(1),
         _ => {
             eprintln!(
-                "failed to execute `git --no-pager diff --color=always --no-index {} {}`",
+                "failed to execute `git --no-pager diff {} --no-index {} {}`",
+                color,
                 &cmd.old.to_str().unwrap(),
                 &cmd.new.to_str().unwrap()
             );
@@ -31,5 +31,6 @@ pub fn update_cli(cli: UniversalFlags, _cmd: UpdateSubcommand) -> anyhow::Result
     }
     let registry_config = RegistryConfig::load();
     let target_dir = moonutil::moon_dir::index();
+    moonutil::common::ensure_online("update the registry index")?;
     mooncake::update::update(&target_dir, &registry_config)
 }
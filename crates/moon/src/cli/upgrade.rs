@@ -16,14 +16,14 @@
 //
 // For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
 
-use anyhow::bail;
 use moonbuild::upgrade::UpgradeSubcommand;
 
 use super::UniversalFlags;
 
 pub fn run_upgrade(cli: UniversalFlags, cmd: UpgradeSubcommand) -> anyhow::Result<i32> {
+    moonutil::common::ensure_online("upgrade the toolchain")?;
     if cli.dry_run {
-        bail!("dry-run is not implemented for upgrade")
+        return moonbuild::upgrade::upgrade_dry_run(cmd);
     }
     moonbuild::upgrade::upgrade(cmd)
 }
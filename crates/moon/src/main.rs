@@ -29,6 +29,7 @@ pub mod rr_build;
 mod run;
 
 use colored::*;
+use moonutil::common::{MOON_ALLOW_PRE_BUILD_SCRIPTS, MOON_OFFLINE};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
 
 /// Initialize logging and tracing-related functionality.
@@ -86,6 +87,14 @@ pub fn main() {
 
     let cli = cli::MoonBuildCli::parse();
     let flags = cli.flags;
+    if flags.allow_pre_build_scripts {
+        // checked where the commands are run, also by nested `moon` commands
+        std::env::set_var(MOON_ALLOW_PRE_BUILD_SCRIPTS, "true");
+    }
+    flags.color.apply();
+    if flags.offline {
+        std::env::set_var(MOON_OFFLINE, "true");
+    }
 
     let _trace_guard = init_tracing();
 
@@ -96,10 +105,12 @@ pub fn main() {
         Build(b) => cli::run_build(&flags, &b),
         Bundle(b) => cli::run_bundle(flags, b),
         Check(c) => cli::run_check(&flags, &c),
-        Clean(_) => cli::run_clean(&flags),
+        Clean(c) => cli::run_clean(&flags, c),
         Coverage(c) => cli::run_coverage(flags, c),
         Doc(d) => cli::run_doc(flags, d),
+        Explain(e) => cli::run_explain(e),
         Fmt(f) => cli::run_fmt(&flags, f),
+        Gen(g) => cli::run_gen(&flags, g),
         GenerateBuildMatrix(b) => cli::generate_build_matrix(&flags, b),
         GenerateTestDriver(g) => cli::generate_test_driver(flags, g),
         Info(i) => cli::run_info(flags, i),
@@ -112,7 +123,7 @@ pub fn main() {
         Register(r) => cli::mooncake_adapter::register_cli(flags, r),
         Remove(r) => cli::remove_cli(flags, r),
         Run(r) => cli::run_run(&flags, r),
-        Test(t) => cli::run_test(flags, t),
+        Test(t) => cli::run_test(flags, *t),
         Tree(t) => cli::tree_cli(flags, t),
         Update(u) => cli::update_cli(flags, u),
         Upgrade(u) => cli::run_upgrade(flags, u),
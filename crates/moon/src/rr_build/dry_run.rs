@@ -18,7 +18,7 @@
 
 //! Handles dry-run printing of build commands.
 
-use std::{path::Path, process::Command};
+use std::{ffi::OsString, path::Path};
 
 use moonbuild_rupes_recta::model::Artifacts;
 
@@ -31,15 +31,23 @@ pub fn print_dry_run<'a>(
     source_dir: &Path,
     target_dir: &Path,
 ) {
-    let default_files = artifacts
+    let default_files = artifact_files(build_graph, artifacts);
+    moonbuild::dry_run::print_build_commands(build_graph, &default_files, source_dir, target_dir);
+}
+
+/// Look up the files of the given artifacts in the build graph.
+pub fn artifact_files<'a>(
+    build_graph: &n2::graph::Graph,
+    artifacts: impl IntoIterator<Item = &'a Artifacts>,
+) -> Vec<n2::graph::FileId> {
+    artifacts
         .into_iter()
         .flat_map(|art| {
             art.artifacts
                 .iter()
                 .flat_map(|file| build_graph.files.lookup(&file.to_string_lossy()))
         })
-        .collect::<Vec<_>>();
-    moonbuild::dry_run::print_build_commands(build_graph, &default_files, source_dir, target_dir);
+        .collect()
 }
 
 /// Print all commands in a dry-run.
@@ -50,12 +58,10 @@ pub fn print_dry_run_all(build_graph: &n2::graph::Graph, source_dir: &Path, targ
     moonbuild::dry_run::print_build_commands(build_graph, &default_files, source_dir, target_dir);
 }
 
-/// Print a command as it would be executed, with the proper escaping.
-pub fn dry_print_command(cmd: &Command) {
-    let args = std::iter::once(cmd.get_program())
-        .chain(cmd.get_args())
-        .map(|x| x.to_string_lossy())
-        .collect::<Vec<_>>();
+/// Print a command line (the program followed by its arguments) as it would
+/// be executed, with the proper escaping.
+pub fn dry_print_command(argv: &[OsString]) {
+    let args = argv.iter().map(|x| x.to_string_lossy()).collect::<Vec<_>>();
     let cmd = shlex::try_join(args.iter().map(|x| &**x)).expect("null in args, should not happen");
     println!("{cmd}");
 }
@@ -34,8 +34,10 @@ use std::{
 
 use anyhow::Context;
 use indexmap::IndexMap;
+use moonbuild::content_hash::ContentHashes;
 use moonbuild::entry::{
-    create_progress_console, render_and_catch_callback, N2RunStats, ResultCatcher,
+    create_progress_console, message_json_callback, render_and_catch_callback, N2RunStats,
+    ResultCatcher,
 };
 use moonbuild_rupes_recta::{
     build_plan::InputDirective,
@@ -48,16 +50,19 @@ use moonutil::{
         DiagnosticLevel, RunMode, TargetBackend, BLACKBOX_TEST_PATCH, MOONBITLANG_CORE,
         WHITEBOX_TEST_PATCH,
     },
+    compiler_flags::OptLevel as CCOptLevel,
     cond_expr::OptLevel,
     features::FeatureGate,
     mooncakes::{sync::AutoSyncFlags, ModuleId},
 };
-use tracing::{instrument, Level};
+use tracing::{instrument, warn, Level};
 
-use crate::cli::BuildFlags;
+use crate::cli::{BuildFlags, WasmOptLevel};
 
 mod dry_run;
-pub use dry_run::{dry_print_command, print_dry_run, print_dry_run_all};
+mod wasm_opt;
+pub use dry_run::{artifact_files, dry_print_command, print_dry_run, print_dry_run_all};
+pub use wasm_opt::WasmOpt;
 
 /// The function that calculates the user intent for the build process.
 ///
@@ -143,6 +148,20 @@ pub struct BuildMeta {
 
     /// The main optimization level used in this compile process
     pub opt_level: OptLevel,
+
+    /// The `wasm-opt` to run on the output after building, from `--wasm-opt`
+    pub wasm_opt: Option<WasmOpt>,
+}
+
+impl BuildMeta {
+    /// Run `wasm-opt` on the output of a successful build if `--wasm-opt` is
+    /// given, pointing the artifacts at the optimized modules.
+    pub fn optimize_wasm(&mut self) -> anyhow::Result<()> {
+        match &self.wasm_opt {
+            Some(wasm_opt) => wasm_opt.optimize(&mut self.artifacts),
+            None => Ok(()),
+        }
+    }
 }
 
 /// Represents the result of the build process
@@ -187,14 +206,20 @@ impl BuildResult {
 ///
 /// This type might be subject to change.
 pub struct CompilePreConfig {
-    frozen: bool,
+    sync_flags: AutoSyncFlags,
     target_backend: Option<TargetBackend>,
     opt_level: OptLevel,
+    /// The optimization level of the C compiler from `--opt-level`
+    native_opt_level: Option<CCOptLevel>,
+    /// The level to run `wasm-opt` at after building, from `--wasm-opt`
+    wasm_opt: Option<WasmOptLevel>,
     action: RunMode,
     debug_symbols: bool,
     use_std: bool,
     debug_export_build_plan: bool,
     enable_coverage: bool,
+    /// Whether to instrument native executables for LLVM coverage
+    pub llvm_coverage: bool,
     output_wat: bool,
     /// Whether to output JSON when compiling with moonc.
     /// Set separately because we don't have the same
@@ -214,17 +239,24 @@ impl CompilePreConfig {
         self,
         preferred_backend: Option<TargetBackend>,
         is_core: bool,
-    ) -> CompileConfig {
+    ) -> anyhow::Result<CompileConfig> {
         let std = self.use_std && !is_core;
         let target_backend = self
             .target_backend
             .or(preferred_backend)
             .unwrap_or_default();
+        if self.native_opt_level.is_some() && !target_backend.is_native() {
+            anyhow::bail!(
+                "`--opt-level` only applies to the native and LLVM backends, not {}",
+                target_backend
+            );
+        }
 
-        CompileConfig {
+        Ok(CompileConfig {
             target_dir: self.target_dir,
             target_backend,
             opt_level: self.opt_level,
+            native_opt_level: self.native_opt_level,
             action: self.action,
             debug_symbols: self.debug_symbols,
             stdlib_path: if std {
@@ -233,6 +265,7 @@ impl CompilePreConfig {
                 None
             },
             enable_coverage: self.enable_coverage,
+            llvm_coverage: self.llvm_coverage,
             output_wat: self.output_wat,
             debug_export_build_plan: self.debug_export_build_plan,
             moonc_output_json: self.moonc_output_json,
@@ -241,6 +274,45 @@ impl CompilePreConfig {
             warn_list: self.warn_list,
             alert_list: self.alert_list,
             info_no_alias: self.info_no_alias,
+        })
+    }
+}
+
+/// A build profile, the bundle of flags selected by `--debug` and `--release`.
+/// The debug profile keeps full debug info and turns off the optimizations,
+/// which also compiles faster. The release profile optimizes and strips the
+/// debug info. `--strip` and `--no-strip` still decide on the debug info.
+///
+/// The artifacts of each profile live in a directory of their own, i.e.
+/// `target/<backend>/debug` and `target/<backend>/release`, so switching
+/// between them doesn't invalidate the other one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuildProfile {
+    pub opt_level: OptLevel,
+    pub debug_symbols: bool,
+}
+
+impl BuildProfile {
+    /// The profile selected by `build_flags`, which is the one of
+    /// `default_opt_level` if neither `--debug` nor `--release` is given.
+    pub fn from_flags(build_flags: &BuildFlags, default_opt_level: OptLevel) -> Self {
+        let opt_level = if build_flags.release {
+            OptLevel::Release
+        } else if build_flags.debug {
+            OptLevel::Debug
+        } else {
+            default_opt_level
+        };
+        let debug_symbols = if build_flags.strip {
+            false
+        } else if build_flags.no_strip {
+            true
+        } else {
+            opt_level == OptLevel::Debug
+        };
+        Self {
+            opt_level,
+            debug_symbols,
         }
     }
 }
@@ -256,22 +328,19 @@ pub fn preconfig_compile(
     default_opt_level: OptLevel,
     action: RunMode,
 ) -> CompilePreConfig {
-    let opt_level = if build_flags.release {
-        OptLevel::Release
-    } else if build_flags.debug {
-        OptLevel::Debug
-    } else {
-        default_opt_level
-    };
+    let profile = BuildProfile::from_flags(build_flags, default_opt_level);
     CompilePreConfig {
-        frozen: auto_sync_flags.frozen,
+        sync_flags: auto_sync_flags.clone(),
         target_dir: target_dir.to_owned(),
         target_backend: build_flags.target_backend,
-        opt_level,
+        opt_level: profile.opt_level,
+        native_opt_level: build_flags.opt_level.map(|it| it.to_cc_opt_level()),
+        wasm_opt: build_flags.wasm_opt,
         action,
-        debug_symbols: !build_flags.strip(),
+        debug_symbols: profile.debug_symbols,
         use_std: build_flags.std(),
         enable_coverage: build_flags.enable_coverage,
+        llvm_coverage: false,
         output_wat: build_flags.output_wat,
         debug_export_build_plan: cli.unstable_feature.rr_export_build_plan,
         // In legacy impl, dry run always force no json
@@ -301,7 +370,7 @@ pub fn plan_build<'a>(
     target_dir: &'a Path,
     calc_user_intent: Box<CalcUserIntentFn<'a>>,
 ) -> anyhow::Result<(BuildMeta, n2::graph::Graph)> {
-    let cfg = ResolveConfig::new_with_load_defaults(preconfig.frozen);
+    let cfg = ResolveConfig::new_with_load_defaults(preconfig.sync_flags.clone());
     let resolve_output = moonbuild_rupes_recta::resolve(&cfg, source_dir)?;
 
     // A couple of debug things:
@@ -337,7 +406,16 @@ pub fn plan_build<'a>(
     // Ultimately we want to determine this from config instead of special cases.
     let is_core = main_module.name == MOONBITLANG_CORE;
 
-    let cx = preconfig.into_compile_config(preferred_backend, is_core);
+    let wasm_opt = preconfig.wasm_opt;
+    let cx = preconfig.into_compile_config(preferred_backend, is_core)?;
+    if cx.target_backend == TargetBackend::Wasi && !unstable_features.wasi {
+        // The output is plain Wasm, which may import host functions of
+        // `moonrun` that `wasmtime` doesn't provide
+        anyhow::bail!("the `wasi` target is experimental, enable it with `-Z wasi`");
+    }
+    let wasm_opt = wasm_opt
+        .map(|level| WasmOpt::new(level, cx.target_backend))
+        .transpose()?;
     let compile_output =
         moonbuild_rupes_recta::compile(&cx, &resolve_output, &intent.intent, &intent.directive)?;
 
@@ -357,6 +435,7 @@ pub fn plan_build<'a>(
         artifacts: compile_output.artifacts,
         target_backend: cx.target_backend,
         opt_level: cx.opt_level,
+        wasm_opt,
     };
 
     Ok((build_meta, compile_output.build_graph))
@@ -400,11 +479,27 @@ pub struct BuildConfig {
     /// Explain and warnings in diagnostics
     pub explain_errors: bool,
 
+    /// Print diagnostics as LSP JSON instead of rendering them
+    pub diagnostics_json: bool,
+
     /// Ask n2 to explain rerun reasons
     pub n2_explain: bool,
 
+    /// Do not show the build progress, even on terminals
+    pub no_progress: bool,
+
     /// The patch file to use
     pub patch_file: Option<PathBuf>,
+
+    /// Print diagnostics as `--message-format json` build messages
+    pub message_json: bool,
+
+    /// Keep building the tasks not depending on failed ones
+    pub keep_going: bool,
+
+    /// Check whether source files changed by their content, see
+    /// [`moonbuild::content_hash`]
+    pub content_hash: bool,
 }
 
 impl BuildConfig {
@@ -415,8 +510,13 @@ impl BuildConfig {
             render_no_loc: flags.render_no_loc,
             generate_metadata: false,
             explain_errors: false,
+            diagnostics_json: false,
             n2_explain: unstable_features.rr_n2_explain,
+            no_progress: flags.no_progress,
             patch_file: None,
+            message_json: false,
+            keep_going: false,
+            content_hash: unstable_features.content_hash,
         }
     }
 }
@@ -429,8 +529,13 @@ impl Default for BuildConfig {
             render_no_loc: DiagnosticLevel::Error,
             generate_metadata: false,
             explain_errors: false,
+            diagnostics_json: false,
             n2_explain: false,
+            no_progress: false,
             patch_file: None,
+            message_json: false,
+            keep_going: false,
+            content_hash: false,
         }
     }
 }
@@ -481,28 +586,40 @@ pub fn execute_build_partial(
 
     let parallelism = cfg
         .parallelism
-        .or_else(|| std::thread::available_parallelism().ok().map(|x| x.into()))
+        .or_else(|| {
+            moonutil::parallelism::default_parallelism()
+                .ok()
+                .map(|x| x.into())
+        })
         .unwrap();
 
     // FIXME: Rewrite the rendering mechanism
     let result_catcher = Arc::new(Mutex::new(ResultCatcher::default()));
-    let callback = render_and_catch_callback(
-        Arc::clone(&result_catcher),
-        cfg.no_render,
-        n2::terminal::use_fancy(),
-        cfg.patch_file.clone(),
-        cfg.explain_errors,
-        cfg.render_no_loc,
-        PathBuf::new(),
-        target_dir.into(),
-    );
-    let mut prog_console = create_progress_console(Some(Box::new(callback)), false);
+    let callback: Box<dyn Fn(&str) + Send> = if cfg.message_json {
+        Box::new(message_json_callback(Arc::clone(&result_catcher)))
+    } else {
+        Box::new(render_and_catch_callback(
+            Arc::clone(&result_catcher),
+            cfg.no_render,
+            cfg.diagnostics_json,
+            n2::terminal::use_fancy(),
+            cfg.patch_file.clone(),
+            cfg.explain_errors,
+            cfg.render_no_loc,
+            PathBuf::new(),
+            target_dir.into(),
+        ))
+    };
+    let mut prog_console = create_progress_console(Some(callback), false, cfg.no_progress);
+    let content_hashes = cfg
+        .content_hash
+        .then(|| ContentHashes::apply(&mut build_graph, target_dir));
     let mut work = n2::work::Work::new(
         build_graph,
         hashes,
         n2_db,
         &n2::work::Options {
-            failures_left: Some(1),
+            failures_left: if cfg.keep_going { None } else { Some(1) },
             parallelism,
             explain: cfg.n2_explain,
             adopt: false,
@@ -515,12 +632,19 @@ pub fn execute_build_partial(
 
     // The actual execution done by the n2 executor
     let res = work.run()?;
+    if let Some(content_hashes) = content_hashes.filter(|_| res.is_some()) {
+        if let Err(e) = content_hashes.save() {
+            warn!("Failed to save the content hashes: {e:?}");
+        }
+    }
 
     let result_catcher = result_catcher.lock().unwrap();
     let stats = N2RunStats {
         n_tasks_executed: res,
         n_errors: result_catcher.n_errors,
         n_warnings: result_catcher.n_warnings,
+        error_files: moonbuild::entry::error_files(&result_catcher.content_writer),
+        outputs: result_catcher.content_writer.clone(),
     };
 
     Ok(stats)
@@ -0,0 +1,207 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Post-processing the output of the Wasm backends with `wasm-opt` of
+//! Binaryen, for `--wasm-opt`.
+//!
+//! The optimized module is written next to the one of moonc, as
+//! `<name>.opt.wasm`, and replaces it in the artifacts of the build, so that
+//! running the build runs the optimized module. The output of moonc is left
+//! untouched, which keeps the build incremental.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use indexmap::IndexMap;
+use moonbuild_rupes_recta::model::{Artifacts, BuildPlanNode};
+use moonutil::common::TargetBackend;
+
+use crate::cli::WasmOptLevel;
+
+const WASM_OPT: &str = "wasm-opt";
+
+/// The features used by the output of the Wasm-GC backend, which `wasm-opt`
+/// rejects the module without.
+const WASM_GC_FEATURES: &[&str] = &[
+    "--enable-gc",
+    "--enable-reference-types",
+    "--enable-exception-handling",
+    "--enable-tail-call",
+    "--enable-bulk-memory",
+    "--enable-nontrapping-float-to-int",
+    "--enable-sign-ext",
+    "--enable-multivalue",
+];
+
+/// A `wasm-opt` found in `PATH`, to run on the modules of a build.
+#[derive(Debug, Clone)]
+pub struct WasmOpt {
+    program: PathBuf,
+    level: WasmOptLevel,
+    backend: TargetBackend,
+}
+
+impl WasmOpt {
+    /// Find `wasm-opt` for optimizing the output of `backend` at `level`.
+    pub fn new(level: WasmOptLevel, backend: TargetBackend) -> anyhow::Result<Self> {
+        if !matches!(backend, TargetBackend::Wasm | TargetBackend::WasmGC) {
+            bail!("`--wasm-opt` only applies to the wasm and wasm-gc backends, not {backend}");
+        }
+        let program = which::which(WASM_OPT).map_err(|_| {
+            anyhow::anyhow!(
+                "`{WASM_OPT}` is not found, but it's required by `--wasm-opt`; install Binaryen \
+                from https://github.com/WebAssembly/binaryen, e.g. with `npm install -g binaryen`"
+            )
+        })?;
+        Ok(Self {
+            program,
+            level,
+            backend,
+        })
+    }
+
+    /// Optimize the modules linked in `artifacts`, replacing them with the
+    /// optimized ones. A module that's the artifact of several nodes is
+    /// optimized once.
+    pub fn optimize(
+        &self,
+        artifacts: &mut IndexMap<BuildPlanNode, Artifacts>,
+    ) -> anyhow::Result<()> {
+        let mut done = HashSet::new();
+        for (node, artifacts) in artifacts {
+            if !matches!(
+                node,
+                BuildPlanNode::LinkCore(_) | BuildPlanNode::MakeExecutable(_)
+            ) {
+                continue;
+            }
+            for path in &mut artifacts.artifacts {
+                if path.extension().is_some_and(|ext| ext == "wasm") {
+                    let optimized = path.with_extension("opt.wasm");
+                    if done.insert(path.clone()) {
+                        self.run(path, &optimized)?;
+                    }
+                    *path = optimized;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn run(&self, input: &Path, output: &Path) -> anyhow::Result<()> {
+        let mut cmd = std::process::Command::new(&self.program);
+        cmd.arg(self.level.to_flag());
+        if self.backend == TargetBackend::WasmGC {
+            cmd.args(WASM_GC_FEATURES);
+        }
+        cmd.arg(input).arg("-o").arg(output);
+        let res = cmd
+            .output()
+            .with_context(|| format!("failed to run `{}`", self.program.display()))?;
+        if !res.status.success() {
+            bail!(
+                "`{WASM_OPT}` failed to optimize {}:\n{}",
+                input.display(),
+                String::from_utf8_lossy(&res.stderr).trim_end()
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use moonbuild_rupes_recta::model::{PackageId, TargetKind};
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_optimize_linked_modules_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        // Copies the input to the output, logging the input
+        let program = dir.join(WASM_OPT);
+        std::fs::write(
+            &program,
+            "#!/bin/sh\nwhile [ \"$#\" -gt 3 ]; do shift; done\n\
+            echo \"$1\" >> \"$(dirname \"$0\")/log\"\ncp \"$1\" \"$3\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+        for name in ["main.wasm", "test.wasm", "lib.wasm"] {
+            std::fs::write(dir.join(name), name).unwrap();
+        }
+
+        let pkg = PackageId::default();
+        let source = pkg.build_target(TargetKind::Source);
+        let test = pkg.build_target(TargetKind::BlackboxTest);
+        let mut artifacts = IndexMap::new();
+        for (node, files) in [
+            (BuildPlanNode::BuildCore(source), vec!["lib.wasm", "lib.mi"]),
+            (BuildPlanNode::LinkCore(source), vec!["main.wasm"]),
+            (BuildPlanNode::MakeExecutable(source), vec!["main.wasm"]),
+            (
+                BuildPlanNode::MakeExecutable(test),
+                vec!["test.wasm", "test.wasm.map"],
+            ),
+        ] {
+            let artifacts_of_node = Artifacts {
+                node,
+                artifacts: files.iter().map(|f| dir.join(f)).collect(),
+            };
+            artifacts.insert(node, artifacts_of_node);
+        }
+
+        let wasm_opt = WasmOpt {
+            program,
+            level: WasmOptLevel::Os,
+            backend: TargetBackend::WasmGC,
+        };
+        wasm_opt.optimize(&mut artifacts).unwrap();
+
+        let files = |node| &artifacts[&node].artifacts;
+        assert_eq!(
+            files(BuildPlanNode::BuildCore(source)),
+            &[dir.join("lib.wasm"), dir.join("lib.mi")]
+        );
+        assert_eq!(
+            files(BuildPlanNode::LinkCore(source)),
+            &[dir.join("main.opt.wasm")]
+        );
+        assert_eq!(
+            files(BuildPlanNode::MakeExecutable(source)),
+            &[dir.join("main.opt.wasm")]
+        );
+        assert_eq!(
+            files(BuildPlanNode::MakeExecutable(test)),
+            &[dir.join("test.opt.wasm"), dir.join("test.wasm.map")]
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("main.opt.wasm")).unwrap(),
+            "main.wasm"
+        );
+        let log = std::fs::read_to_string(dir.join("log")).unwrap();
+        let expected = format!(
+            "{}\n{}\n",
+            dir.join("main.wasm").display(),
+            dir.join("test.wasm").display()
+        );
+        assert_eq!(log, expected);
+    }
+}
@@ -18,11 +18,53 @@
 
 //! Handles spawning of a child process under the govern of `moon run`
 
-use std::process::{ExitStatus, Stdio};
+use std::{
+    io::Write,
+    path::PathBuf,
+    process::{ExitStatus, Stdio},
+};
 
 use anyhow::Context;
-use moonbuild::section_capture::{handle_stdout_async, SectionCapture};
-use tokio::process::Command;
+use moonbuild::section_capture::{
+    handle_output_async, handle_stdout_async, OutputAttribution, SectionCapture,
+};
+use tokio::{
+    io::AsyncWriteExt,
+    process::{Child, Command},
+};
+
+/// Where the standard input of a child process comes from.
+#[derive(Debug, Clone, Default)]
+pub enum ChildStdin {
+    /// No input, i.e. `/dev/null`.
+    #[default]
+    Null,
+    /// Inherit the standard input of `moon`.
+    Inherit,
+    /// Feed the given bytes, then close the input.
+    Bytes(Vec<u8>),
+    /// Read the input from the given file.
+    File(PathBuf),
+}
+
+/// The output of a child process, collected instead of being forwarded to the
+/// output of `moon` as it comes. It keeps the outputs of concurrently running
+/// processes from interleaving.
+#[derive(Debug, Default)]
+pub struct BufferedOutput {
+    /// The `stdout` output, excluding the captured sections.
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl BufferedOutput {
+    /// Write the collected output to the `stdout` and `stderr` of `moon`.
+    pub fn flush(&self) -> std::io::Result<()> {
+        std::io::stdout().lock().write_all(&self.stdout)?;
+        std::io::stderr().lock().write_all(&self.stderr)?;
+        Ok(())
+    }
+}
 
 /// Run a command under the governing of `moon run`.
 ///
@@ -33,15 +75,44 @@ use tokio::process::Command;
 /// `captures` uses a list of [`SectionCapture`] to capture part of the `stdout`
 /// output since the running process might not have any other method to interact
 /// with the host `moon` process.
+///
+/// `stdin` is connected directly to the spawned process. For the JS backend,
+/// the process is the JS runtime which runs the MoonBit program (or the test
+/// driver) in the same process, so the program reads all of it.
+///
+/// If `buffer` is given, the output is collected into it instead of being
+/// forwarded to the output of `moon`.
+///
+/// If `attribution` is given, both `stdout` and `stderr` are read in this task
+/// to split the output between tests, see [`OutputAttribution`].
+///
+/// `on_spawn` is called with the child right after it's spawned, e.g. to
+/// remember its PID for stopping it later.
 pub async fn run<'a>(
     captures: &mut [&mut SectionCapture<'a>],
-    stdin: bool,
+    stdin: ChildStdin,
     mut cmd: Command,
+    mut buffer: Option<&mut BufferedOutput>,
+    attribution: Option<&mut OutputAttribution<'a>>,
+    on_spawn: impl FnOnce(&Child),
 ) -> anyhow::Result<ExitStatus> {
-    if stdin {
-        cmd.stdin(Stdio::inherit());
-    } else {
-        cmd.stdin(Stdio::null());
+    let mut stdin_bytes = None;
+    match stdin {
+        ChildStdin::Null => {
+            cmd.stdin(Stdio::null());
+        }
+        ChildStdin::Inherit => {
+            cmd.stdin(Stdio::inherit());
+        }
+        ChildStdin::Bytes(bytes) => {
+            cmd.stdin(Stdio::piped());
+            stdin_bytes = Some(bytes);
+        }
+        ChildStdin::File(path) => {
+            let file = std::fs::File::open(&path)
+                .with_context(|| format!("Failed to open stdin file {}", path.display()))?;
+            cmd.stdin(file);
+        }
     }
     cmd.stdout(Stdio::piped());
     cmd.stderr(Stdio::piped()); // to prevent `node` and friends changing fd blocking status
@@ -50,13 +121,77 @@ pub async fn run<'a>(
     let mut child = cmd
         .spawn()
         .with_context(|| format!("Failed to spawn command {:?}", cmd))?;
+    on_spawn(&child);
 
+    // Feed stdin in a separate task, so that a child that doesn't read all
+    // of its input won't block us from handling its output
+    let stdin_pipe_task = child
+        .stdin
+        .take()
+        .zip(stdin_bytes)
+        .map(|(mut stdin, bytes)| {
+            tokio::spawn(async move {
+                // The child may exit without reading everything, which is fine
+                let _ = stdin.write_all(&bytes).await;
+            })
+        });
+
+    if let Some(attribution) = attribution {
+        let child_stdout = child
+            .stdout
+            .take()
+            .expect("Child process should have stdout piped");
+        let child_stderr = child
+            .stderr
+            .take()
+            .expect("Child process should have stderr piped");
+        let out = tokio::io::BufReader::new(child_stdout);
+        let err = tokio::io::BufReader::new(child_stderr);
+        match buffer {
+            Some(buffer) => {
+                handle_output_async(
+                    out,
+                    err,
+                    captures,
+                    attribution,
+                    &mut buffer.stdout,
+                    &mut buffer.stderr,
+                )
+                .await?
+            }
+            None => {
+                handle_output_async(
+                    out,
+                    err,
+                    captures,
+                    attribution,
+                    tokio::io::stdout(),
+                    tokio::io::stderr(),
+                )
+                .await?
+            }
+        }
+        let status = child
+            .wait()
+            .await
+            .context("Failed to wait for child process")?;
+        if let Some(task) = stdin_pipe_task {
+            task.await.expect("Failed to pipe stdin to child process");
+        }
+        return Ok(status);
+    }
+
+    let buffered = buffer.is_some();
     let stderr_pipe_task = child.stderr.take().map(|mut stderr| {
         tokio::spawn(async move {
-            let mut proc_stderr = tokio::io::stderr();
-            tokio::io::copy(&mut stderr, &mut proc_stderr)
-                .await
-                .context("Failed to pipe stderr to child process")
+            let mut stderr_buf = Vec::new();
+            let res = if buffered {
+                tokio::io::copy(&mut stderr, &mut stderr_buf).await
+            } else {
+                tokio::io::copy(&mut stderr, &mut tokio::io::stderr()).await
+            };
+            res.context("Failed to pipe stderr to child process")?;
+            anyhow::Ok(stderr_buf)
         })
     });
 
@@ -68,11 +203,17 @@ pub async fn run<'a>(
         .expect("Child process should have stdout piped");
     {
         let mut buf_stdout = tokio::io::BufReader::new(child_stdout);
-        if !captures.is_empty() {
-            handle_stdout_async(buf_stdout, captures).await?;
-        } else {
-            let mut stdout = tokio::io::stdout();
-            tokio::io::copy_buf(&mut buf_stdout, &mut stdout).await?;
+        match (captures.is_empty(), buffer.as_deref_mut()) {
+            (false, Some(buffer)) => {
+                handle_stdout_async(buf_stdout, captures, &mut buffer.stdout).await?
+            }
+            (false, None) => handle_stdout_async(buf_stdout, captures, tokio::io::stdout()).await?,
+            (true, Some(buffer)) => {
+                tokio::io::copy_buf(&mut buf_stdout, &mut buffer.stdout).await?;
+            }
+            (true, None) => {
+                tokio::io::copy_buf(&mut buf_stdout, &mut tokio::io::stdout()).await?;
+            }
         }
     }
 
@@ -82,9 +223,16 @@ pub async fn run<'a>(
         .await
         .context("Failed to wait for child process")?;
 
+    if let Some(task) = stdin_pipe_task {
+        task.await.expect("Failed to pipe stdin to child process");
+    }
     if let Some(task) = stderr_pipe_task {
-        task.await
+        let stderr_buf = task
+            .await
             .expect("Failed to pipe stderr to child process")?;
+        if let Some(buffer) = buffer {
+            buffer.stderr = stderr_buf;
+        }
     }
 
     Ok(status)
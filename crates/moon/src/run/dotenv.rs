@@ -0,0 +1,151 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Loading environment variables from dotenv files, for `--env-file`.
+//!
+//! Each line is `KEY=VALUE`, optionally prefixed by `export`. Blank lines and
+//! lines starting with `#` are ignored. Values may be quoted:
+//!
+//! - In single quotes, the value is taken literally.
+//! - In double quotes, `\n`, `\t`, `\"` and `\\` are unescaped.
+//! - Quoted values may span several lines.
+//! - Unquoted values end at a ` #` comment, and are trimmed.
+//!
+//! Variables are not expanded.
+
+use std::path::Path;
+
+use anyhow::{bail, Context};
+
+/// Load the variables of the dotenv file at `path`. Unless `override_env`,
+/// the variables already set in the environment of `moon` are left out, so
+/// the real environment wins over the file.
+pub fn load_env_file(path: &Path, override_env: bool) -> anyhow::Result<Vec<(String, String)>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read env file {}", path.display()))?;
+    let vars = parse_dotenv(&content).with_context(|| format!("in {}", path.display()))?;
+    Ok(vars
+        .into_iter()
+        .filter(|(key, _)| override_env || std::env::var_os(key).is_none())
+        .collect())
+}
+
+/// Parse the content of a dotenv file into its variables, in order.
+fn parse_dotenv(content: &str) -> anyhow::Result<Vec<(String, String)>> {
+    let mut res = vec![];
+    let mut lines = content.lines().enumerate();
+    while let Some((i, line)) = lines.next() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line.split_once('=') else {
+            bail!("expected `KEY=VALUE` on line {}", i + 1);
+        };
+        let key = key.trim();
+        if key.is_empty()
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        {
+            bail!("invalid variable name `{key}` on line {}", i + 1);
+        }
+        let value = value.trim_start();
+        let value = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let mut quoted = value[1..].to_string();
+                loop {
+                    if let Some(value) = unquote(&quoted, quote) {
+                        break value;
+                    }
+                    let Some((_, next)) = lines.next() else {
+                        bail!("unterminated quoted value of `{key}` on line {}", i + 1);
+                    };
+                    quoted.push('\n');
+                    quoted.push_str(next);
+                }
+            }
+            _ => match value.find(" #") {
+                Some(comment) => value[..comment].trim_end().to_string(),
+                None => value.trim_end().to_string(),
+            },
+        };
+        res.push((key.to_string(), value));
+    }
+    Ok(res)
+}
+
+/// The value in `s` up to the closing `quote`, or `None` if it isn't closed.
+/// Anything after the closing quote, like a comment, is ignored.
+fn unquote(s: &str, quote: char) -> Option<String> {
+    let mut res = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c == quote => return Some(res),
+            '\\' if quote == '"' => match chars.next()? {
+                'n' => res.push('\n'),
+                't' => res.push('\t'),
+                'r' => res.push('\r'),
+                c @ ('"' | '\\') => res.push(c),
+                c => {
+                    res.push('\\');
+                    res.push(c);
+                }
+            },
+            c => res.push(c),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dotenv() {
+        let content = r#"
+# database
+export DB_URL=postgres://localhost:5432/db # local
+EMPTY=
+SPACED = a b
+SINGLE='no $escape \n here'
+DOUBLE="tab\there \"quoted\"" # comment
+MULTI="first
+second"
+HASH=a#b
+"#;
+        let vars = parse_dotenv(content).unwrap();
+        let expected = [
+            ("DB_URL", "postgres://localhost:5432/db"),
+            ("EMPTY", ""),
+            ("SPACED", "a b"),
+            ("SINGLE", "no $escape \\n here"),
+            ("DOUBLE", "tab\there \"quoted\""),
+            ("MULTI", "first\nsecond"),
+            ("HASH", "a#b"),
+        ];
+        assert_eq!(vars, expected.map(|(k, v)| (k.to_string(), v.to_string())));
+
+        assert!(parse_dotenv("NO_VALUE\n").is_err());
+        assert!(parse_dotenv("BAD KEY=1\n").is_err());
+        assert!(parse_dotenv("OPEN=\"never closed\n").is_err());
+    }
+}
@@ -19,12 +19,23 @@
 //! `moon run` facility and friends
 
 mod child;
+pub mod dotenv;
+pub mod remote;
 mod runtest;
 mod runtime;
+mod stop;
 
-pub use child::run;
-pub use runtest::{perform_promotion, run_tests, TestFilter, TestIndex};
-pub use runtime::{command_for, CommandGuard};
+pub use child::{run, ChildStdin};
+pub use runtest::{
+    count_excluded, list_tests, not_run_doc_tests, perform_promotion, run_single_test, run_tests,
+    shard_filter, InFlightTests, PromotionScope, ReplaceableTestResults, Shard, TapReporter,
+    TestCoverage, TestFilter, TestIndex, TestNameFilter, TestRunConfig,
+};
+pub use runtime::{
+    command_for, command_line_for, executable_version, CommandGuard, Debugger, ExecutableSource,
+    JsRuntime, NativeRunner, RuntimeConfig, RuntimeExecutableCache, SpawnedCommand, WasmGcRunner,
+};
+pub use stop::{StopPolicy, StopSignal, DEFAULT_STOP_GRACE};
 
 pub fn default_rt() -> std::io::Result<tokio::runtime::Runtime> {
     tokio::runtime::Builder::new_current_thread()
@@ -0,0 +1,162 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Running native executables on a remote host.
+//!
+//! `moon` doesn't talk to the remote host itself. Instead, it spawns a local
+//! command reaching the remote agent, e.g. `ssh farm moon-remote-agent`, and
+//! the spawned process stands for the remote one: its `stdout` and `stderr`
+//! are the ones of the program, and it exits with the exit code of the
+//! program. Thus the output capture and timeout of local processes apply
+//! unchanged.
+//!
+//! The agent reads the following from its `stdin`:
+//!
+//! 1. A line of JSON, the [`RemoteRequest`] header.
+//! 2. Exactly `size` bytes of the executable to run.
+//!
+//! The agent should then write the executable to a scratch location, make it
+//! executable, run it with `args` and `envs`, and remove it afterwards. The
+//! program itself gets no input.
+
+use std::ffi::OsString;
+use std::path::Path;
+
+use anyhow::Context;
+use moonbuild::entry::TestArgs;
+use serde::Serialize;
+use tokio::process::Command;
+
+use super::child::ChildStdin;
+use super::runtime::CommandGuard;
+
+/// The version of the protocol, bumped on incompatible changes.
+pub const REMOTE_PROTOCOL_VERSION: u32 = 1;
+
+/// Where to run native executables instead of spawning them locally.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RemoteExecutor {
+    /// The program and arguments of the command reaching the remote agent.
+    pub agent: Vec<String>,
+}
+
+/// The header sent to the remote agent before the executable.
+#[derive(Debug, Serialize)]
+pub struct RemoteRequest<'a> {
+    pub version: u32,
+    /// The file name of the executable, for the agent to keep it recognizable
+    pub name: String,
+    /// The size of the executable following the header, in bytes
+    pub size: u64,
+    /// The commandline arguments of the program
+    pub args: Vec<String>,
+    /// Environment variables set on the program
+    pub envs: Vec<(String, String)>,
+    /// The tests to run, if the executable is a test executable. They are
+    /// already part of `args`, but kept for agents scheduling by test.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub test_args: Option<&'a TestArgs>,
+}
+
+impl RemoteExecutor {
+    /// Parse the command of the agent, split like a shell does.
+    pub fn parse(command: &str) -> anyhow::Result<Self> {
+        match shlex::split(command) {
+            Some(agent) if !agent.is_empty() => Ok(Self { agent }),
+            _ => anyhow::bail!("invalid remote executor command: `{command}`"),
+        }
+    }
+
+    /// Create the local command running `executable` on the remote host,
+    /// with the arguments of `test` if given.
+    ///
+    /// The executable is read into memory and sent through the `stdin` of the
+    /// agent, so the guard must not have its `stdin` replaced.
+    pub fn command_for(
+        &self,
+        executable: &Path,
+        test: Option<&TestArgs>,
+        envs: &[(OsString, OsString)],
+    ) -> anyhow::Result<CommandGuard> {
+        let content = std::fs::read(executable)
+            .with_context(|| format!("failed to read {}", executable.display()))?;
+        let request = RemoteRequest {
+            version: REMOTE_PROTOCOL_VERSION,
+            name: executable
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            size: content.len() as u64,
+            args: test
+                .map(TestArgs::to_cli_args_for_native)
+                .unwrap_or_default(),
+            envs: envs
+                .iter()
+                .map(|(k, v)| {
+                    (
+                        k.to_string_lossy().into_owned(),
+                        v.to_string_lossy().into_owned(),
+                    )
+                })
+                .collect(),
+            test_args: test,
+        };
+        let mut payload = serde_json::to_vec(&request).expect("valid remote request");
+        payload.push(b'\n');
+        payload.extend(content);
+
+        let mut cmd = Command::new(&self.agent[0]);
+        cmd.args(&self.agent[1..]);
+        let mut guard = CommandGuard::from(cmd);
+        guard.stdin = ChildStdin::Bytes(payload);
+        Ok(guard)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_remote_executor() {
+        let remote = RemoteExecutor::parse("ssh 'build farm' moon-remote-agent").unwrap();
+        assert_eq!(remote.agent, ["ssh", "build farm", "moon-remote-agent"]);
+        assert!(RemoteExecutor::parse("  ").is_err());
+    }
+
+    #[test]
+    fn test_remote_payload() {
+        let dir = tempfile::tempdir().unwrap();
+        let exe = dir.path().join("foo.blackbox_test");
+        std::fs::write(&exe, b"\x7fELF").unwrap();
+        let remote = RemoteExecutor::parse("agent").unwrap();
+        let envs = [(OsString::from("A"), OsString::from("1"))];
+        let guard = remote.command_for(&exe, None, &envs).unwrap();
+        let ChildStdin::Bytes(payload) = guard.stdin else {
+            panic!("the executable should be sent through stdin");
+        };
+        let newline = payload.iter().position(|&b| b == b'\n').unwrap();
+        let (header, content) = payload.split_at(newline);
+        let header: serde_json::Value = serde_json::from_slice(header).unwrap();
+        assert_eq!(header["name"], "foo.blackbox_test");
+        assert_eq!(header["size"], 4);
+        assert_eq!(header["envs"], serde_json::json!([["A", "1"]]));
+        assert_eq!(&content[1..], b"\x7fELF");
+    }
+}
@@ -45,6 +45,12 @@
            for the next iteration.
     5. After the loop, print the final result as usual.
 
+    ## Running tests from other harnesses
+
+    [`summary::run_test_suite`] runs the tests like the workflow above,
+    returning the results as a [`summary::TestRunSummary`] instead of printing
+    them.
+
     ## Future improvements
 
     There is an ongoing discussion about the snapshot promotion behavior. If we
@@ -57,44 +63,82 @@
     Check the discussion at [core#2684](https://github.com/moonbitlang/core/issues/2684).
 */
 
+mod bench;
+mod coverage;
+mod doc_test;
 mod filter;
+mod junit;
 mod promotion;
-
-use std::{collections::HashMap, path::Path, sync::Arc};
+mod summary;
+mod tap;
+mod timeout;
+
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    ffi::OsString,
+    num::NonZeroUsize,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use anyhow::Context;
+use futures::StreamExt;
 use indexmap::IndexMap;
 use log::warn;
 use moonbuild::{
     benchmark::{render_batch_bench_summary, BATCHBENCH},
-    entry::{CompactTestFormatter, TestArgs},
+    entry::{BenchArgs, CompactTestFormatter, TestArgs},
     expect::{
         render_expect_fail, render_snapshot_fail, ERROR, EXPECT_FAILED, RUNTIME_ERROR,
         SNAPSHOT_TESTING,
     },
     runtest::TestStatistics,
-    section_capture::SectionCapture,
+    section_capture::{OutputAttribution, SectionCapture, TestOutput},
+};
+use moonbuild_rupes_recta::{
+    cond_comp::{get_file_test_kind_full, FileTestKind},
+    model::{BuildPlanNode, BuildTarget, PackageId, TargetKind},
 };
-use moonbuild_rupes_recta::model::{BuildPlanNode, BuildTarget};
 use moonutil::common::{
-    MbtTestInfo, MooncGenTestInfo, MOON_COVERAGE_DELIMITER_BEGIN, MOON_COVERAGE_DELIMITER_END,
-    MOON_TEST_DELIMITER_BEGIN, MOON_TEST_DELIMITER_END,
+    MbtTestInfo, MooncGenTestInfo, TargetBackend, MOON_COVERAGE_DELIMITER_BEGIN,
+    MOON_COVERAGE_DELIMITER_END, MOON_TEST_DELIMITER_BEGIN, MOON_TEST_DELIMITER_END,
+    MOON_TEST_OUTPUT_START,
+};
+use serde::Serialize;
+
+use crate::{
+    rr_build::BuildMeta,
+    run::{
+        child::BufferedOutput,
+        default_rt,
+        runtime::{
+            command_for_cached, CommandTimedOut, RuntimeConfig, RuntimeExecutableCache,
+            RuntimeTimeout,
+        },
+    },
 };
-use tokio::runtime::Runtime;
-
-use crate::{rr_build::BuildMeta, run::default_rt};
 
-pub use filter::TestFilter;
-pub use promotion::perform_promotion;
+pub use coverage::TestCoverage;
+pub use doc_test::not_run_doc_tests;
+pub use filter::{Shard, TestFilter, TestNameFilter};
+pub use promotion::{perform_promotion, PromotionScope};
+pub use tap::TapReporter;
 
+/// The outcome of a test.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum TestResultKind {
+pub enum TestResultKind {
     Passed,
+    /// Failed at first, but passed on a retry
+    Flaky,
     ExpectTestFailed,
     SnapshotTestFailed,
     RuntimeError,
     ExpectPanic,
     Failed,
+    /// Killed after running longer than its declared timeout
+    TimedOut,
 }
 
 #[derive(Debug, Clone)]
@@ -104,11 +148,19 @@ struct TestCaseResult {
     // The metadata structure is read per-executable, so we better own it.
     // Known issue: line numbers can be stale if we are promoting tests
     meta: MbtTestInfo,
+    /// The output printed by the test, for reporting failures
+    output: TestOutput,
+}
+
+impl TestResultKind {
+    pub fn passed(self) -> bool {
+        matches!(self, TestResultKind::Passed | TestResultKind::Flaky)
+    }
 }
 
 impl TestCaseResult {
     pub fn passed(&self) -> bool {
-        matches!(self.kind, TestResultKind::Passed)
+        self.kind.passed()
     }
 }
 
@@ -130,27 +182,201 @@ impl TestIndex {
     }
 }
 
+/// Configuration of how test executables are run.
+#[derive(Clone, Debug, Default)]
+pub struct TestRunConfig {
+    /// The runtimes to run test executables.
+    pub runtime: RuntimeConfig,
+    /// The timeout of each test executable.
+    pub timeout: RuntimeTimeout,
+    /// Extra environment variables of test executables.
+    pub envs: Vec<(OsString, OsString)>,
+    /// The max number of test executables to run concurrently. `None` means
+    /// the default parallelism, see
+    /// [`moonutil::parallelism::default_parallelism`].
+    pub jobs: Option<NonZeroUsize>,
+    /// The seed passed to every test executable of the run.
+    pub seed: Option<u64>,
+    /// The parameters of the bench harness, when running benchmarks.
+    pub bench: Option<BenchArgs>,
+    /// The max number of times to rerun the failed tests of a test
+    /// executable. Tests passing on a retry are reported as flaky.
+    pub retries: u32,
+    /// Stop launching test executables once one has a failed test, and treat
+    /// the running ones as given. `None` runs all of them.
+    pub fail_fast: Option<InFlightTests>,
+    /// The max number of bytes of output kept for each test, the rest is
+    /// dropped. `None` keeps all of it.
+    pub capture_limit: Option<usize>,
+}
+
+/// What to do with the test executables still running when a test fails in
+/// fail-fast mode.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum InFlightTests {
+    #[default]
+    Kill,
+    Finish,
+}
+
 /// Run the tests compiled in this session. Does **not** print or update
 /// snapshots.
 ///
 /// An external driver should check the results for reruns. See [module-level
 /// docs](crate::run::runtest) for more information about the workflow.
+///
+/// If `tap` is given, the results of each test executable are streamed to it
+/// as soon as the executable finishes.
+///
+/// With [`TestRunConfig::fail_fast`], the results stop at the first test
+/// executable with a failed test, and include or leave out the ones still
+/// running then. The executables after it are not run.
 pub fn run_tests(
     build_meta: &BuildMeta,
     target_dir: &Path,
     filter: &TestFilter,
+    config: &TestRunConfig,
+    tap: Option<&mut TapReporter>,
+) -> anyhow::Result<ReplaceableTestResults> {
+    let cache = RuntimeExecutableCache::default();
+    run_tests_with_cache(build_meta, target_dir, filter, config, tap, &cache)
+}
+
+/// [`run_tests`], looking up the runtimes of the executables through `cache`.
+fn run_tests_with_cache(
+    build_meta: &BuildMeta,
+    target_dir: &Path,
+    filter: &TestFilter,
+    config: &TestRunConfig,
+    mut tap: Option<&mut TapReporter>,
+    cache: &RuntimeExecutableCache,
 ) -> anyhow::Result<ReplaceableTestResults> {
     // Gathering artifacts
     let executables = gather_tests(build_meta);
 
     let rt = default_rt().context("Failed to create runtime")?;
-    let mut stats = ReplaceableTestResults::default();
-    for r in executables {
-        let res = run_one_test_executable(build_meta, &rt, target_dir, &r, filter)?;
-        stats.merge_with_target(r.target, res);
+    // Test executables run concurrently on the same thread, sharing the cache
+    // so that runtimes are looked up only once. The JS test drivers are
+    // numbered within the directory of the cache, so they never collide.
+    let jobs = config.jobs.map_or_else(
+        || moonutil::parallelism::default_parallelism().map_or(1, NonZeroUsize::get),
+        NonZeroUsize::get,
+    );
+    // Keep the outputs of concurrent tests apart
+    let buffered = jobs > 1;
+    // Set on the first failure in fail-fast mode. Executables are launched as
+    // their futures are first polled, so checking it there skips the rest.
+    let stopped = &Cell::new(false);
+    let mut results = futures::stream::iter(&executables)
+        .map(|r| async move {
+            if stopped.get() {
+                return (r, None);
+            }
+            let res =
+                run_one_test_executable(build_meta, cache, config, target_dir, r, filter, buffered)
+                    .await;
+            (r, Some(res))
+        })
+        // Results are yielded in order, thus reported in order
+        .buffered(jobs);
+
+    rt.block_on(async {
+        let mut stats = ReplaceableTestResults::default();
+        while let Some((r, res)) = results.next().await {
+            let Some(res) = res else {
+                continue;
+            };
+            let res = res?;
+            if let Some(tap) = tap.as_deref_mut() {
+                let fqn = build_meta.resolve_output.pkg_dirs.fqn(r.target.package);
+                tap.report(&fqn.to_string(), &res);
+            }
+            let failed = res
+                .map
+                .values()
+                .flat_map(|m| m.values())
+                .any(|c| !c.passed());
+            stats.merge_with_target(r.target, res);
+            if failed && !stopped.get() {
+                if let Some(in_flight) = config.fail_fast {
+                    warn!("stopping at the first failed test because of `--fail-fast`");
+                    stopped.set(true);
+                    if in_flight == InFlightTests::Kill {
+                        // Dropping the stream kills the running executables
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(stats)
+    })
+}
+
+/// Run the test block `test "name" { ... }` of package `pkg` in `file`, e.g.
+/// for `moon test --test-name` or the "Run Test" code lens of editors. The
+/// test executables of `pkg` must have been built in this session. Does **not** print or update snapshots,
+/// same as [`run_tests`].
+pub fn run_single_test(
+    build_meta: &BuildMeta,
+    target_dir: &Path,
+    pkg: PackageId,
+    file: &str,
+    name: &str,
+    config: &TestRunConfig,
+    tap: Option<&mut TapReporter>,
+) -> anyhow::Result<ReplaceableTestResults> {
+    let index = find_test_by_name(build_meta, pkg, file, name)?;
+    let mut filter = TestFilter::default();
+    filter.add_autodetermine_target(pkg, Some(file), Some(index));
+    run_tests(build_meta, target_dir, &filter, config, tap)
+}
+
+/// Find the index of the test named `name` in `file` of package `pkg`, by
+/// looking up the metadata of the test executables.
+fn find_test_by_name(
+    build_meta: &BuildMeta,
+    pkg: PackageId,
+    file: &str,
+    name: &str,
+) -> anyhow::Result<TestIndex> {
+    for test in gather_tests(build_meta) {
+        if test.target.package != pkg {
+            continue;
+        }
+        let meta = read_test_meta(test.meta)?;
+        if let Some(index) = find_test_in_meta(&meta, test.target.kind, file, name) {
+            return Ok(index);
+        }
     }
+    anyhow::bail!("cannot find test named {name:?} in {file}")
+}
 
-    Ok(stats)
+/// Find the index of the test named `name` in `file` within the metadata of a
+/// test executable of `kind`.
+fn find_test_in_meta(
+    meta: &MooncGenTestInfo,
+    kind: TargetKind,
+    file: &str,
+    name: &str,
+) -> Option<TestIndex> {
+    let found = [
+        &meta.no_args_tests,
+        &meta.with_args_tests,
+        &meta.async_tests,
+    ]
+    .into_iter()
+    .filter_map(|tests| tests.get(file))
+    .flatten()
+    .find(|t| t.name.as_deref() == Some(name))?;
+    // Tests of a regular source file in the blackbox test target are the
+    // doctests in it
+    let is_doc_test = kind == TargetKind::BlackboxTest
+        && matches!(get_file_test_kind_full(file), FileTestKind::NoTest);
+    Some(if is_doc_test {
+        TestIndex::DocTest(found.index)
+    } else {
+        TestIndex::Regular(found.index)
+    })
 }
 
 #[derive(derive_builder::Builder)]
@@ -173,17 +399,22 @@ pub struct ReplaceableTestResults {
 struct TargetTestResult {
     /// `Map<file, Map<index, result>>`
     map: IndexMap<String, IndexMap<u32, TestCaseResult>>,
+    /// The total time spent running the test executable, including reruns
+    duration: Duration,
 }
 
 #[derive(Default, Clone, Debug)]
 pub struct TestSummary {
     pub total: usize,
     pub passed: usize,
+    /// The passed tests that failed at first, included in `passed`
+    pub flaky: usize,
 }
 
 impl ReplaceableTestResults {
     fn merge_with_target(&mut self, target: BuildTarget, result: TargetTestResult) {
         let entry = self.map.entry(target).or_default();
+        entry.duration += result.duration;
         for (file, file_map) in result.map {
             let file_entry = entry.map.entry(file).or_default();
             for (index, case) in file_map {
@@ -221,13 +452,22 @@ impl ReplaceableTestResults {
     pub fn summary(&self) -> TestSummary {
         let mut total = 0;
         let mut passed = 0;
+        let mut flaky = 0;
         for result in self.map.values() {
             for file_map in result.map.values() {
                 total += file_map.len();
                 passed += file_map.values().filter(|r| r.passed()).count();
+                flaky += file_map
+                    .values()
+                    .filter(|r| r.kind == TestResultKind::Flaky)
+                    .count();
             }
         }
-        TestSummary { total, passed }
+        TestSummary {
+            total,
+            passed,
+            flaky,
+        }
     }
 }
 
@@ -246,6 +486,141 @@ impl TargetTestResult {
             }
         }
     }
+
+    /// Add the results of `other`, run separately, to these.
+    fn extend(&mut self, other: TargetTestResult) {
+        self.duration += other.duration;
+        for (file, file_map) in other.map {
+            for (index, case) in file_map {
+                self.add(&file, index, case);
+            }
+        }
+    }
+
+    /// The failed tests, in the form of [`TestArgs::file_and_index`].
+    fn failed_tests(&self) -> Vec<(String, Vec<std::ops::Range<u32>>)> {
+        self.map
+            .iter()
+            .map(|(file, file_map)| {
+                let ranges = file_map
+                    .iter()
+                    .filter(|(_, r)| !r.passed())
+                    .map(|(&index, _)| index..(index + 1))
+                    .collect::<Vec<_>>();
+                (file.clone(), ranges)
+            })
+            .filter(|(_, ranges)| !ranges.is_empty())
+            .collect()
+    }
+}
+
+/// A test found by [`list_tests`]. `name` is the name accepted by
+/// `--filter`, and `package`, `file` and `index` are those accepted by
+/// `--package`, `--file` and `--index` (`--doc-index` for doc tests).
+#[derive(Debug, Serialize)]
+pub struct ListedTest {
+    pub name: String,
+    pub package: String,
+    pub file: String,
+    pub index: u32,
+    pub doc_test: bool,
+    pub line: Option<usize>,
+    #[serde(skip)]
+    target: BuildTarget,
+}
+
+/// List the tests compiled in this session that `filter` selects, without
+/// running them.
+pub fn list_tests(build_meta: &BuildMeta, filter: &TestFilter) -> anyhow::Result<Vec<ListedTest>> {
+    let mut res = vec![];
+    for test in gather_tests(build_meta) {
+        let (included, file_filt) = filter.check_package(test.target);
+        if !included {
+            continue;
+        }
+        let package = build_meta
+            .resolve_output
+            .pkg_dirs
+            .fqn(test.target.package)
+            .to_string();
+        let meta = read_test_meta(test.meta)?;
+
+        let mut files_and_index = vec![];
+        filter::apply_filter(file_filt, &meta, &mut files_and_index);
+        if filter.doc_only {
+            files_and_index.retain(|(file, _)| doc_test::is_doc_test_file(test.target, file));
+        }
+        if let Some(name_filter) = &filter.name {
+            filter::apply_name_filter(name_filter, &package, &meta, &mut files_and_index);
+        }
+        if let Some(exclude) = &filter.exclude {
+            filter::apply_exclude_filter(exclude, &package, &meta, &mut files_and_index);
+        }
+        for (file, ranges) in files_and_index {
+            let doc_test = doc_test::is_doc_test_file(test.target, &file);
+            let tests = [
+                &meta.no_args_tests,
+                &meta.with_args_tests,
+                &meta.async_tests,
+            ]
+            .into_iter()
+            .filter_map(|list| list.get(&file))
+            .flatten()
+            .filter(|t| ranges.iter().any(|r| r.contains(&t.index)));
+            for t in tests {
+                res.push(ListedTest {
+                    name: filter::test_fqn(&package, &file, t),
+                    package: package.clone(),
+                    file: file.clone(),
+                    index: t.index,
+                    doc_test,
+                    line: t.line_number,
+                    target: test.target,
+                });
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Narrow `filter` down to the tests of `shard`, see [`Shard::select`].
+pub fn shard_filter(
+    build_meta: &BuildMeta,
+    filter: &TestFilter,
+    shard: Shard,
+) -> anyhow::Result<TestFilter> {
+    let tests = list_tests(build_meta, filter)?;
+    let mut pf = filter::PackageFilter::default();
+    for test in shard.select(&tests, |t| &t.name) {
+        pf.add_one(test.target, Some(&test.file), Some(test.index));
+    }
+    Ok(TestFilter {
+        filter: Some(pf),
+        name: filter.name.clone(),
+        exclude: None,
+        doc_only: filter.doc_only,
+    })
+}
+
+/// The number of tests selected by `filter` that its `exclude` skips.
+pub fn count_excluded(build_meta: &BuildMeta, filter: &TestFilter) -> anyhow::Result<usize> {
+    let Some(exclude) = &filter.exclude else {
+        return Ok(0);
+    };
+    let all = list_tests(
+        build_meta,
+        &TestFilter {
+            exclude: None,
+            ..filter.clone()
+        },
+    )?;
+    Ok(all.iter().filter(|t| exclude.is_match(&t.name)).count())
+}
+
+fn read_test_meta(path: &Path) -> anyhow::Result<MooncGenTestInfo> {
+    let meta = std::fs::File::open(path).context("Failed to open test metadata")?;
+    serde_json_lenient::from_reader(meta)
+        .with_context(|| format!("Failed to parse test metadata at {}", path.display()))
 }
 
 /// Gather tests executables from the build metadata.
@@ -290,53 +665,289 @@ fn gather_tests(build_meta: &BuildMeta) -> Vec<TestExecutableToRun<'_>> {
     results
 }
 
-fn run_one_test_executable(
+/// Run one test executable, and then rerun only its failed tests up to
+/// [`TestRunConfig::retries`] times. If `buffered`, its output is printed at
+/// once after each run finishes, instead of as it comes.
+async fn run_one_test_executable(
     build_meta: &BuildMeta,
-    rt: &Runtime, // FIXME: parallel execution
+    cache: &RuntimeExecutableCache,
+    config: &TestRunConfig,
     target_dir: &Path,
-    test: &TestExecutableToRun,
+    test: &TestExecutableToRun<'_>,
     filter: &TestFilter,
+    buffered: bool,
 ) -> Result<TargetTestResult, anyhow::Error> {
     let (included, file_filt) = filter.check_package(test.target);
     if !included {
         return Ok(TargetTestResult::default());
     }
 
-    let fqn = build_meta.resolve_output.pkg_dirs.fqn(test.target.package);
-    let pkgname = fqn.to_string();
+    let pkgname = build_meta
+        .resolve_output
+        .pkg_dirs
+        .fqn(test.target.package)
+        .to_string();
 
-    // Parse test metadata
-    let meta = std::fs::File::open(test.meta).context("Failed to open test metadata")?;
-    let meta: MooncGenTestInfo = serde_json_lenient::from_reader(meta)
-        .with_context(|| format!("Failed to parse test metadata at {}", test.meta.display()))?;
+    let meta = read_test_meta(test.meta)?;
 
     let mut test_args = TestArgs {
         package: pkgname,
         file_and_index: vec![],
+        seed: config.seed,
+        bench: config.bench,
+        timeout_ms: None,
     };
 
     filter::apply_filter(file_filt, &meta, &mut test_args.file_and_index);
+    if filter.doc_only {
+        test_args
+            .file_and_index
+            .retain(|(file, _)| doc_test::is_doc_test_file(test.target, file));
+    }
+    if let Some(name_filter) = &filter.name {
+        filter::apply_name_filter(
+            name_filter,
+            &test_args.package,
+            &meta,
+            &mut test_args.file_and_index,
+        );
+    }
+    if let Some(exclude) = &filter.exclude {
+        filter::apply_exclude_filter(
+            exclude,
+            &test_args.package,
+            &meta,
+            &mut test_args.file_and_index,
+        );
+    }
+    // No need to spawn the executable if none of its tests match
+    if (filter.name.is_some() || filter.exclude.is_some())
+        && test_args.file_and_index.iter().all(|(_, r)| r.is_empty())
+    {
+        return Ok(TargetTestResult::default());
+    }
+
+    let pkg_dir = &build_meta
+        .resolve_output
+        .pkg_dirs
+        .get_package(test.target.package)
+        .root_path;
+    let timeouts = timeout::declared_timeouts(pkg_dir, &meta)?;
+
+    let mut result = run_test_args_split(
+        build_meta, cache, config, target_dir, test, &test_args, &timeouts, &meta, buffered,
+    )
+    .await?;
+
+    for _ in 0..config.retries {
+        let failed = result.failed_tests();
+        if failed.is_empty() {
+            break;
+        }
+        test_args.file_and_index = failed;
+        let retry = run_test_args_split(
+            build_meta, cache, config, target_dir, test, &test_args, &timeouts, &meta, buffered,
+        )
+        .await?;
+        result.duration += retry.duration;
+        for (file, file_map) in retry.map {
+            for (index, mut case) in file_map {
+                if case.passed() {
+                    case.kind = TestResultKind::Flaky;
+                }
+                result.add(&file, index, case);
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Run the tests of `test_args`, each test with a declared timeout in a test
+/// executable of its own, see [`timeout`].
+#[allow(clippy::too_many_arguments)]
+async fn run_test_args_split(
+    build_meta: &BuildMeta,
+    cache: &RuntimeExecutableCache,
+    config: &TestRunConfig,
+    target_dir: &Path,
+    test: &TestExecutableToRun<'_>,
+    test_args: &TestArgs,
+    timeouts: &HashMap<(String, u32), Duration>,
+    meta: &MooncGenTestInfo,
+    buffered: bool,
+) -> anyhow::Result<TargetTestResult> {
+    let mut rest = test_args.clone();
+    let declared = timeout::split_declared(&mut rest, timeouts);
+    let mut result = TargetTestResult::default();
+    if declared.is_empty() || rest.file_and_index.iter().any(|(_, r)| !r.is_empty()) {
+        result = run_test_args(
+            build_meta,
+            cache,
+            config,
+            target_dir,
+            test,
+            &rest,
+            meta.clone(),
+            buffered,
+            None,
+        )
+        .await?;
+    }
+    for (args, timeout) in declared {
+        let single = run_test_args(
+            build_meta,
+            cache,
+            config,
+            target_dir,
+            test,
+            &args,
+            meta.clone(),
+            buffered,
+            Some(timeout),
+        )
+        .await?;
+        result.extend(single);
+    }
+    Ok(result)
+}
 
-    let cmd =
-        crate::run::command_for(build_meta.target_backend, test.executable, Some(&test_args))?;
+/// Run the tests of `test_args` in one test executable.
+///
+/// With a `declared_timeout`, `test_args` holds a single test, which is
+/// reported as [`TestResultKind::TimedOut`] if the executable is killed.
+/// Otherwise the executable timing out is an error.
+#[allow(clippy::too_many_arguments)]
+async fn run_test_args(
+    build_meta: &BuildMeta,
+    cache: &RuntimeExecutableCache,
+    config: &TestRunConfig,
+    target_dir: &Path,
+    test: &TestExecutableToRun<'_>,
+    test_args: &TestArgs,
+    meta: MooncGenTestInfo,
+    buffered: bool,
+    declared_timeout: Option<Duration>,
+) -> anyhow::Result<TargetTestResult> {
+    let fqn = build_meta.resolve_output.pkg_dirs.fqn(test.target.package);
+    let mut cmd = command_for_cached(
+        cache,
+        build_meta.target_backend,
+        &config.runtime,
+        test.executable,
+        Some(test_args),
+        &config.envs,
+        None,
+    )?;
+    cmd.timeout = match declared_timeout {
+        Some(timeout) => Some(timeout + timeout::KILL_GRACE),
+        None => config.timeout.for_backend(build_meta.target_backend),
+    };
     let mut cov_cap = mk_coverage_capture();
     let mut test_cap = make_test_capture();
+    // The JS test driver marks where the output of tests starts
+    let start_marker =
+        (build_meta.target_backend == TargetBackend::Js).then_some(MOON_TEST_OUTPUT_START);
+    let mut attribution = OutputAttribution::new(MOON_TEST_DELIMITER_END, start_marker)
+        .with_limit(config.capture_limit);
+
+    let mut output = buffered.then(BufferedOutput::default);
+    let start = Instant::now();
+    let status = cmd
+        .run_with_timeout(
+            &mut [&mut cov_cap, &mut test_cap],
+            output.as_mut(),
+            Some(&mut attribution),
+        )
+        .await;
+    if let Some(output) = &output {
+        output.flush().context("Failed to print test output")?;
+    }
+    if let (Err(e), Some(timeout)) = (&status, declared_timeout) {
+        if e.is::<CommandTimedOut>() {
+            return Ok(timed_out_result(
+                test_args,
+                &meta,
+                timeout,
+                attribution.into_unfinished(),
+                start.elapsed(),
+            ));
+        }
+    }
+    status.map_err(|e| match e.downcast::<CommandTimedOut>() {
+        Ok(timed_out) => {
+            anyhow::anyhow!("Test for {fqn} {:?} {timed_out}", test.target.kind)
+        }
+        Err(e) => e.context(format!(
+            "Failed to run test for {fqn} {:?}",
+            test.target.kind
+        )),
+    })?;
 
-    rt.block_on(crate::run::run(
-        &mut [&mut cov_cap, &mut test_cap],
-        false,
-        cmd.command,
-    ))
-    .with_context(|| format!("Failed to run test for {fqn} {:?}", test.target.kind))?;
+    let duration = start.elapsed();
 
     handle_finished_coverage(target_dir, cov_cap)?;
 
-    parse_test_results(meta, test_cap).with_context(|| {
-        format!(
-            "Failed to parse test results for {fqn} {:?}",
-            test.target.kind
-        )
-    })
+    let mut result =
+        parse_test_results(meta, test_cap, attribution.finish()).with_context(|| {
+            format!(
+                "Failed to parse test results for {fqn} {:?}",
+                test.target.kind
+            )
+        })?;
+    result.duration = duration;
+    Ok(result)
+}
+
+/// The result of the single test in `test_args`, killed after running longer
+/// than its declared `timeout`.
+fn timed_out_result(
+    test_args: &TestArgs,
+    meta: &MooncGenTestInfo,
+    timeout: Duration,
+    output: TestOutput,
+    duration: Duration,
+) -> TargetTestResult {
+    let (file, ranges) = &test_args.file_and_index[0];
+    let index = ranges[0].start;
+    let info = [
+        &meta.no_args_tests,
+        &meta.with_args_tests,
+        &meta.with_bench_args_tests,
+        &meta.async_tests,
+    ]
+    .into_iter()
+    .filter_map(|tests| tests.get(file))
+    .flatten()
+    .find(|t| t.index == index)
+    .cloned()
+    .unwrap_or_else(|| MbtTestInfo {
+        index,
+        func: String::new(),
+        name: None,
+        line_number: None,
+    });
+    let raw = TestStatistics {
+        package: test_args.package.clone(),
+        filename: file.clone(),
+        index: index.to_string(),
+        test_name: info.name.clone().unwrap_or_else(|| index.to_string()),
+        message: CommandTimedOut(timeout).to_string(),
+    };
+    let mut res = TargetTestResult {
+        duration,
+        ..Default::default()
+    };
+    res.add(
+        file,
+        index,
+        TestCaseResult {
+            kind: TestResultKind::TimedOut,
+            raw: Arc::new(raw),
+            meta: info,
+            output,
+        },
+    );
+    res
 }
 
 fn mk_coverage_capture() -> SectionCapture<'static> {
@@ -347,8 +958,11 @@ fn mk_coverage_capture() -> SectionCapture<'static> {
     )
 }
 
+/// The results of tests, whose messages end up in reports, so they are kept
+/// plain with `--color never`.
 fn make_test_capture() -> SectionCapture<'static> {
     SectionCapture::new(MOON_TEST_DELIMITER_BEGIN, MOON_TEST_DELIMITER_END, false)
+        .strip_ansi(moonutil::common::no_color())
 }
 
 fn handle_finished_coverage(target_dir: &Path, cap: SectionCapture) -> anyhow::Result<()> {
@@ -367,9 +981,12 @@ fn handle_finished_coverage(target_dir: &Path, cap: SectionCapture) -> anyhow::R
     Ok(())
 }
 
+/// Parse the results in `cap`. Each result section contains one result line,
+/// of the test that printed the corresponding item of `outputs`.
 fn parse_test_results(
     meta: MooncGenTestInfo,
     cap: SectionCapture,
+    outputs: Vec<TestOutput>,
 ) -> anyhow::Result<TargetTestResult> {
     let Some(s) = cap.finish() else {
         return Ok(TargetTestResult::default());
@@ -395,10 +1012,12 @@ fn parse_test_results(
 
     // Actual handling of each test case result
     let mut res = TargetTestResult::default();
+    let mut outputs = outputs.into_iter();
     for line in s.lines() {
         if line.is_empty() {
             continue;
         }
+        let output = outputs.next().unwrap_or_default();
 
         let stat: TestStatistics = serde_json_lenient::from_str(line)
             .with_context(|| format!("Failed to parse test summary: {line}"))?;
@@ -437,6 +1056,7 @@ fn parse_test_results(
             kind: result_kind,
             raw: Arc::clone(&stat),
             meta,
+            output,
         };
         res.add(&stat.filename, index, case_result);
     }
@@ -485,6 +1105,10 @@ fn print_test_result(res: &TestCaseResult, module_name: &str, verbose: bool) {
     let formatter = CompactTestFormatter::new(module_name, &res.raw, Some(&res.meta));
 
     match res.kind {
+        TestResultKind::Flaky => {
+            let _ = formatter.write_flaky(&mut std::io::stdout());
+            println!();
+        }
         TestResultKind::Passed if !verbose => {}
         TestResultKind::Passed => {
             if message.starts_with(BATCHBENCH) {
@@ -497,7 +1121,7 @@ fn print_test_result(res: &TestCaseResult, module_name: &str, verbose: bool) {
             }
         }
 
-        TestResultKind::Failed | TestResultKind::RuntimeError => {
+        TestResultKind::Failed | TestResultKind::RuntimeError | TestResultKind::TimedOut => {
             if message.is_empty() {
                 let _ = formatter.write_failure(&mut std::io::stdout());
             } else {
@@ -522,3 +1146,49 @@ fn print_test_result(res: &TestCaseResult, module_name: &str, verbose: bool) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_test_in_meta() {
+        let test = |index, name: &str| MbtTestInfo {
+            index,
+            func: format!("__test_{index}"),
+            name: Some(name.to_string()),
+            line_number: None,
+        };
+        let meta = MooncGenTestInfo {
+            no_args_tests: IndexMap::from([
+                ("lib.mbt".to_string(), vec![test(0, "first")]),
+                ("lib_test.mbt".to_string(), vec![test(0, "a"), test(1, "b")]),
+            ]),
+            with_args_tests: IndexMap::new(),
+            with_bench_args_tests: IndexMap::new(),
+            async_tests: IndexMap::from([("lib_test.mbt".to_string(), vec![test(2, "c")])]),
+        };
+        let find = |kind, file, name| find_test_in_meta(&meta, kind, file, name);
+        assert_eq!(
+            find(TargetKind::BlackboxTest, "lib_test.mbt", "b"),
+            Some(TestIndex::Regular(1))
+        );
+        assert_eq!(
+            find(TargetKind::BlackboxTest, "lib_test.mbt", "c"),
+            Some(TestIndex::Regular(2))
+        );
+        // The tests of a regular file are its doctests in the blackbox target
+        assert_eq!(
+            find(TargetKind::BlackboxTest, "lib.mbt", "first"),
+            Some(TestIndex::DocTest(0))
+        );
+        assert_eq!(
+            find(TargetKind::InlineTest, "lib.mbt", "first"),
+            Some(TestIndex::Regular(0))
+        );
+        assert_eq!(
+            find(TargetKind::BlackboxTest, "lib_test.mbt", "first"),
+            None
+        );
+    }
+}
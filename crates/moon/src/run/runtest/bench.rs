@@ -0,0 +1,47 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Collects the benchmark results of a bench run
+
+use moonbuild::benchmark::{parse_batch_bench_summaries, BenchRecord, BenchReport};
+
+use crate::{rr_build::BuildMeta, run::runtest::ReplaceableTestResults};
+
+impl ReplaceableTestResults {
+    /// Collect the statistics reported by the benchmarks into a report.
+    pub fn bench_report(&self, meta: &BuildMeta) -> anyhow::Result<BenchReport> {
+        let mut benchmarks = vec![];
+        for result in self.map.values() {
+            for case in result.map.values().flat_map(|m| m.values()) {
+                let Some(summaries) = parse_batch_bench_summaries(&case.raw.message) else {
+                    continue;
+                };
+                let test = case.meta.name.as_ref().unwrap_or(&case.raw.test_name);
+                for summary in summaries?.summaries {
+                    benchmarks.push(BenchRecord {
+                        package: case.raw.package.clone(),
+                        filename: case.raw.filename.clone(),
+                        test: test.clone(),
+                        summary,
+                    });
+                }
+            }
+        }
+        Ok(BenchReport::new(meta.target_backend.to_flag(), benchmarks))
+    }
+}
@@ -0,0 +1,390 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Collects the line coverage of test executables run by the runtime, as
+//! opposed to the coverage instrumented by `moonc` itself.
+//!
+//! - Native executables are instrumented for LLVM source-based coverage, and
+//!   the raw profiles are merged and exported with `llvm-profdata` and
+//!   `llvm-cov`.
+//! - JS executables are run by `node` with `NODE_V8_COVERAGE`, and the V8
+//!   coverage of the generated JS (not mapped back to `.mbt` yet) is converted
+//!   to lcov here.
+
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{bail, Context};
+use moonutil::common::TargetBackend;
+use serde::Deserialize;
+use tempfile::TempDir;
+
+use crate::{
+    rr_build::BuildMeta,
+    run::{JsRuntime, RuntimeConfig},
+};
+
+use super::gather_tests;
+
+/// The raw coverage data written by the test executables of a run, which is
+/// converted to an lcov report after the run. The data is removed when this
+/// is dropped.
+pub struct TestCoverage {
+    kind: CoverageKind,
+    dir: TempDir,
+}
+
+#[derive(Clone, Copy)]
+enum CoverageKind {
+    Llvm,
+    V8,
+}
+
+impl TestCoverage {
+    /// Prepare a temporary directory under `target_dir` for the coverage data
+    /// of executables of `backend` run with `runtime`.
+    pub fn new(
+        backend: TargetBackend,
+        runtime: &RuntimeConfig,
+        target_dir: &Path,
+    ) -> anyhow::Result<Self> {
+        let kind = match backend {
+            TargetBackend::Native => CoverageKind::Llvm,
+            TargetBackend::Js if runtime.js_runtime == JsRuntime::Node => CoverageKind::V8,
+            TargetBackend::Js => bail!("`--coverage` of the JS backend needs `node` to run tests"),
+            _ => bail!("`--coverage` only supports the native and JS backends, not {backend}"),
+        };
+        let dir = tempfile::Builder::new()
+            .prefix("coverage-")
+            .tempdir_in(target_dir)
+            .context("failed to create the coverage directory")?;
+        Ok(Self { kind, dir })
+    }
+
+    /// The environment variable making the test executables write their
+    /// coverage into the directory.
+    pub fn env(&self) -> (OsString, OsString) {
+        match self.kind {
+            // `%p` keeps the profiles of concurrent processes apart, and `%m`
+            // those of different executables
+            CoverageKind::Llvm => (
+                "LLVM_PROFILE_FILE".into(),
+                self.dir.path().join("%p-%m.profraw").into(),
+            ),
+            // Node names the files after the process itself
+            CoverageKind::V8 => ("NODE_V8_COVERAGE".into(), self.dir.path().into()),
+        }
+    }
+
+    /// Export the coverage of the test executables of `build_meta` to
+    /// `output` as lcov, merging the data of all processes.
+    pub fn export(self, build_meta: &BuildMeta, output: &Path) -> anyhow::Result<()> {
+        let lcov = match self.kind {
+            CoverageKind::Llvm => self.export_llvm(build_meta)?,
+            CoverageKind::V8 => self.export_v8(build_meta)?,
+        };
+        std::fs::write(output, lcov)
+            .with_context(|| format!("failed to write coverage to {}", output.display()))
+    }
+
+    /// The files written into the directory with the given extension, sorted
+    /// to keep the merged report stable.
+    fn data_files(&self, extension: &str) -> anyhow::Result<Vec<PathBuf>> {
+        let mut files = vec![];
+        for entry in std::fs::read_dir(self.dir.path())? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|x| x == extension) {
+                files.push(path);
+            }
+        }
+        if files.is_empty() {
+            bail!("no coverage data was written by the test executables");
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    fn export_llvm(&self, build_meta: &BuildMeta) -> anyhow::Result<Vec<u8>> {
+        let profiles = self.data_files("profraw")?;
+        let merged = self.dir.path().join("merged.profdata");
+        let mut merge = Command::new(llvm_tool("llvm-profdata")?);
+        merge
+            .arg("merge")
+            .arg("-sparse")
+            .args(&profiles)
+            .arg("-o")
+            .arg(&merged);
+        run_tool(merge, "llvm-profdata")?;
+
+        // The first executable is the main object, and the others are given
+        // with `-object`
+        let tests = gather_tests(build_meta);
+        let mut cov = Command::new(llvm_tool("llvm-cov")?);
+        cov.arg("export")
+            .arg("-format=lcov")
+            .arg(format!("-instr-profile={}", merged.display()));
+        for (i, test) in tests.iter().enumerate() {
+            if i > 0 {
+                cov.arg("-object");
+            }
+            cov.arg(test.executable);
+        }
+        run_tool(cov, "llvm-cov")
+    }
+
+    fn export_v8(&self, build_meta: &BuildMeta) -> anyhow::Result<Vec<u8>> {
+        // Scripts other than the test executables, like the test drivers and
+        // the internal ones of node, are left out
+        let mut lines = BTreeMap::<PathBuf, LineCounts>::new();
+        for test in gather_tests(build_meta) {
+            lines.insert(dunce::canonicalize(test.executable)?, LineCounts::default());
+        }
+
+        for file in self.data_files("json")? {
+            let content = std::fs::read_to_string(&file)?;
+            let coverage: V8Coverage = serde_json::from_str(&content)
+                .with_context(|| format!("failed to parse V8 coverage {}", file.display()))?;
+            for mut script in coverage.result {
+                let path = file_url_to_path(&script.url).and_then(|p| dunce::canonicalize(p).ok());
+                let Some(path) = path else {
+                    continue;
+                };
+                let Some(counts) = lines.get_mut(&path) else {
+                    continue;
+                };
+                let source = std::fs::read_to_string(&path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                counts.merge(LineCounts::from_v8(&source, &mut script.ranges()));
+            }
+        }
+
+        let mut lcov = String::new();
+        for (path, counts) in &lines {
+            counts.write_lcov(path, &mut lcov);
+        }
+        Ok(lcov.into_bytes())
+    }
+}
+
+#[derive(Deserialize)]
+struct V8Coverage {
+    result: Vec<V8Script>,
+}
+
+#[derive(Deserialize)]
+struct V8Script {
+    url: String,
+    functions: Vec<V8Function>,
+}
+
+impl V8Script {
+    fn ranges(&mut self) -> Vec<V8Range> {
+        self.functions
+            .iter_mut()
+            .flat_map(|f| std::mem::take(&mut f.ranges))
+            .collect()
+    }
+}
+
+#[derive(Deserialize)]
+struct V8Function {
+    ranges: Vec<V8Range>,
+}
+
+/// A range of the script in UTF-16 code units, and how many times it ran.
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+struct V8Range {
+    start_offset: usize,
+    end_offset: usize,
+    count: u64,
+}
+
+/// The execution count of each line of a script, `None` for the lines
+/// without code.
+#[derive(Default, Debug, PartialEq, Eq)]
+struct LineCounts(Vec<Option<u64>>);
+
+impl LineCounts {
+    /// Count the lines of `source` by the innermost range containing the
+    /// first non-blank character of each line.
+    fn from_v8(source: &str, ranges: &mut [V8Range]) -> Self {
+        // (line, offset of the first non-blank character)
+        let mut starts = vec![];
+        let mut line_count = 0;
+        let mut offset = 0;
+        for (i, line) in source.split('\n').enumerate() {
+            line_count += 1;
+            let indent = line.chars().take_while(|c| c.is_whitespace());
+            let indent_len: usize = indent.clone().map(char::len_utf16).sum();
+            if line.chars().count() > indent.count() {
+                starts.push((i, offset + indent_len));
+            }
+            offset += line.encode_utf16().count() + 1;
+        }
+
+        // Outer ranges come before the ones nested in them, which override
+        // the counts
+        ranges.sort_by_key(|r| (r.start_offset, std::cmp::Reverse(r.end_offset)));
+        let mut counts = vec![None; line_count];
+        for r in ranges.iter() {
+            let first = starts.partition_point(|&(_, off)| off < r.start_offset);
+            for &(line, off) in &starts[first..] {
+                if off >= r.end_offset {
+                    break;
+                }
+                counts[line] = Some(r.count);
+            }
+        }
+        Self(counts)
+    }
+
+    /// Add the counts of another run of the same script.
+    fn merge(&mut self, other: LineCounts) {
+        if self.0.len() < other.0.len() {
+            self.0.resize(other.0.len(), None);
+        }
+        for (a, b) in self.0.iter_mut().zip(other.0) {
+            *a = match (*a, b) {
+                (Some(a), Some(b)) => Some(a + b),
+                (a, b) => a.or(b),
+            };
+        }
+    }
+
+    fn write_lcov(&self, path: &Path, out: &mut String) {
+        use std::fmt::Write;
+
+        let _ = writeln!(out, "SF:{}", path.display());
+        let (mut found, mut hit) = (0, 0);
+        for (line, count) in self.0.iter().enumerate() {
+            if let Some(count) = count {
+                let _ = writeln!(out, "DA:{},{count}", line + 1);
+                found += 1;
+                if *count > 0 {
+                    hit += 1;
+                }
+            }
+        }
+        let _ = writeln!(out, "LF:{found}\nLH:{hit}\nend_of_record");
+    }
+}
+
+/// Convert a `file://` URL of V8 to a path, decoding the percent-encoded
+/// bytes.
+fn file_url_to_path(url: &str) -> Option<PathBuf> {
+    let path = url.strip_prefix("file://")?;
+    // `file:///C:/foo` on Windows
+    let path = if cfg!(windows) {
+        path.strip_prefix('/').unwrap_or(path)
+    } else {
+        path
+    };
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut iter = path.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    Some(PathBuf::from(String::from_utf8(bytes).ok()?))
+}
+
+/// Find an LLVM tool in `PATH`.
+fn llvm_tool(name: &str) -> anyhow::Result<PathBuf> {
+    which::which(name).with_context(|| {
+        format!("`{name}` is needed to collect native coverage, but it is not found in PATH")
+    })
+}
+
+/// Run an LLVM tool and return its stdout.
+fn run_tool(mut cmd: Command, name: &str) -> anyhow::Result<Vec<u8>> {
+    let out = cmd
+        .output()
+        .with_context(|| format!("failed to run `{name}`"))?;
+    if !out.status.success() {
+        bail!(
+            "`{name}` failed with {}:\n{}",
+            out.status,
+            String::from_utf8_lossy(&out.stderr)
+        );
+    }
+    Ok(out.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_v8_line_counts() {
+        let source = "function f() {\n  if (x) {\n    g();\n  }\n\n}\nf();";
+        let mut ranges = [
+            // The nested block of `if` never runs
+            V8Range {
+                start_offset: 24,
+                end_offset: 38,
+                count: 0,
+            },
+            V8Range {
+                start_offset: 0,
+                end_offset: source.len(),
+                count: 1,
+            },
+            V8Range {
+                start_offset: 0,
+                end_offset: 41,
+                count: 2,
+            },
+        ];
+        let counts = LineCounts::from_v8(source, &mut ranges);
+        assert_eq!(
+            counts,
+            LineCounts(vec![
+                Some(2),
+                Some(2),
+                Some(0),
+                Some(0),
+                None,
+                Some(2),
+                Some(1)
+            ])
+        );
+
+        let mut merged = LineCounts(vec![Some(1), None]);
+        merged.merge(counts);
+        assert_eq!(merged.0[..3], [Some(3), Some(2), Some(0)]);
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn test_file_url_to_path() {
+        assert_eq!(
+            file_url_to_path("file:///tmp/a%20b/x.js"),
+            Some(PathBuf::from("/tmp/a b/x.js"))
+        );
+        assert_eq!(file_url_to_path("node:internal/main"), None);
+    }
+}
@@ -0,0 +1,152 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Doc tests, i.e. the code blocks in the doc comments of regular source
+//! files and in `.mbt.md` files. They are compiled into the blackbox test
+//! target of the package, so the tests of regular source files in that target
+//! are the doc tests, see [`is_doc_test_file`].
+
+use anyhow::Context;
+use moonbuild_rupes_recta::{
+    cond_comp::{get_file_test_kind_full, FileTestKind},
+    model::{BuildTarget, TargetKind},
+};
+
+use super::{gather_tests, TestFilter};
+use crate::rr_build::BuildMeta;
+
+/// The tag of the code blocks that are not run as doc tests.
+const NO_RUN: &str = "no-run";
+
+/// A code block in doc comments that is not run, because it's marked `no-run`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotRunDocTest {
+    pub package: String,
+    pub file: String,
+    /// The 1-based line of the opening fence of the code block
+    pub line: usize,
+}
+
+/// Whether the tests of `file` in `target` are doc tests.
+pub(super) fn is_doc_test_file(target: BuildTarget, file: &str) -> bool {
+    target.kind == TargetKind::BlackboxTest
+        && matches!(get_file_test_kind_full(file), FileTestKind::NoTest)
+}
+
+/// Find the code blocks marked `no-run` in the files of the doc tests that
+/// `filter` selects.
+pub fn not_run_doc_tests(
+    build_meta: &BuildMeta,
+    filter: &TestFilter,
+) -> anyhow::Result<Vec<NotRunDocTest>> {
+    let mut res = vec![];
+    for test in gather_tests(build_meta) {
+        if test.target.kind != TargetKind::BlackboxTest {
+            continue;
+        }
+        let (included, file_filt) = filter.check_package(test.target);
+        if !included {
+            continue;
+        }
+        let pkg = build_meta
+            .resolve_output
+            .pkg_dirs
+            .get_package(test.target.package);
+        let files = pkg
+            .source_files
+            .iter()
+            .map(|path| (path, true))
+            .chain(pkg.mbt_md_files.iter().map(|path| (path, false)));
+        for (path, in_doc_comments) in files {
+            let file = path.file_name().unwrap_or_default().to_string_lossy();
+            if !is_doc_test_file(test.target, &file)
+                || file_filt.is_some_and(|ff| !ff.0.contains_key(file.as_ref()))
+            {
+                continue;
+            }
+            let content = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            for line in no_run_fences(&content, in_doc_comments) {
+                res.push(NotRunDocTest {
+                    package: pkg.fqn.to_string(),
+                    file: file.to_string(),
+                    line,
+                });
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// The 1-based lines of the opening fences of the code blocks marked
+/// `no-run` in `content`. In source files, code blocks are in `///` comments.
+fn no_run_fences(content: &str, in_doc_comments: bool) -> Vec<usize> {
+    let mut res = vec![];
+    let mut in_block = false;
+    for (i, line) in content.lines().enumerate() {
+        let mut line = line.trim_start();
+        if in_doc_comments {
+            let Some(comment) = line.strip_prefix("///") else {
+                // A code block never outlives its doc comment
+                in_block = false;
+                continue;
+            };
+            line = comment.trim_start();
+        }
+        let Some(info) = line.strip_prefix("```") else {
+            continue;
+        };
+        if in_block {
+            in_block = false;
+            continue;
+        }
+        in_block = true;
+        if info
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .any(|tag| tag == NO_RUN)
+        {
+            res.push(i + 1);
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_run_fences() {
+        let source = "\
+/// ```no-run
+/// assert_true(true)
+/// ```
+///
+/// ```mbt
+/// assert_true(true)
+/// ```
+pub fn f() -> Unit {}
+
+/// ```mbt no-run
+/// ```
+";
+        assert_eq!(no_run_fences(source, true), [1, 10]);
+        let markdown = "```mbt\n```\n\n```moonbit,no-run\nlet x = 1\n```\n";
+        assert_eq!(no_run_fences(markdown, false), [4]);
+    }
+}
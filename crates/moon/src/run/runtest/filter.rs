@@ -16,8 +16,9 @@
 //
 // For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
 
-use std::{collections::BTreeSet, ops::Range};
+use std::{collections::BTreeSet, ops::Range, path::Path};
 
+use anyhow::Context;
 use indexmap::IndexMap;
 use moonbuild::test_utils::indices_to_ranges;
 use moonbuild_rupes_recta::{
@@ -25,6 +26,7 @@ use moonbuild_rupes_recta::{
     model::{BuildTarget, PackageId, TargetKind},
 };
 use moonutil::common::{MbtTestInfo, MooncGenTestInfo};
+use regex::Regex;
 
 use crate::run::TestIndex;
 
@@ -53,11 +55,167 @@ pub struct FileFilter(pub IndexMap<String, Option<IndexFilter>>);
 #[derive(Default, Debug, Clone, PartialEq, Eq)]
 pub struct PackageFilter(pub IndexMap<BuildTarget, Option<FileFilter>>);
 
+/// Filter over the fully-qualified names of tests, see [`test_fqn`].
+#[derive(Debug, Clone)]
+pub struct TestNameFilter {
+    regex: Regex,
+    /// The pattern given by the user, for messages
+    pub pattern: String,
+}
+
+impl TestNameFilter {
+    /// A glob matching the whole name, where `*` matches any characters and
+    /// `?` matches one.
+    pub fn glob(pattern: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            regex: Regex::new(&glob_to_regex(pattern))
+                .with_context(|| format!("invalid glob `{pattern}`"))?,
+            pattern: pattern.to_string(),
+        })
+    }
+
+    /// The union of the globs listed in the file at `path`, one per line,
+    /// ignoring blank lines and `#` comments. `None` if the file lists none.
+    pub fn glob_file(path: &Path) -> anyhow::Result<Option<Self>> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let globs = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(glob_to_regex)
+            .collect::<Vec<_>>();
+        if globs.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            regex: Regex::new(&globs.join("|"))
+                .with_context(|| format!("invalid globs in {}", path.display()))?,
+            pattern: path.display().to_string(),
+        }))
+    }
+
+    /// The union of `filters`, matching the names that any of them matches.
+    pub fn any(mut filters: Vec<Self>) -> Option<Self> {
+        if filters.len() <= 1 {
+            return filters.pop();
+        }
+        let regex = filters
+            .iter()
+            .map(|f| format!("(?:{})", f.regex.as_str()))
+            .collect::<Vec<_>>()
+            .join("|");
+        let pattern = filters
+            .iter()
+            .map(|f| f.pattern.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        Some(Self {
+            regex: Regex::new(&regex).expect("a union of valid regexes is valid"),
+            pattern,
+        })
+    }
+
+    /// A regex matching any part of the name.
+    pub fn regex(pattern: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            regex: Regex::new(pattern).with_context(|| format!("invalid regex `{pattern}`"))?,
+            pattern: pattern.to_string(),
+        })
+    }
+
+    pub fn is_match(&self, fqn: &str) -> bool {
+        self.regex.is_match(fqn)
+    }
+}
+
+/// The regex of a glob matching the whole name.
+fn glob_to_regex(pattern: &str) -> String {
+    let mut re = String::from("^");
+    for c in pattern.chars() {
+        match c {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            c => re.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    re.push('$');
+    re
+}
+
+impl PartialEq for TestNameFilter {
+    fn eq(&self, other: &Self) -> bool {
+        self.regex.as_str() == other.regex.as_str()
+    }
+}
+
+impl Eq for TestNameFilter {}
+
+/// One of the `count` shards of the tests, from `--shard index/count` with a
+/// 1-based `index`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    pub index: u32,
+    pub count: u32,
+}
+
+impl std::str::FromStr for Shard {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (index, count) = s
+            .split_once('/')
+            .ok_or_else(|| format!("expected `<index>/<count>`, got `{s}`"))?;
+        let index = index
+            .parse::<u32>()
+            .map_err(|e| format!("invalid shard index `{index}`: {e}"))?;
+        let count = count
+            .parse::<u32>()
+            .map_err(|e| format!("invalid shard count `{count}`: {e}"))?;
+        if index == 0 || index > count {
+            return Err(format!("shard index must be between 1 and {count}"));
+        }
+        Ok(Self { index, count })
+    }
+}
+
+impl Shard {
+    /// Pick the tests of this shard out of `names`. Tests are sorted by name
+    /// and dealt to the shards in turn, so the shards of a suite are disjoint
+    /// and always get the same tests. When the count of tests isn't divisible
+    /// by [`Self::count`], the first shards get one more test than the rest.
+    pub fn select<'a, T>(&self, tests: &'a [T], name: impl Fn(&T) -> &str) -> Vec<&'a T> {
+        let mut sorted = tests.iter().collect::<Vec<_>>();
+        sorted.sort_by(|a, b| name(a).cmp(name(b)));
+        sorted
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| i % self.count as usize == (self.index - 1) as usize)
+            .map(|(_, t)| t)
+            .collect()
+    }
+}
+
+/// The fully-qualified name of a test, like `username/hello/lib/hello_test.mbt::name`.
+/// Tests without a name are named after their index.
+pub fn test_fqn(package: &str, file: &str, info: &MbtTestInfo) -> String {
+    match &info.name {
+        Some(name) => format!("{package}/{file}::{name}"),
+        None => format!("{package}/{file}::{}", info.index),
+    }
+}
+
 /// Root filter used by the test runner.
 /// `filter == None` means no restriction (allow everything).
 #[derive(Default, Debug, Clone, PartialEq, Eq)]
 pub struct TestFilter {
     pub filter: Option<PackageFilter>,
+    /// Only run the tests with matching names, on top of `filter`
+    pub name: Option<TestNameFilter>,
+    /// Skip the tests with matching names, on top of `name`
+    pub exclude: Option<TestNameFilter>,
+    /// Only run the doc tests, on top of `filter`
+    pub doc_only: bool,
 }
 
 impl TestFilter {
@@ -125,6 +283,10 @@ impl TestFilter {
     /// - Present with Some(FileFilter) => (true, Some(FileFilter)).
     #[must_use]
     pub fn check_package(&self, package: BuildTarget) -> (bool, Option<&FileFilter>) {
+        // Doc tests are only in the blackbox test target
+        if self.doc_only && package.kind != TargetKind::BlackboxTest {
+            return (false, None);
+        }
         match &self.filter {
             None => (true, None),
             Some(pf) => match pf.0.get(&package) {
@@ -189,6 +351,64 @@ fn all_ranges(infos: &[MbtTestInfo]) -> Vec<Range<u32>> {
     indices_to_ranges(actual_indices)
 }
 
+/// Narrow down `files_and_index` from [`apply_filter`] to the tests whose
+/// fully-qualified names match `name_filter`.
+pub fn apply_name_filter(
+    name_filter: &TestNameFilter,
+    package: &str,
+    meta: &MooncGenTestInfo,
+    files_and_index: &mut [(String, Vec<std::ops::Range<u32>>)],
+) {
+    retain_by_name(package, meta, files_and_index, |name| {
+        name_filter.is_match(name)
+    });
+}
+
+/// Drop the tests whose fully-qualified names match `exclude` from
+/// `files_and_index`, returning how many were dropped.
+pub fn apply_exclude_filter(
+    exclude: &TestNameFilter,
+    package: &str,
+    meta: &MooncGenTestInfo,
+    files_and_index: &mut [(String, Vec<std::ops::Range<u32>>)],
+) -> usize {
+    retain_by_name(package, meta, files_and_index, |name| {
+        !exclude.is_match(name)
+    })
+}
+
+/// Keep the tests in `files_and_index` whose fully-qualified names satisfy
+/// `keep`, returning how many were dropped.
+fn retain_by_name(
+    package: &str,
+    meta: &MooncGenTestInfo,
+    files_and_index: &mut [(String, Vec<std::ops::Range<u32>>)],
+    keep: impl Fn(&str) -> bool,
+) -> usize {
+    let mut dropped = 0;
+    let lists = [
+        &meta.no_args_tests,
+        &meta.with_args_tests,
+        &meta.async_tests,
+    ];
+    for (file, ranges) in files_and_index.iter_mut() {
+        let matched = lists
+            .iter()
+            .filter_map(|list| list.get(file.as_str()))
+            .flatten()
+            .filter(|t| ranges.iter().any(|r| r.contains(&t.index)))
+            .filter(|t| {
+                let kept = keep(&test_fqn(package, file, t));
+                dropped += usize::from(!kept);
+                kept
+            })
+            .map(|t| t.index)
+            .collect::<Vec<_>>();
+        *ranges = indices_to_ranges(matched);
+    }
+    dropped
+}
+
 pub fn apply_filter(
     file_filt: Option<&FileFilter>,
     meta: &MooncGenTestInfo,
@@ -421,4 +641,63 @@ mod test {
 
         expect![[r#"[("my_file.mbt", [])]"#]].assert_eq(&format!("{:?}", out));
     }
+
+    #[test]
+    fn test_name_filter() {
+        let meta = example_meta();
+        let glob = super::TestNameFilter::glob("*o").unwrap();
+        assert!(glob.is_match("pkg/file1.mbt::zero"));
+        assert!(!glob.is_match("pkg/file1.mbt::zero!"));
+
+        let mut out = vec![];
+        super::apply_filter(None, &meta, &mut out);
+        super::apply_name_filter(&glob, "pkg", &meta, &mut out);
+        expect![[r#"[("file1.mbt", [0..1]), ("file2.mbt", [2..3]), ("doc_tests.mbt", []), ("file1.mbt", []), ("my_file.mbt", []), ("param_file.mbt", [])]"#]]
+        .assert_eq(&format!("{:?}", out));
+
+        let regex = super::TestNameFilter::regex(r"^pkg/file1\.mbt::").unwrap();
+        let mut out = vec![];
+        super::apply_filter(None, &meta, &mut out);
+        super::apply_name_filter(&regex, "pkg", &meta, &mut out);
+        expect![[r#"[("file1.mbt", [0..2, 4..5]), ("file2.mbt", []), ("doc_tests.mbt", []), ("file1.mbt", [2..3]), ("my_file.mbt", []), ("param_file.mbt", [])]"#]]
+        .assert_eq(&format!("{:?}", out));
+
+        let union = super::TestNameFilter::any(vec![glob, regex]).unwrap();
+        assert!(union.is_match("pkg/file2.mbt::two"));
+        assert!(union.is_match("pkg/file1.mbt::one"));
+        assert!(!union.is_match("pkg/file2.mbt::three"));
+    }
+
+    #[test]
+    fn test_glob_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("quarantine.txt");
+        std::fs::write(&path, "# flaky\npkg/file1.mbt::*\n\n  *::two\n").unwrap();
+        let filter = super::TestNameFilter::glob_file(&path).unwrap().unwrap();
+        assert!(filter.is_match("pkg/file1.mbt::zero"));
+        assert!(filter.is_match("pkg/file2.mbt::two"));
+        assert!(!filter.is_match("pkg/file2.mbt::zero"));
+
+        std::fs::write(&path, "# nothing quarantined\n").unwrap();
+        assert!(super::TestNameFilter::glob_file(&path).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_shard() {
+        let tests = ["e", "b", "a", "d", "c"];
+        let select = |s: &str| {
+            let shard: super::Shard = s.parse().unwrap();
+            shard
+                .select(&tests, |t| t)
+                .into_iter()
+                .copied()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(select("1/2"), ["a", "c", "e"]);
+        assert_eq!(select("2/2"), ["b", "d"]);
+        assert_eq!(select("3/3"), ["c"]);
+        assert!("0/2".parse::<super::Shard>().is_err());
+        assert!("3/2".parse::<super::Shard>().is_err());
+        assert!("1".parse::<super::Shard>().is_err());
+    }
 }
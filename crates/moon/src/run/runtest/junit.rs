@@ -0,0 +1,313 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Renders test results as JUnit XML
+
+use std::fmt::Write;
+
+use moonbuild_rupes_recta::model::{PackageId, TargetKind};
+
+use crate::{
+    rr_build::BuildMeta,
+    run::runtest::{doc_test::NotRunDocTest, ReplaceableTestResults, TestResultKind},
+};
+
+impl ReplaceableTestResults {
+    /// Render the results as a JUnit XML document. Each build target becomes a
+    /// `<testsuite>`, and each test a `<testcase>` with the package as its
+    /// classname.
+    ///
+    /// Tests of a build target run in the same process, so only the time of
+    /// the whole `<testsuite>` is known.
+    ///
+    /// The doc tests in `skipped` are reported as `<skipped>` test cases of the
+    /// blackbox test target of their package.
+    pub fn to_junit_xml(&self, meta: &BuildMeta, skipped: &[NotRunDocTest]) -> String {
+        self.render_junit_xml(
+            |pkg| {
+                meta.resolve_output
+                    .pkg_dirs
+                    .get_package(pkg)
+                    .fqn
+                    .to_string()
+            },
+            skipped,
+        )
+    }
+
+    /// [`Self::to_junit_xml`], with `fqn` giving the name of each package.
+    fn render_junit_xml(
+        &self,
+        fqn: impl Fn(PackageId) -> String,
+        skipped: &[NotRunDocTest],
+    ) -> String {
+        let summary = self.summary();
+        let mut out = String::new();
+        out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let _ = writeln!(
+            out,
+            "<testsuites tests=\"{}\" failures=\"{}\" skipped=\"{}\">",
+            summary.total + skipped.len(),
+            summary.total - summary.passed,
+            skipped.len()
+        );
+        let mut pending_skipped = skipped.iter().collect::<Vec<_>>();
+        for (target, result) in &self.map {
+            let fqn = fqn(target.package);
+            let skipped = if target.kind == TargetKind::BlackboxTest {
+                let (this, rest) = pending_skipped.into_iter().partition(|t| t.package == fqn);
+                pending_skipped = rest;
+                this
+            } else {
+                vec![]
+            };
+            let cases = result.map.values().flat_map(|m| m.values());
+            let total = cases.clone().count();
+            let failures = cases.clone().filter(|r| !r.passed()).count();
+            let _ = writeln!(
+                out,
+                "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">",
+                escape_attr(&format!("{fqn} ({:?})", target.kind)),
+                total + skipped.len(),
+                failures,
+                skipped.len(),
+                result.duration.as_secs_f64()
+            );
+            for case in cases {
+                let _ = write!(
+                    out,
+                    "    <testcase classname=\"{}\" name=\"{}\" file=\"{}\"",
+                    escape_attr(&fqn),
+                    escape_attr(&case.raw.test_name),
+                    escape_attr(&case.raw.filename)
+                );
+                let failure_type = match case.kind {
+                    TestResultKind::Passed | TestResultKind::Flaky => {
+                        out.push_str("/>\n");
+                        continue;
+                    }
+                    TestResultKind::ExpectTestFailed => "ExpectTestFailed",
+                    TestResultKind::SnapshotTestFailed => "SnapshotTestFailed",
+                    TestResultKind::RuntimeError => "RuntimeError",
+                    TestResultKind::ExpectPanic => "ExpectPanic",
+                    TestResultKind::Failed => "Failed",
+                    TestResultKind::TimedOut => "TimedOut",
+                };
+                let message = match case.kind {
+                    TestResultKind::ExpectPanic => "panic is expected",
+                    _ => &case.raw.message,
+                };
+                let _ = writeln!(
+                    out,
+                    ">\n      <failure type=\"{}\" message=\"{}\">{}</failure>",
+                    failure_type,
+                    escape_attr(message.lines().next().unwrap_or_default()),
+                    escape(message)
+                );
+                // Only the output of failed tests, to keep the report small
+                for (tag, output) in [
+                    ("system-out", &case.output.stdout),
+                    ("system-err", &case.output.stderr),
+                ] {
+                    if !output.is_empty() {
+                        let _ = writeln!(out, "      <{tag}>{}</{tag}>", escape(output));
+                    }
+                }
+                out.push_str("    </testcase>\n");
+            }
+            write_skipped(&mut out, &skipped);
+            out.push_str("  </testsuite>\n");
+        }
+        // The blackbox test targets without any test run
+        while let Some(first) = pending_skipped.first() {
+            let package = first.package.clone();
+            let (skipped, rest) = pending_skipped
+                .into_iter()
+                .partition::<Vec<_>, _>(|t| t.package == package);
+            pending_skipped = rest;
+            let _ = writeln!(
+                out,
+                "  <testsuite name=\"{}\" tests=\"{}\" failures=\"0\" skipped=\"{}\" time=\"0.000\">",
+                escape_attr(&format!("{package} ({:?})", TargetKind::BlackboxTest)),
+                skipped.len(),
+                skipped.len()
+            );
+            write_skipped(&mut out, &skipped);
+            out.push_str("  </testsuite>\n");
+        }
+        out.push_str("</testsuites>\n");
+        out
+    }
+}
+
+/// Write the `<testcase>` of each doc test in `skipped`.
+fn write_skipped(out: &mut String, skipped: &[&NotRunDocTest]) {
+    for t in skipped {
+        let _ = writeln!(
+            out,
+            "    <testcase classname=\"{}\" name=\"{}\" file=\"{}\">\n      <skipped message=\"no-run\"/>\n    </testcase>",
+            escape_attr(&t.package),
+            escape_attr(&format!("doc test at line {}", t.line)),
+            escape_attr(&t.file)
+        );
+    }
+}
+
+/// Escape a string for use in XML text.
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            // Not allowed in XML 1.0, e.g. ANSI escapes in messages
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Escape a string for use in XML attribute values, where whitespace would be
+/// normalized to spaces if not escaped.
+fn escape_attr(s: &str) -> String {
+    escape(s)
+        .replace('\t', "&#9;")
+        .replace('\n', "&#10;")
+        .replace('\r', "&#13;")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use expect_test::expect;
+    use moonbuild::{runtest::TestStatistics, section_capture::TestOutput};
+    use moonutil::common::MbtTestInfo;
+
+    use super::*;
+    use crate::run::runtest::{TargetTestResult, TestCaseResult};
+
+    fn case(kind: TestResultKind, name: &str, message: &str, stdout: &str) -> TestCaseResult {
+        TestCaseResult {
+            kind,
+            raw: Arc::new(TestStatistics {
+                package: "user/mod/lib".into(),
+                filename: "lib_test.mbt".into(),
+                index: "0".into(),
+                test_name: name.into(),
+                message: message.into(),
+            }),
+            meta: MbtTestInfo {
+                index: 0,
+                func: "__test_0".into(),
+                name: Some(name.into()),
+                line_number: None,
+            },
+            output: TestOutput {
+                stdout: stdout.into(),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_render_junit_xml() {
+        let pkg = PackageId::default();
+        let mut results = ReplaceableTestResults::default();
+        let mut inline = TargetTestResult {
+            duration: Duration::from_millis(1500),
+            ..Default::default()
+        };
+        inline.add(
+            "lib_test.mbt",
+            0,
+            case(
+                TestResultKind::Passed,
+                "it's \"<x>\" & y\tz\r\nw",
+                "",
+                "ignored",
+            ),
+        );
+        inline.add(
+            "lib_test.mbt",
+            1,
+            case(
+                TestResultKind::Failed,
+                "fails",
+                "a < b && 'c'\nnext\u{1b}[31m line",
+                "printed\u{0}\n",
+            ),
+        );
+        inline.add(
+            "lib_test.mbt",
+            2,
+            case(TestResultKind::ExpectPanic, "panics", "ignored", ""),
+        );
+        results.merge_with_target(pkg.build_target(TargetKind::InlineTest), inline);
+        let mut blackbox = TargetTestResult::default();
+        blackbox.add("lib.mbt", 0, case(TestResultKind::Flaky, "flaky", "", ""));
+        results.merge_with_target(pkg.build_target(TargetKind::BlackboxTest), blackbox);
+
+        let skipped = [
+            NotRunDocTest {
+                package: "user/mod/lib".into(),
+                file: "lib.mbt".into(),
+                line: 3,
+            },
+            NotRunDocTest {
+                package: "user/mod/other".into(),
+                file: "README.mbt.md".into(),
+                line: 7,
+            },
+        ];
+        let xml = results.render_junit_xml(|_| "user/mod/lib".to_string(), &skipped);
+        expect![[r#"
+            <?xml version="1.0" encoding="UTF-8"?>
+            <testsuites tests="6" failures="2" skipped="2">
+              <testsuite name="user/mod/lib (InlineTest)" tests="3" failures="2" skipped="0" time="1.500">
+                <testcase classname="user/mod/lib" name="it&apos;s &quot;&lt;x&gt;&quot; &amp; y&#9;z&#13;&#10;w" file="lib_test.mbt"/>
+                <testcase classname="user/mod/lib" name="fails" file="lib_test.mbt">
+                  <failure type="Failed" message="a &lt; b &amp;&amp; &apos;c&apos;">a &lt; b &amp;&amp; &apos;c&apos;
+            next[31m line</failure>
+                  <system-out>printed
+            </system-out>
+                </testcase>
+                <testcase classname="user/mod/lib" name="panics" file="lib_test.mbt">
+                  <failure type="ExpectPanic" message="panic is expected">panic is expected</failure>
+                </testcase>
+              </testsuite>
+              <testsuite name="user/mod/lib (BlackboxTest)" tests="2" failures="0" skipped="1" time="0.000">
+                <testcase classname="user/mod/lib" name="flaky" file="lib_test.mbt"/>
+                <testcase classname="user/mod/lib" name="doc test at line 3" file="lib.mbt">
+                  <skipped message="no-run"/>
+                </testcase>
+              </testsuite>
+              <testsuite name="user/mod/other (BlackboxTest)" tests="1" failures="0" skipped="1" time="0.000">
+                <testcase classname="user/mod/other" name="doc test at line 7" file="README.mbt.md">
+                  <skipped message="no-run"/>
+                </testcase>
+              </testsuite>
+            </testsuites>
+        "#]]
+        .assert_eq(&xml);
+    }
+}
@@ -18,19 +18,91 @@
 
 //! Handles test promotion
 
+use std::{collections::HashSet, path::PathBuf};
+
 use anyhow::Context;
-use moonbuild::expect::{apply_expect, apply_snapshot};
+use colored::Colorize;
+use moonbuild::expect::{
+    apply_expect, apply_snapshot, render_expect_fail, render_snapshot_fail, update_target_file,
+};
 use tracing::info;
 
 use crate::run::runtest::{
     filter::PackageFilter, ReplaceableTestResults, TestCaseResult, TestResultKind,
 };
 
-/// Perform promotion on all test snapshots and expect tests met. Returns
-/// the total number of tests promoted, along with a filter indicating which
-/// tests needs rerunning.
+/// Which of the failed expect and snapshot tests to promote. Kept across the
+/// update passes, so that rejected updates aren't asked again.
+#[derive(Default)]
+pub struct PromotionScope {
+    /// Only promote the tests rewriting these (canonicalized) files
+    pub only_files: Option<Vec<PathBuf>>,
+    /// Show the diff of each update and ask whether to accept it
+    pub interactive: bool,
+    /// Failure messages of the rejected updates
+    rejected: HashSet<String>,
+    /// Reject all the following updates without asking
+    skip_rest: bool,
+}
+
+impl PromotionScope {
+    pub fn new(only_files: Option<Vec<PathBuf>>, interactive: bool) -> Self {
+        Self {
+            only_files,
+            interactive,
+            ..Default::default()
+        }
+    }
+
+    fn select(&mut self, result: &TestCaseResult) -> anyhow::Result<bool> {
+        let msg = &result.raw.message;
+        if let Some(only_files) = &self.only_files {
+            let in_scope = update_target_file(msg)
+                .and_then(|f| dunce::canonicalize(f).ok())
+                .is_some_and(|f| only_files.contains(&f));
+            if !in_scope {
+                return Ok(false);
+            }
+        }
+        if !self.interactive {
+            return Ok(true);
+        }
+        if self.skip_rest || self.rejected.contains(msg) {
+            return Ok(false);
+        }
+
+        println!();
+        if matches!(result.kind, TestResultKind::SnapshotTestFailed) {
+            render_snapshot_fail(msg)?;
+        } else {
+            render_expect_fail(msg)?;
+        }
+        let choice = dialoguer::Select::new()
+            .with_prompt(format!("Update {}?", result.raw.test_name.bold()))
+            .items(&["accept", "reject", "skip the rest"])
+            .default(0)
+            .interact()
+            .context("failed to read the answer to the update")?;
+        match choice {
+            0 => Ok(true),
+            1 => {
+                self.rejected.insert(msg.clone());
+                Ok(false)
+            }
+            _ => {
+                self.skip_rest = true;
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// Perform promotion on the test snapshots and expect tests met that are in
+/// `scope`. Returns the total number of tests promoted, along with a filter
+/// indicating which tests needs rerunning.
 pub fn perform_promotion(
     results: &ReplaceableTestResults,
+    scope: &mut PromotionScope,
 ) -> anyhow::Result<(usize, PackageFilter)> {
     let mut res = PackageFilter::default();
 
@@ -40,6 +112,13 @@ pub fn perform_promotion(
     for (target, target_result) in &results.map {
         for (file, v) in &target_result.map {
             for (idx, result) in v {
+                let promotable = matches!(
+                    result.kind,
+                    TestResultKind::SnapshotTestFailed | TestResultKind::ExpectTestFailed
+                );
+                if promotable && !scope.select(result)? {
+                    continue;
+                }
                 match result.kind {
                     TestResultKind::SnapshotTestFailed => {
                         info!(?target, file, idx, "Need to update snapshot");
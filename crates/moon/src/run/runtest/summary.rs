@@ -0,0 +1,175 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Running tests from a harness of one's own, e.g. a dashboard or a detector
+//! of flaky tests. Nothing is printed and no snapshot is updated; the outcome
+//! of each test is returned instead, see [`run_test_suite`].
+
+// `moon` itself reports through `ReplaceableTestResults`
+#![allow(unused)]
+
+use std::{num::NonZeroUsize, path::Path, time::Duration};
+
+use moonbuild::section_capture::TestOutput;
+use moonbuild_rupes_recta::model::TargetKind;
+use moonutil::common::TargetBackend;
+
+use super::{
+    filter::test_fqn, run_tests_with_cache, ReplaceableTestResults, TestFilter, TestNameFilter,
+    TestResultKind, TestRunConfig,
+};
+use crate::{rr_build::BuildMeta, run::runtime::RuntimeExecutableCache};
+
+/// The tests to run, out of the ones built in the session.
+#[derive(Debug, Clone, Default)]
+pub struct RunTestOptions {
+    /// The full names of the packages to test, all of them if empty
+    pub packages: Vec<String>,
+    /// Only run the tests whose fully-qualified names match the glob, see
+    /// [`TestNameFilter::glob`]
+    pub filter: Option<String>,
+    /// The backend the tests are expected to be built for. It's fixed by the
+    /// build, so a different one is an error rather than a rebuild.
+    pub backend: Option<TargetBackend>,
+    /// The max number of test executables to run concurrently, the default
+    /// parallelism if `None`
+    pub jobs: Option<NonZeroUsize>,
+}
+
+/// The results of [`run_test_suite`].
+#[derive(Debug, Clone)]
+pub struct TestRunSummary {
+    pub backend: TargetBackend,
+    pub tests: Vec<TestOutcome>,
+    /// The test executables run, in the order of `tests`
+    pub executables: Vec<ExecutableRun>,
+}
+
+/// The outcome of one test.
+#[derive(Debug, Clone)]
+pub struct TestOutcome {
+    /// The fully-qualified name of the test, as matched by
+    /// [`RunTestOptions::filter`]
+    pub name: String,
+    pub package: String,
+    pub file: String,
+    pub index: u32,
+    pub kind: TestResultKind,
+    /// The message of the failure, empty if the test passed
+    pub message: String,
+    /// The output printed by the test
+    pub output: TestOutput,
+}
+
+/// A test executable that was run. Its tests run in the same process, so only
+/// the time of the whole executable is known.
+#[derive(Debug, Clone)]
+pub struct ExecutableRun {
+    pub package: String,
+    pub kind: TargetKind,
+    /// The number of tests run in it
+    pub tests: usize,
+    /// The time spent running it, including retries
+    pub duration: Duration,
+}
+
+impl TestRunSummary {
+    pub fn passed(&self) -> bool {
+        self.tests.iter().all(|t| t.kind.passed())
+    }
+}
+
+/// Run the tests selected by `options`, whose executables must have been
+/// built in this session, and return the outcome of each. The runtimes of the
+/// executables are looked up through `cache`, so callers running tests
+/// repeatedly only look them up once.
+pub fn run_test_suite(
+    build_meta: &BuildMeta,
+    target_dir: &Path,
+    options: &RunTestOptions,
+    cache: &RuntimeExecutableCache,
+) -> anyhow::Result<TestRunSummary> {
+    if let Some(backend) = options.backend {
+        if backend != build_meta.target_backend {
+            anyhow::bail!(
+                "the tests are built for `{}`, not `{}`",
+                build_meta.target_backend.to_flag(),
+                backend.to_flag()
+            );
+        }
+    }
+    let mut filter = TestFilter::default();
+    for name in &options.packages {
+        let found = build_meta
+            .resolve_output
+            .pkg_dirs
+            .all_packages()
+            .find(|(_, pkg)| pkg.fqn.to_string() == *name);
+        let Some((id, _)) = found else {
+            anyhow::bail!("cannot find package `{name}`");
+        };
+        filter.add_autodetermine_target(id, None, None);
+    }
+    filter.name = options
+        .filter
+        .as_deref()
+        .map(TestNameFilter::glob)
+        .transpose()?;
+    let config = TestRunConfig {
+        jobs: options.jobs,
+        ..Default::default()
+    };
+    let results = run_tests_with_cache(build_meta, target_dir, &filter, &config, None, cache)?;
+    Ok(results.to_summary(build_meta))
+}
+
+impl ReplaceableTestResults {
+    /// Flatten the results into a [`TestRunSummary`].
+    pub fn to_summary(&self, meta: &BuildMeta) -> TestRunSummary {
+        let mut tests = vec![];
+        let mut executables = vec![];
+        for (target, result) in &self.map {
+            let package = meta.resolve_output.pkg_dirs.fqn(target.package).to_string();
+            let before = tests.len();
+            for (file, file_map) in &result.map {
+                for (&index, case) in file_map {
+                    tests.push(TestOutcome {
+                        name: test_fqn(&package, file, &case.meta),
+                        package: package.clone(),
+                        file: file.clone(),
+                        index,
+                        kind: case.kind,
+                        message: case.raw.message.clone(),
+                        output: case.output.clone(),
+                    });
+                }
+            }
+            executables.push(ExecutableRun {
+                package,
+                kind: target.kind,
+                tests: tests.len() - before,
+                duration: result.duration,
+            });
+        }
+        TestRunSummary {
+            backend: meta.target_backend,
+            tests,
+            executables,
+        }
+    }
+}
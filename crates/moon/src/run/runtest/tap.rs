@@ -0,0 +1,191 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Streams test results in the Test Anything Protocol (TAP)
+
+use std::io::Write;
+
+use crate::run::runtest::{TargetTestResult, TestResultKind};
+
+/// Prints the test results as TAP version 13 to stdout while the tests run.
+///
+/// The results of each test executable are printed as soon as it finishes, so
+/// the total number of tests is unknown until the end. Thus the plan is
+/// printed last by [`TapReporter::finish`], and only counts the tests that
+/// actually ran, excluding the filtered out ones.
+pub struct TapReporter {
+    count: usize,
+}
+
+impl TapReporter {
+    /// Print the TAP version header and start reporting.
+    pub fn start() -> Self {
+        println!("TAP version 13");
+        TapReporter { count: 0 }
+    }
+
+    pub(super) fn report(&mut self, package: &str, result: &TargetTestResult) {
+        let _ = self.write_report(&mut std::io::stdout().lock(), package, result);
+    }
+
+    /// Write the test points of `result`, numbered after the ones before.
+    fn write_report(
+        &mut self,
+        out: &mut impl Write,
+        package: &str,
+        result: &TargetTestResult,
+    ) -> std::io::Result<()> {
+        for case in result.map.values().flat_map(|m| m.values()) {
+            self.count += 1;
+            // `#` starts a directive in TAP, so it must be escaped in names,
+            // along with the escaping `\` itself
+            let name = format!("{package}::{}::{}", case.raw.filename, case.raw.test_name)
+                .replace('\\', "\\\\")
+                .replace('#', "\\#");
+            if case.passed() {
+                writeln!(out, "ok {} - {}", self.count, name)?;
+                if case.kind == TestResultKind::Flaky {
+                    writeln!(out, "# flaky: failed at first, passed on retry")?;
+                }
+                continue;
+            }
+            writeln!(out, "not ok {} - {}", self.count, name)?;
+            let message = match case.kind {
+                TestResultKind::ExpectPanic => "panic is expected",
+                _ => &case.raw.message,
+            };
+            writeln!(out, "  ---")?;
+            writeln!(out, "  kind: {:?}", case.kind)?;
+            for (key, text) in [
+                ("message", message),
+                ("stdout", case.output.stdout.as_str()),
+                ("stderr", case.output.stderr.as_str()),
+            ] {
+                if !text.is_empty() {
+                    writeln!(out, "  {key}: |-")?;
+                    for line in text.lines() {
+                        writeln!(out, "    {line}")?;
+                    }
+                }
+            }
+            writeln!(out, "  ...")?;
+        }
+        Ok(())
+    }
+
+    /// Print the trailing plan.
+    pub fn finish(self) {
+        println!("{}", self.plan());
+    }
+
+    /// The plan line, counting the tests reported so far.
+    fn plan(&self) -> String {
+        format!("1..{}", self.count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use expect_test::expect;
+    use moonbuild::{runtest::TestStatistics, section_capture::TestOutput};
+    use moonutil::common::MbtTestInfo;
+
+    use super::*;
+    use crate::run::runtest::TestCaseResult;
+
+    fn result(cases: &[(TestResultKind, &str, &str)]) -> TargetTestResult {
+        let mut result = TargetTestResult::default();
+        for (index, &(kind, name, message)) in (0..).zip(cases) {
+            let case = TestCaseResult {
+                kind,
+                raw: Arc::new(TestStatistics {
+                    filename: "lib_test.mbt".into(),
+                    test_name: name.into(),
+                    message: message.into(),
+                    ..Default::default()
+                }),
+                meta: MbtTestInfo {
+                    index,
+                    func: format!("__test_{index}"),
+                    name: Some(name.into()),
+                    line_number: None,
+                },
+                output: TestOutput {
+                    stdout: if kind.passed() { "" } else { "out 1\nout 2\n" }.into(),
+                    ..Default::default()
+                },
+            };
+            result.add("lib_test.mbt", index, case);
+        }
+        result
+    }
+
+    #[test]
+    fn test_tap_report() {
+        let mut tap = TapReporter { count: 0 };
+        let mut out = vec![];
+        tap.write_report(
+            &mut out,
+            "user/mod/a",
+            &result(&[
+                (TestResultKind::Passed, "issue #1", ""),
+                (TestResultKind::Failed, "fails", "line 1\nline 2"),
+            ]),
+        )
+        .unwrap();
+        // The tests filtered out of an executable are never reported, so the
+        // numbering and the plan only count the reported ones
+        tap.write_report(
+            &mut out,
+            "user/mod/b",
+            &result(&[
+                (TestResultKind::Flaky, "a\\#b", ""),
+                (TestResultKind::ExpectPanic, "panics", "ignored"),
+            ]),
+        )
+        .unwrap();
+        expect![[r#"
+            ok 1 - user/mod/a::lib_test.mbt::issue \#1
+            not ok 2 - user/mod/a::lib_test.mbt::fails
+              ---
+              kind: Failed
+              message: |-
+                line 1
+                line 2
+              stdout: |-
+                out 1
+                out 2
+              ...
+            ok 3 - user/mod/b::lib_test.mbt::a\\\#b
+            # flaky: failed at first, passed on retry
+            not ok 4 - user/mod/b::lib_test.mbt::panics
+              ---
+              kind: ExpectPanic
+              message: |-
+                panic is expected
+              stdout: |-
+                out 1
+                out 2
+              ...
+        "#]]
+        .assert_eq(&String::from_utf8(out).unwrap());
+        assert_eq!(tap.plan(), "1..4");
+    }
+}
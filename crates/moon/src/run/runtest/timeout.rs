@@ -0,0 +1,239 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Per-test timeouts, declared by a pragma comment among the comments and
+//! attributes right above the test block:
+//!
+//! ```moonbit
+//! ///|
+//! // moon:timeout 2m
+//! test "slow" { ... }
+//! ```
+//!
+//! The duration is a number with the unit `ms`, `s` or `m`. A test with a
+//! declared timeout runs in a test executable of its own, with the timeout
+//! passed in [`TestArgs::timeout_ms`], and the process is killed if it runs
+//! longer than the timeout plus [`KILL_GRACE`]. The declared timeout replaces
+//! the per-backend one for that test.
+//!
+//! [`TestArgs::timeout_ms`]: moonbuild::entry::TestArgs::timeout_ms
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use moonbuild::entry::TestArgs;
+use moonutil::common::MooncGenTestInfo;
+
+const PRAGMA: &str = "moon:timeout";
+
+/// The time given on top of a declared timeout before the test process is
+/// killed, covering the startup of the runtime, so that a test is never killed
+/// while within its declared budget.
+pub(super) const KILL_GRACE: Duration = Duration::from_secs(5);
+
+/// The declared timeouts of the tests in `meta`, keyed by the file and the
+/// index of the test. The files are looked up in `pkg_dir`.
+pub(super) fn declared_timeouts(
+    pkg_dir: &Path,
+    meta: &MooncGenTestInfo,
+) -> anyhow::Result<HashMap<(String, u32), Duration>> {
+    let mut res = HashMap::new();
+    for (file, tests) in [
+        &meta.no_args_tests,
+        &meta.with_args_tests,
+        &meta.with_bench_args_tests,
+        &meta.async_tests,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        // Files without any pragma are the common case, so skip parsing them
+        let Ok(content) = std::fs::read_to_string(pkg_dir.join(file)) else {
+            continue;
+        };
+        if !content.contains(PRAGMA) {
+            continue;
+        }
+        let lines = content.lines().collect::<Vec<_>>();
+        for t in tests {
+            let Some(line) = t.line_number else {
+                continue;
+            };
+            if let Some(timeout) = pragma_above(&lines, line)
+                .map_err(|e| anyhow::anyhow!("{e} in {file}, above line {line}"))?
+            {
+                res.insert((file.clone(), t.index), timeout);
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Find the timeout pragma above the 1-based `line`, among the comment and
+/// attribute lines directly preceding it.
+fn pragma_above(lines: &[&str], line: usize) -> anyhow::Result<Option<Duration>> {
+    let above = lines.get(..line.saturating_sub(1)).unwrap_or_default();
+    for l in above.iter().rev().map(|l| l.trim()) {
+        if !(l.starts_with("//") || l.starts_with('#')) {
+            break;
+        }
+        let Some(value) = l.trim_start_matches('/').trim().strip_prefix(PRAGMA) else {
+            continue;
+        };
+        let value = value.trim();
+        return match parse_duration(value) {
+            Some(d) => Ok(Some(d)),
+            None => anyhow::bail!("invalid test timeout `{value}`, expected e.g. `30s`"),
+        };
+    }
+    Ok(None)
+}
+
+fn parse_duration(s: &str) -> Option<Duration> {
+    let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit())?);
+    let n = number.parse().ok()?;
+    match unit {
+        "ms" => Some(Duration::from_millis(n)),
+        "s" => Some(Duration::from_secs(n)),
+        "m" => Some(Duration::from_secs(n * 60)),
+        _ => None,
+    }
+}
+
+/// Split the tests with a declared timeout out of `test_args`, each into
+/// [`TestArgs`] of its own along with its timeout. The rest stay in
+/// `test_args`.
+#[allow(clippy::single_range_in_vec_init)] // clippy warns about our ranges
+pub(super) fn split_declared(
+    test_args: &mut TestArgs,
+    timeouts: &HashMap<(String, u32), Duration>,
+) -> Vec<(TestArgs, Duration)> {
+    let mut res = vec![];
+    if timeouts.is_empty() {
+        return res;
+    }
+    let single = |file: &str, index: u32, timeout: Duration| TestArgs {
+        package: test_args.package.clone(),
+        file_and_index: vec![(file.to_owned(), vec![index..index + 1])],
+        seed: test_args.seed,
+        bench: test_args.bench,
+        timeout_ms: Some(timeout.as_millis() as u64),
+    };
+    let mut file_and_index = std::mem::take(&mut test_args.file_and_index);
+    for (file, ranges) in &mut file_and_index {
+        let mut rest = vec![];
+        for range in ranges.drain(..) {
+            let mut start = range.start;
+            for index in range.clone() {
+                let Some(&timeout) = timeouts.get(&(file.clone(), index)) else {
+                    continue;
+                };
+                if start < index {
+                    rest.push(start..index);
+                }
+                start = index + 1;
+                res.push((single(file, index, timeout), timeout));
+            }
+            if start < range.end {
+                rest.push(start..range.end);
+            }
+        }
+        *ranges = rest;
+    }
+    test_args.file_and_index = file_and_index;
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pragma_above() {
+        let lines = [
+            "///|",
+            "// moon:timeout 2m",
+            "#skip",
+            "test \"slow\" {",
+            "}",
+            "",
+            "/// moon:timeout 500ms",
+            "test {",
+            "}",
+            "// moon:timeout forever",
+            "test {",
+        ];
+        assert_eq!(
+            pragma_above(&lines, 4).unwrap(),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            pragma_above(&lines, 8).unwrap(),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(pragma_above(&lines, 5).unwrap(), None);
+        assert!(pragma_above(&lines, 11).is_err());
+    }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn test_split_declared() {
+        let mut test_args = TestArgs {
+            package: "a/b".into(),
+            file_and_index: vec![
+                (String::from("a_test.mbt"), vec![0..4]),
+                (String::from("b_test.mbt"), vec![0..1]),
+            ],
+            seed: None,
+            bench: None,
+            timeout_ms: None,
+        };
+        let timeouts = HashMap::from([
+            ((String::from("a_test.mbt"), 1), Duration::from_secs(1)),
+            ((String::from("b_test.mbt"), 0), Duration::from_secs(2)),
+        ]);
+        let split = split_declared(&mut test_args, &timeouts);
+        assert_eq!(
+            test_args.file_and_index,
+            [
+                (String::from("a_test.mbt"), vec![0..1, 2..4]),
+                (String::from("b_test.mbt"), vec![])
+            ]
+        );
+        let split = split
+            .iter()
+            .map(|(args, timeout)| (args.file_and_index.clone(), args.timeout_ms, *timeout))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            split,
+            [
+                (
+                    vec![(String::from("a_test.mbt"), vec![1..2])],
+                    Some(1000),
+                    Duration::from_secs(1)
+                ),
+                (
+                    vec![(String::from("b_test.mbt"), vec![0..1])],
+                    Some(2000),
+                    Duration::from_secs(2)
+                ),
+            ]
+        );
+    }
+}
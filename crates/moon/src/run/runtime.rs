@@ -19,59 +19,362 @@
 //! Handles which runtime to use to run a specific output.
 
 use std::{
-    cell::OnceCell,
+    cell::{Cell, OnceCell, RefCell},
+    collections::HashMap,
+    ffi::{OsStr, OsString},
     path::{Path, PathBuf},
+    process::{ExitStatus, Stdio},
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
 };
 
-use moonbuild::entry::TestArgs;
-use moonutil::common::TargetBackend;
+use anyhow::{bail, Context};
+use moonbuild::{
+    entry::TestArgs,
+    section_capture::{OutputAttribution, SectionCapture},
+};
+
+use super::child::{BufferedOutput, ChildStdin};
+use super::remote::RemoteExecutor;
+use super::stop::StopPolicy;
+use moonutil::{
+    common::{TargetBackend, MOON_TEST_OUTPUT_START},
+    moon_dir::MOON_DIRS,
+};
 use tempfile::TempDir;
-use tokio::process::Command;
+use tokio::process::{Child, Command};
 
 macro_rules! cache {
+    (@override) => {
+        None
+    };
+    (@override $override:path) => {
+        Some($override as OverrideFn)
+    };
     ($(
         $(#[$attr:meta])*
         $id:ident(
             $first_candidate:expr
             $(,$candidate:expr)* $(,)?
         )
+        $(with $override:path)?
     ),*$(,)?) => {
         /// A non-global cache for finding executables to use in compilation
         #[derive(Default)]
         pub struct RuntimeExecutableCache {
             $(
                 $(#[$attr])*
-                $id: OnceCell<PathBuf>
-            ),*
+                $id: OnceCell<(PathBuf, ExecutableSource)>,
+            )*
+            /// User-mode qemu executables, keyed by the architecture
+            qemu: RefCell<HashMap<String, PathBuf>>,
+            /// Whether `wasmtime` is checked to support Wasm GC
+            wasmtime_gc_checked: OnceCell<()>,
+            /// The sampling profiler found, see [`RuntimeExecutableCache::profiler`]
+            profiler: OnceCell<(Profiler, PathBuf)>,
+            /// Whether `rustica-engine` is checked to be compatible
+            #[cfg(feature = "moongres")]
+            rustica_engine_checked: OnceCell<()>,
+            /// The shared directory of JS test drivers
+            js_driver_dir: JsDriverDir,
         }
 
         impl RuntimeExecutableCache {
             $(
                 $(#[$attr])*
-                pub fn $id(&self) -> &Path {
-                    self.$id.get_or_init(|| {
-                        which::which($first_candidate)
-                        $(.or_else(|_| which::which($candidate)))*
-                        .unwrap_or($first_candidate.into())
-                    })
+                pub fn $id(&self) -> anyhow::Result<&Path> {
+                    let candidates = [$first_candidate $(, $candidate)*];
+                    let override_path = cache!(@override $($override)?);
+                    resolve_executable(&self.$id, &candidates, override_path).map(|(path, _)| path)
                 }
             )*
+
+            /// Find all the runtime executables, keyed by their names, along
+            /// with where each is found.
+            #[allow(clippy::vec_init_then_push)]
+            pub fn all_executables(
+                &self,
+            ) -> Vec<(&'static str, anyhow::Result<(&Path, ExecutableSource)>)> {
+                let mut res = vec![];
+                $(
+                    $(#[$attr])*
+                    res.push({
+                        let candidates = [$first_candidate $(, $candidate)*];
+                        let override_path = cache!(@override $($override)?);
+                        (
+                            $first_candidate,
+                            resolve_executable(&self.$id, &candidates, override_path),
+                        )
+                    });
+                )*
+                res
+            }
         }
     };
 }
 
 cache! {
     node("node", "node.cmd"),
-    moonrun("moonrun"),
+    deno("deno", "deno.exe"),
+    bun("bun", "bun.exe"),
+    moonrun("moonrun") with moonbuild::moonrun_path_override,
+    tcc("tcc", "tcc.exe"),
+    lldb("lldb", "lldb.exe"),
+    gdb("gdb", "gdb.exe"),
+    wasmtime("wasmtime", "wasmtime.exe"),
     #[cfg(feature = "moongres")]
     rustica_engine("rustica-engine", "rustica-engine.exe"),
 }
 
+/// The override of the path of an executable, e.g. from an environment
+/// variable.
+type OverrideFn = fn() -> anyhow::Result<Option<PathBuf>>;
+
+/// Where an executable of [`RuntimeExecutableCache`] is found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExecutableSource {
+    /// The environment variable overriding it, e.g. `MOON_MOONRUN_PATH`
+    Env,
+    /// The directory of the `moon` executable, where the toolchain installs
+    /// its executables
+    NextToMoon,
+    /// A directory in `PATH`
+    Path,
+    /// Nowhere, so the bare name is used and fails when spawned
+    NotFound,
+}
+
+impl std::fmt::Display for ExecutableSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ExecutableSource::Env => "env override",
+            ExecutableSource::NextToMoon => "next to moon",
+            ExecutableSource::Path => "PATH",
+            ExecutableSource::NotFound => "not found",
+        })
+    }
+}
+
+/// Find an executable once for `cell`, in the order of [`ExecutableSource`]:
+/// from `override_path` if it returns a path, then next to `moon`, then in
+/// `PATH`, each time trying all `candidates`.
+fn resolve_executable<'a>(
+    cell: &'a OnceCell<(PathBuf, ExecutableSource)>,
+    candidates: &[&str],
+    override_path: Option<OverrideFn>,
+) -> anyhow::Result<(&'a Path, ExecutableSource)> {
+    if let Some((path, source)) = cell.get() {
+        return Ok((path, *source));
+    }
+    let next_to_moon = || {
+        let moon = std::env::current_exe().ok()?;
+        let dir = moon.parent()?;
+        candidates
+            .iter()
+            .map(|it| dir.join(it))
+            .find(|it| it.is_file())
+    };
+    let found = if let Some(path) = override_path.map(|f| f()).transpose()?.flatten() {
+        (path, ExecutableSource::Env)
+    } else if let Some(path) = next_to_moon() {
+        (path, ExecutableSource::NextToMoon)
+    } else if let Some(path) = candidates.iter().find_map(|it| which::which(it).ok()) {
+        (path, ExecutableSource::Path)
+    } else {
+        (PathBuf::from(candidates[0]), ExecutableSource::NotFound)
+    };
+    let (path, source) = cell.get_or_init(|| found);
+    Ok((path, *source))
+}
+
+impl RuntimeExecutableCache {
+    /// Find the user-mode qemu to emulate the given architecture, i.e.
+    /// `qemu-<arch>`.
+    pub fn qemu(&self, arch: &str) -> PathBuf {
+        self.qemu
+            .borrow_mut()
+            .entry(arch.to_owned())
+            .or_insert_with(|| {
+                let name = format!("qemu-{arch}");
+                which::which(&name).unwrap_or(name.into())
+            })
+            .clone()
+    }
+
+    /// Find a sampling profiler for native executables: `perf` or `samply`
+    /// on Linux, preferring the former, and `samply` elsewhere.
+    pub fn profiler(&self) -> anyhow::Result<(Profiler, &Path)> {
+        if let Some((profiler, path)) = self.profiler.get() {
+            return Ok((*profiler, path));
+        }
+        let found = if cfg!(target_os = "linux") {
+            which::which("perf")
+                .map(|it| (Profiler::Perf, it))
+                .or_else(|_| which::which("samply").map(|it| (Profiler::Samply, it)))
+        } else {
+            which::which("samply").map(|it| (Profiler::Samply, it))
+        };
+        let Ok(found) = found else {
+            bail!(
+                "no supported profiler is found in PATH, please install {}",
+                if cfg!(target_os = "linux") {
+                    "`perf`, or `samply` with `cargo install samply`"
+                } else {
+                    "`samply` with `cargo install samply`"
+                }
+            );
+        };
+        let (profiler, path) = self.profiler.get_or_init(|| found);
+        Ok((*profiler, path))
+    }
+
+    /// Find `wasmtime`, ensuring it's new enough to run Wasm GC modules. The
+    /// version is only checked once per cache.
+    fn wasmtime_with_gc(&self) -> anyhow::Result<&Path> {
+        let wasmtime = self.wasmtime()?;
+        check_min_version(
+            &self.wasmtime_gc_checked,
+            wasmtime,
+            &WASMTIME_MIN_GC_VERSION,
+            "to support Wasm GC",
+        )?;
+        Ok(wasmtime)
+    }
+
+    /// Find `rustica-engine`, ensuring it's compatible with this `moon`. The
+    /// version is only checked once per cache.
+    #[cfg(feature = "moongres")]
+    fn rustica_engine_compatible(&self) -> anyhow::Result<&Path> {
+        let engine = self.rustica_engine()?;
+        check_min_version(
+            &self.rustica_engine_checked,
+            engine,
+            &RUSTICA_ENGINE_MIN_VERSION,
+            "for MoonGRES targets",
+        )?;
+        Ok(engine)
+    }
+}
+
+/// The first version of `wasmtime` supporting the GC proposal.
+const WASMTIME_MIN_GC_VERSION: semver::Version = semver::Version::new(27, 0, 0);
+
+/// The minimum version of `rustica-engine` understanding the test spec and
+/// the output produced by this `moon`. Bump it together with any change of
+/// the format.
+#[cfg(feature = "moongres")]
+const RUSTICA_ENGINE_MIN_VERSION: semver::Version = semver::Version::new(0, 2, 0);
+
+/// Bail if the version of `exe` is older than `min`, unless it's already
+/// `checked`. `purpose` completes the error message, e.g. "to support Wasm GC".
+fn check_min_version(
+    checked: &OnceCell<()>,
+    exe: &Path,
+    min: &semver::Version,
+    purpose: &str,
+) -> anyhow::Result<()> {
+    if checked.get().is_some() {
+        return Ok(());
+    }
+    let version =
+        executable_version(exe).map_err(|e| explain_missing(exe.as_os_str(), purpose, e))?;
+    if &version < min {
+        bail!(
+            "{} is version {}, but {} or later is required {}",
+            exe.display(),
+            version,
+            min,
+            purpose
+        );
+    }
+    let _ = checked.set(());
+    Ok(())
+}
+
+/// Get the version of an executable by running `<exe> --version`, taking the
+/// first word of the output that parses as a version, e.g. `27.0.0` in
+/// `wasmtime 27.0.0 (8eefa2365 2024-11-20)`.
+pub fn executable_version(exe: &Path) -> anyhow::Result<semver::Version> {
+    let output = std::process::Command::new(exe)
+        .arg("--version")
+        .output()
+        .with_context(|| format!("failed to run `{} --version`", exe.display()))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout
+        .split_whitespace()
+        .find_map(|word| semver::Version::parse(word.trim_start_matches('v')).ok())
+        .with_context(|| {
+            format!(
+                "failed to parse the version of {} from: {}",
+                exe.display(),
+                stdout.trim()
+            )
+        })
+}
+
+/// How to install the runtime executable `name`, or `None` if it isn't one.
+fn install_hint(name: &str) -> Option<String> {
+    const TOOLCHAIN: &str = "it comes with the MoonBit toolchain, reinstall it from https://www.moonbitlang.com/download";
+    let hint = match name {
+        "node" => {
+            "install Node.js from https://nodejs.org, or choose another runtime with `--js-runtime`"
+                .into()
+        }
+        "deno" => "install Deno from https://deno.com".into(),
+        "bun" => "install Bun from https://bun.sh".into(),
+        "moonrun" => format!(
+            "{TOOLCHAIN}, or point `{}` to it",
+            moonbuild::MOON_MOONRUN_PATH_ENV
+        ),
+        "tcc" => TOOLCHAIN.into(),
+        "wasmtime" => "install Wasmtime from https://wasmtime.dev".into(),
+        "rustica-engine" => "install `rustica-engine` and make sure it's in `PATH`".into(),
+        "lldb" | "gdb" => "install it with the package manager of your system".into(),
+        _ if name.starts_with("qemu-") => {
+            "install the user-mode emulation of QEMU, e.g. the `qemu-user` package".into()
+        }
+        _ => return None,
+    };
+    Some(hint)
+}
+
+/// Explain the failure `e` to spawn `program`, if the program is a runtime
+/// executable that's not found, telling how to install it. `needed_for`
+/// completes the message, e.g. "to run the js backend".
+fn explain_missing(program: &OsStr, needed_for: &str, e: anyhow::Error) -> anyhow::Error {
+    let not_found = e
+        .downcast_ref::<std::io::Error>()
+        .is_some_and(|it| it.kind() == std::io::ErrorKind::NotFound)
+        // Not some other file, e.g. the one of `--stdin-file`
+        && which::which(program).is_err();
+    let name = Path::new(program)
+        .file_stem()
+        .unwrap_or(program)
+        .to_string_lossy();
+    match install_hint(&name) {
+        Some(hint) if not_found => e.context(format!(
+            "`{name}` is not found, but it's required {needed_for}; {hint}"
+        )),
+        _ => e,
+    }
+}
+
 /// A guarded command info that removes the temporary file/dir(s) when it gets
 /// out of scope.
 pub struct CommandGuard {
     _temp_file: Option<TempDir>, // for destructor
     pub command: Command,
+    /// The maximum duration the command is allowed to run, if any.
+    pub timeout: Option<Duration>,
+    /// The standard input of the command, applied the same way for all
+    /// backends.
+    pub stdin: ChildStdin,
+    /// The backend whose output the command runs, to tell which backend a
+    /// missing runtime is required for.
+    pub backend: Option<TargetBackend>,
+    /// How the command is stopped on timeout, or when terminating it after
+    /// [`Self::spawn`].
+    pub stop: StopPolicy,
 }
 
 impl From<Command> for CommandGuard {
@@ -79,10 +382,296 @@ impl From<Command> for CommandGuard {
         Self {
             _temp_file: None,
             command,
+            timeout: None,
+            stdin: ChildStdin::default(),
+            backend: None,
+            stop: StopPolicy::default(),
+        }
+    }
+}
+
+impl CommandGuard {
+    /// Run the command with [`crate::run::run`]. If the command is still
+    /// running after [`Self::timeout`], it is stopped by [`Self::stop`] and a
+    /// [`CommandTimedOut`] error is returned. Temporary files are removed
+    /// after the command finishes either way.
+    ///
+    /// A non-zero exit is not an error; check the returned status instead.
+    ///
+    /// The output is collected into `buffer` if given, and split between
+    /// tests by `attribution` if given, see [`crate::run::run`].
+    pub async fn run_with_timeout<'a>(
+        self,
+        captures: &mut [&mut SectionCapture<'a>],
+        buffer: Option<&mut BufferedOutput>,
+        attribution: Option<&mut OutputAttribution<'a>>,
+    ) -> anyhow::Result<ExitStatus> {
+        let program = self.command.as_std().get_program().to_owned();
+        let backend = self.backend;
+        let stop = self.stop;
+        let Some(timeout) = self.timeout else {
+            return crate::run::run(
+                captures,
+                self.stdin,
+                self.command,
+                buffer,
+                attribution,
+                |_| {},
+            )
+            .await
+            .map_err(|e| explain_spawn_error(&program, backend, e));
+        };
+
+        let mut command = self.command;
+        stop.prepare(&mut command);
+        let pid = AtomicU32::new(0);
+        let fut = crate::run::run(
+            captures,
+            self.stdin,
+            command,
+            buffer,
+            attribution,
+            |child| {
+                stop.adopt(child);
+                pid.store(child.id().unwrap_or(0), Ordering::Relaxed);
+            },
+        );
+        tokio::pin!(fut);
+        match tokio::time::timeout(timeout, &mut fut).await {
+            Ok(res) => res.map_err(|e| explain_spawn_error(&program, backend, e)),
+            Err(_) => {
+                // Keep handling the output while the child gets to exit. The
+                // child is spawned with `kill_on_drop`, so dropping the future
+                // kills it if it's still running after the grace.
+                let pid = pid.load(Ordering::Relaxed);
+                if pid != 0 && stop.request(pid) {
+                    let _ = tokio::time::timeout(stop.grace, &mut fut).await;
+                }
+                Err(CommandTimedOut(timeout).into())
+            }
+        }
+    }
+
+    /// Start the command in the background, for it to run while `moon` does
+    /// something else, e.g. watches files. Unlike [`Self::run_with_timeout`],
+    /// it writes to the `stdout` and `stderr` of `moon` directly, since
+    /// nothing would forward them while `moon` is busy. [`Self::timeout`]
+    /// doesn't apply.
+    ///
+    /// Must be called within a Tokio runtime.
+    pub fn spawn(mut self) -> anyhow::Result<SpawnedCommand> {
+        let stdin = match &self.stdin {
+            ChildStdin::Null => Stdio::null(),
+            ChildStdin::Inherit => Stdio::inherit(),
+            ChildStdin::File(path) => std::fs::File::open(path)
+                .with_context(|| format!("Failed to open stdin file {}", path.display()))?
+                .into(),
+            ChildStdin::Bytes(_) => bail!("a command in the background can't be fed bytes"),
+        };
+        self.command.stdin(stdin);
+        self.command.kill_on_drop(true);
+        self.stop.prepare(&mut self.command);
+        let child = self
+            .command
+            .spawn()
+            .with_context(|| format!("Failed to spawn command {:?}", self.command))
+            .map_err(|e| {
+                explain_spawn_error(self.command.as_std().get_program(), self.backend, e)
+            })?;
+        self.stop.adopt(&child);
+        Ok(SpawnedCommand {
+            child,
+            _guard: self,
+        })
+    }
+}
+
+/// A command running in the background, started by [`CommandGuard::spawn`].
+/// It's killed when dropped.
+pub struct SpawnedCommand {
+    child: Child,
+    _guard: CommandGuard, // for the temporary files
+}
+
+impl SpawnedCommand {
+    /// Stop the command by [`CommandGuard::stop`], if it's still running. It's
+    /// first asked to exit, and killed if it doesn't within the grace, or
+    /// can't be asked.
+    pub async fn terminate(mut self) -> anyhow::Result<ExitStatus> {
+        if let Some(status) = self.child.try_wait()? {
+            return Ok(status);
+        }
+        let stop = self._guard.stop;
+        let asked = self.child.id().is_some_and(|pid| stop.request(pid));
+        if asked {
+            if let Ok(status) = tokio::time::timeout(stop.grace, self.child.wait()).await {
+                return Ok(status?);
+            }
+        }
+        self.child.kill().await?;
+        Ok(self.child.wait().await?)
+    }
+}
+
+/// Explain a failure to spawn `program` with [`explain_missing`], if it's the
+/// runtime of `backend`.
+fn explain_spawn_error(
+    program: &OsStr,
+    backend: Option<TargetBackend>,
+    e: anyhow::Error,
+) -> anyhow::Error {
+    match backend {
+        Some(backend) => explain_missing(program, &format!("to run the {backend} backend"), e),
+        None => e,
+    }
+}
+
+/// The error returned by [`CommandGuard::run_with_timeout`] when the command
+/// runs longer than its timeout.
+#[derive(Debug, Clone, Copy)]
+pub struct CommandTimedOut(pub Duration);
+
+impl std::fmt::Display for CommandTimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "timed out after {:.1}s", self.0.as_secs_f64())
+    }
+}
+
+impl std::error::Error for CommandTimedOut {}
+
+/// Timeouts of running MoonBit executables, configurable per backend since
+/// the startup time differs a lot between runtimes (e.g. Wasm vs Native).
+#[derive(Clone, Debug, Default)]
+pub struct RuntimeTimeout {
+    /// The timeout of backends without a specific one. `None` means no
+    /// timeout.
+    pub default: Option<Duration>,
+    /// Backend-specific timeouts, overriding [`Self::default`].
+    pub per_backend: HashMap<TargetBackend, Duration>,
+}
+
+impl RuntimeTimeout {
+    pub fn for_backend(&self, backend: TargetBackend) -> Option<Duration> {
+        self.per_backend.get(&backend).copied().or(self.default)
+    }
+}
+
+/// How to execute the output of Native backend.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NativeRunner {
+    /// Run the precompiled native executable directly.
+    #[default]
+    DirectExecutable,
+    /// Run the emitted C source with `tcc -run`, skipping the C compiler.
+    Tcc,
+}
+
+/// The debugger to run native executables under.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Debugger {
+    Lldb,
+    Gdb,
+}
+
+/// A sampling profiler to run native executables under.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Profiler {
+    Perf,
+    Samply,
+}
+
+impl Profiler {
+    /// The profile recorded for `executable`, placed next to it.
+    pub fn output_path(self, executable: &Path) -> PathBuf {
+        let mut name = executable.file_name().unwrap_or_default().to_owned();
+        name.push(match self {
+            Profiler::Perf => ".perf.data",
+            Profiler::Samply => ".profile.json.gz",
+        });
+        executable.with_file_name(name)
+    }
+
+    /// The command to view the profile at `path`.
+    pub fn view_command(self, path: &Path) -> String {
+        match self {
+            Profiler::Perf => format!("perf report -i {}", path.display()),
+            Profiler::Samply => format!("samply load {}", path.display()),
+        }
+    }
+}
+
+/// The runtime to execute the output of WasmGC backend. `wasmtime` is run
+/// with the GC proposal enabled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum WasmGcRunner {
+    #[default]
+    Moonrun,
+    Wasmtime,
+}
+
+/// The runtime to execute the output of JS backend.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum JsRuntime {
+    #[default]
+    Node,
+    Deno,
+    Bun,
+}
+
+impl JsRuntime {
+    /// The extension of the generated test driver script.
+    fn driver_extension(self) -> &'static str {
+        match self {
+            JsRuntime::Node | JsRuntime::Deno => "cjs",
+            // Bun resolves CommonJS in `.js` files fine, but treats `.cjs`
+            // differently when resolving the required module
+            JsRuntime::Bun => "js",
         }
     }
 }
 
+/// Configuration of which runtimes to use to run MoonBit executables.
+#[derive(Clone, Debug, Default)]
+pub struct RuntimeConfig {
+    /// How to run executables of the Native backend.
+    pub native_runner: NativeRunner,
+    /// The runtime to run executables of the WasmGC backend.
+    pub wasm_gc_runner: WasmGcRunner,
+    /// The runtime to run executables of the JS backend.
+    pub js_runtime: JsRuntime,
+    /// Extra flags passed to `node`, e.g. `--max-old-space-size=4096`. They
+    /// are placed before the script, so `node` interprets them instead of the
+    /// program. Ignored by other JS runtimes.
+    pub node_flags: Vec<String>,
+    /// The target triple native executables are built for, e.g.
+    /// `aarch64-unknown-linux-gnu`. If its architecture differs from the host,
+    /// native executables are run under the matching user-mode qemu.
+    pub cross_target: Option<String>,
+    /// The debugger to run native executables under, with the arguments of
+    /// the program passed after the executable.
+    pub debugger: Option<Debugger>,
+    /// Run native executables under a sampling profiler, see
+    /// [`RuntimeExecutableCache::profiler`]. The profile is written next to
+    /// the executable.
+    pub profile: bool,
+    /// Run native executables on a remote host instead, see
+    /// [`crate::run::remote`].
+    pub remote: Option<RemoteExecutor>,
+    /// Environment variables set on the program, loaded from `--env-file`.
+    /// The `envs` given to [`command_for_cached`] take precedence over them.
+    pub envs: Vec<(String, String)>,
+    /// How programs are stopped when they time out or are restarted.
+    pub stop: StopPolicy,
+}
+
+impl RuntimeConfig {
+    /// The architecture to emulate with qemu, if running cross-architecture.
+    fn qemu_arch(&self) -> Option<&str> {
+        let arch = self.cross_target.as_deref()?.split('-').next()?;
+        (arch != std::env::consts::ARCH).then_some(arch)
+    }
+}
+
 /// Returns a command to run the given MoonBit executable of a specific
 /// `backend`. The returning command is suitable for adding more commandline
 /// arguments that are directly passed to the MoonBit program being executed.
@@ -95,27 +684,184 @@ impl From<Command> for CommandGuard {
 /// file in WASM or WASM-GC backends, a `.js` file in JS backend, or a native
 /// executable in Native or LLVM backends.
 ///
-/// ### Note
+/// `config` selects the runtimes to use, see [`RuntimeConfig`].
 ///
-/// Currently there's no support for using `tcc` to execute the target program.
+/// `cwd` is the working directory of the spawned process. If it's `None`, the
+/// process inherits the one of `moon`.
 pub fn command_for(
     backend: TargetBackend,
+    config: &RuntimeConfig,
     mbt_executable: &Path,
     test: Option<&TestArgs>,
+    cwd: Option<&Path>,
 ) -> anyhow::Result<CommandGuard> {
+    let mut cache = RuntimeExecutableCache::default();
+    let mut guard = command_for_cached(&cache, backend, config, mbt_executable, test, &[], cwd)?;
+    // The cache is dropped here, so the guard takes over the driver directory
+    guard._temp_file = cache.js_driver_dir.dir.take();
+    Ok(guard)
+}
+
+/// Returns the argv, i.e. the program followed by all arguments, of the
+/// command [`command_for`] returns with the same arguments. It's used to
+/// display the command in dry-run.
+///
+/// Unlike [`command_for`], no temporary file is created. The test driver
+/// needed to run JS tests is shown as a placeholder path instead.
+pub fn command_line_for(
+    backend: TargetBackend,
+    config: &RuntimeConfig,
+    mbt_executable: &Path,
+    test: Option<&TestArgs>,
+) -> anyhow::Result<Vec<OsString>> {
+    if let Some(remote) = &config.remote {
+        check_remote_supported(backend, config)?;
+        return Ok(remote
+            .agent
+            .iter()
+            .map(OsString::from)
+            .chain([format!("<{} sent through stdin>", mbt_executable.display()).into()])
+            .collect());
+    }
     let cache = RuntimeExecutableCache::default();
-    command_for_cached(&cache, backend, mbt_executable, test)
+    let cmd = match (backend, test) {
+        (TargetBackend::Js, Some(t)) => {
+            let mut cmd = js_runtime_command(&cache, config)?;
+            cmd.arg(format!("<test driver of {}>", mbt_executable.display()));
+            cmd.arg(serde_json::to_string(t).expect("Failed to serialize test args"));
+            cmd
+        }
+        _ => base_command_for(&cache, backend, config, mbt_executable, test)?.command,
+    };
+    let cmd = cmd.as_std();
+    Ok(std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|x| x.to_owned())
+        .collect())
 }
 
+/// Same as [`command_for`], but uses the given executable cache.
+///
+/// The JS test drivers are written into a directory owned by `cache`, which
+/// is removed when `cache` is dropped. Thus `cache` must outlive the returned
+/// command.
+///
+/// When `config.native_runner` is [`NativeRunner::Tcc`], the C source emitted
+/// next to `mbt_executable` (with the `.c` extension) of the Native backend is
+/// executed by `tcc -run` together with the runtime source, instead of the
+/// precompiled executable.
+///
+/// `envs` are set on the spawned process in addition to the environment
+/// inherited from `moon` and `config.envs`. If the same key appears more than
+/// once, the last one wins. For the JS backend, they are set on the JS runtime process running
+/// the test driver, which runs the tests in the same process.
+///
+/// If `cwd` is given, `mbt_executable` is made absolute before building the
+/// command, so that neither the executable nor the JS test driver referencing
+/// it is resolved against the changed working directory.
+///
+/// With `config.remote`, the command runs `mbt_executable` on the remote host
+/// through the agent, and `envs` are sent to the agent instead. A `cwd` is not
+/// supported then, as the agent decides where the program runs.
 pub fn command_for_cached(
     cache: &RuntimeExecutableCache,
     backend: TargetBackend,
+    config: &RuntimeConfig,
     mbt_executable: &Path,
     test: Option<&TestArgs>,
+    envs: &[(OsString, OsString)],
+    cwd: Option<&Path>,
 ) -> anyhow::Result<CommandGuard> {
+    if let Some(remote) = &config.remote {
+        check_remote_supported(backend, config)?;
+        if cwd.is_some() {
+            bail!("a working directory is not supported with a remote executor");
+        }
+        let envs = config
+            .envs
+            .iter()
+            .map(|(k, v)| (OsString::from(k), OsString::from(v)))
+            .chain(envs.iter().cloned())
+            .collect::<Vec<_>>();
+        return remote.command_for(mbt_executable, test, &envs);
+    }
+    let absolute_executable;
+    let mbt_executable = if cwd.is_some() {
+        absolute_executable = std::path::absolute(mbt_executable).with_context(|| {
+            format!(
+                "failed to get the absolute path of {}",
+                mbt_executable.display()
+            )
+        })?;
+        &absolute_executable
+    } else {
+        mbt_executable
+    };
+    let mut guard = base_command_for(cache, backend, config, mbt_executable, test)?;
+    guard.backend = Some(backend);
+    guard.stop = config.stop;
+    guard.command.envs(config.envs.iter().map(|(k, v)| (k, v)));
+    guard.command.envs(envs.iter().map(|(k, v)| (k, v)));
+    if let Some(cwd) = cwd {
+        guard.command.current_dir(cwd);
+    }
+    Ok(guard)
+}
+
+/// Bail if the executables of `backend` can't run on a remote host with
+/// `config`. Only native executables run directly are supported for now.
+fn check_remote_supported(backend: TargetBackend, config: &RuntimeConfig) -> anyhow::Result<()> {
+    if !matches!(backend, TargetBackend::Native | TargetBackend::LLVM) {
+        bail!("remote execution is only supported for native/LLVM");
+    }
+    if config.native_runner == NativeRunner::Tcc {
+        bail!("remote execution is not supported with `tcc -run`");
+    }
+    if config.debugger.is_some() || config.profile || config.qemu_arch().is_some() {
+        bail!("remote execution cannot be combined with a debugger, a profiler or qemu");
+    }
+    Ok(())
+}
+
+fn base_command_for(
+    cache: &RuntimeExecutableCache,
+    backend: TargetBackend,
+    config: &RuntimeConfig,
+    mbt_executable: &Path,
+    test: Option<&TestArgs>,
+) -> anyhow::Result<CommandGuard> {
+    for (enabled, what) in [
+        (config.debugger.is_some(), "debugger wrapping"),
+        (config.profile, "profiling"),
+    ] {
+        if !enabled {
+            continue;
+        }
+        if !matches!(backend, TargetBackend::Native | TargetBackend::LLVM) {
+            bail!("{what} is only supported for native/LLVM");
+        }
+        if config.native_runner == NativeRunner::Tcc {
+            bail!("{what} is not supported with `tcc -run`");
+        }
+        if config.qemu_arch().is_some() {
+            bail!("{what} is not supported for native executables of another architecture");
+        }
+    }
     match backend {
+        TargetBackend::WasmGC if config.wasm_gc_runner == WasmGcRunner::Wasmtime => {
+            // The test args are passed as the program argument
+            let mut cmd = Command::new(cache.wasmtime_with_gc()?);
+            cmd.arg("run")
+                .args(["-W", "function-references", "-W", "gc"])
+                .arg(mbt_executable)
+                .arg("--");
+            if let Some(t) = test {
+                cmd.arg(serde_json::to_string(t).expect("valid test args"));
+            }
+            Ok(cmd.into())
+        }
         TargetBackend::Wasm | TargetBackend::WasmGC => {
-            let mut cmd = Command::new(cache.moonrun());
+            let mut cmd = Command::new(cache.moonrun()?);
             if let Some(t) = test {
                 cmd.arg("--test-args");
                 cmd.arg(serde_json::to_string(t).unwrap());
@@ -124,9 +870,20 @@ pub fn command_for_cached(
             cmd.arg("--");
             Ok(cmd.into())
         }
+        TargetBackend::Wasi => {
+            // Flags of `wasmtime` come before `--`, and arguments of the
+            // program come after it. The test args are passed as to native
+            // executables, since there's no host to ask for them.
+            let mut cmd = Command::new(cache.wasmtime()?);
+            cmd.arg("run").arg(mbt_executable).arg("--");
+            if let Some(t) = test {
+                cmd.args(t.to_cli_args_for_native());
+            }
+            Ok(cmd.into())
+        }
         #[cfg(feature = "moongres")]
         TargetBackend::MoonGRES => {
-            let mut cmd = Command::new(cache.rustica_engine());
+            let mut cmd = Command::new(cache.rustica_engine_compatible()?);
             if let Some(t) = test {
                 cmd.arg("moontest")
                     .arg("--spec")
@@ -138,40 +895,187 @@ pub fn command_for_cached(
             Ok(cmd.into())
         }
         TargetBackend::Js => {
+            let mut cmd = js_runtime_command(cache, config)?;
             if let Some(t) = test {
-                let (dir, driver) = create_js_driver(mbt_executable, t)?;
-                let mut cmd = Command::new(cache.node());
-                cmd.arg("--enable-source-maps");
+                let driver =
+                    cache
+                        .js_driver_dir
+                        .create_driver(config.js_runtime, mbt_executable, t)?;
                 cmd.arg(driver);
                 cmd.arg(serde_json::to_string(t).expect("Failed to serialize test args"));
-                Ok(CommandGuard {
-                    _temp_file: Some(dir),
-                    command: cmd,
-                })
+                Ok(cmd.into())
             } else {
-                let mut cmd = Command::new(cache.node());
                 cmd.arg(mbt_executable);
                 Ok(cmd.into())
             }
         }
+        TargetBackend::Native if config.native_runner == NativeRunner::Tcc => {
+            if config.qemu_arch().is_some() {
+                bail!("`tcc -run` cannot run native executables of another architecture");
+            }
+            let c_source = native_c_source(mbt_executable);
+            if !c_source.exists() {
+                bail!(
+                    "C source `{}` for `tcc -run` does not exist",
+                    c_source.display()
+                );
+            }
+            let mut cmd = Command::new(cache.tcc()?);
+            cmd.arg(format!("-I{}", MOON_DIRS.moon_include_path.display()))
+                .arg(format!("-L{}", MOON_DIRS.moon_lib_path.display()))
+                .arg(MOON_DIRS.moon_lib_path.join("runtime.c"))
+                .arg("-lm")
+                .arg("-DMOONBIT_NATIVE_NO_SYS_HEADER")
+                .arg("-run")
+                .arg(c_source);
+            if let Some(t) = test {
+                cmd.args(t.to_cli_args_for_native());
+            }
+            Ok(cmd.into())
+        }
         TargetBackend::Native | TargetBackend::LLVM => {
-            let mut cmd = Command::new(mbt_executable);
+            let mut cmd = match config.qemu_arch() {
+                Some(arch) => {
+                    let mut cmd = Command::new(cache.qemu(arch));
+                    cmd.arg(mbt_executable);
+                    cmd
+                }
+                None => match config.debugger {
+                    // The arguments after the executable go to the program
+                    Some(Debugger::Lldb) => {
+                        let mut cmd = Command::new(cache.lldb()?);
+                        cmd.arg("--").arg(mbt_executable);
+                        cmd
+                    }
+                    Some(Debugger::Gdb) => {
+                        let mut cmd = Command::new(cache.gdb()?);
+                        cmd.arg("--args").arg(mbt_executable);
+                        cmd
+                    }
+                    None if config.profile => {
+                        let (profiler, path) = cache.profiler()?;
+                        let mut cmd = Command::new(path);
+                        match profiler {
+                            Profiler::Perf => cmd.args(["record", "-g", "-o"]),
+                            Profiler::Samply => cmd.args(["record", "--save-only", "-o"]),
+                        };
+                        cmd.arg(profiler.output_path(mbt_executable))
+                            .arg("--")
+                            .arg(mbt_executable);
+                        cmd
+                    }
+                    None => Command::new(mbt_executable),
+                },
+            };
             if let Some(t) = test {
-                cmd.arg(t.to_cli_args_for_native());
+                cmd.args(t.to_cli_args_for_native());
             }
             Ok(cmd.into())
         }
     }
 }
 
-fn create_js_driver(js_path: &Path, test_args: &TestArgs) -> anyhow::Result<(TempDir, PathBuf)> {
+/// Get the C source emitted by `link-core` for a native executable.
+///
+/// The executable may have no extension (e.g. `foo.blackbox_test`) on
+/// non-Windows platforms, so we can't just replace its extension.
+fn native_c_source(executable: &Path) -> PathBuf {
+    if executable.extension().is_some_and(|ext| ext == "exe") {
+        executable.with_extension("c")
+    } else {
+        let mut path = executable.as_os_str().to_owned();
+        path.push(".c");
+        path.into()
+    }
+}
+
+/// Create the command of the JS runtime, up to the point before the script to
+/// run.
+fn js_runtime_command(
+    cache: &RuntimeExecutableCache,
+    config: &RuntimeConfig,
+) -> anyhow::Result<Command> {
+    let cmd = match config.js_runtime {
+        JsRuntime::Node => {
+            let mut cmd = Command::new(cache.node()?);
+            cmd.arg("--enable-source-maps");
+            cmd.args(&config.node_flags);
+            cmd
+        }
+        JsRuntime::Deno => {
+            // Deno enables source maps by default. Programs may read the
+            // environment, which Deno denies unless allowed.
+            let mut cmd = Command::new(cache.deno()?);
+            cmd.arg("run").arg("--allow-read").arg("--allow-env");
+            cmd
+        }
+        JsRuntime::Bun => {
+            // Bun enables source maps by default
+            let mut cmd = Command::new(cache.bun()?);
+            cmd.arg("run");
+            cmd
+        }
+    };
+    Ok(cmd)
+}
+
+/// A temporary directory holding the JS test drivers, shared by all test
+/// commands created from the same [`RuntimeExecutableCache`]. The directory is
+/// removed when the cache is dropped.
+#[derive(Default)]
+struct JsDriverDir {
+    dir: OnceCell<TempDir>,
+    /// The number of drivers created, used to name each driver uniquely
+    count: Cell<usize>,
+}
+
+impl JsDriverDir {
+    fn dir(&self) -> anyhow::Result<&Path> {
+        if let Some(dir) = self.dir.get() {
+            return Ok(dir.path());
+        }
+        let dir =
+            TempDir::new().context("Failed to create temporary directory for JS testing script")?;
+
+        // prevent node use the outer layer package.json with `"type": "module"`.
+        // Bun also respects the nearest package.json, so this is needed for
+        // `.js` drivers under Bun just as well.
+        let package_json = dir.path().join("package.json");
+        std::fs::write(package_json, "{}")
+            .context("Failed to write temporary package.json for JS testing script")?;
+
+        Ok(self.dir.get_or_init(|| dir).path())
+    }
+
+    /// Write a driver for the given test into the shared directory and return
+    /// its path. Each driver gets a unique name, so that test processes
+    /// running concurrently never share a driver file.
+    fn create_driver(
+        &self,
+        runtime: JsRuntime,
+        js_path: &Path,
+        test_args: &TestArgs,
+    ) -> anyhow::Result<PathBuf> {
+        let index = self.count.get();
+        self.count.set(index + 1);
+
+        let js_file = self
+            .dir()?
+            .join(format!("driver-{index}.{}", runtime.driver_extension()));
+        std::fs::write(&js_file, js_driver_content(runtime, js_path, test_args))
+            .context("Failed to write temporary JS test driver script")?;
+        Ok(js_file)
+    }
+}
+
+fn js_driver_content(runtime: JsRuntime, js_path: &Path, test_args: &TestArgs) -> String {
     let js_driver_text = include_str!(concat!(
         env!("CARGO_MANIFEST_DIR"),
         "/../moonbuild/template/test_driver/js_driver.js"
     ));
 
     // This replicates the original behavior, needs fixing later
-    let js_driver = js_driver_text
+    let mut js_driver = js_driver_text
         .replace(
             "origin_js_path",
             &js_path.display().to_string().replace("\\", "/"),
@@ -183,16 +1087,117 @@ fn create_js_driver(js_path: &Path, test_args: &TestArgs) -> anyhow::Result<(Tem
         .replace(
             "let packageName = \"\"",
             &format!("let packageName = {:?}", test_args.package),
+        )
+        .replace(
+            "for (param of testParams) {",
+            &format!(
+                "console.log({0:?})\nconsole.error({0:?})\nfor (param of testParams) {{",
+                MOON_TEST_OUTPUT_START
+            ),
+        );
+    if runtime == JsRuntime::Deno {
+        // Deno only provides the Node globals to CommonJS modules on a
+        // best-effort basis, so import `process` explicitly
+        js_driver.insert_str(0, "const process = require('node:process')\n");
+    }
+    js_driver
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deno_command() {
+        let cache = RuntimeExecutableCache::default();
+        let config = RuntimeConfig {
+            js_runtime: JsRuntime::Deno,
+            ..Default::default()
+        };
+        let cmd = js_runtime_command(&cache, &config).unwrap();
+        let args: Vec<_> = cmd.as_std().get_args().collect();
+        assert_eq!(args, ["run", "--allow-read", "--allow-env"]);
+    }
+
+    #[test]
+    fn test_wasi_command() {
+        let cache = RuntimeExecutableCache::default();
+        let test = TestArgs {
+            package: "username/hello/lib".into(),
+            file_and_index: vec![("hello.mbt".into(), vec![0..2, 5..6])],
+            seed: Some(42),
+            bench: None,
+            timeout_ms: None,
+        };
+        let guard = base_command_for(
+            &cache,
+            TargetBackend::Wasi,
+            &RuntimeConfig::default(),
+            Path::new("lib.wasm"),
+            Some(&test),
+        )
+        .unwrap();
+        let args: Vec<_> = guard.command.as_std().get_args().collect();
+        // each test arg is an argument of the program, after `--`
+        assert_eq!(
+            args,
+            [
+                "run",
+                "lib.wasm",
+                "--",
+                "hello.mbt:0-2/hello.mbt:5-6",
+                "seed=42"
+            ]
         );
+    }
 
-    let dir = TempDir::new().expect("Failed to create temporary directory for JS testing script");
-    let js_file = dir.path().join("driver.cjs");
-    std::fs::write(&js_file, js_driver).expect("Failed to write temporary JS test driver script");
+    #[cfg(all(unix, feature = "moongres"))]
+    #[test]
+    fn test_rustica_engine_min_version() {
+        use std::os::unix::fs::PermissionsExt;
 
-    // prevent node use the outer layer package.json with `"type": "module"`
-    let package_json = dir.path().join("package.json");
-    std::fs::write(package_json, "{}")
-        .expect("Failed to write temporary package.json for JS testing script");
+        let dir = tempfile::tempdir().unwrap();
+        let engine = |version: &str| {
+            let path = dir.path().join(format!("rustica-engine-{version}"));
+            std::fs::write(&path, format!("#!/bin/sh\necho rustica-engine {version}\n")).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+            path
+        };
 
-    Ok((dir, js_file))
+        let checked = OnceCell::new();
+        let old = engine("0.1.0");
+        let err = check_min_version(
+            &checked,
+            &old,
+            &RUSTICA_ENGINE_MIN_VERSION,
+            "for MoonGRES targets",
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "{} is version 0.1.0, but {RUSTICA_ENGINE_MIN_VERSION} or later is required for MoonGRES targets",
+                old.display()
+            )
+        );
+        assert!(checked.get().is_none());
+
+        let current = engine(&RUSTICA_ENGINE_MIN_VERSION.to_string());
+        check_min_version(
+            &checked,
+            &current,
+            &RUSTICA_ENGINE_MIN_VERSION,
+            "for MoonGRES targets",
+        )
+        .unwrap();
+        assert!(checked.get().is_some());
+        // only checked once
+        check_min_version(
+            &checked,
+            &old,
+            &RUSTICA_ENGINE_MIN_VERSION,
+            "for MoonGRES targets",
+        )
+        .unwrap();
+    }
 }
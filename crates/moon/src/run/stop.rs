@@ -0,0 +1,151 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Stopping spawned commands gracefully, when they time out or are superseded
+//! in watch mode. A command is first asked to exit, and killed only if it
+//! doesn't within a grace period, so that it can flush its state or remove its
+//! lock files.
+//!
+//! On Unix, the command is asked with a signal, `SIGTERM` by default. Windows
+//! has no signals, so the command is sent `CTRL_BREAK_EVENT` instead, which
+//! needs it to lead a process group of its own. Such a process misses the
+//! Ctrl-C of the console, so it's also put in a job object that kills it when
+//! `moon` exits.
+
+use std::time::Duration;
+
+use tokio::process::{Child, Command};
+
+/// The default time a command gets to exit after being asked to.
+pub const DEFAULT_STOP_GRACE: Duration = Duration::from_secs(2);
+
+/// How a running command is asked to exit, see [`StopPolicy`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum StopSignal {
+    #[default]
+    Term,
+    Int,
+    Kill,
+}
+
+/// How to stop a running command: ask it with `signal`, then kill it if it's
+/// still running after `grace`. With [`StopSignal::Kill`], it's killed right
+/// away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StopPolicy {
+    pub signal: StopSignal,
+    pub grace: Duration,
+}
+
+impl Default for StopPolicy {
+    fn default() -> Self {
+        Self {
+            signal: StopSignal::default(),
+            grace: DEFAULT_STOP_GRACE,
+        }
+    }
+}
+
+impl StopPolicy {
+    /// Prepare `cmd` before spawning it, for it to be stopped gracefully.
+    pub(crate) fn prepare(&self, cmd: &mut Command) {
+        #[cfg(windows)]
+        if self.signal != StopSignal::Kill {
+            cmd.creation_flags(windows_sys::Win32::System::Threading::CREATE_NEW_PROCESS_GROUP);
+        }
+        #[cfg(not(windows))]
+        let _ = cmd;
+    }
+
+    /// Set up `child` right after spawning it from a command prepared by
+    /// [`Self::prepare`].
+    pub(crate) fn adopt(&self, child: &Child) {
+        #[cfg(windows)]
+        if self.signal != StopSignal::Kill {
+            windows::kill_on_exit(child);
+        }
+        #[cfg(not(windows))]
+        let _ = child;
+    }
+
+    /// Ask the process `pid` to exit. Returns `false` if it's not asked, e.g.
+    /// because it's gone or the policy is to kill it, so that it should be
+    /// killed right away.
+    pub(crate) fn request(&self, pid: u32) -> bool {
+        match self.signal {
+            StopSignal::Kill => false,
+            #[cfg(windows)]
+            StopSignal::Term | StopSignal::Int => windows::request_break(pid),
+            #[cfg(not(windows))]
+            StopSignal::Term => moonbuild::request_termination(pid, moonbuild::Signal::Term),
+            #[cfg(not(windows))]
+            StopSignal::Int => moonbuild::request_termination(pid, moonbuild::Signal::Interrupt),
+        }
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::sync::OnceLock;
+
+    use tokio::process::Child;
+    use windows_sys::Win32::Foundation::HANDLE;
+    use windows_sys::Win32::System::Console::{GenerateConsoleCtrlEvent, CTRL_BREAK_EVENT};
+    use windows_sys::Win32::System::JobObjects::{
+        AssignProcessToJobObject, CreateJobObjectW, JobObjectExtendedLimitInformation,
+        SetInformationJobObject, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+        JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+    };
+
+    /// The job object killing its processes once it's closed, which happens
+    /// only when `moon` exits. The handle is kept as an integer, since raw
+    /// pointers are not `Sync`.
+    fn job() -> Option<usize> {
+        static JOB: OnceLock<Option<usize>> = OnceLock::new();
+        *JOB.get_or_init(|| unsafe {
+            let job = CreateJobObjectW(std::ptr::null(), std::ptr::null());
+            if job.is_null() {
+                return None;
+            }
+            let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = std::mem::zeroed();
+            info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+            let ok = SetInformationJobObject(
+                job,
+                JobObjectExtendedLimitInformation,
+                &info as *const _ as *const _,
+                std::mem::size_of_val(&info) as u32,
+            );
+            (ok != 0).then_some(job as usize)
+        })
+    }
+
+    /// Put `child` in [`job`]. It's best effort: a child left out is still
+    /// killed on drop, just not if `moon` is killed.
+    pub(super) fn kill_on_exit(child: &Child) {
+        if let (Some(job), Some(process)) = (job(), child.raw_handle()) {
+            unsafe {
+                AssignProcessToJobObject(job as HANDLE, process as HANDLE);
+            }
+        }
+    }
+
+    /// Send `CTRL_BREAK_EVENT` to the process group led by `pid`.
+    pub(super) fn request_break(pid: u32) -> bool {
+        unsafe { GenerateConsoleCtrlEvent(CTRL_BREAK_EVENT, pid) != 0 }
+    }
+}
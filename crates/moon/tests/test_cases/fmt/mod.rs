@@ -113,6 +113,61 @@ fn test_moon_fmt_002() {
     );
 }
 
+#[cfg(unix)]
+#[test]
+fn test_moon_fmt_check_list_and_diff() {
+    let dir = TestDir::new("fmt");
+    let files = [
+        "lib/hello.mbt",
+        "lib/hello_wbtest.mbt",
+        "lib/test.mbt.md",
+        "main/main.mbt",
+    ];
+
+    let stdout = get_err_stdout(&dir, ["fmt", "--check"]);
+    for file in files {
+        assert!(stdout.contains(file), "{stdout}");
+    }
+    assert!(!stdout.contains("diff --git"), "{stdout}");
+
+    let stdout = get_err_stdout(&dir, ["fmt", "--check", "--diff"]);
+    assert_eq!(
+        stdout.matches("diff --git").count(),
+        files.len(),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("-pub fn hello() -> String { \"Hello, world!\" }"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("+pub fn hello() -> String {"), "{stdout}");
+
+    // the sources are left untouched
+    check(
+        read(dir.join("lib").join("hello.mbt")),
+        expect![[r#"
+            pub fn hello() -> String { "Hello, world!" }
+        "#]],
+    );
+}
+
+#[test]
+fn test_moon_fmt_diff_dry_run() {
+    let dir = TestDir::new("fmt");
+    check(
+        get_stdout(
+            &dir,
+            ["fmt", "--check", "--diff", "--sort-input", "--dry-run"],
+        ),
+        expect![[r#"
+            moon tool format-and-diff --old ./lib/test.mbt.md --new ./target/wasm-gc/release/format/lib/test.mbt.md --diff
+            moon tool format-and-diff --old ./main/main.mbt --new ./target/wasm-gc/release/format/main/main.mbt --diff
+            moon tool format-and-diff --old ./lib/hello_wbtest.mbt --new ./target/wasm-gc/release/format/lib/hello_wbtest.mbt --diff
+            moon tool format-and-diff --old ./lib/hello.mbt --new ./target/wasm-gc/release/format/lib/hello.mbt --diff
+        "#]],
+    );
+}
+
 #[test]
 fn test_moon_fmt_extra_args() {
     let dir = TestDir::new("fmt");
@@ -145,3 +145,97 @@ fn test_preferred_target() {
     test_target(&dir, &mod_json_path, &mut mod_json, "wasm-gc");
     test_target(&dir, &mod_json_path, &mut mod_json, "native");
 }
+
+#[test]
+fn test_moon_target_dir_env() {
+    let dir = TestDir::new("hello");
+    let target = tempfile::TempDir::new().unwrap();
+    check(
+        get_stdout_with_envs(&dir, ["run", "main"], [("MOON_TARGET_DIR", target.path())]),
+        expect![[r#"
+            Hello, world!
+        "#]],
+    );
+    assert!(!dir.join("target").exists());
+    assert!(target.path().join("wasm-gc").exists());
+}
+
+#[test]
+fn test_build_message_format_json() {
+    let dir = TestDir::new("hello");
+    let out = get_stdout(&dir, ["build", "--message-format", "json"]);
+    let messages = out
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    let last = messages.last().unwrap();
+    assert_eq!(last["reason"], "build-finished");
+    assert_eq!(last["success"], true);
+    let main = messages
+        .iter()
+        .find(|m| m["reason"] == "build-artifact" && m["package"] == "hello/main")
+        .unwrap();
+    assert_eq!(main["backend"], "wasm-gc");
+    assert!(std::path::Path::new(main["executable"].as_str().unwrap()).exists());
+}
+
+#[test]
+fn test_gen_build_graph() {
+    let dir = TestDir::new("hello");
+    let graph = || {
+        let out = get_stdout(&dir, ["gen", "build-graph", "--format", "json"]);
+        serde_json::from_str::<serde_json::Value>(&out).unwrap()
+    };
+    let main = |graph: &serde_json::Value| {
+        graph["nodes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|n| n["package"] == "hello/main")
+            .cloned()
+            .unwrap()
+    };
+    let before = graph();
+    assert_eq!(main(&before)["backend"], "wasm-gc");
+    assert_eq!(main(&before)["up_to_date"], false);
+    assert!(before["cycles"].as_array().unwrap().is_empty());
+
+    get_stdout(&dir, ["build"]);
+    assert_eq!(main(&graph())["up_to_date"], true);
+
+    let dot = get_stdout(&dir, ["gen", "build-graph"]);
+    assert!(dot.starts_with("digraph"));
+    assert!(dot.contains("\"hello/main\""));
+}
+
+#[test]
+fn test_wasi_target_experimental() {
+    let dir = TestDir::new("hello");
+
+    let err = get_err_stderr(&dir, ["build", "--target", "wasi"]);
+    assert!(
+        err.contains("the `wasi` target is only supported with `-Z rupes_recta,wasi`"),
+        "{err}"
+    );
+    let err = get_err_stderr(&dir, ["-Z", "rupes_recta", "build", "--target", "wasi"]);
+    assert!(
+        err.contains("the `wasi` target is experimental, enable it with `-Z wasi`"),
+        "{err}"
+    );
+
+    let out = get_stdout(
+        &dir,
+        [
+            "-Z",
+            "rupes_recta,wasi",
+            "run",
+            "main",
+            "--target",
+            "wasi",
+            "--dry-run",
+        ],
+    );
+    let run = out.lines().last().unwrap();
+    assert!(run.starts_with("wasmtime run "), "{out}");
+    assert!(run.ends_with("main.wasm --"), "{out}");
+}
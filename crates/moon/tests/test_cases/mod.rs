@@ -92,6 +92,42 @@ fn test_no_work_to_do() {
     assert!(out.contains("moon: no work to do"));
 }
 
+#[test]
+fn test_no_work_to_do_after_touch_with_content_hash() {
+    let dir = TestDir::new("moon_new/plain");
+    let source = dir.join("lib/hello.mbt");
+    for unstable in ["content_hash", "rupes_recta,content_hash"] {
+        get_stderr(&dir, ["-Z", unstable, "build"]);
+        let out = get_stderr(&dir, ["-Z", unstable, "build"]);
+        assert!(out.contains("no work to do"), "with `-Z {unstable}`: {out}");
+
+        // touched without changing the content
+        let touched = std::time::SystemTime::now() + std::time::Duration::from_secs(10);
+        std::fs::File::options()
+            .write(true)
+            .open(&source)
+            .unwrap()
+            .set_modified(touched)
+            .unwrap();
+        let out = get_stderr(&dir, ["-Z", unstable, "build"]);
+        assert!(out.contains("no work to do"), "with `-Z {unstable}`: {out}");
+        // the source itself is left as is
+        assert_eq!(
+            std::fs::metadata(&source).unwrap().modified().unwrap(),
+            touched
+        );
+
+        let mut content = std::fs::read_to_string(&source).unwrap();
+        content.push('\n');
+        std::fs::write(&source, content).unwrap();
+        let out = get_stderr(&dir, ["-Z", unstable, "build"]);
+        assert!(
+            !out.contains("no work to do"),
+            "with `-Z {unstable}`: {out}"
+        );
+    }
+}
+
 #[test]
 fn test_moon_test_release() {
     let dir = TestDir::new("test_release.in");
@@ -392,6 +428,19 @@ fn test_nonexistent_package() {
     );
 }
 
+#[test]
+fn test_moon_build_keep_going() {
+    let dir = TestDir::new("keep_going.in");
+    let stderr = get_err_stderr(&dir, ["build", "--keep-going"]);
+    assert!(
+        stderr.contains("Failed packages:\n  username/hello/broken\n"),
+        "{stderr}"
+    );
+    assert!(!stderr.contains("Skipped packages"), "{stderr}");
+    // the independent package is still built
+    assert!(dir.join("target/wasm-gc/release/build/ok/ok.core").exists());
+}
+
 #[test]
 fn mooncakes_io_smoke_test() {
     if std::env::var("CI").is_err() {
@@ -945,6 +994,29 @@ fn test_moon_run_with_cli_args() {
     assert!(s.contains("\"中文\", \"😄👍\", \"hello\", \"1242\", \"--flag\""));
 }
 
+#[test]
+fn test_moon_run_args_on_all_backends() {
+    let dir = TestDir::new("moon_run_args_all_backends.in");
+
+    // The program sees its arguments last on every backend, with nothing of
+    // the runtimes after them
+    for unstable in ["", "rupes_recta"] {
+        for target in ["wasm-gc", "js", "native"] {
+            let s = get_stdout(
+                &dir,
+                [
+                    "-Z", unstable, "run", "main", "--target", target, "--", "中文", "hello",
+                    "--flag",
+                ],
+            );
+            assert!(
+                s.contains("\"中文\", \"hello\", \"--flag\"]"),
+                "unexpected args on {target} with `-Z {unstable}`: {s}"
+            );
+        }
+    }
+}
+
 #[test]
 fn test_third_party() {
     if std::env::var("CI").is_err() {
@@ -2105,7 +2177,7 @@ fn test_snapshot_test() {
         expect![[r#"
             [username/hello] test lib/hello_test.mbt:7 ("snapshot in blackbox test") failed
             expect test failed at $ROOT/src/lib/hello_test.mbt:9:3
-            Diff:
+            Diff: $ROOT/src/lib/__snapshot__/003.txt
             ----
             Hello, world!
             ----
@@ -2119,7 +2191,7 @@ fn test_snapshot_test() {
 
             [username/hello] test lib/hello.mbt:10 ("test snapshot 1") failed
             expect test failed at $ROOT/src/lib/hello.mbt:14:3
-            Diff:
+            Diff: $ROOT/src/lib/__snapshot__/001.txt
             ----
             hello
             snapshot
@@ -2136,7 +2208,7 @@ fn test_snapshot_test() {
 
             [username/hello] test lib/hello.mbt:22 ("test snapshot 2") failed
             expect test failed at $ROOT/src/lib/hello.mbt:26:3
-            Diff:
+            Diff: $ROOT/src/lib/__snapshot__/002.txt
             ----
             should
             be
@@ -2161,7 +2233,7 @@ fn test_snapshot_test() {
         expect![[r#"
             [username/hello] test lib/hello_test.mbt:7 ("snapshot in blackbox test") failed
             expect test failed at $ROOT/src/lib/hello_test.mbt:9:3
-            Diff:
+            Diff: $ROOT/src/lib/__snapshot__/003.txt
             ----
             Hello, world!
             ----
@@ -2175,7 +2247,7 @@ fn test_snapshot_test() {
 
             [username/hello] test lib/hello.mbt:10 ("test snapshot 1") failed
             expect test failed at $ROOT/src/lib/hello.mbt:14:3
-            Diff:
+            Diff: $ROOT/src/lib/__snapshot__/001.txt
             ----
             hello
             snapshot
@@ -2192,7 +2264,7 @@ fn test_snapshot_test() {
 
             [username/hello] test lib/hello.mbt:22 ("test snapshot 2") failed
             expect test failed at $ROOT/src/lib/hello.mbt:26:3
-            Diff:
+            Diff: $ROOT/src/lib/__snapshot__/002.txt
             ----
             should
             be
@@ -2278,7 +2350,7 @@ fn test_snapshot_test_target_js() {
         expect![[r#"
             [username/hello] test lib/hello_test.mbt:7 ("snapshot in blackbox test") failed
             expect test failed at $ROOT/src/lib/hello_test.mbt:9:3
-            Diff:
+            Diff: $ROOT/src/lib/__snapshot__/003.txt
             ----
             Hello, world!
             ----
@@ -2292,7 +2364,7 @@ fn test_snapshot_test_target_js() {
 
             [username/hello] test lib/hello.mbt:10 ("test snapshot 1") failed
             expect test failed at $ROOT/src/lib/hello.mbt:14:3
-            Diff:
+            Diff: $ROOT/src/lib/__snapshot__/001.txt
             ----
             hello
             snapshot
@@ -2309,7 +2381,7 @@ fn test_snapshot_test_target_js() {
 
             [username/hello] test lib/hello.mbt:22 ("test snapshot 2") failed
             expect test failed at $ROOT/src/lib/hello.mbt:26:3
-            Diff:
+            Diff: $ROOT/src/lib/__snapshot__/002.txt
             ----
             should
             be
@@ -4978,18 +5050,23 @@ fn test_pre_build_dirty() {
     let file = dir.join("src/lib/a.mbt");
 
     assert!(!file.exists(), "prebuild.txt should not exist yet");
-    let _first_prebuild = get_stderr(&dir, ["check"]);
+    // Still runs without the flag, with a deprecation warning
+    let first_prebuild = get_stderr(&dir, ["check"]);
     assert!(file.exists(), "prebuild.txt should exist after prebuild");
+    assert!(
+        first_prebuild.contains("Running it without `--allow-pre-build-scripts` is deprecated"),
+        "{first_prebuild}"
+    );
     let mtime = file.metadata().unwrap().modified().unwrap();
 
     check(
-        get_stderr(&dir, ["check"]),
+        get_stderr(&dir, ["check", "--allow-pre-build-scripts"]),
         expect![[r#"
             Finished. moon: no work to do
         "#]],
     );
     check(
-        get_stderr(&dir, ["check"]),
+        get_stderr(&dir, ["check", "--allow-pre-build-scripts"]),
         expect![[r#"
             Finished. moon: no work to do
         "#]],
@@ -5114,7 +5191,7 @@ fn native_backend_test_filter() {
         expect![[r#"
             [username/hello] test lib/hello.mbt:22 ("D") failed
             expect test failed at $ROOT/lib/hello.mbt:24:3
-            Diff:
+            Diff: $ROOT/lib/__snapshot__/test.d
             ----
             test D
 
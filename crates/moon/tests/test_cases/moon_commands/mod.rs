@@ -47,6 +47,8 @@ fn test_moon_help() {
               package                Package the current module
               update                 Update the package registry index
               coverage               Code coverage utilities
+              explain                Print the explanation of a compiler error code
+              gen                    Generate files for external tools
               generate-build-matrix  Generate build matrix for benchmarking (legacy feature)
               upgrade                Upgrade toolchains
               shell-completion       Generate shell completion for bash/elvish/fish/pwsh/zsh to stdout
@@ -60,7 +62,7 @@ fn test_moon_help() {
               -C, --directory <SOURCE_DIR>
                       The source code directory. Defaults to the current directory
                   --target-dir <TARGET_DIR>
-                      The target directory. Defaults to `source_dir/target`
+                      The target directory. Defaults to `$MOON_TARGET_DIR`, or `source_dir/target` if unset
               -q, --quiet
                       Suppress output
               -v, --verbose
@@ -71,12 +73,27 @@ fn test_moon_help() {
                       Do not actually run the command
                   --build-graph
                       Generate build graph
+                  --allow-pre-build-scripts
+                      Allow the `pre-build` commands of packages to run, other than `:embed`. They can run arbitrary code, and running them without this flag is deprecated [env: MOON_ALLOW_PRE_BUILD_SCRIPTS=]
+                  --offline
+                      Never access the network. Commands that need it fail right away, and dependencies must already be cached [env: MOON_OFFLINE=]
+                  --color <WHEN>
+                      When to color the output. `auto` colors it if stdout is a terminal and `NO_COLOR` is not set [default: auto] [possible values: auto, always, never]
               -Z, --unstable-feature <UNSTABLE_FEATURE>
                       Unstable flags to MoonBuild [env: MOON_UNSTABLE=] [default: ]
         "#]],
     );
 }
 
+#[test]
+fn test_moon_gen_completions() {
+    let dir = TestDir::new_empty();
+    let script = get_stdout(&dir, ["gen", "completions", "bash"]);
+    assert!(script.contains("shell-completion"));
+    assert!(script.contains("wasm-gc"));
+    assert!(script.contains("native"));
+}
+
 #[test]
 #[ignore]
 #[cfg(unix)]
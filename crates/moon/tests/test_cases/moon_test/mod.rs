@@ -325,3 +325,34 @@ fn test_async_test() {
     let last_line = out2.lines().last().unwrap_or("");
     check(last_line, expect!["Total tests: 1, passed: 0, failed: 1."])
 }
+
+#[test]
+fn test_run_test_by_name() {
+    let dir = TestDir::new("moon_test");
+    let args = |name| {
+        [
+            "-Z",
+            "rupes_recta",
+            "test",
+            "-C",
+            "plain",
+            "--package",
+            "moontest/lib5",
+            "--file",
+            "hello_wbtest.mbt",
+            "--test-name",
+            name,
+        ]
+    };
+    check(
+        get_stdout(&dir, args("with space")),
+        expect![[r#"
+            Total tests: 1, passed: 1, failed: 0.
+        "#]],
+    );
+    let err = get_err_stderr(&dir, args("missing"));
+    assert!(
+        err.contains("cannot find test named \"missing\" in hello_wbtest.mbt"),
+        "unexpected error: {err}"
+    );
+}
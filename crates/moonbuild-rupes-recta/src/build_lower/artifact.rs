@@ -336,8 +336,8 @@ fn linked_core_artifact_ext(
     wasm_use_wat: bool, // TODO: centralize knobs
 ) -> &'static str {
     match backend {
-        TargetBackend::Wasm | TargetBackend::WasmGC if wasm_use_wat => ".wat",
-        TargetBackend::Wasm | TargetBackend::WasmGC => ".wasm",
+        TargetBackend::Wasm | TargetBackend::WasmGC | TargetBackend::Wasi if wasm_use_wat => ".wat",
+        TargetBackend::Wasm | TargetBackend::WasmGC | TargetBackend::Wasi => ".wasm",
         #[cfg(feature = "moongres")]
         TargetBackend::MoonGRES if wasm_use_wat => ".wat",
         #[cfg(feature = "moongres")]
@@ -355,8 +355,8 @@ fn make_executable_artifact_ext(
     wasm_use_wat: bool,
 ) -> &'static str {
     match backend {
-        TargetBackend::Wasm | TargetBackend::WasmGC if wasm_use_wat => ".wat",
-        TargetBackend::Wasm | TargetBackend::WasmGC => ".wasm",
+        TargetBackend::Wasm | TargetBackend::WasmGC | TargetBackend::Wasi if wasm_use_wat => ".wat",
+        TargetBackend::Wasm | TargetBackend::WasmGC | TargetBackend::Wasi => ".wasm",
         #[cfg(feature = "moongres")]
         TargetBackend::MoonGRES if wasm_use_wat => ".wat",
         #[cfg(feature = "moongres")]
@@ -178,6 +178,19 @@ impl<'a> BuildPlanLowerContext<'a> {
             shlex::try_join(cmd.commandline.iter().map(|x| x.as_str()))
                 .expect("No `nul` should occur here"),
         );
+        // Same as the legacy descriptions, shown in the progress of running tasks
+        let desc_pkg = match node {
+            BuildPlanNode::BuildCStub(pkg, _)
+            | BuildPlanNode::ArchiveCStubs(pkg)
+            | BuildPlanNode::RunPrebuild(pkg, _) => {
+                Some(self.packages.get_package(pkg).fqn.clone())
+            }
+            _ => fqn.clone(),
+        };
+        build.desc = Some(match desc_pkg {
+            Some(pkg) => format!("{}: {}", node.step_name(), pkg),
+            None => node.step_name().to_string(),
+        });
 
         self.debug_print_command_and_files(node, &build);
         self.lowered(build).map_err(|e| LoweringError::N2 {
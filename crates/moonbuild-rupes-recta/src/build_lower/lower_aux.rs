@@ -18,6 +18,9 @@
 
 //! Lowering implementation for auxiliary build steps.
 
+use std::fmt::Write;
+use std::path::Path;
+
 use moonutil::{
     common::DriverKind,
     compiler_flags::{
@@ -215,8 +218,23 @@ impl<'a> super::BuildPlanLowerContext<'a> {
         // Note: we are tracking dependencies between prebuild commands via n2.
         // Ideally we can do this ourselves, but n2 does it anyway so we don't bother.
 
+        // Fail if the command didn't produce all declared outputs
+        let quote = |p: &Path| {
+            let p = p.display().to_string();
+            shlex::try_quote(&p).map_or(p.clone(), |q| q.into_owned())
+        };
+        let mut script = info.command.clone();
+        for output in &info.resolved_outputs {
+            let output = quote(output);
+            write!(
+                script,
+                " && {{ test -e {output} || {{ echo \"pre-build command did not produce {output}\" >&2; exit 1; }}; }}"
+            )
+            .expect("write can't fail");
+        }
+
         BuildCommand {
-            commandline: vec!["sh".into(), "-c".into(), info.command.clone()],
+            commandline: vec!["sh".into(), "-c".into(), script],
             extra_inputs: info.resolved_inputs.clone(),
         }
     }
@@ -49,6 +49,9 @@ use crate::{
 
 use super::{compiler, context::BuildPlanLowerContext, BuildCommand};
 
+/// The C compiler flags for LLVM source-based coverage. They need clang.
+const LLVM_COVERAGE_FLAGS: &[&str] = &["-fprofile-instr-generate", "-fcoverage-mapping"];
+
 impl<'a> BuildPlanLowerContext<'a> {
     fn is_module_third_party(&self, mid: ModuleId) -> bool {
         // This is usually a small vector, so this perf overhead is okay.
@@ -57,7 +60,8 @@ impl<'a> BuildPlanLowerContext<'a> {
 
     pub(super) fn set_flags(&self) -> compiler::CompilationFlags {
         compiler::CompilationFlags {
-            no_opt: self.opt.opt_level == OptLevel::Debug,
+            no_opt: self.opt.opt_level == OptLevel::Debug
+                || self.opt.native_opt_level == Some(CCOptLevel::None),
             symbols: self.opt.debug_symbols,
             source_map: self.opt.target_backend.supports_source_map() && self.opt.debug_symbols,
             enable_coverage: false,
@@ -423,6 +427,7 @@ impl<'a> BuildPlanLowerContext<'a> {
             (false, true) => CCOptLevel::Debug,
             (false, false) => CCOptLevel::None,
         };
+        let opt_level = self.opt.native_opt_level.unwrap_or(opt_level);
 
         let config = CCConfigBuilder::default()
             .no_sys_header(true)
@@ -529,6 +534,7 @@ impl<'a> BuildPlanLowerContext<'a> {
             OptLevel::Release => CCOptLevel::Speed,
             OptLevel::Debug => CCOptLevel::Debug,
         };
+        let opt_level = self.opt.native_opt_level.unwrap_or(opt_level);
         let config = CCConfigBuilder::default()
             .no_sys_header(true)
             .output_ty(CCOutputType::Executable) // TODO: support compiling to library
@@ -538,10 +544,15 @@ impl<'a> BuildPlanLowerContext<'a> {
             .define_use_shared_runtime_macro(false)
             .build()
             .expect("Failed to build CC configuration for executable");
+        let mut c_flags = info.c_flags.clone();
+        if self.opt.llvm_coverage {
+            // Only the program is instrumented, not the runtime or C stubs
+            c_flags.extend(LLVM_COVERAGE_FLAGS.iter().map(|s| s.to_string()));
+        }
         let cc_cmd = make_cc_command_pure(
             resolve_cc(CC::default(), info.cc.clone()), // TODO: no clone
             config,
-            &info.c_flags,
+            &c_flags,
             sources.iter().map(|x| x.display().to_string()),
             &self.opt.target_dir_root.display().to_string(),
             &self
@@ -24,7 +24,7 @@ use indexmap::IndexMap;
 use log::{debug, info};
 use moonutil::{
     common::{RunMode, TargetBackend},
-    compiler_flags::CompilerPaths,
+    compiler_flags::{CompilerPaths, OptLevel as CCOptLevel},
     cond_expr::OptLevel,
     mooncakes::ModuleSource,
 };
@@ -58,11 +58,16 @@ pub struct BuildOptions {
     pub target_backend: TargetBackend,
     pub os: OperatingSystem,
     pub opt_level: OptLevel,
+    /// Overrides the optimization level of the C compiler derived from
+    /// `opt_level`, with `None` also turning off the optimizations of moonc.
+    pub native_opt_level: Option<CCOptLevel>,
     pub action: RunMode,
 
     // Detailed configuration -- some of them might live better in configs
     pub debug_symbols: bool,
     pub enable_coverage: bool,
+    /// Instrument native executables for LLVM source-based coverage.
+    pub llvm_coverage: bool,
     pub output_wat: bool,
     pub moonc_output_json: bool,
     pub docs_serve: bool,
@@ -29,8 +29,8 @@ use indexmap::{set::MutableValues, IndexSet};
 use log::{debug, trace};
 use moonutil::{
     common::{
-        DEP_PATH, DOT_MBT_DOT_MD, MOD_DIR, MOONCAKE_BIN, MOON_BIN_DIR, MOON_MOD_JSON,
-        MOON_PKG_JSON, PKG_DIR,
+        pre_build_command_allowed, DEP_PATH, DOT_MBT_DOT_MD, MOD_DIR, MOONCAKE_BIN, MOON_BIN_DIR,
+        MOON_MOD_JSON, MOON_PKG_JSON, PKG_DIR,
     },
     compiler_flags::CC,
 };
@@ -569,6 +569,15 @@ impl<'a> BuildPlanConstructor<'a> {
         let module = &self.input.module_dirs[pkg.module];
         let prebuild_cmd =
             &pkg.raw.pre_build.as_ref().expect("Prebuild must exist")[index as usize];
+        if !pre_build_command_allowed(&prebuild_cmd.command) {
+            warn!(
+                "Package {} has a pre-build command `{}`, which can run arbitrary code. \
+                Running it without `--allow-pre-build-scripts` is deprecated, \
+                and will be an error in a future version.",
+                pkg.fqn,
+                prebuild_cmd.command
+            );
+        }
 
         // Warn about suspicious outputs
         for output in prebuild_cmd.output.iter() {
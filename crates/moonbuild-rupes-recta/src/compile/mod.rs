@@ -22,7 +22,7 @@ use indexmap::IndexMap;
 use log::{debug, info};
 use moonutil::{
     common::{RunMode, TargetBackend},
-    compiler_flags::CompilerPaths,
+    compiler_flags::{CompilerPaths, OptLevel as CCOptLevel},
     cond_expr::OptLevel,
     moon_dir::MOON_DIRS,
 };
@@ -44,6 +44,9 @@ pub struct CompileConfig {
     pub target_backend: TargetBackend,
     /// The optimization level to use for the compilation.
     pub opt_level: OptLevel,
+    /// The optimization level of the C compiler for the native and LLVM
+    /// backends, instead of the one derived from `opt_level`.
+    pub native_opt_level: Option<CCOptLevel>,
     /// The action done in this operation, currently only used in legacy directory layout
     pub action: RunMode,
     /// Whether to emit debug symbols.
@@ -63,6 +66,9 @@ pub struct CompileConfig {
     pub debug_export_build_plan: bool,
     /// Enable code coverage instrumentation.
     pub enable_coverage: bool,
+    /// Instrument native executables for LLVM source-based coverage, so that
+    /// running them writes raw profiles to `LLVM_PROFILE_FILE`.
+    pub llvm_coverage: bool,
     /// Output WAT instead of WASM binary format.
     pub output_wat: bool,
     /// Whether to output JSON or human-readable error code
@@ -137,9 +143,11 @@ pub fn compile(
         target_dir_root: cx.target_dir.clone(),
         target_backend: cx.target_backend,
         opt_level: cx.opt_level,
+        native_opt_level: cx.native_opt_level,
         action: cx.action,
 
         enable_coverage: cx.enable_coverage,
+        llvm_coverage: cx.llvm_coverage,
         debug_symbols: cx.debug_symbols,
         output_wat: cx.output_wat,
         moonc_output_json: cx.moonc_output_json,
@@ -31,7 +31,8 @@
 //! module into a more generic one, probably named "source utility" or similar.
 
 use log::*;
-use std::path::Path;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 
 use moonutil::mooncakes::{result::ResolvedEnv, ModuleId, ModuleSource};
 use n2::graph::Build;
@@ -89,8 +90,14 @@ pub struct FmtConfig {
     /// Checks the formatting without writing to files
     pub check_only: bool,
 
+    /// Print the differences when checking, instead of only the file names
+    pub show_diff: bool,
+
     /// Extra arguments to pass to the formatter
     pub extra_args: Vec<String>,
+
+    /// Only format these files, as canonicalized paths
+    pub only_files: Option<HashSet<PathBuf>>,
 }
 
 /// Generate the necessary build graph for the formatter operation
@@ -155,6 +162,11 @@ fn format_node(
     pkg: &DiscoveredPackage,
     file: &Path,
 ) -> anyhow::Result<()> {
+    if let Some(only_files) = &cfg.only_files {
+        if !file.canonicalize().is_ok_and(|f| only_files.contains(&f)) {
+            return Ok(());
+        }
+    }
     let cmd: Vec<String> = if cfg.check_only {
         let mut cmd = vec![
             "moon".into(),
@@ -171,6 +183,9 @@ fn format_node(
         if cfg.block_style {
             cmd.push("-block-style".into());
         }
+        if cfg.show_diff {
+            cmd.push("--diff".into());
+        }
         cmd.extend_from_slice(&cfg.extra_args);
         cmd
     } else {
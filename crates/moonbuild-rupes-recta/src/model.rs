@@ -156,6 +156,24 @@ impl BuildPlanNode {
             | BuildPlanNode::RunPrebuild(_, _) => None,
         }
     }
+
+    /// The name of the build step, as shown in the build progress
+    pub fn step_name(&self) -> &'static str {
+        match self {
+            BuildPlanNode::Check(_) => "check",
+            BuildPlanNode::BuildCore(_) => "build-package",
+            BuildPlanNode::BuildCStub(_, _) => "compile-stub",
+            BuildPlanNode::ArchiveCStubs(_) => "archive-stub",
+            BuildPlanNode::LinkCore(_) => "link-core",
+            BuildPlanNode::MakeExecutable(_) => "make-exe",
+            BuildPlanNode::GenerateTestInfo(_) => "gen-test-driver",
+            BuildPlanNode::GenerateMbti(_) => "gen-mbti",
+            BuildPlanNode::Bundle(_) => "bundle",
+            BuildPlanNode::BuildRuntimeLib => "compile-runtime",
+            BuildPlanNode::RunPrebuild(_, _) => "pre-build",
+            BuildPlanNode::BuildDocs => "build-docs",
+        }
+    }
 }
 
 /// Represents a list of artifact(s) corresponding to a single build node.
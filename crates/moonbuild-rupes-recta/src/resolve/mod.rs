@@ -64,13 +64,13 @@ pub struct ResolveConfig {
 }
 
 impl ResolveConfig {
-    /// Creates a new `ResolveConfig` with whether to freeze package resolving,
+    /// Creates a new `ResolveConfig` with the given flags of package syncing,
     /// and other flags populated from the environment with a sensible default.
     ///
     /// This method performs IO to load the registry configuration,
-    pub fn new_with_load_defaults(frozen: bool) -> Self {
+    pub fn new_with_load_defaults(sync_flags: AutoSyncFlags) -> Self {
         Self {
-            sync_flags: AutoSyncFlags { frozen },
+            sync_flags,
             registry_config: RegistryConfig::load(),
         }
     }
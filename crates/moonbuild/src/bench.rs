@@ -111,6 +111,7 @@ pub fn f() -> Unit {{
                 alert_list: None,
                 targets: None,
                 pre_build: None,
+                post_build: None,
                 bin_name: None,
                 bin_target: None,
                 supported_targets: None,
@@ -208,6 +209,7 @@ pub fn write(config: &Config, base_dir: &Path) {
         alert_list: None,
         targets: None,
         pre_build: None,
+        post_build: None,
         bin_name: None,
         bin_target: None,
         supported_targets: None,
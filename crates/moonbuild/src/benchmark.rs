@@ -17,10 +17,11 @@
 // For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
 
 use colored::Colorize;
+use indexmap::IndexMap;
 
 pub const BATCHBENCH: &str = "@BATCH_BENCH ";
 
-#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct BenchSummary {
     pub name: Option<String>,
     pub min: f64,
@@ -43,6 +44,62 @@ pub struct BatchBenchSummaries {
     pub summaries: Vec<BenchSummary>,
 }
 
+/// Parse the summaries reported by the bench harness in a test message, if
+/// the message is one.
+pub fn parse_batch_bench_summaries(msg: &str) -> Option<anyhow::Result<BatchBenchSummaries>> {
+    let msg = msg.strip_prefix(BATCHBENCH)?;
+    Some(
+        serde_json_lenient::from_str::<BatchBenchSummaries>(msg)
+            .map_err(|e| anyhow::anyhow!("failed to parse batch benchmark summary: {e}\n {msg}")),
+    )
+}
+
+/// The results of a bench run, as exported by `moon bench --bench-output json`.
+///
+/// Timings of different backends are not comparable, so a report covers only
+/// one backend. The benchmarks are sorted by [`BenchRecord::key`], so that
+/// reports of different runs can be diffed.
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct BenchReport {
+    pub backend: String,
+    pub benchmarks: Vec<BenchRecord>,
+}
+
+/// The statistics of one benchmark. The bench harness only reports the
+/// statistics of the timings, not the samples themselves.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BenchRecord {
+    pub package: String,
+    pub filename: String,
+    /// The name of the test block running the benchmark
+    pub test: String,
+    #[serde(flatten)]
+    pub summary: BenchSummary,
+}
+
+impl BenchRecord {
+    /// Identifies the benchmark across reports.
+    pub fn key(&self) -> String {
+        match &self.summary.name {
+            Some(name) => format!(
+                "{}::{}::{}::{}",
+                self.package, self.filename, self.test, name
+            ),
+            None => format!("{}::{}::{}", self.package, self.filename, self.test),
+        }
+    }
+}
+
+impl BenchReport {
+    pub fn new(backend: &str, mut benchmarks: Vec<BenchRecord>) -> Self {
+        benchmarks.sort_by_cached_key(BenchRecord::key);
+        BenchReport {
+            backend: backend.to_string(),
+            benchmarks,
+        }
+    }
+}
+
 fn auto_select_unit(us: f64) -> String {
     if us < 1e3 {
         format!("{us:>6.2} µs")
@@ -56,10 +113,9 @@ fn auto_select_unit(us: f64) -> String {
 }
 
 pub fn render_batch_bench_summary(msg: &str) {
-    assert!(msg.starts_with(BATCHBENCH));
-    let msg = &msg[BATCHBENCH.len()..];
-    let summary = serde_json_lenient::from_str::<BatchBenchSummaries>(msg)
-        .unwrap_or_else(|e| panic!("failed to parse batch benchmark summary: {e}\n {msg}"));
+    let summary = parse_batch_bench_summaries(msg)
+        .expect("message should be a batch benchmark summary")
+        .unwrap_or_else(|e| panic!("{e}"));
     let max_name_len = summary
         .summaries
         .iter()
@@ -98,3 +154,135 @@ pub fn render_batch_bench_summary(msg: &str) {
         }
     }
 }
+
+/// How a benchmark changed from the baseline report to the current one.
+#[derive(Debug, Clone, Copy)]
+pub enum BenchChange {
+    /// Only in the current report
+    Added { current: f64 },
+    /// Only in the baseline report
+    Removed { baseline: f64 },
+    /// The mean time changed by `pct` percent, positive if slower
+    Changed {
+        baseline: f64,
+        current: f64,
+        pct: f64,
+    },
+}
+
+/// Match the benchmarks of two reports by [`BenchRecord::key`], in the order
+/// of keys.
+pub fn compare_bench_reports(
+    baseline: &BenchReport,
+    current: &BenchReport,
+) -> Vec<(String, BenchChange)> {
+    let mut baseline_means: IndexMap<String, f64> = baseline
+        .benchmarks
+        .iter()
+        .map(|b| (b.key(), b.summary.mean))
+        .collect();
+    let mut changes = vec![];
+    for b in &current.benchmarks {
+        let key = b.key();
+        let change = match baseline_means.shift_remove(&key) {
+            Some(mean) => BenchChange::Changed {
+                baseline: mean,
+                current: b.summary.mean,
+                pct: (b.summary.mean - mean) / mean * 100.0,
+            },
+            None => BenchChange::Added {
+                current: b.summary.mean,
+            },
+        };
+        changes.push((key, change));
+    }
+    changes.extend(
+        baseline_means
+            .into_iter()
+            .map(|(key, baseline)| (key, BenchChange::Removed { baseline })),
+    );
+    changes.sort_by(|a, b| a.0.cmp(&b.0));
+    changes
+}
+
+/// Print the comparison as a table, highlighting the benchmarks that changed
+/// by more than `threshold` percent. Returns whether any benchmark regressed
+/// by more than `threshold`.
+pub fn render_bench_comparison(changes: &[(String, BenchChange)], threshold: f64) -> bool {
+    let max_name_len = changes.iter().map(|(k, _)| k.len()).max().unwrap_or(0);
+    println!(
+        "{:<width$}  {:>10}  {:>10}  change",
+        "name",
+        "baseline",
+        "current",
+        width = max_name_len
+    );
+    let mut regressed = false;
+    for (key, change) in changes {
+        let (baseline, current, verdict) = match *change {
+            BenchChange::Added { current } => (
+                "-".into(),
+                auto_select_unit(current),
+                "added".bright_black().to_string(),
+            ),
+            BenchChange::Removed { baseline } => (
+                auto_select_unit(baseline),
+                "-".into(),
+                "removed".bright_black().to_string(),
+            ),
+            BenchChange::Changed {
+                baseline,
+                current,
+                pct,
+            } => {
+                let text = format!("{pct:+.2}%");
+                let verdict = if pct > threshold {
+                    regressed = true;
+                    format!("{} (regressed)", text.red().bold())
+                } else if pct < -threshold {
+                    format!("{} (improved)", text.green().bold())
+                } else {
+                    text
+                };
+                (
+                    auto_select_unit(baseline),
+                    auto_select_unit(current),
+                    verdict,
+                )
+            }
+        };
+        println!(
+            "{:<width$}  {:>10}  {:>10}  {}",
+            key,
+            baseline,
+            current,
+            verdict,
+            width = max_name_len
+        );
+    }
+    regressed
+}
+
+#[test]
+fn test_compare_bench_reports() {
+    let record = |test: &str, mean: f64| BenchRecord {
+        package: "a/b".into(),
+        filename: "b.mbt".into(),
+        test: test.into(),
+        summary: BenchSummary {
+            mean,
+            ..Default::default()
+        },
+    };
+    let baseline = BenchReport::new("wasm-gc", vec![record("x", 100.), record("old", 1.)]);
+    let current = BenchReport::new("wasm-gc", vec![record("x", 120.), record("new", 1.)]);
+    let changes = compare_bench_reports(&baseline, &current);
+    let keys: Vec<_> = changes.iter().map(|(k, _)| k.as_str()).collect();
+    assert_eq!(
+        keys,
+        ["a/b::b.mbt::new", "a/b::b.mbt::old", "a/b::b.mbt::x"]
+    );
+    assert!(matches!(changes[0].1, BenchChange::Added { .. }));
+    assert!(matches!(changes[1].1, BenchChange::Removed { .. }));
+    assert!(matches!(changes[2].1, BenchChange::Changed { pct, .. } if (pct - 20.).abs() < 1e-9));
+}
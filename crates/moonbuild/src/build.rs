@@ -16,15 +16,28 @@
 //
 // For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
 
-use crate::{MOONRUN_EXECUTABLE, NODE_EXECUTABLE};
+use crate::entry::{
+    catch_callback, message_json_callback, n2_run_with_callback, run_moon_x_build, MoonXBuildState,
+};
+use crate::{moonrun_executable, NODE_EXECUTABLE};
 
 use super::gen;
 use anyhow::Context;
-use moonutil::common::MoonbuildOpt;
+use mooncake::pkg::sync::auto_sync;
+use moonutil::common::{
+    BuildOpt, CompilerFlagsOptions, DiagnosticLevel, FileLock, MoonbuildOpt, PrePostBuild, RunMode,
+    TargetBackend,
+};
+use moonutil::dirs::{mk_arch_mode_dir, recreate_moon_db};
 use moonutil::module::ModuleDB;
+use moonutil::mooncakes::sync::AutoSyncFlags;
+use moonutil::mooncakes::RegistryConfig;
+use moonutil::package::{ArtifactSelector, Package, PostBuildCopy};
 use n2::load::State;
-use std::path::Path;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
 
 use moonutil::common::MooncOpt;
 
@@ -40,12 +53,411 @@ pub fn load_moon_proj(
     gen::gen_build::gen_n2_build_state(&n2_input, target_dir, moonc_opt, moonbuild_opt)
 }
 
+/// Options of [`build_project`].
+#[derive(Debug, Clone)]
+pub struct BuildOptions {
+    /// The directory containing `moon.mod.json`
+    pub project_root: PathBuf,
+    /// The backend to build for; `None` to use the preferred target of the
+    /// module, or the default one
+    pub target_backend: Option<TargetBackend>,
+    /// The target directory; `None` to use `project_root/target`
+    pub target_dir: Option<PathBuf>,
+    /// Max parallel build tasks; `None` to use the default
+    pub jobs: Option<usize>,
+    /// Build in debug mode instead of release mode
+    pub debug: bool,
+    /// Whether to strip the debug info; `None` to strip it in release mode
+    pub strip: Option<bool>,
+}
+
+impl BuildOptions {
+    pub fn new(project_root: impl Into<PathBuf>) -> Self {
+        Self {
+            project_root: project_root.into(),
+            target_backend: None,
+            target_dir: None,
+            jobs: None,
+            debug: false,
+            strip: None,
+        }
+    }
+}
+
+/// The result of [`build_project`].
+#[derive(Debug, Clone)]
+pub struct BuildResult {
+    /// Whether the build finished without errors
+    pub success: bool,
+    pub n_errors: usize,
+    pub n_warnings: usize,
+    /// The diagnostics reported by `moonc`, as the JSON lines it emitted
+    pub diagnostics: Vec<String>,
+    /// The packages of the project, excluding dependencies
+    pub packages: Vec<PackageOutcome>,
+}
+
+/// The outcome of building a single package.
+#[derive(Debug, Clone)]
+pub struct PackageOutcome {
+    /// The full name of the package
+    pub name: String,
+    /// Whether no error was reported in the sources of the package
+    pub success: bool,
+    /// The linked executable of main packages, or the `.core` of others
+    pub artifact: PathBuf,
+}
+
+/// Build the project at `opts.project_root` like `moon build` does, syncing
+/// the dependencies first.
+///
+/// Nothing is printed: the diagnostics are collected into the result instead.
+/// An `Err` means the build couldn't be set up or run, while compile errors
+/// are reported with [`BuildResult::success`] being false.
+pub fn build_project(opts: &BuildOptions) -> anyhow::Result<BuildResult> {
+    let source_dir = dunce::canonicalize(&opts.project_root).with_context(|| {
+        format!(
+            "failed to find the project root `{}`",
+            opts.project_root.display()
+        )
+    })?;
+    let raw_target_dir = opts
+        .target_dir
+        .clone()
+        .unwrap_or_else(|| source_dir.join("target"));
+    std::fs::create_dir_all(&raw_target_dir).with_context(|| {
+        format!(
+            "failed to create target directory `{}`",
+            raw_target_dir.display()
+        )
+    })?;
+
+    let (resolved_env, dir_sync_result) = auto_sync(
+        &source_dir,
+        &AutoSyncFlags {
+            frozen: false,
+            locked: false,
+        },
+        &RegistryConfig::load(),
+        true,
+    )?;
+
+    let run_mode = RunMode::Build;
+    let moonc_opt = compiler_flags(&source_dir, opts)?;
+    let target_dir = mk_arch_mode_dir(&source_dir, &raw_target_dir, &moonc_opt, run_mode)?;
+    let _lock = FileLock::lock(&target_dir)?;
+
+    let moonbuild_opt = MoonbuildOpt {
+        source_dir: source_dir.clone(),
+        raw_target_dir,
+        target_dir,
+        sort_input: false,
+        run_mode,
+        quiet: true,
+        verbose: false,
+        build_graph: false,
+        test_opt: None,
+        check_opt: None,
+        build_opt: Some(BuildOpt {
+            install_path: None,
+            filter_package: None,
+            keep_going: false,
+        }),
+        fmt_opt: None,
+        args: vec![],
+        output_json: false,
+        no_parallelize: false,
+        parallelism: opts.jobs,
+        use_tcc_run: false,
+        dynamic_stub_libs: None,
+        render_no_loc: DiagnosticLevel::default(),
+        no_progress: true,
+        content_hash: false,
+    };
+
+    let scan = || {
+        moonutil::scan::scan(
+            false,
+            None,
+            &resolved_env,
+            &dir_sync_result,
+            &moonc_opt,
+            &moonbuild_opt,
+        )
+    };
+    let mut module = scan()?;
+    crate::build_script::run_prebuild_config(
+        &moonc_opt,
+        &dir_sync_result,
+        &moonbuild_opt,
+        &resolved_env,
+        &mut module,
+    )?;
+    if module.contain_pre_build() {
+        if let MoonXBuildState::WorkDone =
+            run_moon_x_build(&moonbuild_opt, &module, &PrePostBuild::PreBuild)?
+        {
+            // pre-build tasks may generate new source files
+            recreate_moon_db(&module.source_dir, &moonbuild_opt.target_dir)?;
+            module = scan()?;
+        }
+    }
+    moonutil::common::set_native_backend_link_flags(
+        run_mode,
+        moonc_opt.build_opt.target_backend,
+        &mut module,
+    )?;
+
+    let state = load_moon_proj(&module, &moonc_opt, &moonbuild_opt)?;
+    let logger = Arc::new(Mutex::new(Default::default()));
+    let make_callback =
+        || -> Box<dyn Fn(&str) + Send> { Box::new(catch_callback(Arc::clone(&logger))) };
+    let stats = n2_run_with_callback(state, &moonbuild_opt, &logger, make_callback)?;
+    let diagnostics = std::mem::take(&mut logger.lock().unwrap().content_writer);
+
+    let packages = module
+        .get_all_packages()
+        .values()
+        .filter(|pkg| !pkg.is_third_party)
+        .map(|pkg| PackageOutcome {
+            name: pkg.full_name(),
+            success: !stats
+                .error_files
+                .iter()
+                .any(|p| p.starts_with(&pkg.root_path)),
+            artifact: package_artifact(pkg, &moonc_opt).0,
+        })
+        .collect();
+
+    Ok(BuildResult {
+        success: stats.successful(),
+        n_errors: stats.n_errors,
+        n_warnings: stats.n_warnings,
+        diagnostics,
+        packages,
+    })
+}
+
+/// The primary artifact of `pkg`, and whether it's a linked executable. It's
+/// the file to run for linked packages, and the `.core` for the others.
+fn package_artifact(pkg: &Package, moonc_opt: &MooncOpt) -> (PathBuf, bool) {
+    let backend = moonc_opt.link_opt.target_backend;
+    let linked =
+        pkg.is_main || pkg.force_link || pkg.link.as_ref().is_some_and(|l| l.need_link(backend));
+    let artifact = if !linked {
+        pkg.artifact.with_extension("core")
+    } else if matches!(backend, TargetBackend::Native | TargetBackend::LLVM) {
+        pkg.artifact.with_extension(backend.to_extension())
+    } else {
+        pkg.artifact
+            .with_extension(moonc_opt.link_opt.output_format.to_str())
+    };
+    (artifact, linked)
+}
+
+/// An event reported by `moon build --message-format json`, printed as one
+/// line of JSON to stdout.
+#[derive(Debug, Serialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+pub enum BuildMessage {
+    /// A diagnostic reported by `moonc`, as it emitted
+    CompilerMessage { message: serde_json_lenient::Value },
+    /// The artifacts of a package are built
+    BuildArtifact {
+        package: String,
+        /// The backend, as passed to `--target`
+        backend: String,
+        filenames: Vec<PathBuf>,
+        /// The file to run for the backend, `None` if the package isn't linked
+        executable: Option<PathBuf>,
+    },
+    /// The build is finished, always the last event
+    BuildFinished {
+        success: bool,
+        n_errors: usize,
+        n_warnings: usize,
+    },
+}
+
+impl BuildMessage {
+    pub fn print(&self) {
+        println!(
+            "{}",
+            serde_json_lenient::to_string(self).expect("serializing build message")
+        );
+    }
+}
+
+/// Run the build like [`crate::entry::run_build`], but report the
+/// diagnostics, the artifacts and the result as [`BuildMessage`]s.
+pub fn run_build_json(
+    moonc_opt: &MooncOpt,
+    moonbuild_opt: &MoonbuildOpt,
+    module: &ModuleDB,
+) -> anyhow::Result<i32> {
+    let state = load_moon_proj(module, moonc_opt, moonbuild_opt)?;
+    let logger = Arc::new(Mutex::new(Default::default()));
+    let make_callback =
+        || -> Box<dyn Fn(&str) + Send> { Box::new(message_json_callback(Arc::clone(&logger))) };
+    let stats = n2_run_with_callback(state, moonbuild_opt, &logger, make_callback)?;
+
+    if stats.successful() {
+        let filter_package = moonbuild_opt
+            .build_opt
+            .as_ref()
+            .and_then(|it| it.filter_package.as_ref());
+        let pkgs = if let Some(filter_package) = filter_package {
+            &module.get_filtered_packages_and_its_deps_by_pkgname(filter_package)?
+        } else {
+            module.get_all_packages()
+        };
+        let backend = moonc_opt.link_opt.target_backend;
+        for pkg in pkgs.values().filter(|pkg| !pkg.is_third_party) {
+            let (artifact, linked) = package_artifact(pkg, moonc_opt);
+            BuildMessage::BuildArtifact {
+                package: pkg.full_name(),
+                backend: backend.to_flag().to_string(),
+                filenames: vec![artifact.clone()],
+                executable: linked.then_some(artifact),
+            }
+            .print();
+        }
+    }
+    BuildMessage::BuildFinished {
+        success: stats.successful(),
+        n_errors: stats.n_errors,
+        n_warnings: stats.n_warnings,
+    }
+    .print();
+    Ok(stats.return_code_for_success())
+}
+
+/// Copy the executables of the packages in `module` by their post-build rules,
+/// after a successful legacy build. Packages that aren't linked have no
+/// executable, so their rules are skipped.
+pub fn run_post_build(moonc_opt: &MooncOpt, module: &ModuleDB) -> anyhow::Result<()> {
+    for pkg in module.get_all_packages().values() {
+        if pkg.is_third_party || pkg.post_build.is_empty() {
+            continue;
+        }
+        if let (executable, true) = package_artifact(pkg, moonc_opt) {
+            copy_post_build_artifacts(&pkg.root_path, &pkg.post_build, &executable)?;
+        }
+    }
+    Ok(())
+}
+
+/// Apply the post-build `rules` of the package in `pkg_dir`, whose linked
+/// output is `executable`.
+///
+/// The destination directories are created as needed, and destinations that
+/// already have the same content are left untouched.
+pub fn copy_post_build_artifacts(
+    pkg_dir: &Path,
+    rules: &[PostBuildCopy],
+    executable: &Path,
+) -> anyhow::Result<()> {
+    for rule in rules {
+        let artifact = match rule.from {
+            ArtifactSelector::Executable => executable,
+        };
+        let mut dest = pkg_dir.join(&rule.to);
+        if rule.to.ends_with('/') {
+            dest.push(artifact.file_name().context("artifact without file name")?);
+        }
+        let content = std::fs::read(artifact)
+            .with_context(|| format!("failed to read `{}`", artifact.display()))?;
+        if std::fs::read(&dest).is_ok_and(|it| it == content) {
+            continue;
+        }
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create `{}`", parent.display()))?;
+        }
+        // `copy` keeps the permissions, e.g. of native executables
+        std::fs::copy(artifact, &dest).with_context(|| {
+            format!(
+                "failed to copy `{}` to `{}`",
+                artifact.display(),
+                dest.display()
+            )
+        })?;
+    }
+    Ok(())
+}
+
+/// Split the packages of `module` that failed to build into the ones with
+/// errors in their own sources, and the ones skipped because of a failed
+/// (transitive) dependency. `error_files` are the source files with error
+/// diagnostics, see [`N2RunStats::error_files`].
+///
+/// [`N2RunStats::error_files`]: crate::entry::N2RunStats::error_files
+pub fn failed_packages(
+    module: &ModuleDB,
+    error_files: &[PathBuf],
+) -> anyhow::Result<(Vec<String>, Vec<String>)> {
+    let packages = module.get_all_packages();
+    let mut failed = vec![];
+    for file in error_files {
+        // The innermost package containing the file, as sub-directories may
+        // be packages of their own
+        let owner = packages
+            .values()
+            .filter(|pkg| file.starts_with(&pkg.root_path))
+            .max_by_key(|pkg| pkg.root_path.components().count());
+        if let Some(pkg) = owner {
+            failed.push(pkg.full_name());
+        }
+    }
+    failed.sort();
+    failed.dedup();
+
+    let mut skipped = vec![];
+    for name in packages.keys() {
+        if failed.contains(name) {
+            continue;
+        }
+        let deps = module.get_filtered_packages_and_its_deps_by_pkgname(name)?;
+        if deps.keys().any(|dep| failed.contains(dep)) {
+            skipped.push(name.clone());
+        }
+    }
+    skipped.sort();
+    Ok((failed, skipped))
+}
+
+/// Print the summary of a `--keep-going` build that failed.
+pub fn print_failed_packages(failed: &[String], skipped: &[String]) {
+    if !failed.is_empty() {
+        eprintln!("Failed packages:");
+        for name in failed {
+            eprintln!("  {name}");
+        }
+    }
+    if !skipped.is_empty() {
+        eprintln!("Skipped packages due to failed dependencies:");
+        for name in skipped {
+            eprintln!("  {name}");
+        }
+    }
+}
+
+/// The compiler flags of [`build_project`], like `moon build` without any
+/// flag but the ones in `opts`.
+fn compiler_flags(source_dir: &Path, opts: &BuildOptions) -> anyhow::Result<MooncOpt> {
+    MooncOpt::for_module(
+        source_dir,
+        &CompilerFlagsOptions {
+            target_backend: opts.target_backend,
+            debug: opts.debug,
+            strip: opts.strip,
+            ..Default::default()
+        },
+    )
+}
+
 pub fn run_wat(path: &Path, args: &[String], verbose: bool) -> anyhow::Result<()> {
-    let mut cmd = Command::new(
-        MOONRUN_EXECUTABLE
-            .as_deref()
-            .context("Unable to find the `moonrun` executable, please reinstall")?,
-    );
+    let mut cmd = Command::new(moonrun_executable()?);
     cmd.arg(path).args(args);
     run(cmd, verbose)
 }
@@ -96,3 +508,129 @@ fn run(mut subprocess: Command, verbose: bool) -> anyhow::Result<()> {
         anyhow::bail!("failed to run")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{module, package};
+    use moonutil::common::OutputFormat;
+
+    #[test]
+    fn test_run_post_build() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        std::fs::write(dir.join("moon.mod.json"), r#"{ "name": "username/hello" }"#).unwrap();
+        let moonc_opt = compiler_flags(dir, &BuildOptions::new(dir)).unwrap();
+        let rules = |to: &[&str]| -> Vec<PostBuildCopy> {
+            to.iter()
+                .map(|to| PostBuildCopy {
+                    from: ArtifactSelector::Executable,
+                    to: to.to_string(),
+                })
+                .collect()
+        };
+        let mut main = package(dir, "main", &["lib"]);
+        main.is_main = true;
+        main.post_build = rules(&["dist/", "out/app.wasm"]);
+        let mut lib = package(dir, "lib", &[]);
+        lib.post_build = rules(&["dist/"]);
+        let module = module(dir, vec![main, lib]);
+
+        std::fs::create_dir_all(dir.join("target/main")).unwrap();
+        std::fs::create_dir_all(dir.join("target/lib")).unwrap();
+        std::fs::write(dir.join("target/main/main.wasm"), "main").unwrap();
+        std::fs::write(dir.join("target/lib/lib.core"), "lib").unwrap();
+        run_post_build(&moonc_opt, &module).unwrap();
+
+        let copied = dir.join("main/dist/main.wasm");
+        assert_eq!(std::fs::read_to_string(&copied).unwrap(), "main");
+        assert_eq!(
+            std::fs::read_to_string(dir.join("main/out/app.wasm")).unwrap(),
+            "main"
+        );
+        // not linked, so there is nothing to copy
+        assert!(!dir.join("lib/dist").exists());
+
+        // the same content is left untouched
+        let mtime = std::time::SystemTime::UNIX_EPOCH;
+        std::fs::File::options()
+            .write(true)
+            .open(&copied)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+        run_post_build(&moonc_opt, &module).unwrap();
+        let modified = || std::fs::metadata(&copied).unwrap().modified().unwrap();
+        assert_eq!(modified(), mtime);
+
+        std::fs::write(dir.join("target/main/main.wasm"), "changed").unwrap();
+        run_post_build(&moonc_opt, &module).unwrap();
+        assert_eq!(std::fs::read_to_string(&copied).unwrap(), "changed");
+        assert_ne!(modified(), mtime);
+    }
+
+    #[test]
+    fn test_failed_packages() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let module = module(
+            dir,
+            vec![
+                package(dir, "lib", &[]),
+                // a package in a sub-directory of another
+                package(dir, "lib/inner", &[]),
+                package(dir, "mid", &["lib/inner"]),
+                package(dir, "main", &["mid"]),
+                package(dir, "other", &["lib"]),
+            ],
+        );
+
+        let (failed, skipped) = failed_packages(
+            &module,
+            &[dir.join("lib/inner/a.mbt"), dir.join("lib/inner/b.mbt")],
+        )
+        .unwrap();
+        assert_eq!(failed, ["username/hello/lib/inner"]);
+        assert_eq!(skipped, ["username/hello/main", "username/hello/mid"]);
+
+        let (failed, skipped) = failed_packages(&module, &[dir.join("elsewhere.mbt")]).unwrap();
+        assert!(failed.is_empty());
+        assert!(skipped.is_empty());
+    }
+
+    #[test]
+    fn test_build_options_compiler_flags() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("moon.mod.json"),
+            r#"{ "name": "username/hello", "preferred-target": "wasi" }"#,
+        )
+        .unwrap();
+
+        let mut opts = BuildOptions::new(dir.path());
+        let release = compiler_flags(dir.path(), &opts).unwrap();
+        assert_eq!(release.build_opt.target_backend, TargetBackend::Wasi);
+        assert_eq!(release.link_opt.output_format, OutputFormat::Wasm);
+        assert!(!release.build_opt.debug_flag);
+        assert!(release.build_opt.strip_flag);
+
+        opts.target_backend = Some(TargetBackend::Js);
+        opts.debug = true;
+        let debug = compiler_flags(dir.path(), &opts).unwrap();
+        assert_eq!(debug.link_opt.output_format, OutputFormat::Js);
+        assert!(debug.build_opt.debug_flag);
+        assert!(!debug.build_opt.strip_flag);
+        assert!(debug.link_opt.source_map);
+
+        opts.strip = Some(true);
+        assert!(
+            compiler_flags(dir.path(), &opts)
+                .unwrap()
+                .build_opt
+                .strip_flag
+        );
+
+        let missing = tempfile::tempdir().unwrap();
+        assert!(compiler_flags(missing.path(), &BuildOptions::new(missing.path())).is_err());
+    }
+}
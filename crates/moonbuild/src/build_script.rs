@@ -18,11 +18,15 @@
 
 //! Handles build configuration script running. Runs pre-build configuration
 //! scripts and modify the build graph accordingly.
+//!
+//! The output of each script is cached in the target directory together with
+//! a fingerprint of everything the script may depend on, so that the script is
+//! only rerun when the fingerprint changes.
 
 use std::{
     collections::HashMap,
     io::Write,
-    path::Path,
+    path::{Path, PathBuf},
     process::{Command, Stdio},
     str::FromStr,
 };
@@ -30,13 +34,15 @@ use std::{
 use anyhow::{anyhow, Context};
 use log::warn;
 use moonutil::{
-    build_script::{BuildScriptEnvironment, BuildScriptOutput},
-    common::{MoonbuildOpt, MooncOpt},
+    build_script::{BuildScriptEnvironment, BuildScriptOutput, RerunIfKind},
+    common::{get_moon_version, MoonbuildOpt, MooncOpt},
     module::{ModuleDB, MoonMod},
     mooncakes::{result::ResolvedEnv, DirSyncResult, ModuleName},
     path::PathComponent,
 };
 use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 use crate::{NODE_EXECUTABLE, PYTHON_EXECUTABLE};
 
@@ -60,9 +66,12 @@ pub fn run_prebuild_config(
         if let Some(prebuild) = &def.__moonbit_unstable_prebuild {
             // just run `node {prebuild.js}` and read the output
             let dir = dir_sync_result.get(id).expect("module not found");
-            let input = make_prebuild_input_from_module(moonc_opt, build_opt, def, dir, &env_vars);
-
-            let output = run_build_script_for_module(module, dir, input, prebuild)?;
+            let cache_path = cache_path(build_opt, module.name());
+            let output = cached_or_run(&cache_path, moonc_opt, dir, prebuild, &env_vars, || {
+                let input =
+                    make_prebuild_input_from_module(moonc_opt, build_opt, def, dir, &env_vars);
+                run_build_script_for_module(module, dir, input, prebuild)
+            })?;
             pkg_outputs.insert(
                 PathComponent::from_str(&module.name().to_string()).with_context(|| {
                     format!("Name of module `{}` cannot be parsed", &module.name())
@@ -257,6 +266,155 @@ fn run_build_script_for_module(
     Ok(output)
 }
 
+/// The cached output of a build script, stored as
+/// `<target_dir>/build-script/<module>.json`.
+#[derive(Serialize, Deserialize)]
+struct CachedBuildScriptOutput {
+    fingerprint: String,
+    output: BuildScriptOutput,
+}
+
+fn cache_path(build_opt: &MoonbuildOpt, module: &ModuleName) -> PathBuf {
+    build_opt
+        .target_dir
+        .join("build-script")
+        .join(format!("{}.json", module.to_string().replace('/', "-")))
+}
+
+/// Reuse the cached output of the build script `prebuild` of the module in
+/// `dir` if it's up to date, or `run` the script and cache its output.
+fn cached_or_run(
+    cache_path: &Path,
+    moonc_opt: &MooncOpt,
+    dir: &Path,
+    prebuild: &str,
+    env_vars: &HashMap<String, String>,
+    run: impl FnOnce() -> anyhow::Result<BuildScriptOutput>,
+) -> anyhow::Result<BuildScriptOutput> {
+    match load_cached_output(cache_path, moonc_opt, dir, prebuild, env_vars) {
+        Some(output) => Ok(output),
+        None => save_cached_output(cache_path, moonc_opt, dir, prebuild, env_vars, run()?),
+    }
+}
+
+/// Returns the cached output of the build script if its fingerprint still
+/// matches. Any error reading the cache is treated as a miss.
+fn load_cached_output(
+    cache_path: &Path,
+    moonc_opt: &MooncOpt,
+    dir: &Path,
+    prebuild: &str,
+    env_vars: &HashMap<String, String>,
+) -> Option<BuildScriptOutput> {
+    let content = std::fs::read(cache_path).ok()?;
+    let cached: CachedBuildScriptOutput = serde_json::from_slice(&content).ok()?;
+    let fingerprint = fingerprint(moonc_opt, dir, prebuild, env_vars, &cached.output.rerun_if)?;
+    if fingerprint != cached.fingerprint {
+        return None;
+    }
+    log::info!(
+        "Build script `{}` is up to date, reusing its output from `{}`",
+        prebuild,
+        cache_path.display()
+    );
+    Some(cached.output)
+}
+
+fn save_cached_output(
+    cache_path: &Path,
+    moonc_opt: &MooncOpt,
+    dir: &Path,
+    prebuild: &str,
+    env_vars: &HashMap<String, String>,
+    output: BuildScriptOutput,
+) -> anyhow::Result<BuildScriptOutput> {
+    let Some(fingerprint) = fingerprint(moonc_opt, dir, prebuild, env_vars, &output.rerun_if)
+    else {
+        // The script or one of its inputs can't be read, don't cache
+        return Ok(output);
+    };
+    let cached = CachedBuildScriptOutput {
+        fingerprint,
+        output,
+    };
+    if let Some(parent) = cache_path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create `{}`", parent.display()))?;
+    }
+    std::fs::write(cache_path, serde_json::to_vec(&cached)?)
+        .with_context(|| format!("failed to write `{}`", cache_path.display()))?;
+    Ok(cached.output)
+}
+
+/// Compute the fingerprint of a build script run. It covers the moon version,
+/// the target backend, the script source, and the inputs declared in
+/// `rerun_if`. A script that declares no inputs may depend on anything it
+/// receives, so the whole environment is included instead.
+///
+/// Returns `None` if the script itself can't be read.
+fn fingerprint(
+    moonc_opt: &MooncOpt,
+    dir: &Path,
+    prebuild: &str,
+    env_vars: &HashMap<String, String>,
+    rerun_if: &[RerunIfKind],
+) -> Option<String> {
+    let mut hasher = Sha256::new();
+    let mut feed = |s: &[u8]| {
+        // Length-prefixed, so that adjacent fields can't run into each other
+        hasher.update((s.len() as u64).to_le_bytes());
+        hasher.update(s);
+    };
+    feed(get_moon_version().as_bytes());
+    feed(moonc_opt.link_opt.target_backend.to_flag().as_bytes());
+    feed(dir.to_string_lossy().as_bytes());
+    feed(prebuild.as_bytes());
+    feed(&std::fs::read(dir.join(prebuild)).ok()?);
+
+    if rerun_if.is_empty() {
+        let mut env: Vec<_> = env_vars.iter().collect();
+        env.sort();
+        for (k, v) in env {
+            feed(k.as_bytes());
+            feed(v.as_bytes());
+        }
+    }
+    for kind in rerun_if {
+        match kind {
+            RerunIfKind::Env(name) => {
+                feed(b"env");
+                feed(name.as_bytes());
+                // Distinguish an unset variable from an empty one
+                match env_vars.get(name) {
+                    Some(v) => feed(format!("={v}").as_bytes()),
+                    None => feed(b""),
+                }
+            }
+            RerunIfKind::File(path) => {
+                feed(b"file");
+                feed(path.as_bytes());
+                feed(&std::fs::read(dir.join(path)).unwrap_or_default());
+            }
+            RerunIfKind::Dir(path) => {
+                feed(b"dir");
+                feed(path.as_bytes());
+                let mut entries: Vec<_> = walkdir::WalkDir::new(dir.join(path))
+                    .into_iter()
+                    .filter_map(|e| e.ok())
+                    .filter(|e| e.file_type().is_file())
+                    .map(|e| e.into_path())
+                    .collect();
+                entries.sort();
+                for entry in entries {
+                    feed(entry.to_string_lossy().as_bytes());
+                    feed(&std::fs::read(&entry).unwrap_or_default());
+                }
+            }
+        }
+    }
+    Some(format!("{:x}", hasher.finalize()))
+}
+
 fn make_prebuild_input_from_module(
     moonc_opt: &MooncOpt,
     build_opt: &MoonbuildOpt,
@@ -276,3 +434,132 @@ fn make_prebuild_input_from_module(
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use moonutil::common::{CompilerFlagsOptions, TargetBackend};
+    use std::cell::Cell;
+
+    const SCRIPT: &str = "build.js";
+
+    /// A module with a build script in a temporary directory.
+    fn module_dir() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("moon.mod.json"),
+            r#"{ "name": "username/hello" }"#,
+        )
+        .unwrap();
+        std::fs::write(dir.path().join(SCRIPT), "console.log('{}')").unwrap();
+        dir
+    }
+
+    fn moonc_opt(dir: &Path, backend: TargetBackend) -> MooncOpt {
+        let opts = CompilerFlagsOptions {
+            target_backend: Some(backend),
+            ..Default::default()
+        };
+        MooncOpt::for_module(dir, &opts).unwrap()
+    }
+
+    fn output(rerun_if: &str) -> BuildScriptOutput {
+        serde_json::from_str(&format!(r#"{{ "rerun_if": {rerun_if} }}"#)).unwrap()
+    }
+
+    fn env(vars: &[(&str, &str)]) -> HashMap<String, String> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_cache_hit_skips_script() {
+        let dir = module_dir();
+        let dir = dir.path();
+        let moonc_opt = moonc_opt(dir, TargetBackend::WasmGC);
+        let cache_path = dir.join("target/build-script/username-hello.json");
+        let env_vars = env(&[("FOO", "1")]);
+        let runs = Cell::new(0);
+        let run = || {
+            runs.set(runs.get() + 1);
+            Ok(output(r#"[{ "Env": "FOO" }]"#))
+        };
+
+        cached_or_run(&cache_path, &moonc_opt, dir, SCRIPT, &env_vars, run).unwrap();
+        assert_eq!(runs.get(), 1);
+        assert!(cache_path.exists());
+        let cached = cached_or_run(&cache_path, &moonc_opt, dir, SCRIPT, &env_vars, run).unwrap();
+        assert_eq!(runs.get(), 1);
+        assert!(matches!(&cached.rerun_if[..], [RerunIfKind::Env(name)] if name == "FOO"));
+
+        // an input changes
+        let env_vars = env(&[("FOO", "2")]);
+        cached_or_run(&cache_path, &moonc_opt, dir, SCRIPT, &env_vars, run).unwrap();
+        assert_eq!(runs.get(), 2);
+
+        // the script changes
+        std::fs::write(dir.join(SCRIPT), "console.log('{ }')").unwrap();
+        cached_or_run(&cache_path, &moonc_opt, dir, SCRIPT, &env_vars, run).unwrap();
+        assert_eq!(runs.get(), 3);
+        cached_or_run(&cache_path, &moonc_opt, dir, SCRIPT, &env_vars, run).unwrap();
+        assert_eq!(runs.get(), 3);
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let dir = module_dir();
+        let dir = dir.path();
+        let wasm_gc = moonc_opt(dir, TargetBackend::WasmGC);
+        let fp = |moonc_opt: &MooncOpt, env_vars: &HashMap<String, String>, rerun_if: &str| {
+            let rerun_if = output(rerun_if).rerun_if;
+            fingerprint(moonc_opt, dir, SCRIPT, env_vars, &rerun_if).unwrap()
+        };
+        let no_env = env(&[]);
+
+        // the script source
+        let before = fp(&wasm_gc, &no_env, "[]");
+        assert_eq!(fp(&wasm_gc, &no_env, "[]"), before);
+        std::fs::write(dir.join(SCRIPT), "console.log('{ }')").unwrap();
+        assert_ne!(fp(&wasm_gc, &no_env, "[]"), before);
+
+        // the backend
+        let js = moonc_opt(dir, TargetBackend::Js);
+        assert_ne!(fp(&js, &no_env, "[]"), fp(&wasm_gc, &no_env, "[]"));
+
+        // a variable of `rerun_if`, from unset to empty
+        let rerun_if_env = r#"[{ "Env": "FOO" }]"#;
+        let unset = fp(&wasm_gc, &no_env, rerun_if_env);
+        let empty = fp(&wasm_gc, &env(&[("FOO", "")]), rerun_if_env);
+        assert_ne!(unset, empty);
+        assert_ne!(fp(&wasm_gc, &env(&[("FOO", "1")]), rerun_if_env), empty);
+        // other variables don't matter then
+        assert_eq!(fp(&wasm_gc, &env(&[("BAR", "1")]), rerun_if_env), unset);
+
+        // the whole environment without `rerun_if`
+        assert_ne!(
+            fp(&wasm_gc, &env(&[("BAR", "1")]), "[]"),
+            fp(&wasm_gc, &no_env, "[]")
+        );
+
+        // a file of `rerun_if`
+        let rerun_if_file = r#"[{ "File": "input.txt" }]"#;
+        let missing = fp(&wasm_gc, &no_env, rerun_if_file);
+        std::fs::write(dir.join("input.txt"), "a").unwrap();
+        let a = fp(&wasm_gc, &no_env, rerun_if_file);
+        assert_ne!(a, missing);
+        std::fs::write(dir.join("input.txt"), "b").unwrap();
+        assert_ne!(fp(&wasm_gc, &no_env, rerun_if_file), a);
+
+        // a directory of `rerun_if`
+        let rerun_if_dir = r#"[{ "Dir": "data" }]"#;
+        std::fs::create_dir_all(dir.join("data/nested")).unwrap();
+        std::fs::write(dir.join("data/a.txt"), "a").unwrap();
+        let before = fp(&wasm_gc, &no_env, rerun_if_dir);
+        std::fs::write(dir.join("data/nested/b.txt"), "b").unwrap();
+        let added = fp(&wasm_gc, &no_env, rerun_if_dir);
+        assert_ne!(added, before);
+        std::fs::write(dir.join("data/nested/b.txt"), "c").unwrap();
+        assert_ne!(fp(&wasm_gc, &no_env, rerun_if_dir), added);
+    }
+}
@@ -16,6 +16,9 @@
 //
 // For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
 
+use std::path::Path;
+
+use anyhow::Context;
 use n2::load::State;
 
 use moonutil::{
@@ -39,3 +42,27 @@ pub fn load_moon_proj(
     log::debug!("{:#?}", input);
     super::gen::gen_bundle::gen_n2_bundle_state(&input, target_dir, moonc_opt, moonbuild_opt)
 }
+
+/// Copy the bundled artifacts into `output_dir`, creating it if needed and
+/// overwriting any previous bundle in it.
+pub fn copy_to_output_dir<'a>(
+    artifacts: impl IntoIterator<Item = &'a Path>,
+    output_dir: &Path,
+) -> anyhow::Result<()> {
+    std::fs::create_dir_all(output_dir)
+        .with_context(|| format!("failed to create `{}`", output_dir.display()))?;
+    for artifact in artifacts {
+        let Some(file_name) = artifact.file_name() else {
+            continue;
+        };
+        let dest = output_dir.join(file_name);
+        std::fs::copy(artifact, &dest).with_context(|| {
+            format!(
+                "failed to copy `{}` to `{}`",
+                artifact.display(),
+                dest.display()
+            )
+        })?;
+    }
+    Ok(())
+}
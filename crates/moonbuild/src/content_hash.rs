@@ -0,0 +1,219 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Up-to-date checks of source files by their content
+//!
+//! n2 decides whether to rerun a build by the mtimes of its inputs, so a file
+//! touched without being changed, e.g. by switching git branches back and
+//! forth, rebuilds all its dependents. To avoid that, the builds are made to
+//! depend on a stamp file in the target directory for each source file, i.e.
+//! every input no build produces, instead of the source file itself. The
+//! stamp is rewritten only when the content of its source changes, so n2
+//! considers a touched but unchanged source up to date. The source files
+//! themselves are never modified.
+//!
+//! The SHA-256 hash and mtime of each source file are recorded in a manifest
+//! in the target directory, to tell whether the content changed without
+//! hashing the files whose mtime didn't. The manifest is discarded when the
+//! compiler version changes, as the outputs have to be rebuilt in that case
+//! anyway.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// The manifest file in the target directory
+pub const CONTENT_HASH_MANIFEST: &str = "content-hashes.json";
+
+/// The directory of the stamp files in the target directory
+const STAMP_DIR: &str = "content-stamps";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    compiler_version: String,
+    files: BTreeMap<PathBuf, FileRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct FileRecord {
+    /// Nanoseconds since the Unix epoch
+    mtime: u64,
+    len: u64,
+    sha256: String,
+}
+
+/// The mtime and length of the file, which are checked before hashing it.
+fn stat(path: &Path) -> Option<(u64, u64)> {
+    let meta = std::fs::metadata(path).ok()?;
+    let mtime = meta
+        .modified()
+        .ok()?
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .ok()?;
+    Some((u64::try_from(mtime.as_nanos()).ok()?, meta.len()))
+}
+
+fn hash_file(path: &Path) -> Option<String> {
+    let content = std::fs::read(path).ok()?;
+    Some(format!("{:x}", Sha256::digest(content)))
+}
+
+/// The hashes of the source files of a build graph, recorded across builds.
+pub struct ContentHashes {
+    manifest_path: PathBuf,
+    stamp_dir: PathBuf,
+    manifest: Manifest,
+}
+
+impl ContentHashes {
+    /// Load the manifest in `target_dir`, and make the builds of `graph`
+    /// depend on the stamps of their sources. Sources that can't be read, or
+    /// that are in `target_dir`, are depended on directly as before.
+    pub fn apply(graph: &mut n2::graph::Graph, target_dir: &Path) -> Self {
+        let mut this = Self::load(target_dir);
+        let sources = graph
+            .files
+            .all_ids()
+            .filter(|&id| {
+                let file = graph.file(id);
+                file.input.is_none() && !Path::new(&file.name).starts_with(target_dir)
+            })
+            .collect::<Vec<_>>();
+        for id in sources {
+            let Some(stamp) = this.stamp(Path::new(&graph.file(id).name)) else {
+                continue;
+            };
+            let stamp_id = graph
+                .files
+                .id_from_canonical(stamp.to_string_lossy().into_owned());
+            let dependents = std::mem::take(&mut graph.files.by_id[id].dependents);
+            for &build in &dependents {
+                for input in graph.builds[build].ins.ids.iter_mut() {
+                    if *input == id {
+                        *input = stamp_id;
+                    }
+                }
+            }
+            graph.files.by_id[stamp_id].dependents.extend(dependents);
+        }
+        this
+    }
+
+    fn load(target_dir: &Path) -> Self {
+        let manifest_path = target_dir.join(CONTENT_HASH_MANIFEST);
+        let compiler_version = moonutil::common::get_moonc_version().unwrap_or_default();
+        let manifest = std::fs::read_to_string(&manifest_path)
+            .ok()
+            .and_then(|s| serde_json::from_str::<Manifest>(&s).ok())
+            .filter(|m| m.compiler_version == compiler_version)
+            .unwrap_or_else(|| Manifest {
+                compiler_version,
+                files: BTreeMap::new(),
+            });
+        ContentHashes {
+            manifest_path,
+            stamp_dir: target_dir.join(STAMP_DIR),
+            manifest,
+        }
+    }
+
+    /// The stamp of the source file at `path`, rewritten if the content of
+    /// the file changed since it was last recorded. Only a file whose mtime
+    /// or length changed is hashed again.
+    fn stamp(&mut self, path: &Path) -> Option<PathBuf> {
+        let (mtime, len) = stat(path)?;
+        let record = self.manifest.files.get(path);
+        let (changed, sha256) = match record {
+            Some(record) if record.mtime == mtime && record.len == len => {
+                (false, record.sha256.clone())
+            }
+            _ => {
+                let sha256 = hash_file(path)?;
+                (!record.is_some_and(|r| r.sha256 == sha256), sha256)
+            }
+        };
+        let name = format!("{:x}", Sha256::digest(path.as_os_str().as_encoded_bytes()));
+        let stamp = self.stamp_dir.join(name);
+        if changed || !stamp.exists() {
+            if changed {
+                log::debug!("{} is changed", path.display());
+            }
+            std::fs::create_dir_all(&self.stamp_dir).ok()?;
+            // The new mtime of the stamp reruns its dependents
+            std::fs::write(&stamp, &sha256).ok()?;
+        }
+        self.manifest
+            .files
+            .insert(path.to_path_buf(), FileRecord { mtime, len, sha256 });
+        Some(stamp)
+    }
+
+    /// Record the hashes of the sources, after a successful build. The
+    /// hashes aren't recorded after a failed one, so that the sources changed
+    /// since the last successful build are checked again next time.
+    pub fn save(self) -> anyhow::Result<()> {
+        std::fs::write(&self.manifest_path, serde_json::to_string(&self.manifest)?)?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_stamp_of_touched_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("a.mbt");
+    std::fs::write(&file, "fn main {}").unwrap();
+    let target_dir = dir.path().join("target");
+    let stamp_of = |file: &Path| {
+        let mut hashes = ContentHashes::load(&target_dir);
+        let stamp = hashes.stamp(file).unwrap();
+        hashes.save().unwrap();
+        stamp
+    };
+    std::fs::create_dir_all(&target_dir).unwrap();
+    let stamp = stamp_of(&file);
+    assert!(stamp.starts_with(&target_dir));
+    let stamp_mtime = || std::fs::metadata(&stamp).unwrap().modified().unwrap();
+    let original = stamp_mtime();
+
+    // touched: the stamp stays, and so does the new mtime of the source
+    let touched =
+        std::fs::metadata(&file).unwrap().modified().unwrap() + std::time::Duration::from_secs(10);
+    std::fs::File::options()
+        .write(true)
+        .open(&file)
+        .unwrap()
+        .set_modified(touched)
+        .unwrap();
+    assert_eq!(stamp_of(&file), stamp);
+    assert_eq!(stamp_mtime(), original);
+    assert_eq!(
+        std::fs::metadata(&file).unwrap().modified().unwrap(),
+        touched
+    );
+
+    // changed: the stamp is rewritten
+    std::fs::write(&file, "fn main { () }").unwrap();
+    assert_eq!(stamp_of(&file), stamp);
+    assert_eq!(
+        std::fs::read_to_string(&stamp).unwrap(),
+        hash_file(&file).unwrap()
+    );
+}
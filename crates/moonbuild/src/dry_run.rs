@@ -77,6 +77,7 @@ pub fn print_run_commands(
                 TargetBackend::Wasm | TargetBackend::WasmGC => "moonrun ",
                 #[cfg(feature = "moongres")]
                 TargetBackend::MoonGRES => "rustica-engine run ",
+                TargetBackend::Wasi => "wasmtime run ",
                 TargetBackend::Js => "node ",
                 TargetBackend::Native | TargetBackend::LLVM => {
                     // stub.o would be default for native and llvm, skip them
@@ -165,7 +166,7 @@ fn create_file_sorting_cache(graph: &Graph) -> HashMap<FileId, (String, usize)>
 ///
 /// This function provides stable output order based on file names and
 /// the build graph structure, independent of graph insertion order.
-fn stable_toposort_graph(graph: &Graph, inputs: &[FileId]) -> Vec<BuildId> {
+pub(crate) fn stable_toposort_graph(graph: &Graph, inputs: &[FileId]) -> Vec<BuildId> {
     let key_cache = create_file_sorting_cache(graph);
     let by_file_name = |k: &FileId| {
         let (name, last_slash) = &key_cache[k];
@@ -22,6 +22,7 @@ use log::warn;
 use moonutil::module::ModuleDB;
 use moonutil::package::Package;
 use moonutil::path::PathComponent;
+use moonutil::render::MooncDiagnostic;
 use n2::graph::FileId;
 use n2::load::State;
 use n2::progress::{DumbConsoleProgress, FancyConsoleProgress, Progress};
@@ -38,7 +39,9 @@ use anyhow::Context;
 use colored::Colorize;
 
 use crate::benchmark::{render_batch_bench_summary, BATCHBENCH};
+use crate::build::BuildMessage;
 use crate::check::normal::write_pkg_lst;
+use crate::content_hash::ContentHashes;
 use crate::expect::{apply_snapshot, render_snapshot_fail};
 use crate::runtest::TestStatistics;
 use crate::test_utils::indices_to_ranges;
@@ -51,17 +54,15 @@ use moonutil::common::{
 
 use std::sync::{Arc, Mutex};
 
-fn default_parallelism() -> anyhow::Result<usize> {
-    let par = std::thread::available_parallelism()?;
-    Ok(usize::from(par))
-}
-
 #[allow(clippy::type_complexity)]
 pub fn create_progress_console(
     callback: Option<Box<dyn Fn(&str) + Send>>,
     verbose: bool,
+    no_progress: bool,
 ) -> Box<dyn Progress> {
-    if terminal::use_fancy() {
+    // The fancy console keeps the progress below the diagnostics printed by
+    // `callback`, and is only used on terminals
+    if !no_progress && terminal::use_fancy() {
         Box::new(FancyConsoleProgress::new(verbose, callback))
     } else {
         Box::new(DumbConsoleProgress::new(verbose, callback))
@@ -129,6 +130,7 @@ impl ResultCatcher {
 pub fn render_and_catch_callback(
     catcher: Arc<Mutex<ResultCatcher>>,
     output_json: bool,
+    diagnostics_json: bool,
     use_fancy: bool,
     check_patch_file: Option<PathBuf>,
     explain: bool,
@@ -141,7 +143,9 @@ pub fn render_and_catch_callback(
             .split('\n')
             .filter(|it| !it.is_empty())
             .for_each(|content| {
-                let report_kind = if output_json {
+                let report_kind = if diagnostics_json {
+                    moonutil::render::MooncDiagnostic::print_lsp_json(content)
+                } else if output_json {
                     println!("{content}");
                     None
                 } else {
@@ -160,6 +164,40 @@ pub fn render_and_catch_callback(
     }
 }
 
+/// Like [`render_and_catch_callback`], but only catches the output without
+/// printing anything.
+pub fn catch_callback(catcher: Arc<Mutex<ResultCatcher>>) -> impl Fn(&str) {
+    move |output: &str| {
+        output
+            .split('\n')
+            .filter(|it| !it.is_empty())
+            .for_each(|content| {
+                let report_kind =
+                    serde_json_lenient::from_str::<moonutil::render::MooncDiagnostic>(content)
+                        .ok()
+                        .map(|it| it.get_level_and_color().0);
+                catcher.lock().unwrap().append_content(content, report_kind);
+            });
+    }
+}
+
+/// Like [`catch_callback`], but also prints each diagnostic as a
+/// [`BuildMessage::CompilerMessage`] to stdout. Output that isn't a
+/// diagnostic goes to stderr as-is.
+pub fn message_json_callback(catcher: Arc<Mutex<ResultCatcher>>) -> impl Fn(&str) {
+    let catch = catch_callback(catcher);
+    move |output: &str| {
+        catch(output);
+        output
+            .split('\n')
+            .filter(|it| !it.is_empty())
+            .for_each(|content| match serde_json_lenient::from_str(content) {
+                Ok(message) => BuildMessage::CompilerMessage { message }.print(),
+                Err(_) => eprintln!("{content}"),
+            });
+    }
+}
+
 pub fn n2_simple_run_interface(
     state: n2::load::State,
     moonbuild_opt: &MoonbuildOpt,
@@ -176,6 +214,10 @@ pub fn n2_simple_run_interface(
         .check_opt
         .as_ref()
         .is_some_and(|it| it.explain);
+    let diagnostics_json = moonbuild_opt
+        .check_opt
+        .as_ref()
+        .is_some_and(|it| it.diagnostics_json);
 
     let (target_dir, source_dir) = (
         moonbuild_opt.target_dir.clone(),
@@ -185,6 +227,7 @@ pub fn n2_simple_run_interface(
     let render_and_catch = render_and_catch_callback(
         Arc::clone(&logger),
         output_json,
+        diagnostics_json,
         use_fancy,
         check_patch_file,
         explain,
@@ -195,8 +238,11 @@ pub fn n2_simple_run_interface(
 
     // TODO: generate build graph for pre_build?
 
-    let mut progress =
-        create_progress_console(Some(Box::new(render_and_catch)), moonbuild_opt.verbose);
+    let mut progress = create_progress_console(
+        Some(Box::new(render_and_catch)),
+        moonbuild_opt.verbose,
+        moonbuild_opt.no_progress,
+    );
     let options = work::Options {
         parallelism: get_parallelism(moonbuild_opt)?,
         failures_left: Some(10),
@@ -231,8 +277,8 @@ pub fn get_parallelism(opt: &MoonbuildOpt) -> anyhow::Result<usize> {
             .context("Failed to parse MOON_MAX_PAR_TASKS to get the parallelism for building")
     } else if let Some(par) = opt.parallelism {
         Ok(par)
-    } else if let Ok(val) = default_parallelism() {
-        Ok(val)
+    } else if let Ok(val) = moonutil::parallelism::default_parallelism() {
+        Ok(val.get())
     } else {
         warn!("Failed to get the parallelism for building, falling back to 1 parallel task");
         Ok(1)
@@ -246,6 +292,10 @@ pub struct N2RunStats {
 
     pub n_errors: usize,
     pub n_warnings: usize,
+    /// The source files with error diagnostics
+    pub error_files: Vec<PathBuf>,
+    /// The output lines of the build tasks, including the diagnostics
+    pub outputs: Vec<String>,
 }
 
 impl N2RunStats {
@@ -285,34 +335,65 @@ pub fn n2_run_interface(
         .check_opt
         .as_ref()
         .is_some_and(|it| it.explain);
+    let diagnostics_json = moonbuild_opt
+        .check_opt
+        .as_ref()
+        .is_some_and(|it| it.diagnostics_json);
 
     let target_dir = moonbuild_opt.target_dir.clone();
     let source_dir = moonbuild_opt.source_dir.clone();
     let render_no_loc = moonbuild_opt.render_no_loc;
-    let render_and_catch = render_and_catch_callback(
-        Arc::clone(&logger),
-        output_json,
-        use_fancy,
-        check_patch_file,
-        explain,
-        render_no_loc,
-        source_dir.clone(),
-        target_dir.clone(),
-    );
+    let make_callback = || -> Box<dyn Fn(&str) + Send> {
+        Box::new(render_and_catch_callback(
+            Arc::clone(&logger),
+            output_json,
+            diagnostics_json,
+            use_fancy,
+            check_patch_file.clone(),
+            explain,
+            render_no_loc,
+            source_dir.clone(),
+            target_dir.clone(),
+        ))
+    };
+    n2_run_with_callback(state, moonbuild_opt, &logger, make_callback)
+}
 
+/// Run the build in `state`, passing the output of every build task to the
+/// callback made by `make_callback`, which should catch it into `logger`.
+///
+/// If there's no work to do, the output of the last run is replayed to a new
+/// callback instead.
+#[allow(clippy::type_complexity)]
+pub fn n2_run_with_callback(
+    mut state: n2::load::State,
+    moonbuild_opt: &MoonbuildOpt,
+    logger: &Arc<Mutex<ResultCatcher>>,
+    make_callback: impl Fn() -> Box<dyn Fn(&str) + Send>,
+) -> anyhow::Result<N2RunStats> {
     if moonbuild_opt.build_graph {
         vis_build_graph(&state, moonbuild_opt);
     }
 
-    let mut progress =
-        create_progress_console(Some(Box::new(render_and_catch)), moonbuild_opt.verbose);
+    let mut progress = create_progress_console(
+        Some(make_callback()),
+        moonbuild_opt.verbose,
+        moonbuild_opt.no_progress,
+    );
+    let keep_going = moonbuild_opt
+        .build_opt
+        .as_ref()
+        .is_some_and(|it| it.keep_going);
     let options = work::Options {
         parallelism: get_parallelism(moonbuild_opt)?,
-        failures_left: Some(10),
+        failures_left: if keep_going { None } else { Some(10) },
         explain: false,
         adopt: false,
         dirty_on_output: true,
     };
+    let content_hashes = moonbuild_opt
+        .content_hash
+        .then(|| ContentHashes::apply(&mut state.graph, &moonbuild_opt.target_dir));
     let mut work = work::Work::new(
         state.graph,
         state.hashes,
@@ -331,6 +412,11 @@ pub fn n2_run_interface(
     }
 
     let res = trace::scope("work.run", || work.run())?;
+    if let Some(content_hashes) = content_hashes.filter(|_| res.is_some()) {
+        if let Err(e) = content_hashes.save() {
+            warn!("failed to save the content hashes: {e:?}");
+        }
+    }
 
     let output_path = moonbuild_opt
         .target_dir
@@ -342,22 +428,7 @@ pub fn n2_run_interface(
         let raw_json = std::fs::read_to_string(&output_path)
             .context(format!("failed to open `{}`", output_path.display()))?;
 
-        let check_patch_file = moonbuild_opt
-            .check_opt
-            .as_ref()
-            .and_then(|it| it.patch_file.clone());
-
-        let callback = render_and_catch_callback(
-            Arc::clone(&logger),
-            output_json,
-            use_fancy,
-            check_patch_file,
-            explain,
-            render_no_loc,
-            source_dir,
-            target_dir,
-        );
-
+        let callback = make_callback();
         raw_json.lines().for_each(callback);
     } else {
         let mut output_file = std::fs::File::create(output_path)?;
@@ -373,9 +444,25 @@ pub fn n2_run_interface(
         n_tasks_executed: res,
         n_errors: logger.n_errors,
         n_warnings: logger.n_warnings,
+        error_files: error_files(&logger.content_writer),
+        outputs: logger.content_writer.clone(),
     })
 }
 
+/// The source files that the error diagnostics in `outputs`, the output
+/// lines of build tasks, are located in.
+pub fn error_files(outputs: &[String]) -> Vec<PathBuf> {
+    let mut files = outputs
+        .iter()
+        .filter_map(|it| serde_json_lenient::from_str::<MooncDiagnostic>(it).ok())
+        .filter(|it| it.level == "error")
+        .map(|it| PathBuf::from(it.location.path))
+        .collect::<Vec<_>>();
+    files.sort();
+    files.dedup();
+    files
+}
+
 fn vis_build_graph(state: &State, moonbuild_opt: &MoonbuildOpt) {
     let path = moonbuild_opt.target_dir.join("build_graph.dot");
     let source_dir = moonbuild_opt.source_dir.display().to_string();
@@ -450,8 +537,20 @@ pub fn run_moon_x_build(
 
     let x_build_state = crate::pre_build::load_moon_x_build(moonbuild_opt, module, build_type)?;
     if let Some(x_build_state) = x_build_state {
+        let outputs: Vec<PathBuf> = x_build_state
+            .default
+            .iter()
+            .map(|&id| PathBuf::from(&x_build_state.graph.file(id).name))
+            .collect();
         let pre_build_result = n2_simple_run_interface(x_build_state, moonbuild_opt)?;
         render_x_build_result(pre_build_result, moonbuild_opt.quiet, build_type)?;
+        if let Some(missing) = outputs.iter().find(|o| !o.exists()) {
+            anyhow::bail!(
+                "{} task(s) succeeded, but didn't produce the declared output `{}`",
+                build_type.name(),
+                missing.display()
+            );
+        }
         Ok(MoonXBuildState::WorkDone)
     } else {
         Ok(MoonXBuildState::NoWork)
@@ -494,6 +593,13 @@ pub fn run_check(
     let result = n2_run_interface(state, moonbuild_opt)?;
 
     write_pkg_lst(module, &moonbuild_opt.raw_target_dir)?;
+    if let Some(path) = moonbuild_opt
+        .check_opt
+        .as_ref()
+        .and_then(|it| it.sarif.as_ref())
+    {
+        moonutil::sarif::write_sarif(path, &result.outputs, &moonbuild_opt.source_dir)?;
+    }
     render_result(&result, moonbuild_opt.quiet, "checking")
 }
 
@@ -506,6 +612,14 @@ pub fn run_build(
         crate::build::load_moon_proj(module, moonc_opt, moonbuild_opt)
     })?;
     let result = n2_run_interface(state, moonbuild_opt)?;
+    let keep_going = moonbuild_opt
+        .build_opt
+        .as_ref()
+        .is_some_and(|it| it.keep_going);
+    if keep_going && !result.successful() {
+        let (failed, skipped) = crate::build::failed_packages(module, &result.error_files)?;
+        crate::build::print_failed_packages(&failed, &skipped);
+    }
     render_result(&result, moonbuild_opt.quiet, "building")
 }
 
@@ -578,6 +692,7 @@ pub fn run_run(
         TargetBackend::MoonGRES => {
             crate::build::run_moongres(&wat_path, &moonbuild_opt.args, moonbuild_opt.verbose)
         }
+        TargetBackend::Wasi => anyhow::bail!("the `wasi` target can't run with the legacy build"),
         TargetBackend::Js => {
             crate::build::run_js(&wat_path, &moonbuild_opt.args, moonbuild_opt.verbose)
         }
@@ -728,6 +843,12 @@ pub fn run_test(
     let filter_doc_index = test_opt.as_ref().and_then(|it| it.filter_doc_index);
 
     let printed = Arc::new(AtomicBool::new(false));
+    // Test executables run concurrently, and the updates of their expect tests
+    // may rewrite the same file, e.g. for the inline tests and the doc tests
+    // of a source file. The updates take turns, each rebuilding and rerunning
+    // its test, so that none patches a file with locations from before another
+    // update rewrote it.
+    let update_lock = Arc::new(tokio::sync::Mutex::new(()));
     let mut test_artifacts = TestArtifacts {
         artifacts_path: vec![],
     };
@@ -775,6 +896,9 @@ pub fn run_test(
             let mut test_args = TestArgs {
                 package: pkgname.clone(),
                 file_and_index: vec![],
+                seed: None,
+                bench: None,
+                timeout_ms: None,
             };
             for (file_name, test_metadata) in &file_test_info_map {
                 let filter_index = filter_index.or(filter_doc_index);
@@ -820,6 +944,7 @@ pub fn run_test(
             }
 
             let printed = Arc::clone(&printed);
+            let update_lock = Arc::clone(&update_lock);
             let moonc_opt = Arc::clone(&moonc_opt);
             let moonbuild_opt = Arc::clone(&moonbuild_opt);
             let module = Arc::clone(&module);
@@ -848,6 +973,7 @@ pub fn run_test(
                             &artifact_path,
                             &moonbuild_opt.target_dir,
                             printed,
+                            &update_lock,
                             &file_test_info_map,
                         )
                         .await?;
@@ -918,6 +1044,43 @@ pub fn run_test(
 pub struct TestArgs {
     pub package: String,
     pub file_and_index: Vec<(String, Vec<std::ops::Range<u32>>)>,
+    /// Seed for tests that randomize, so that a run can be reproduced. Every
+    /// backend of a run receives the same seed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// Parameters of the bench harness, only when running benchmarks
+    #[serde(flatten)]
+    pub bench: Option<BenchArgs>,
+    /// The timeout declared in source of the single test to run, for test
+    /// harnesses enforcing it themselves. The test process is killed a bit
+    /// after it regardless.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+}
+
+/// How the bench harness measures each benchmark.
+#[derive(serde::Serialize, Clone, Copy, Debug)]
+pub struct BenchArgs {
+    /// The number of iterations to run before measuring
+    pub warmup_iters: u32,
+    /// The minimum time to spend measuring, in milliseconds
+    pub min_measure_time_ms: u64,
+}
+
+impl BenchArgs {
+    /// The defaults of each backend. JIT-compiled backends need more warmup to
+    /// produce stable timings.
+    pub fn default_for(backend: TargetBackend) -> Self {
+        let (warmup_iters, min_measure_time_ms) = match backend {
+            TargetBackend::Js => (20, 1000),
+            TargetBackend::Wasm | TargetBackend::WasmGC => (10, 500),
+            _ => (2, 300),
+        };
+        BenchArgs {
+            warmup_iters,
+            min_measure_time_ms,
+        }
+    }
 }
 
 impl TestArgs {
@@ -946,7 +1109,9 @@ impl TestArgs {
         serde_json::to_string(&test_params).unwrap_or_else(|_| "[]".to_string())
     }
 
-    pub fn to_cli_args_for_native(&self) -> String {
+    /// The arguments of a native or WASI test executable: the tests to run,
+    /// followed by the optional parameters as `key=value`.
+    pub fn to_cli_args_for_native(&self) -> Vec<String> {
         let mut args = vec![];
         let file_and_index = &self.file_and_index;
         for (file, ranges) in file_and_index {
@@ -954,7 +1119,13 @@ impl TestArgs {
                 args.push(format!("{}:{}-{}", file, range.start, range.end));
             }
         }
-        args.join("/")
+        let mut cli_args = vec![args.join("/")];
+        cli_args.extend(self.seed.map(|seed| format!("seed={seed}")));
+        if let Some(bench) = self.bench {
+            cli_args.push(format!("warmup_iters={}", bench.warmup_iters));
+            cli_args.push(format!("min_measure_time_ms={}", bench.min_measure_time_ms));
+        }
+        cli_args
     }
 }
 
@@ -983,6 +1154,7 @@ async fn execute_test(
             )
             .await
         }
+        TargetBackend::Wasi => anyhow::bail!("the `wasi` target can't run with the legacy build"),
         TargetBackend::Js => {
             crate::runtest::run_js(
                 &artifact_path.with_extension("cjs"),
@@ -1089,6 +1261,12 @@ impl<'a> CompactTestFormatter<'a> {
         write!(w, " {}", "ok".green().bold())
     }
 
+    pub fn write_flaky<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        self.write_common_prefix(false, w)?;
+        write!(w, " {}", "ok".green().bold())?;
+        write!(w, " ({})", "flaky".yellow().bold())
+    }
+
     pub fn write_failure<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
         self.write_common_prefix(false, w)?;
         write!(w, " {}", "failed".red().bold())
@@ -1126,6 +1304,7 @@ async fn handle_test_result(
     artifact_path: &Path,
     target_dir: &Path,
     printed: Arc<AtomicBool>,
+    update_lock: &tokio::sync::Mutex<()>,
     file_test_info_map: &FileTestInfo,
 ) -> anyhow::Result<()> {
     let output_failure_in_json = moonbuild_opt
@@ -1182,6 +1361,9 @@ async fn handle_test_result(
                     let test_args = TestArgs {
                         package: stat.package.clone(),
                         file_and_index: vec![(stat.filename.clone(), vec![index..(index + 1)])],
+                        seed: None,
+                        bench: None,
+                        timeout_ms: None,
                     };
                     let rerun = execute_test(
                         moonbuild_opt,
@@ -1267,6 +1449,7 @@ async fn handle_test_result(
                         );
                         printed.store(true, std::sync::atomic::Ordering::SeqCst);
                     }
+                    let _update = update_lock.lock().await;
 
                     // here need to rerun the test to get the new error message
                     // since the previous apply expect may add or delete some line, which make the error message out of date
@@ -1276,6 +1459,9 @@ async fn handle_test_result(
                     let test_args = TestArgs {
                         package: origin_err.package.clone(),
                         file_and_index: vec![(filename, vec![index..(index + 1)])],
+                        seed: None,
+                        bench: None,
+                        timeout_ms: None,
                     };
                     let rerun = execute_test(
                         moonbuild_opt,
@@ -1380,15 +1566,24 @@ async fn handle_test_result(
     Ok(())
 }
 
+/// Bundle the module. If `output_dir` is given, the bundled `.core` file is
+/// also copied there.
 pub fn run_bundle(
     module: &ModuleDB,
     moonbuild_opt: &MoonbuildOpt,
     moonc_opt: &MooncOpt,
+    output_dir: Option<&Path>,
 ) -> anyhow::Result<i32> {
     let state = crate::bundle::load_moon_proj(module, moonc_opt, moonbuild_opt)?;
     let result = n2_run_interface(state, moonbuild_opt)?;
     write_pkg_lst(module, &moonbuild_opt.raw_target_dir)?;
     render_result(&result, moonbuild_opt.quiet, "bundle")?;
+    if let (Some(output_dir), true) = (output_dir, result.successful()) {
+        // Same name as in `gen_bundle`
+        let name = module.name.split('/').next_back().unwrap_or("bundle");
+        let core = moonbuild_opt.target_dir.join(name).with_extension("core");
+        crate::bundle::copy_to_output_dir([core.as_path()], output_dir)?;
+    }
     Ok(0)
 }
 
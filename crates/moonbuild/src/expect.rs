@@ -22,7 +22,7 @@ use colored::Colorize;
 use moonutil::common::line_col_to_byte_idx;
 use std::collections::BTreeSet;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 #[derive(Debug, Default)]
@@ -128,14 +128,27 @@ impl std::str::FromStr for ExpectFailedRaw {
     }
 }
 
+/// The directory of the snapshot files, next to the source files of tests.
+pub const SNAPSHOT_DIR: &str = "__snapshot__";
+
+/// The file that the snapshot test in the source file `filename` stores its
+/// snapshot in, where `name` is the file name given to the snapshot.
+pub fn snapshot_file_path(filename: &str, name: &Path) -> anyhow::Result<PathBuf> {
+    let source = dunce::canonicalize(filename)
+        .with_context(|| format!("failed to find the source file {filename}"))?;
+    let dir = source
+        .parent()
+        .with_context(|| format!("no parent directory of {}", source.display()))?;
+    Ok(dir.join(SNAPSHOT_DIR).join(name))
+}
+
 pub fn expect_failed_to_snapshot_result(efr: ExpectFailedRaw) -> SnapshotResult {
     let filename = parse_filename(&efr.loc).unwrap();
-    let expect_file = dunce::canonicalize(PathBuf::from(&filename))
-        .unwrap()
-        .parent()
-        .unwrap()
-        .join("__snapshot__")
-        .join(efr.expect.as_deref().unwrap_or_default());
+    let expect_file = snapshot_file_path(
+        &filename,
+        Path::new(efr.expect.as_deref().unwrap_or_default()),
+    )
+    .unwrap();
 
     let file_content = if expect_file.exists() {
         Some(std::fs::read_to_string(&expect_file).unwrap())
@@ -156,6 +169,14 @@ pub fn expect_failed_to_snapshot_result(efr: ExpectFailedRaw) -> SnapshotResult
     }
 }
 
+#[test]
+fn test_snapshot_file_path() {
+    let source = concat!(env!("CARGO_MANIFEST_DIR"), "/src/expect.rs");
+    let path = snapshot_file_path(source, Path::new("001.txt")).unwrap();
+    assert!(path.ends_with("src/__snapshot__/001.txt"));
+    assert!(snapshot_file_path("no_such_file.mbt", Path::new("001.txt")).is_err());
+}
+
 #[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
 pub struct SnapshotResult {
     pub loc: String,
@@ -664,7 +685,12 @@ fn push_multi_line_string(
         output.push_str(&content);
     } else {
         let lines: Vec<&str> = content.split('\n').collect();
-        if !output.ends_with("\n") {
+        // The patch is applied after `prev_char`, if nothing is output yet
+        let at_line_start = match output.chars().last() {
+            Some(c) => c == '\n',
+            None => prev_char == Some(&'\n'),
+        };
+        if !at_line_start {
             output.push('\n');
         }
         for (i, line) in lines.iter().enumerate() {
@@ -733,7 +759,12 @@ fn push_multi_line_string_internal(
     output
 }
 
+/// Apply the patches of each file from the last one to the first, so the
+/// offsets of the ones left stay valid. Patches overlapping an applied one,
+/// e.g. of an expect in a helper reached by several tests with different
+/// actual values, are left out and reported after the others are applied.
 fn apply_patch(pp: &PackagePatch) -> anyhow::Result<()> {
+    let mut conflicts = vec![];
     for (filename, patches) in pp.patches.iter() {
         let content = std::fs::read_to_string(filename)?;
         // TODO: share content_chars with gen_patch
@@ -750,86 +781,89 @@ fn apply_patch(pp: &PackagePatch) -> anyhow::Result<()> {
 
         let lines: Vec<&str> = content.split('\n').collect();
         let line_index = line_index::LineIndex::new(&content);
-        let mut output = String::new();
-        let mut i = 0u32;
-        let mut k = 0usize;
-        while k < patches.len() {
-            let is_doc_test = patches[k].is_doc_test;
-            let patch = &patches[k];
+
+        // Stable, so the first of the patches at the same offset wins
+        let mut patches = patches.iter().collect::<Vec<_>>();
+        patches.sort_by_key(|patch| std::cmp::Reverse(patch.range.start()));
+
+        let mut output_chars = content_chars.clone();
+        let mut applied: Option<line_index::TextRange> = None;
+        for patch in patches {
             let start = patch.range.start();
             let end = patch.range.end();
-            #[allow(clippy::comparison_chain)]
-            if i < u32::from(start) {
-                for item in content_chars
-                    .iter()
-                    .take(usize::from(start))
-                    .skip(i as usize)
-                {
-                    output.push(*item);
+            if let Some(applied) = applied {
+                if end > applied.start() || start == applied.start() {
+                    conflicts.push(format!(
+                        "{}:{}:{}",
+                        filename,
+                        patch.line_start + 1,
+                        patch.col_start + 1
+                    ));
+                    continue;
                 }
-                i = u32::from(start);
-            } else if i == u32::from(start) {
-                // infer indent
-                let utf8_start = char_offset_to_byte_offset[&usize::from(start)];
-                let start_point = line_index.line_col(text_size::TextSize::new(utf8_start as u32));
-                let line = lines[start_point.line as usize];
-                let spaces = line.find(|c| c != ' ').unwrap_or(0);
-
-                if let Some(padding) = &patch.left_padding {
-                    output.push_str(padding);
-                    if patch.kind == TargetKind::Call && patch.actual.contains('\n') {
-                        output.push('\n');
-                        if !is_doc_test {
-                            output.push_str(" ".repeat(spaces + 2).as_str());
-                        }
+            }
+            let is_doc_test = patch.is_doc_test;
+
+            // infer indent
+            let utf8_start = char_offset_to_byte_offset[&usize::from(start)];
+            let start_point = line_index.line_col(text_size::TextSize::new(utf8_start as u32));
+            let line = lines[start_point.line as usize];
+            let spaces = line.find(|c| c != ' ').unwrap_or(0);
+
+            let mut output = String::new();
+            if let Some(padding) = &patch.left_padding {
+                output.push_str(padding);
+                if patch.kind == TargetKind::Call && patch.actual.contains('\n') {
+                    output.push('\n');
+                    if !is_doc_test {
+                        output.push_str(" ".repeat(spaces + 2).as_str());
                     }
                 }
+            }
 
-                match patch.mode.as_deref() {
-                    None => {
-                        let escape_info = detect_escape_info(&patch.actual);
-                        if (!escape_info.newline && !escape_info.quote) || escape_info.ascii_control
-                        {
-                            output.push_str(&to_moonbit_style(&patch.actual, true));
-                        } else {
-                            let next_char = content_chars[usize::from(end)..].first();
-                            let prev_char = content_chars[..usize::from(start)].last();
-                            push_multi_line_string(
-                                &mut output,
-                                spaces + 2,
-                                &patch.actual,
-                                prev_char,
-                                next_char,
-                                is_doc_test,
-                            );
-                        }
-                    }
-                    Some("json") => {
-                        output.push_str(&patch.actual.to_string());
-                    }
-                    Some(mode) => {
-                        anyhow::bail!("unsupported mode: {:?} in expect testing", mode);
+            match patch.mode.as_deref() {
+                None => {
+                    let escape_info = detect_escape_info(&patch.actual);
+                    if (!escape_info.newline && !escape_info.quote) || escape_info.ascii_control {
+                        output.push_str(&to_moonbit_style(&patch.actual, true));
+                    } else {
+                        let next_char = content_chars[usize::from(end)..].first();
+                        let prev_char = content_chars[..usize::from(start)].last();
+                        push_multi_line_string(
+                            &mut output,
+                            spaces + 2,
+                            &patch.actual,
+                            prev_char,
+                            next_char,
+                            is_doc_test,
+                        );
                     }
                 }
-
-                if let Some(padding) = &patch.right_padding {
-                    output.push_str(padding);
+                Some("json") => {
+                    output.push_str(&patch.actual.to_string());
+                }
+                Some(mode) => {
+                    anyhow::bail!("unsupported mode: {:?} in expect testing", mode);
                 }
-
-                i = u32::from(end);
-                k += 1;
-            } else {
-                anyhow::bail!("unreachable state in expect test, please report this bug");
             }
-        }
-        if i < content_chars.len() as u32 {
-            for item in content_chars.into_iter().skip(i as usize) {
-                output.push(item);
+
+            if let Some(padding) = &patch.right_padding {
+                output.push_str(padding);
             }
+
+            output_chars.splice(usize::from(start)..usize::from(end), output.chars());
+            applied = Some(patch.range);
         }
-        std::fs::write(filename, output)?;
+        std::fs::write(filename, output_chars.into_iter().collect::<String>())?;
     }
 
+    if !conflicts.is_empty() {
+        conflicts.sort();
+        anyhow::bail!(
+            "conflicting results of the expect tests at {}, rerun the tests to update them",
+            conflicts.join(", ")
+        );
+    }
     Ok(())
 }
 
@@ -850,12 +884,7 @@ pub fn apply_snapshot<'a>(messages: impl IntoIterator<Item = &'a str>) -> anyhow
         let filename = parse_filename(&snapshot.loc)?;
         let actual = snapshot.actual.clone();
         let expect_file = &snapshot.expect_file;
-        let expect_file = dunce::canonicalize(PathBuf::from(&filename))
-            .unwrap()
-            .parent()
-            .unwrap()
-            .join("__snapshot__")
-            .join(expect_file);
+        let expect_file = snapshot_file_path(&filename, expect_file)?;
 
         if !expect_file.parent().unwrap().exists() {
             std::fs::create_dir_all(expect_file.parent().unwrap())?;
@@ -873,6 +902,19 @@ pub fn apply_snapshot<'a>(messages: impl IntoIterator<Item = &'a str>) -> anyhow
     Ok(())
 }
 
+/// The source file that updating the expect or snapshot test failure `msg`
+/// would rewrite, or `None` if it's not such a failure.
+pub fn update_target_file(msg: &str) -> Option<String> {
+    if let Some(json_str) = msg.strip_prefix(EXPECT_FAILED) {
+        Some(parse_expect_failed_message(json_str).ok()?.filename)
+    } else if let Some(json_str) = msg.strip_prefix(SNAPSHOT_TESTING) {
+        let rep = ExpectFailedRaw::from_str(json_str).ok()?;
+        parse_filename(&rep.loc).ok()
+    } else {
+        None
+    }
+}
+
 pub fn apply_expect<'a>(messages: impl IntoIterator<Item = &'a str>) -> anyhow::Result<()> {
     // dbg!(&messages);
     let targets = collect(messages)?;
@@ -883,6 +925,36 @@ pub fn apply_expect<'a>(messages: impl IntoIterator<Item = &'a str>) -> anyhow::
     Ok(())
 }
 
+#[test]
+fn test_apply_expects_in_one_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("hello.mbt");
+    std::fs::write(
+        &file,
+        "test {\n  inspect(1, content=\"x\")\n  inspect(2, content=\"y\")\n}\n",
+    )
+    .unwrap();
+    let file = file.to_str().unwrap().replace('\\', "/");
+    let message = |line: u32, expect: &str, actual: &str| {
+        format!(
+            r#"{EXPECT_FAILED}{{"loc": "{file}:{line}:3-{line}:26", "args_loc": "[\"{file}:{line}:11-{line}:12\", \"{file}:{line}:22-{line}:25\", null, null]", "expect": "{expect}", "actual": "{actual}"}}"#
+        )
+    };
+    // The second result of the first expect conflicts with the first result,
+    // so it's reported and left to the next update
+    let messages = [
+        message(3, "y", "2"),
+        message(2, "x", "1"),
+        message(2, "x", "3"),
+    ];
+    let err = apply_expect(messages.iter().map(|m| m.as_str())).unwrap_err();
+    assert!(err.to_string().contains(&format!("{file}:2:22")), "{err}");
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("hello.mbt")).unwrap(),
+        "test {\n  inspect(1, content=\"1\")\n  inspect(2, content=\"2\")\n}\n"
+    );
+}
+
 fn format_chunks(chunks: Vec<dissimilar::Chunk>) -> String {
     let mut buf = String::new();
     for chunk in chunks {
@@ -896,6 +968,36 @@ fn format_chunks(chunks: Vec<dissimilar::Chunk>) -> String {
     buf
 }
 
+/// A structural diff of `expect` and `actual` if both are JSON documents with
+/// objects in them, where a text diff would be noisy. Plain values and arrays
+/// of them keep the text diff.
+fn json_diff(expect: &str, actual: &str) -> Option<String> {
+    fn parse(s: &str) -> Option<serde_json::Value> {
+        let v: serde_json::Value = serde_json::from_str(s).ok()?;
+        let structured = match &v {
+            serde_json::Value::Object(_) => true,
+            serde_json::Value::Array(xs) => xs.iter().any(|x| x.is_object()),
+            _ => false,
+        };
+        structured.then_some(v)
+    }
+    let (expect, actual) = (parse(expect)?, parse(actual)?);
+    let diff = json_structural_diff::JsonDiff::diff(&expect, &actual, false).diff?;
+    Some(json_structural_diff::colorize(&diff, true))
+}
+
+#[test]
+fn test_json_diff() {
+    assert!(json_diff(r#"{"a": 1, "b": [1]}"#, r#"{"a": 2, "b": [1]}"#).is_some());
+    assert!(json_diff(r#"[{"a": 1}]"#, r#"[{"a": 1}, {"b": 2}]"#).is_some());
+    // Unchanged
+    assert!(json_diff(r#"{"a": 1}"#, r#"{ "a": 1 }"#).is_none());
+    // Not worth a structural diff
+    assert!(json_diff("[1, 2, 3]", "[1, 2]").is_none());
+    assert!(json_diff("1234", "1235").is_none());
+    assert!(json_diff(r#"{"a": 1}"#, "not json").is_none());
+}
+
 pub fn render_expect_fail(msg: &str) -> anyhow::Result<()> {
     assert!(msg.starts_with(EXPECT_FAILED));
     let json_str = &msg[EXPECT_FAILED.len()..];
@@ -914,6 +1016,13 @@ pub fn render_expect_fail(msg: &str) -> anyhow::Result<()> {
         return Ok(());
     }
 
+    if let Some(diffs) = json_diff(&rep.expect, &rep.actual) {
+        println!("expect test failed at {}", rep.loc.raw);
+        println!("{}", "Diff:".bold());
+        println!("{diffs}");
+        return Ok(());
+    }
+
     let d = dissimilar::diff(&rep.expect, &rep.actual);
     println!(
         r#"expect test failed at {}
@@ -940,12 +1049,7 @@ pub fn snapshot_eq(msg: &str) -> anyhow::Result<bool> {
     let filename = parse_filename(&snapshot.loc)?;
     let actual = snapshot.actual.clone();
     let expect_file = &snapshot.expect_file;
-    let expect_file = dunce::canonicalize(PathBuf::from(&filename))
-        .unwrap()
-        .parent()
-        .unwrap()
-        .join("__snapshot__")
-        .join(expect_file);
+    let expect_file = snapshot_file_path(&filename, expect_file)?;
 
     let expect = if expect_file.exists() {
         std::fs::read_to_string(&expect_file)?
@@ -971,12 +1075,7 @@ pub fn render_snapshot_fail(msg: &str) -> anyhow::Result<(bool, String, String)>
     let loc = parse_loc(&snapshot.loc)?;
     let actual = snapshot.actual.clone();
     let expect_file = &snapshot.expect_file;
-    let expect_file = dunce::canonicalize(PathBuf::from(&filename))
-        .unwrap()
-        .parent()
-        .unwrap()
-        .join("__snapshot__")
-        .join(expect_file);
+    let expect_file = snapshot_file_path(&filename, expect_file)?;
 
     let expect = if expect_file.exists() {
         std::fs::read_to_string(&expect_file)?
@@ -984,11 +1083,24 @@ pub fn render_snapshot_fail(msg: &str) -> anyhow::Result<(bool, String, String)>
         "".to_string()
     };
     let eq = actual == expect;
+    // The diff header names the snapshot file, which is what an update
+    // rewrites, rather than the source
     if !eq {
+        if let Some(diffs) = json_diff(&expect, &actual) {
+            println!(
+                "expect test failed at {}:{}:{}",
+                filename,
+                loc.line_start + 1,
+                loc.col_start + 1
+            );
+            println!("{} {}", "Diff:".bold(), expect_file.display());
+            println!("{diffs}");
+            return Ok((eq, expect, actual));
+        }
         let d = dissimilar::diff(&expect, &actual);
         println!(
             r#"expect test failed at {}:{}:{}
-{}
+{} {}
 ----
 {}
 ----
@@ -997,6 +1109,7 @@ pub fn render_snapshot_fail(msg: &str) -> anyhow::Result<(bool, String, String)>
             loc.line_start + 1,
             loc.col_start + 1,
             "Diff:".bold(),
+            expect_file.display(),
             format_chunks(d)
         );
     }
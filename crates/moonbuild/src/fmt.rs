@@ -62,6 +62,7 @@ pub fn gen_fmt(
     moonbuild_opt: &MoonbuildOpt,
 ) -> anyhow::Result<N2FmtInput> {
     let mut items = vec![];
+    let only_files = &moonbuild_opt.fmt_opt.as_ref().unwrap().only_files;
     for (_, pkg) in m.get_all_packages().iter() {
         if pkg.is_third_party {
             continue;
@@ -73,6 +74,11 @@ pub fn gen_fmt(
             .chain(pkg.test_files.iter())
             .chain(pkg.mbt_md_files.iter())
         {
+            if let Some(only_files) = only_files {
+                if !dunce::canonicalize(f).is_ok_and(|f| only_files.contains(&f)) {
+                    continue;
+                }
+            }
             let item = FmtItem {
                 input: f.display().to_string(),
                 output: moonbuild_opt
@@ -252,6 +258,7 @@ fn gen_fmt_to_command(
             .is_line(),
         "--block-style",
     )
+    .arg_with_cond(moonbuild_opt.fmt_opt.as_ref().unwrap().diff, "--diff")
     .args(&moonbuild_opt.fmt_opt.as_ref().unwrap().extra_args)
     .build();
     build.cmdline = Some(command);
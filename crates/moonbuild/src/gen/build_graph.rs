@@ -0,0 +1,325 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Package dependency graph (`moon gen build-graph`) for visualizing why a
+//! change triggers a rebuild.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::time::SystemTime;
+
+use indexmap::IndexMap;
+use moonutil::common::{TargetBackend, MOON_PKG_JSON};
+use moonutil::module::ModuleDB;
+use moonutil::package::Package;
+use petgraph::graph::{DiGraph, NodeIndex};
+use serde::Serialize;
+
+/// The packages of a module and the imports between them.
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+    /// The import cycles found, each as the packages it goes through. The
+    /// build can't schedule these, so they only show up because of a bug.
+    pub cycles: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GraphNode {
+    /// The full name of the package
+    pub package: String,
+    pub backend: String,
+    pub is_third_party: bool,
+    /// Whether the `.core` of the package is newer than its sources, and all
+    /// of its dependencies are up to date too
+    pub up_to_date: bool,
+}
+
+/// An edge from a package to one of its imports.
+#[derive(Debug, Clone, Serialize)]
+pub struct GraphEdge {
+    pub from: String,
+    pub to: String,
+}
+
+/// Collect the graph of the packages in `module`, built for `backend`.
+///
+/// Only the imports of the package sources are edges, the imports of tests
+/// don't affect building. Imports of packages outside `module`, like the
+/// standard library, are left out.
+pub fn build_graph(module: &ModuleDB, backend: TargetBackend) -> BuildGraph {
+    let packages = module.get_all_packages();
+    let mut graph = DiGraph::<&str, ()>::new();
+    let indices: IndexMap<&str, NodeIndex> = packages
+        .keys()
+        .map(|name| (name.as_str(), graph.add_node(name.as_str())))
+        .collect();
+    let mut edges = vec![];
+    for (name, pkg) in packages {
+        for dep in &pkg.imports {
+            let dep = dep.path.make_full_path();
+            if let Some((dep, &to)) = indices.get_key_value(dep.as_str()) {
+                graph.add_edge(indices[name.as_str()], to, ());
+                edges.push(GraphEdge {
+                    from: name.clone(),
+                    to: dep.to_string(),
+                });
+            }
+        }
+    }
+
+    // The strongly connected components come with the dependencies first, so
+    // the up-to-date state of the dependencies is known when reaching a
+    // package. A cycle is a component of several packages, or a package
+    // importing itself.
+    let mut up_to_date = HashMap::new();
+    let mut cycles = vec![];
+    for scc in petgraph::algo::tarjan_scc(&graph) {
+        let is_cycle = scc.len() > 1 || graph.contains_edge(scc[0], scc[0]);
+        if is_cycle {
+            let mut names = scc
+                .iter()
+                .map(|&i| graph[i].to_string())
+                .collect::<Vec<_>>();
+            names.sort();
+            cycles.push(names);
+        }
+        for &i in &scc {
+            let fresh = !is_cycle
+                && graph
+                    .neighbors(i)
+                    .all(|dep| up_to_date.get(&dep).copied().unwrap_or(false))
+                && is_core_fresh(&packages[graph[i]]);
+            up_to_date.insert(i, fresh);
+        }
+    }
+    cycles.sort();
+
+    let nodes = indices
+        .iter()
+        .map(|(&name, i)| GraphNode {
+            package: name.to_string(),
+            backend: backend.to_flag().to_string(),
+            is_third_party: packages[name].is_third_party,
+            up_to_date: up_to_date[i],
+        })
+        .collect();
+    BuildGraph {
+        nodes,
+        edges,
+        cycles,
+    }
+}
+
+/// Whether the `.core` of `pkg` exists and is newer than its sources and
+/// `moon.pkg.json`.
+fn is_core_fresh(pkg: &Package) -> bool {
+    let mtime = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    let Some(built) = mtime(&pkg.artifact.with_extension("core")) else {
+        return false;
+    };
+    let pkg_json = pkg.root_path.join(MOON_PKG_JSON);
+    pkg.files
+        .keys()
+        .map(|it| it.as_path())
+        .chain(std::iter::once(pkg_json.as_path()))
+        .all(|it| mtime(it).unwrap_or(SystemTime::UNIX_EPOCH) <= built)
+}
+
+impl BuildGraph {
+    /// Render the graph in the DOT language of Graphviz. Packages that are
+    /// not up to date are filled, and the edges of import cycles are red.
+    pub fn to_dot(&self) -> String {
+        let in_cycle = |edge: &GraphEdge| {
+            self.cycles
+                .iter()
+                .any(|c| c.contains(&edge.from) && c.contains(&edge.to))
+        };
+        let mut out = String::new();
+        out.push_str("digraph \"build graph\" {\n");
+        out.push_str("  node [shape=box];\n");
+        for node in &self.nodes {
+            let _ = write!(
+                out,
+                "  {} [label=\"{}\\n({})\"",
+                quote(&node.package),
+                escape(&node.package),
+                escape(&node.backend)
+            );
+            if !node.up_to_date {
+                out.push_str(", style=filled, fillcolor=lightyellow");
+            }
+            if node.is_third_party {
+                out.push_str(", color=gray");
+            }
+            out.push_str("];\n");
+        }
+        for edge in &self.edges {
+            let _ = write!(out, "  {} -> {}", quote(&edge.from), quote(&edge.to));
+            if in_cycle(edge) {
+                out.push_str(" [color=red]");
+            }
+            out.push_str(";\n");
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+/// Quote a DOT identifier.
+fn quote(s: &str) -> String {
+    format!("\"{}\"", escape(s))
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{module, package};
+    use std::time::Duration;
+
+    /// Write the package `rel` with a source file, and its `.core` if
+    /// `built`, newer than the sources.
+    fn write_package(dir: &Path, rel: &str, built: bool) {
+        let pkg_dir = dir.join(rel);
+        std::fs::create_dir_all(&pkg_dir).unwrap();
+        std::fs::write(pkg_dir.join("lib.mbt"), "").unwrap();
+        std::fs::write(pkg_dir.join(MOON_PKG_JSON), "{}").unwrap();
+        if built {
+            let target = dir.join("target").join(rel);
+            std::fs::create_dir_all(&target).unwrap();
+            let core = std::fs::File::create(target.join(format!("{rel}.core"))).unwrap();
+            core.set_modified(SystemTime::now() + Duration::from_secs(10))
+                .unwrap();
+        }
+    }
+
+    fn up_to_date(graph: &BuildGraph) -> Vec<(&str, bool)> {
+        graph
+            .nodes
+            .iter()
+            .map(|it| (it.package.as_str(), it.up_to_date))
+            .collect()
+    }
+
+    #[test]
+    fn test_up_to_date_propagation() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        write_package(dir, "lib", true);
+        write_package(dir, "main", true);
+        write_package(dir, "stale", false);
+        write_package(dir, "app", true);
+        let module = module(
+            dir,
+            vec![
+                package(dir, "lib", &[]),
+                package(dir, "main", &["lib"]),
+                package(dir, "stale", &[]),
+                package(dir, "app", &["stale", "lib"]),
+            ],
+        );
+
+        let graph = build_graph(&module, TargetBackend::WasmGC);
+        assert_eq!(
+            up_to_date(&graph),
+            [
+                ("username/hello/lib", true),
+                ("username/hello/main", true),
+                ("username/hello/stale", false),
+                // built, but one of its imports is not
+                ("username/hello/app", false),
+            ]
+        );
+        assert_eq!(graph.edges.len(), 3);
+        assert!(graph.cycles.is_empty());
+        assert!(graph.nodes.iter().all(|it| it.backend == "wasm-gc"));
+    }
+
+    #[test]
+    fn test_cycles() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        for rel in ["a", "b", "c", "d"] {
+            write_package(dir, rel, true);
+        }
+        let module = module(
+            dir,
+            vec![
+                package(dir, "a", &["b"]),
+                package(dir, "b", &["a"]),
+                package(dir, "c", &["c"]),
+                package(dir, "d", &["a"]),
+            ],
+        );
+
+        let graph = build_graph(&module, TargetBackend::WasmGC);
+        assert_eq!(
+            graph.cycles,
+            [
+                vec!["username/hello/a", "username/hello/b"],
+                vec!["username/hello/c"],
+            ]
+        );
+        assert!(graph.nodes.iter().all(|it| !it.up_to_date));
+
+        let dot = graph.to_dot();
+        assert!(dot.contains("\"username/hello/a\" -> \"username/hello/b\" [color=red];\n"));
+        assert!(dot.contains("\"username/hello/c\" -> \"username/hello/c\" [color=red];\n"));
+        assert!(dot.contains("\"username/hello/d\" -> \"username/hello/a\";\n"));
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let graph = BuildGraph {
+            nodes: vec![
+                GraphNode {
+                    package: r#"user/"quoted"\pkg"#.into(),
+                    backend: "wasm-gc".into(),
+                    is_third_party: false,
+                    up_to_date: false,
+                },
+                GraphNode {
+                    package: "moonbitlang/x/stack".into(),
+                    backend: "wasm-gc".into(),
+                    is_third_party: true,
+                    up_to_date: true,
+                },
+            ],
+            edges: vec![GraphEdge {
+                from: r#"user/"quoted"\pkg"#.into(),
+                to: "moonbitlang/x/stack".into(),
+            }],
+            cycles: vec![],
+        };
+        expect_test::expect![[r#"
+            digraph "build graph" {
+              node [shape=box];
+              "user/\"quoted\"\\pkg" [label="user/\"quoted\"\\pkg\n(wasm-gc)", style=filled, fillcolor=lightyellow];
+              "moonbitlang/x/stack" [label="moonbitlang/x/stack\n(wasm-gc)", color=gray];
+              "user/\"quoted\"\\pkg" -> "moonbitlang/x/stack";
+            }
+        "#]]
+        .assert_eq(&graph.to_dot());
+    }
+}
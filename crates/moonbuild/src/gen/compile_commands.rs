@@ -0,0 +1,98 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Compilation database (`compile_commands.json`) for external tools.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use n2::densemap::Index;
+use n2::graph::{FileId, Graph};
+use serde::Serialize;
+
+pub const COMPILE_COMMANDS_JSON: &str = "compile_commands.json";
+
+/// Extensions of the source files that get an entry in the database.
+const SOURCE_EXTENSIONS: &[&str] = &["mbt", "c", "cc", "cpp", "cxx"];
+
+/// An entry of the compilation database, in the format described by
+/// <https://clang.llvm.org/docs/JSONCompilationDatabase.html>.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CompileCommand {
+    pub directory: PathBuf,
+    pub file: String,
+    pub arguments: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+}
+
+/// Collect the commands building `default` into a compilation database.
+///
+/// A build with several source inputs, like `moonc build-package`, gets one
+/// entry for each of them. The entries follow the same stable order as the
+/// dry-run output.
+pub fn compile_commands(
+    graph: &Graph,
+    default: &[FileId],
+    source_dir: &Path,
+) -> Vec<CompileCommand> {
+    let mut sorted_default = default.to_vec();
+    sorted_default.sort_by_key(|a| a.index());
+
+    let mut res = vec![];
+    for b in crate::dry_run::stable_toposort_graph(graph, &sorted_default) {
+        let build = &graph.builds[b];
+        let Some(cmdline) = &build.cmdline else {
+            continue;
+        };
+        let Some(arguments) = shlex::split(cmdline) else {
+            continue;
+        };
+        let output = build.outs().first().map(|&id| graph.file(id).name.clone());
+        for &id in build.explicit_ins() {
+            let file = &graph.file(id).name;
+            let is_source = Path::new(file)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| SOURCE_EXTENSIONS.contains(&ext));
+            if is_source {
+                res.push(CompileCommand {
+                    directory: source_dir.to_path_buf(),
+                    file: file.clone(),
+                    arguments: arguments.clone(),
+                    output: output.clone(),
+                });
+            }
+        }
+    }
+    res
+}
+
+/// Write the compilation database to `compile_commands.json` in `source_dir`,
+/// returning the path written.
+pub fn write_compile_commands(
+    commands: &[CompileCommand],
+    source_dir: &Path,
+) -> anyhow::Result<PathBuf> {
+    let path = source_dir.join(COMPILE_COMMANDS_JSON);
+    let mut content = serde_json::to_string_pretty(commands)?;
+    content.push('\n');
+    std::fs::write(&path, content)
+        .with_context(|| format!("failed to write `{}`", path.display()))?;
+    Ok(path)
+}
@@ -1511,6 +1511,7 @@ pub fn gen_n2_build_state(
                     TargetBackend::Wasm | TargetBackend::WasmGC => "moonrun".to_string(),
                     #[cfg(feature = "moongres")]
                     TargetBackend::MoonGRES => "rustica-engine run".to_string(),
+                    TargetBackend::Wasi => "wasmtime run".to_string(),
                     TargetBackend::Js => "node".to_string(),
                 };
 
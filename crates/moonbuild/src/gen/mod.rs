@@ -17,6 +17,8 @@
 // For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
 
 pub mod cmd_builder;
+pub mod build_graph;
+pub mod compile_commands;
 pub mod gen_build;
 pub mod gen_bundle;
 pub mod gen_check;
@@ -24,6 +24,7 @@ pub mod build;
 pub mod build_script;
 pub mod bundle;
 pub mod check;
+pub mod content_hash;
 pub mod doc_http;
 pub mod dry_run;
 pub mod entry;
@@ -34,51 +35,203 @@ pub mod new;
 pub mod pre_build;
 pub mod runtest;
 pub mod section_capture;
+#[cfg(test)]
+mod test_fixtures;
 pub mod test_utils;
+pub mod timings;
 pub mod upgrade;
 pub mod watch;
 
+use std::path::{Path, PathBuf};
 use std::sync::LazyLock;
 
+use anyhow::{bail, Context};
 use sysinfo::{ProcessExt, System, SystemExt};
 
+/// The PID file shared by all kinds of watchers before they got their own
+/// ones. Only kept to clean it up.
 pub const MOON_PID_NAME: &str = ".moon.pid";
 
-pub fn watcher_is_running(pid_path: &std::path::Path) -> anyhow::Result<bool> {
+/// The kind of a watcher. Watchers of different kinds can run concurrently in
+/// the same target directory, each having its own PID file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Build,
+    Check,
+    Run,
+}
+
+impl WatchKind {
+    pub const ALL: [WatchKind; 3] = [WatchKind::Build, WatchKind::Check, WatchKind::Run];
+
+    pub fn pid_file_name(self) -> &'static str {
+        match self {
+            WatchKind::Build => ".moon.build.pid",
+            WatchKind::Check => ".moon.check.pid",
+            WatchKind::Run => ".moon.run.pid",
+        }
+    }
+
+    /// The path of the PID file of this kind of watcher in `target_dir`.
+    pub fn pid_path(self, target_dir: &Path) -> PathBuf {
+        target_dir.join(self.pid_file_name())
+    }
+}
+
+/// The status of the watcher recorded in a PID file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatcherStatus {
+    /// There is no PID file.
+    NotStarted,
+    /// The PID file exists, but the watcher is no longer running.
+    Stale { pid: usize },
+    /// The watcher is running.
+    Running { pid: usize },
+}
+
+impl WatcherStatus {
+    pub fn is_running(self) -> bool {
+        matches!(self, WatcherStatus::Running { .. })
+    }
+}
+
+/// Check whether the watcher of the given kind in `target_dir` is still
+/// running.
+///
+/// The PID file contains the PID and the start time of the watcher, written by
+/// [`write_watcher_pid`]. A process is only considered to be the watcher if
+/// both match, since PIDs get recycled. PID files without a start time can't
+/// be verified and are treated as stale.
+pub fn watcher_is_running(target_dir: &Path, kind: WatchKind) -> anyhow::Result<WatcherStatus> {
+    watcher_status(&kind.pid_path(target_dir))
+}
+
+fn watcher_status(pid_path: &Path) -> anyhow::Result<WatcherStatus> {
     if !pid_path.exists() {
-        return Ok(false);
+        return Ok(WatcherStatus::NotStarted);
     }
 
-    let pid = std::fs::read_to_string(pid_path)?;
-    let pid = pid.parse::<usize>()?;
-    let pid = sysinfo::Pid::from(pid);
+    let content = std::fs::read_to_string(pid_path)?;
+    let (pid, start_time) = match content.trim().split_once(' ') {
+        Some((pid, start_time)) => (pid.parse::<usize>()?, Some(start_time.parse::<u64>()?)),
+        None => (content.trim().parse::<usize>()?, None),
+    };
     let mut sys = System::new();
     sys.refresh_processes();
-    if let Some(p) = sys.process(pid) {
-        if p.name() == "moon" {
-            Ok(true)
-        } else {
-            Ok(false)
-        }
+    let running = sys
+        .process(sysinfo::Pid::from(pid))
+        .is_some_and(|p| p.name() == "moon" && start_time.is_some_and(|t| p.start_time() == t));
+    if running {
+        Ok(WatcherStatus::Running { pid })
     } else {
-        Ok(false)
+        Ok(WatcherStatus::Stale { pid })
     }
 }
 
-static MOONRUN_EXECUTABLE: LazyLock<Option<std::path::PathBuf>> = LazyLock::new(|| {
+/// Remove the legacy [`MOON_PID_NAME`] file in `target_dir`, unless the
+/// watcher recorded in it is still running.
+pub fn remove_legacy_pid_file(target_dir: &Path) -> anyhow::Result<()> {
+    let pid_path = target_dir.join(MOON_PID_NAME);
+    match watcher_status(&pid_path) {
+        Ok(WatcherStatus::NotStarted | WatcherStatus::Running { .. }) => Ok(()),
+        Ok(WatcherStatus::Stale { .. }) | Err(_) => std::fs::remove_file(&pid_path)
+            .with_context(|| format!("failed to remove `{}`", pid_path.display())),
+    }
+}
+
+/// Write the PID and the start time of the current process to the PID file
+/// of the given kind in `target_dir`, marking it as the running watcher.
+pub fn write_watcher_pid(target_dir: &Path, kind: WatchKind) -> anyhow::Result<()> {
+    let pid_path = kind.pid_path(target_dir);
+    let pid = sysinfo::get_current_pid().map_err(|e| anyhow::anyhow!(e))?;
+    let mut sys = System::new();
+    sys.refresh_process(pid);
+    let start_time = sys
+        .process(pid)
+        .context("failed to get the info of the current process")?
+        .start_time();
+    std::fs::write(&pid_path, format!("{pid} {start_time}"))
+        .with_context(|| format!("failed to write `{}`", pid_path.display()))
+}
+
+pub use sysinfo::Signal;
+
+/// Ask the process `pid` to exit by sending it `signal`, e.g. `SIGTERM`.
+/// Returns `false` if the process is gone, or the signal is not supported on
+/// this platform, i.e. on Windows.
+pub fn request_termination(pid: u32, signal: Signal) -> bool {
+    let pid = sysinfo::Pid::from(pid as usize);
+    let mut sys = System::new();
+    sys.refresh_process(pid);
+    sys.process(pid)
+        .and_then(|p| p.kill_with(signal))
+        .unwrap_or(false)
+}
+
+/// The environment variable to override the path of `moonrun`.
+pub const MOON_MOONRUN_PATH_ENV: &str = "MOON_MOONRUN_PATH";
+
+/// Returns the path of `moonrun` specified by [`MOON_MOONRUN_PATH_ENV`], if
+/// any. It's an error if the variable points to a path that is not an
+/// executable file.
+pub fn moonrun_path_override() -> anyhow::Result<Option<PathBuf>> {
+    let Some(path) = std::env::var_os(MOON_MOONRUN_PATH_ENV) else {
+        return Ok(None);
+    };
+    let path = PathBuf::from(path);
+    let meta = std::fs::metadata(&path).with_context(|| {
+        format!(
+            "`{}` is set to `{}`, which does not exist",
+            MOON_MOONRUN_PATH_ENV,
+            path.display()
+        )
+    })?;
+    if !meta.is_file() {
+        bail!(
+            "`{}` is set to `{}`, which is not a file",
+            MOON_MOONRUN_PATH_ENV,
+            path.display()
+        );
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if meta.permissions().mode() & 0o111 == 0 {
+            bail!(
+                "`{}` is set to `{}`, which is not executable",
+                MOON_MOONRUN_PATH_ENV,
+                path.display()
+            );
+        }
+    }
+    Ok(Some(path))
+}
+
+static MOONRUN_EXECUTABLE: LazyLock<anyhow::Result<Option<PathBuf>>> = LazyLock::new(|| {
+    if let Some(moonrun) = moonrun_path_override()? {
+        return Ok(Some(moonrun));
+    }
     let moonrun = "moonrun";
     // Prefer the one next to the current executable
     if let Ok(current_exe) = std::env::current_exe() {
         if let Some(exe_dir) = current_exe.parent() {
             let moonrun = exe_dir.join(moonrun);
             if moonrun.exists() {
-                return Some(moonrun);
+                return Ok(Some(moonrun));
             }
         }
     }
     // Fallback to search in PATH
-    which::which(moonrun).ok()
+    Ok(which::which(moonrun).ok())
 });
+
+fn moonrun_executable() -> anyhow::Result<&'static Path> {
+    match &*MOONRUN_EXECUTABLE {
+        Ok(Some(moonrun)) => Ok(moonrun),
+        Ok(None) => bail!("Unable to find the `moonrun` executable, please reinstall"),
+        Err(e) => bail!("{e:#}"),
+    }
+}
 static NODE_EXECUTABLE: LazyLock<Option<std::path::PathBuf>> = LazyLock::new(|| {
     ["node.cmd", "node"]
         .iter()
@@ -24,6 +24,7 @@ use colored::Colorize;
 
 use handlebars::Handlebars;
 
+use moonutil::common::TargetBackend;
 use moonutil::git::{git_init_repo, is_in_git_repo};
 use serde::{Deserialize, Serialize};
 
@@ -53,6 +54,27 @@ struct TemplateEnv {
     username: String,
     module: String,
     package: String,
+    /// The preferred target backend of the module, if chosen
+    backend: Option<String>,
+}
+
+/// The layout of a new module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NewLayout {
+    /// A library package exposing the public API, without any main package
+    Lib,
+    /// The library package along with a main package `cmd/main` using it
+    Exec,
+}
+
+impl NewLayout {
+    /// Whether the template file at `path` belongs to this layout.
+    fn includes(self, path: &Path) -> bool {
+        match self {
+            NewLayout::Lib => !path.starts_with("cmd"),
+            NewLayout::Exec => true,
+        }
+    }
 }
 
 impl Template {
@@ -60,9 +82,20 @@ impl Template {
         toml::from_str(toml_str).context("Failed to parse template from TOML")
     }
 
-    fn create(&self, base_dir: &Path, user: &String, module: &String) -> anyhow::Result<()> {
+    fn create(
+        &self,
+        base_dir: &Path,
+        user: &String,
+        module: &String,
+        backend: Option<TargetBackend>,
+        layout: NewLayout,
+    ) -> anyhow::Result<()> {
         let reg = Handlebars::new();
         for entry in &self.files {
+            let (TemplateFile::PlainFile { path, .. } | TemplateFile::SymLink { path, .. }) = entry;
+            if !layout.includes(path) {
+                continue;
+            }
             match &entry {
                 TemplateFile::PlainFile {
                     content,
@@ -80,6 +113,7 @@ impl Template {
                             .unwrap()
                             .to_string_lossy()
                             .to_string(),
+                        backend: backend.map(|b| b.to_flag().to_string()),
                     };
 
                     // Special case where the file depend on the current package
@@ -186,15 +220,28 @@ pub fn create_or_warning(path: &Path) -> anyhow::Result<()> {
     Ok(())
 }
 
-pub fn moon_new_default(target_dir: &Path, user: String, name: String) -> anyhow::Result<i32> {
+/// Create a new module in `target_dir` with the given layout. If `backend` is
+/// given, it becomes the preferred target of the module.
+pub fn moon_new_default(
+    target_dir: &Path,
+    user: String,
+    name: String,
+    backend: Option<TargetBackend>,
+    layout: NewLayout,
+) -> anyhow::Result<i32> {
     let template: Template =
         Template::from_toml(include_str!("../template/moon_new_template.toml"))
             .context("failed to load template")?;
 
     std::fs::create_dir_all(target_dir).context("failed to create target directory")?;
 
-    template.create(target_dir, &user, &name)?;
+    template.create(target_dir, &user, &name, backend, layout)?;
 
+    finish_new(target_dir, &user, &name)
+}
+
+/// Initialize a git repository for the new module if it's not in one.
+fn finish_new(target_dir: &Path, user: &str, name: &str) -> anyhow::Result<i32> {
     match is_in_git_repo(target_dir) {
         Ok(b) => {
             if !b {
@@ -227,3 +274,257 @@ pub fn moon_new_default(target_dir: &Path, user: String, name: String) -> anyhow
 
     Ok(0)
 }
+
+/// The name of the manifest of a custom template.
+const TEMPLATE_MANIFEST: &str = "moon.template.json";
+
+/// The manifest of a custom template, at the root of the template.
+#[derive(Debug, Default, Deserialize)]
+struct TemplateManifest {
+    /// The files to substitute the variables in, relative to the template. All
+    /// text files are templated by default.
+    templated: Option<Vec<std::path::PathBuf>>,
+    /// Extra variables to ask for
+    #[serde(default)]
+    prompts: Vec<TemplatePrompt>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TemplatePrompt {
+    /// The variable name, substituted as `{{name}}`
+    name: String,
+    /// The question to ask, the variable name by default
+    #[serde(default)]
+    message: Option<String>,
+    /// The value when not asked interactively
+    #[serde(default)]
+    default: String,
+}
+
+/// Create a new module in `target_dir` from a custom template, which is a
+/// local directory or a git repository URL.
+///
+/// The template tree is copied as is, except that `{{project_name}}`,
+/// `{{author}}` and the variables of the prompts in `moon.template.json` are
+/// substituted in text files. Binary files are copied verbatim.
+pub fn moon_new_from_template(
+    target_dir: &Path,
+    template: &str,
+    user: String,
+    name: String,
+    force: bool,
+) -> anyhow::Result<i32> {
+    if !force
+        && target_dir.is_dir()
+        && std::fs::read_dir(target_dir)
+            .context(format!("failed to read {}", target_dir.display()))?
+            .next()
+            .is_some()
+    {
+        anyhow::bail!(
+            "`{}` is not empty, use `--force` to create the module in it anyway",
+            target_dir.display()
+        );
+    }
+
+    let is_git_url = ["https://", "http://", "ssh://", "git@"]
+        .iter()
+        .any(|p| template.starts_with(p))
+        || template.ends_with(".git");
+    let cloned;
+    let template_dir = if is_git_url {
+        moonutil::common::ensure_online(&format!("clone template {template}"))?;
+        cloned = tempfile::tempdir().context("failed to create temporary directory")?;
+        moonutil::git::git_clone_shallow(template, cloned.path())
+            .context(format!("failed to clone template {template}"))?;
+        cloned.path()
+    } else {
+        Path::new(template)
+    };
+    if !template_dir.is_dir() {
+        anyhow::bail!("template `{}` is not a directory", template_dir.display());
+    }
+
+    let interactive = std::io::IsTerminal::is_terminal(&std::io::stdin());
+    render_template(template_dir, target_dir, &user, &name, interactive)?;
+
+    finish_new(target_dir, &user, &name)
+}
+
+/// Render the template in `template_dir` into `target_dir`, asking for the
+/// values of the prompts if `interactive`, or taking their defaults.
+fn render_template(
+    template_dir: &Path,
+    target_dir: &Path,
+    user: &str,
+    name: &str,
+    interactive: bool,
+) -> anyhow::Result<()> {
+    let manifest_path = template_dir.join(TEMPLATE_MANIFEST);
+    let manifest: TemplateManifest = if manifest_path.exists() {
+        let content = std::fs::read_to_string(&manifest_path)
+            .context(format!("failed to read {}", manifest_path.display()))?;
+        serde_json_lenient::from_str(&content)
+            .context(format!("failed to parse {}", manifest_path.display()))?
+    } else {
+        TemplateManifest::default()
+    };
+
+    let mut vars = vec![
+        ("project_name".to_string(), name.to_string()),
+        ("author".to_string(), user.to_string()),
+    ];
+    for prompt in &manifest.prompts {
+        let value = if interactive {
+            dialoguer::Input::<String>::new()
+                .with_prompt(prompt.message.as_deref().unwrap_or(&prompt.name))
+                .default(prompt.default.clone())
+                .interact_text()
+                .context(format!("failed to read the value of {}", prompt.name))?
+        } else {
+            prompt.default.clone()
+        };
+        vars.push((prompt.name.clone(), value));
+    }
+
+    std::fs::create_dir_all(target_dir).context("failed to create target directory")?;
+    for entry in walkdir::WalkDir::new(template_dir)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|e| e.file_name() != ".git")
+    {
+        let entry = entry.context("failed to read template")?;
+        let rel = entry
+            .path()
+            .strip_prefix(template_dir)
+            .expect("entries are in the template");
+        if rel == Path::new(TEMPLATE_MANIFEST) {
+            continue;
+        }
+        let dest = target_dir.join(rel);
+        if entry.file_type().is_dir() {
+            std::fs::create_dir_all(&dest)
+                .context(format!("Failed to create directory: {}", dest.display()))?;
+            continue;
+        }
+        let templated = manifest
+            .templated
+            .as_ref()
+            .map_or(true, |files| files.iter().any(|f| f == rel));
+        let content = std::fs::read(entry.path())
+            .context(format!("Failed to read file: {}", entry.path().display()))?;
+        match String::from_utf8(content) {
+            // NUL bytes hardly appear in text files
+            Ok(text) if templated && !text.contains('\0') => {
+                let rendered = vars.iter().fold(text, |text, (key, value)| {
+                    text.replace(&format!("{{{{{key}}}}}"), value)
+                });
+                std::fs::write(&dest, rendered)
+                    .context(format!("Failed to write to file: {}", dest.display()))?;
+                let permissions = entry
+                    .metadata()
+                    .context(format!("Failed to read file: {}", entry.path().display()))?
+                    .permissions();
+                std::fs::set_permissions(&dest, permissions)
+                    .context(format!("Failed to set permissions: {}", dest.display()))?;
+            }
+            // `copy` keeps the permissions
+            _ => {
+                std::fs::copy(entry.path(), &dest)
+                    .context(format!("Failed to write to file: {}", dest.display()))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A template with a nested directory, a binary file, a script and a
+    /// manifest templating only some files.
+    fn write_template(dir: &Path) {
+        std::fs::create_dir_all(dir.join("src/lib")).unwrap();
+        std::fs::write(
+            dir.join(TEMPLATE_MANIFEST),
+            r#"{
+                "templated": ["moon.mod.json", "src/lib/hello.mbt", "run.sh"],
+                "prompts": [{ "name": "greeting", "default": "Hello" }]
+            }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("moon.mod.json"),
+            r#"{ "name": "{{author}}/{{project_name}}" }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("src/lib/hello.mbt"),
+            "pub fn hello() -> String {\n  \"{{greeting}}\"\n}\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("README.md"), "# {{project_name}}\n").unwrap();
+        std::fs::write(dir.join("logo.bin"), b"{{author}}\0\xff").unwrap();
+        std::fs::write(dir.join("run.sh"), "echo {{project_name}}\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(dir.join("run.sh"), std::fs::Permissions::from_mode(0o755))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_render_template() {
+        let template = tempfile::tempdir().unwrap();
+        write_template(template.path());
+        let target = tempfile::tempdir().unwrap();
+        let target = target.path();
+        render_template(template.path(), target, "username", "hello", false).unwrap();
+
+        let read = |path: &str| std::fs::read_to_string(target.join(path)).unwrap();
+        assert_eq!(read("moon.mod.json"), r#"{ "name": "username/hello" }"#);
+        // the default of the prompt
+        assert_eq!(
+            read("src/lib/hello.mbt"),
+            "pub fn hello() -> String {\n  \"Hello\"\n}\n"
+        );
+        // not in `templated`
+        assert_eq!(read("README.md"), "# {{project_name}}\n");
+        assert_eq!(
+            std::fs::read(target.join("logo.bin")).unwrap(),
+            b"{{author}}\0\xff"
+        );
+        assert_eq!(read("run.sh"), "echo hello\n");
+        assert!(!target.join(TEMPLATE_MANIFEST).exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(target.join("run.sh"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o755);
+        }
+    }
+
+    #[test]
+    fn test_new_from_template_non_empty() {
+        let template = tempfile::tempdir().unwrap();
+        write_template(template.path());
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(target.path().join("existing.txt"), "").unwrap();
+
+        let err = moon_new_from_template(
+            target.path(),
+            template.path().to_str().unwrap(),
+            "username".into(),
+            "hello".into(),
+            false,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("is not empty"), "{err}");
+        assert!(!target.path().join("moon.mod.json").exists());
+    }
+}
@@ -19,8 +19,10 @@
 use std::path::PathBuf;
 use std::rc::Rc;
 
+use log::warn;
 use moonutil::common::{
-    MoonbuildOpt, PrePostBuild, DEP_PATH, MOD_DIR, MOONCAKE_BIN, MOON_BIN_DIR, PKG_DIR,
+    pre_build_command_allowed, MoonbuildOpt, PrePostBuild, DEP_PATH, MOD_DIR, MOONCAKE_BIN,
+    MOON_BIN_DIR, PKG_DIR,
 };
 use moonutil::module::ModuleDB;
 use moonutil::package::StringOrArray;
@@ -54,6 +56,16 @@ pub fn load_moon_x_build(
                 let input = &rule.input;
                 let output = &rule.output;
                 let command = &rule.command;
+                if !pre_build_command_allowed(command) {
+                    warn!(
+                        "package `{}` has a {} command `{}`, which can run arbitrary code. \
+                        Running it without `--allow-pre-build-scripts` is deprecated, \
+                        and will be an error in a future version.",
+                        pkg.full_name(),
+                        build_type.name(),
+                        command
+                    );
+                }
                 let inputs = match input {
                     StringOrArray::String(s) => {
                         vec![cwd.join(s)]
@@ -70,11 +70,7 @@ pub async fn run_wat(
     file_test_info_map: &FileTestInfo,
     verbose: bool,
 ) -> anyhow::Result<Vec<Result<TestStatistics, TestFailedStatus>>> {
-    let mut cmd = tokio::process::Command::new(
-        crate::MOONRUN_EXECUTABLE
-            .as_deref()
-            .context("Unable to find the `moonrun` executable, please reinstall")?,
-    );
+    let mut cmd = tokio::process::Command::new(crate::moonrun_executable()?);
     cmd.arg(path)
         .arg("--test-args")
         .arg(serde_json_lenient::to_string(args).expect("valid JSON"));
@@ -140,11 +136,11 @@ pub async fn run_native(
             .arg("-DMOONBIT_USE_SHARED_RUNTIME")
             .arg("-run")
             .arg(path)
-            .arg(args);
+            .args(args);
         cmd
     } else {
         let mut cmd = tokio::process::Command::new(path);
-        cmd.arg(args);
+        cmd.args(args);
         cmd
     };
     run(path, cmd, target_dir, file_test_info_map, verbose).await
@@ -158,7 +154,7 @@ pub async fn run_llvm(
     verbose: bool,
 ) -> anyhow::Result<Vec<Result<TestStatistics, TestFailedStatus>>> {
     let mut cmd = tokio::process::Command::new(path);
-    cmd.arg(args.to_cli_args_for_native());
+    cmd.args(args.to_cli_args_for_native());
     run(path, cmd, target_dir, file_test_info_map, verbose).await
 }
 
@@ -182,7 +178,8 @@ async fn run(
     let mut stdout = execution.stdout.take().unwrap();
 
     let mut test_capture =
-        SectionCapture::new(MOON_TEST_DELIMITER_BEGIN, MOON_TEST_DELIMITER_END, false);
+        SectionCapture::new(MOON_TEST_DELIMITER_BEGIN, MOON_TEST_DELIMITER_END, false)
+            .strip_ansi(moonutil::common::no_color());
     let mut coverage_capture = SectionCapture::new(
         MOON_COVERAGE_DELIMITER_BEGIN,
         MOON_COVERAGE_DELIMITER_END,
@@ -16,9 +16,40 @@
 //
 // For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
 
-use std::io::BufRead;
+use std::{borrow::Cow, io::BufRead};
 
-use tokio::io::{AsyncBufRead, AsyncWriteExt};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The longest line read from a child at once. Longer lines are split, so
+/// that a test printing in a loop without newlines doesn't take unbounded
+/// memory.
+const MAX_LINE: usize = 64 * 1024;
+
+/// Read a line of at most [`MAX_LINE`] bytes into `buf`, returning `false` at
+/// the end of the input. Unlike `read_line`, this is cancel safe, as the
+/// bytes read are kept in `buf`.
+async fn read_line_bounded(
+    reader: &mut (impl AsyncBufRead + Unpin),
+    buf: &mut Vec<u8>,
+) -> std::io::Result<bool> {
+    let limit = MAX_LINE.saturating_sub(buf.len()) as u64;
+    let n = reader.take(limit).read_until(b'\n', buf).await?;
+    Ok(n > 0)
+}
+
+/// Take the line read by [`read_line_bounded`] out of `buf`. A char split by
+/// [`MAX_LINE`] is left in `buf` for the next line, unless at the end of the
+/// input, and invalid UTF-8 is replaced.
+fn take_line(buf: &mut Vec<u8>, eof: bool) -> String {
+    let keep = match std::str::from_utf8(buf) {
+        Err(e) if e.error_len().is_none() && !eof => buf.len() - e.valid_up_to(),
+        _ => 0,
+    };
+    let rest = buf.split_off(buf.len() - keep);
+    let line = String::from_utf8_lossy(buf).into_owned();
+    *buf = rest;
+    line
+}
 
 pub struct SectionCapture<'a> {
     begin_delimiter: &'a str,
@@ -27,6 +58,7 @@ pub struct SectionCapture<'a> {
     include_delimiters: bool,
     found_begin: bool,
     found_end: bool,
+    strip: Option<AnsiStripper>,
 }
 
 pub enum LineCaptured {
@@ -44,6 +76,21 @@ impl<'a> SectionCapture<'a> {
             include_delimiters,
             found_begin: false,
             found_end: false,
+            strip: None,
+        }
+    }
+
+    /// Remove the ANSI escape sequences from the captured section if `strip`,
+    /// for sections stored or reported as plain text.
+    pub fn strip_ansi(mut self, strip: bool) -> Self {
+        self.strip = strip.then(AnsiStripper::default);
+        self
+    }
+
+    fn push(&mut self, s: &str) {
+        match &mut self.strip {
+            Some(strip) => strip.feed(s, &mut self.capture_buffer),
+            None => self.capture_buffer.push_str(s),
         }
     }
 
@@ -53,7 +100,7 @@ impl<'a> SectionCapture<'a> {
             self.found_begin = true;
             self.found_end = false;
             if self.include_delimiters {
-                self.capture_buffer.push_str(line);
+                self.push(line);
             }
             let end_index = line.trim_end().len() - self.begin_delimiter.len();
             return Some(LineCaptured::Suffix(end_index));
@@ -61,7 +108,7 @@ impl<'a> SectionCapture<'a> {
         if self.found_begin && line.starts_with(self.end_delimiter) {
             self.found_end = true;
             if self.include_delimiters {
-                self.capture_buffer.push_str(line);
+                self.push(line);
             }
             let start_index = self.end_delimiter.len();
             if line.trim().len() == self.end_delimiter.len() {
@@ -72,7 +119,7 @@ impl<'a> SectionCapture<'a> {
             }
         }
         if self.found_begin && !self.found_end {
-            self.capture_buffer.push_str(line);
+            self.push(line);
             return Some(LineCaptured::All);
         }
         None
@@ -88,6 +135,103 @@ impl<'a> SectionCapture<'a> {
     }
 }
 
+/// Removes the ANSI escape sequences of a stream of text fed in pieces. A
+/// sequence split between two pieces is still removed whole, leaving the text
+/// around it intact.
+#[derive(Debug, Default, Clone)]
+pub struct AnsiStripper {
+    state: EscapeState,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+enum EscapeState {
+    #[default]
+    Text,
+    /// After an `ESC`
+    Escape,
+    /// In a CSI sequence, e.g. colors: parameters up to a final byte
+    Csi,
+    /// In an OSC sequence, e.g. hyperlinks: up to BEL or ST
+    Osc,
+    /// After an `ESC` in an OSC sequence, which is ST if `\` follows
+    OscEscape,
+}
+
+impl AnsiStripper {
+    /// Append `s` to `out`, without the escape sequences. A sequence cut
+    /// short by a newline ends there, so a stray `ESC` never swallows the rest
+    /// of the stream.
+    pub fn feed(&mut self, s: &str, out: &mut String) {
+        use EscapeState::*;
+        for c in s.chars() {
+            self.state = match (self.state, c) {
+                (Text, '\x1b') => Escape,
+                (Escape, '[') => Csi,
+                (Escape, ']') => Osc,
+                // Other sequences are two characters
+                (Escape, '\n') | (Text, _) => {
+                    out.push(c);
+                    Text
+                }
+                (Escape, _) => Text,
+                (Csi, '\x20'..='\x3f') => Csi,
+                (Csi, '\x40'..='\x7e') => Text,
+                (Osc | OscEscape, '\x07') | (OscEscape, '\\') => Text,
+                (Osc | OscEscape, '\x1b') => OscEscape,
+                (Csi | Osc | OscEscape, '\n') => {
+                    out.push(c);
+                    Text
+                }
+                (Csi, _) => Text,
+                (Osc | OscEscape, _) => Osc,
+            };
+        }
+    }
+}
+
+/// Remove the ANSI escape sequences in `s`.
+pub fn strip_ansi_escapes(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    AnsiStripper::default().feed(s, &mut out);
+    out
+}
+
+/// The uncaptured output of tests keeps its escapes unless colors are
+/// disabled with `--color never`, when `strip` is given. Each stream has a
+/// stripper of its own, for sequences split between reads.
+fn strip_if_no_color<'s>(s: &'s str, strip: &mut Option<AnsiStripper>) -> Cow<'s, str> {
+    match strip {
+        Some(strip) => {
+            let mut out = String::with_capacity(s.len());
+            strip.feed(s, &mut out);
+            Cow::Owned(out)
+        }
+        None => Cow::Borrowed(s),
+    }
+}
+
+#[test]
+fn test_strip_ansi_escapes() {
+    assert_eq!(
+        strip_ansi_escapes("\x1b[1;31merror\x1b[0m: failed\n"),
+        "error: failed\n"
+    );
+    assert_eq!(
+        strip_ansi_escapes("\x1b]8;;https://example.com\x1b\\link\x1b]8;;\x07"),
+        "link"
+    );
+    assert_eq!(strip_ansi_escapes("plain"), "plain");
+    assert_eq!(strip_ansi_escapes("\x1b[31\nnext\n"), "\nnext\n");
+
+    // A sequence split between pieces
+    let mut strip = AnsiStripper::default();
+    let mut out = String::new();
+    for piece in ["ok \x1b", "[3", "2mgreen\x1b]8;;url\x1b", "\\ done"] {
+        strip.feed(piece, &mut out);
+    }
+    assert_eq!(out, "ok green done");
+}
+
 /// Pipes the child stdout to stdout, with the ability to capture sections of the output.
 /// If any of the captures captures a line, the line will not be printed to stdout.
 /// The captures are processed in order: a line is captured by the first capture that captures it.
@@ -97,6 +241,7 @@ pub fn handle_stdout<P: FnMut(&str)>(
     captures: &mut [&mut SectionCapture],
     mut print: P,
 ) -> anyhow::Result<()> {
+    let mut strip = moonutil::common::no_color().then(AnsiStripper::default);
     let mut buf = String::new();
 
     loop {
@@ -109,48 +254,239 @@ pub fn handle_stdout<P: FnMut(&str)>(
             .iter_mut()
             .find_map(|capture| capture.feed_line(&buf));
         match capture_status {
-            None => print(&buf),
+            None => print(&strip_if_no_color(&buf, &mut strip)),
             Some(LineCaptured::All) => {}
-            Some(LineCaptured::Prefix(start_index)) => print(&buf[start_index..]),
-            Some(LineCaptured::Suffix(end_index)) => print(&buf[..end_index]),
+            Some(LineCaptured::Prefix(start_index)) => {
+                print(&strip_if_no_color(&buf[start_index..], &mut strip))
+            }
+            Some(LineCaptured::Suffix(end_index)) => {
+                print(&strip_if_no_color(&buf[..end_index], &mut strip))
+            }
         }
     }
     Ok(())
 }
 
-/// Async version of [`handle_stdout`].
+/// Async version of [`handle_stdout`]. The output that isn't captured is
+/// written to `stdout`.
 pub async fn handle_stdout_async<'a>(
     proc: impl AsyncBufRead,
     captures: &mut [&mut SectionCapture<'a>],
+    stdout: impl AsyncWrite,
 ) -> anyhow::Result<()> {
-    use tokio::io::AsyncBufReadExt;
-    let mut buf = String::new();
+    let mut strip = moonutil::common::no_color().then(AnsiStripper::default);
+    let mut bytes = vec![];
 
     tokio::pin!(proc);
-    let mut stdout = tokio::io::stdout();
+    tokio::pin!(stdout);
 
     loop {
-        buf.clear();
-        let n = proc.read_line(&mut buf).await?;
-        if n == 0 {
+        let eof = !read_line_bounded(&mut proc, &mut bytes).await?;
+        if eof && bytes.is_empty() {
             break;
         }
+        let buf = take_line(&mut bytes, eof);
         let capture_status = captures
             .iter_mut()
             .find_map(|capture| capture.feed_line(&buf));
-        match capture_status {
-            None => stdout.write_all(buf.as_bytes()).await?,
-            Some(LineCaptured::All) => {}
-            Some(LineCaptured::Prefix(start_index)) => {
-                stdout.write_all(buf[start_index..].as_bytes()).await?
+        let printed = match capture_status {
+            None => &buf[..],
+            Some(LineCaptured::All) => "",
+            Some(LineCaptured::Prefix(start_index)) => &buf[start_index..],
+            Some(LineCaptured::Suffix(end_index)) => &buf[..end_index],
+        };
+        stdout
+            .write_all(strip_if_no_color(printed, &mut strip).as_bytes())
+            .await?;
+    }
+    Ok(())
+}
+
+/// The output of one test, excluding the captured sections.
+#[derive(Debug, Default, Clone)]
+pub struct TestOutput {
+    pub stdout: String,
+    pub stderr: String,
+    /// Whether the output was cut at the limit of
+    /// [`OutputAttribution::with_limit`]
+    pub truncated: bool,
+}
+
+/// Splits the uncaptured output of a test executable into the output of each
+/// test.
+///
+/// Tests in an executable run one after another, and each prints its result
+/// section when it finishes. Thus the output since the end of the previous
+/// result section belongs to the test of the next one. `stderr` is read
+/// alongside `stdout`, so the attribution of `stderr` is best-effort.
+pub struct OutputAttribution<'a> {
+    end_delimiter: &'a str,
+    /// The line printed to both streams by the test driver right before the
+    /// first test. The output before it is the preamble of the driver or the
+    /// runtime, which belongs to no test.
+    start_marker: Option<&'a str>,
+    stdout_started: bool,
+    stderr_started: bool,
+    /// The most bytes of output kept for each test
+    limit: Option<usize>,
+    current: TestOutput,
+    outputs: Vec<TestOutput>,
+}
+
+impl<'a> OutputAttribution<'a> {
+    pub fn new(end_delimiter: &'a str, start_marker: Option<&'a str>) -> Self {
+        OutputAttribution {
+            end_delimiter,
+            start_marker,
+            stdout_started: start_marker.is_none(),
+            stderr_started: start_marker.is_none(),
+            limit: None,
+            current: TestOutput::default(),
+            outputs: vec![],
+        }
+    }
+
+    /// Keep at most `limit` bytes of the output of each test. The rest is
+    /// dropped, from both the output of the test and what
+    /// [`handle_output_async`] writes, after a note of the truncation. The
+    /// child is still read to the end, so it never blocks on a full pipe.
+    pub fn with_limit(mut self, limit: Option<usize>) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Whether `line` is the start marker, which should not be printed.
+    fn is_start_marker(&self, line: &str) -> bool {
+        self.start_marker == Some(line.trim_end())
+    }
+
+    /// Cut `s`, output of the current test, to what's left of the limit.
+    fn cut<'s>(&mut self, s: &'s str) -> Cow<'s, str> {
+        let Some(limit) = self.limit else {
+            return Cow::Borrowed(s);
+        };
+        if self.current.truncated {
+            return Cow::Borrowed("");
+        }
+        let left = limit.saturating_sub(self.current.stdout.len() + self.current.stderr.len());
+        if s.len() <= left {
+            return Cow::Borrowed(s);
+        }
+        let mut end = left;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.current.truncated = true;
+        Cow::Owned(format!(
+            "{}\n... output truncated at {limit} bytes\n",
+            &s[..end]
+        ))
+    }
+
+    /// Feed a line of `stdout`, of which `printed` is not captured. Returns
+    /// the part of `printed` to write.
+    fn feed_stdout<'s>(&mut self, line: &str, printed: &'s str) -> Cow<'s, str> {
+        if !self.stdout_started {
+            self.stdout_started = self.is_start_marker(line);
+            return Cow::Borrowed(printed);
+        }
+        let printed = self.cut(printed);
+        self.current.stdout.push_str(&printed);
+        if line.starts_with(self.end_delimiter) {
+            self.outputs.push(std::mem::take(&mut self.current));
+        }
+        printed
+    }
+
+    /// Feed a line of `stderr`, returning the part of it to write.
+    fn feed_stderr<'s>(&mut self, line: &'s str) -> Cow<'s, str> {
+        if !self.stderr_started {
+            self.stderr_started = self.is_start_marker(line);
+            return Cow::Borrowed(line);
+        }
+        let line = self.cut(line);
+        self.current.stderr.push_str(&line);
+        line
+    }
+
+    /// Returns the output of each test, in the order of the result sections.
+    pub fn finish(self) -> Vec<TestOutput> {
+        self.outputs
+    }
+
+    /// Returns the output since the last result section, i.e. the output of
+    /// the test still running when the executable was killed.
+    pub fn into_unfinished(self) -> TestOutput {
+        self.current
+    }
+}
+
+/// Like [`handle_stdout_async`], but also pipes the child `stderr` to
+/// `stderr`, and attributes the output to each test with `attribution`. The
+/// start marker of `attribution` is not written.
+pub async fn handle_output_async<'a>(
+    proc_stdout: impl AsyncBufRead,
+    proc_stderr: impl AsyncBufRead,
+    captures: &mut [&mut SectionCapture<'a>],
+    attribution: &mut OutputAttribution<'a>,
+    stdout: impl AsyncWrite,
+    stderr: impl AsyncWrite,
+) -> anyhow::Result<()> {
+    let mut out_strip = moonutil::common::no_color().then(AnsiStripper::default);
+    let mut err_strip = out_strip.clone();
+    let mut out_bytes = vec![];
+    let mut err_bytes = vec![];
+    let mut out_done = false;
+    let mut err_done = false;
+
+    tokio::pin!(proc_stdout);
+    tokio::pin!(proc_stderr);
+    tokio::pin!(stdout);
+    tokio::pin!(stderr);
+
+    while !(out_done && err_done) {
+        // Partially read lines are kept in the buffers when the other branch
+        // wins, so they are only taken after a full line is read
+        tokio::select! {
+            // Prefer `stderr`, which a test usually writes before its result
+            biased;
+            read = read_line_bounded(&mut proc_stderr, &mut err_bytes), if !err_done => {
+                err_done = !read?;
+                if err_bytes.is_empty() {
+                    continue;
+                }
+                let line = take_line(&mut err_bytes, err_done);
+                let line = strip_if_no_color(&line, &mut err_strip);
+                let written = attribution.feed_stderr(&line);
+                if !attribution.is_start_marker(&line) {
+                    stderr.write_all(written.as_bytes()).await?;
+                }
             }
-            Some(LineCaptured::Suffix(end_index)) => {
-                stdout.write_all(buf[..end_index].as_bytes()).await?
+            read = read_line_bounded(&mut proc_stdout, &mut out_bytes), if !out_done => {
+                out_done = !read?;
+                if out_bytes.is_empty() {
+                    continue;
+                }
+                let line = take_line(&mut out_bytes, out_done);
+                let capture_status = captures
+                    .iter_mut()
+                    .find_map(|capture| capture.feed_line(&line));
+                let printed = match capture_status {
+                    None if attribution.is_start_marker(&line) => "",
+                    None => &line[..],
+                    Some(LineCaptured::All) => "",
+                    Some(LineCaptured::Prefix(start_index)) => &line[start_index..],
+                    Some(LineCaptured::Suffix(end_index)) => &line[..end_index],
+                };
+                let printed = strip_if_no_color(printed, &mut out_strip);
+                let written = attribution.feed_stdout(&line, &printed);
+                stdout.write_all(written.as_bytes()).await?;
             }
         }
     }
     Ok(())
 }
+
 #[test]
 fn test_handle_output() {
     let out = "abcde
@@ -215,3 +551,74 @@ fghij";
     assert_eq!(buf, out);
     assert!(capture.finish().is_none());
 }
+
+#[test]
+fn test_handle_output_attribution() {
+    let out = "preamble
+---start---
+first
+---begin---
+result 1
+---end---
+---begin---
+result 2
+---end---
+";
+    let err = "warning
+---start---
+oops
+";
+    let mut capture = SectionCapture::new("---begin---", "---end---", false);
+    let mut attribution = OutputAttribution::new("---end---", Some("---start---"));
+    let mut printed = Vec::new();
+    let mut printed_err = Vec::new();
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap()
+        .block_on(handle_output_async(
+            out.as_bytes(),
+            err.as_bytes(),
+            &mut [&mut capture],
+            &mut attribution,
+            &mut printed,
+            &mut printed_err,
+        ))
+        .unwrap();
+    assert_eq!(String::from_utf8(printed).unwrap(), "preamble\nfirst\n");
+    assert_eq!(String::from_utf8(printed_err).unwrap(), "warning\noops\n");
+    assert_eq!(capture.finish().unwrap(), "result 1\nresult 2\n");
+    let outputs = attribution.finish();
+    assert_eq!(outputs.len(), 2);
+    assert_eq!(outputs[0].stdout, "first\n");
+    assert_eq!(outputs[0].stderr, "oops\n");
+    assert_eq!(outputs[1].stdout, "");
+}
+
+#[test]
+fn test_handle_output_limit() {
+    let out = "0123456789abcdefghij\n---end---\nok\n---end---\n";
+    let mut attribution = OutputAttribution::new("---end---", None).with_limit(Some(16));
+    let mut printed = Vec::new();
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap()
+        .block_on(handle_output_async(
+            out.as_bytes(),
+            &b""[..],
+            &mut [],
+            &mut attribution,
+            &mut printed,
+            tokio::io::sink(),
+        ))
+        .unwrap();
+    let truncated = "0123456789abcdef\n... output truncated at 16 bytes\n";
+    assert_eq!(
+        String::from_utf8(printed).unwrap(),
+        format!("{truncated}ok\n---end---\n")
+    );
+    let outputs = attribution.finish();
+    assert_eq!(outputs[0].stdout, truncated);
+    assert!(outputs[0].truncated);
+    assert_eq!(outputs[1].stdout, "ok\n---end---\n");
+    assert!(!outputs[1].truncated);
+}
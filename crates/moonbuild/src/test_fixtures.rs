@@ -0,0 +1,126 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Packages and modules built by hand for unit tests, which can't scan a
+//! real module without the toolchain.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use indexmap::IndexMap;
+use moonutil::common::TargetBackend;
+use moonutil::cond_expr::CompileCondition;
+use moonutil::module::ModuleDB;
+use moonutil::package::Package;
+use moonutil::path::{ImportComponent, ImportPath, PathComponent};
+use petgraph::graph::DiGraph;
+
+/// The name of the module of [`package`].
+pub const MODULE_NAME: &str = "username/hello";
+
+fn path_component(s: &str) -> PathComponent {
+    PathComponent {
+        components: s.split('/').map(str::to_string).collect(),
+    }
+}
+
+/// The package `rel` of [`MODULE_NAME`] in `module_dir`, with its sources
+/// `*.mbt` of the directory and its artifact in `module_dir/target`.
+/// `imports` are other packages of the module, by their `rel` too.
+pub fn package(module_dir: &Path, rel: &str, imports: &[&str]) -> Package {
+    let root_path = module_dir.join(rel);
+    let files = std::fs::read_dir(&root_path)
+        .into_iter()
+        .flatten()
+        .map(|it| it.unwrap().path())
+        .filter(|it| it.extension().is_some_and(|ext| ext == "mbt"))
+        .map(|it| (it, CompileCondition::default()))
+        .collect();
+    let name = rel.rsplit('/').next().unwrap();
+    Package {
+        is_main: false,
+        force_link: false,
+        is_third_party: false,
+        root_path: root_path.clone(),
+        module_root: module_dir.join("moon.mod.json").into(),
+        root: path_component(MODULE_NAME),
+        rel: path_component(rel),
+        files,
+        wbtest_files: IndexMap::new(),
+        test_files: IndexMap::new(),
+        mbt_md_files: IndexMap::new(),
+        files_contain_test_block: vec![],
+        with_sub_package: None,
+        is_sub_package: false,
+        imports: imports
+            .iter()
+            .map(|it| ImportComponent {
+                path: ImportPath {
+                    module_name: MODULE_NAME.to_string(),
+                    rel_path: path_component(it),
+                    is_3rd: false,
+                },
+                alias: None,
+                sub_package: false,
+            })
+            .collect(),
+        wbtest_imports: vec![],
+        test_imports: vec![],
+        generated_test_drivers: vec![],
+        artifact: module_dir.join("target").join(rel).join(name),
+        link: None,
+        warn_list: None,
+        alert_list: None,
+        targets: None,
+        pre_build: None,
+        post_build: vec![],
+        patch_file: None,
+        no_mi: false,
+        install_path: None,
+        bin_name: None,
+        bin_target: TargetBackend::WasmGC,
+        enable_value_tracing: false,
+        supported_targets: HashSet::from([TargetBackend::WasmGC]),
+        stub_lib: None,
+        virtual_pkg: None,
+        virtual_mbti_file: None,
+        implement: None,
+        overrides: None,
+        link_flags: None,
+        link_libs: vec![],
+        link_search_paths: vec![],
+    }
+}
+
+/// The module in `module_dir` of the `packages` made with [`package`].
+pub fn module(module_dir: &Path, packages: Vec<Package>) -> ModuleDB {
+    ModuleDB::new(
+        module_dir.to_path_buf(),
+        MODULE_NAME.to_string(),
+        packages
+            .into_iter()
+            .map(|pkg| (pkg.full_name(), pkg))
+            .collect(),
+        vec![],
+        vec![],
+        DiGraph::new(),
+        TargetBackend::WasmGC.to_backend_ext().into(),
+        "debug".into(),
+        None,
+    )
+}
@@ -0,0 +1,188 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! Timings of build tasks, for `--timings`
+//!
+//! n2 records the span of every task it runs in its trace, so the timings are
+//! read back from a trace file in the target directory after the build.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use colored::Colorize;
+use moonutil::common::TargetBackend;
+use n2::trace;
+use serde::{Deserialize, Serialize};
+
+/// The trace file in the target directory
+const TIMINGS_TRACE: &str = "timings.trace.json";
+
+/// The JSON report in the target directory
+pub const TIMINGS_JSON: &str = "moon-timings.json";
+
+/// How many of the slowest tasks to show in the summary
+const SUMMARY_LEN: usize = 10;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskTiming {
+    pub package: String,
+    /// The kind of the task, e.g. `build-package`
+    pub kind: String,
+    /// Milliseconds since the first task started
+    pub start_ms: f64,
+    pub duration_ms: f64,
+    pub backend: String,
+}
+
+/// An event in the Chrome trace format written by n2
+#[derive(Debug, Deserialize)]
+struct TraceEvent {
+    name: String,
+    ph: String,
+    /// Microseconds since the trace was opened
+    ts: u64,
+    #[serde(default)]
+    dur: u64,
+}
+
+/// Start recording the tasks of the build.
+pub fn start(target_dir: &Path) -> anyhow::Result<PathBuf> {
+    std::fs::create_dir_all(target_dir)?;
+    let path = target_dir.join(TIMINGS_TRACE);
+    trace::open(&path.display().to_string())
+        .context(format!("failed to open `{}`", path.display()))?;
+    Ok(path)
+}
+
+/// Stop recording, and read the timings of the tasks run.
+pub fn finish(trace_path: &Path, backend: TargetBackend) -> anyhow::Result<Vec<TaskTiming>> {
+    trace::close();
+    let content = std::fs::read_to_string(trace_path)
+        .context(format!("failed to read `{}`", trace_path.display()))?;
+    let _ = std::fs::remove_file(trace_path);
+    parse_trace(&content, backend)
+}
+
+/// The kind and package of a task, from its description, e.g.
+/// `build-package: user/mod/pkg`, or from its command line if there's none.
+fn task_of(name: &str) -> Option<(String, String)> {
+    if let Some((kind, package)) = name.split_once(": ") {
+        if !kind.contains(' ') {
+            return Some((kind.to_string(), package.to_string()));
+        }
+    }
+    let args = shlex::split(name)?;
+    let package = args
+        .iter()
+        .position(|arg| arg == "-pkg")
+        .and_then(|i| args.get(i + 1))?;
+    let program = Path::new(args.first()?).file_stem()?.to_string_lossy();
+    let kind = match args.get(1) {
+        Some(subcommand) if program == "moonc" => subcommand.clone(),
+        _ => program.into_owned(),
+    };
+    Some((kind, package.clone()))
+}
+
+fn parse_trace(content: &str, backend: TargetBackend) -> anyhow::Result<Vec<TaskTiming>> {
+    let events: Vec<TraceEvent> =
+        serde_json::from_str(content).context("failed to parse the build trace")?;
+    let tasks: Vec<_> = events
+        .into_iter()
+        .filter(|e| e.ph == "X")
+        .filter_map(|e| task_of(&e.name).map(|task| (task, e)))
+        .collect();
+    let first = tasks.iter().map(|(_, e)| e.ts).min().unwrap_or_default();
+    let mut timings: Vec<_> = tasks
+        .into_iter()
+        .map(|((kind, package), e)| TaskTiming {
+            package,
+            kind,
+            start_ms: (e.ts - first) as f64 / 1000.0,
+            duration_ms: e.dur as f64 / 1000.0,
+            backend: backend.to_flag().to_string(),
+        })
+        .collect();
+    timings.sort_by(|a, b| a.start_ms.total_cmp(&b.start_ms));
+    Ok(timings)
+}
+
+/// Print the slowest tasks, and write all of them as JSON into `json_dir` if
+/// given.
+pub fn report(timings: &[TaskTiming], json_dir: Option<&Path>) -> anyhow::Result<()> {
+    if let Some(dir) = json_dir {
+        let path = dir.join(TIMINGS_JSON);
+        std::fs::write(&path, serde_json::to_string_pretty(timings)?)
+            .context(format!("failed to write `{}`", path.display()))?;
+        eprintln!("Timings written to {}", path.display());
+    }
+    if timings.is_empty() {
+        eprintln!("Timings: no task was run");
+        return Ok(());
+    }
+    let mut slowest: Vec<_> = timings.iter().collect();
+    slowest.sort_by(|a, b| b.duration_ms.total_cmp(&a.duration_ms));
+    let total: f64 = timings
+        .iter()
+        .map(|t| t.start_ms + t.duration_ms)
+        .fold(0.0, f64::max);
+    eprintln!(
+        "{} {} tasks in {:.2}s, the slowest:",
+        "Timings:".bold(),
+        timings.len(),
+        total / 1000.0
+    );
+    for t in slowest.into_iter().take(SUMMARY_LEN) {
+        eprintln!(
+            "  {:>8.2}s  {} ({})",
+            t.duration_ms / 1000.0,
+            t.package,
+            t.kind
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_parse_trace() {
+    let trace = r#"[
+{"pid":0, "name":"build-package: user/mod/lib", "ts":100, "tid": 1, "ph":"X", "dur":2000}
+,{"pid":0, "name":"moonc link-core ./lib.core -pkg user/mod/main -o main.wasm", "ts":2500, "tid": 1, "ph":"X", "dur":500}
+,{"pid":0, "name":"work.run", "ts":0, "tid": 0, "ph":"X", "dur":3500}
+]"#;
+    let timings = parse_trace(trace, TargetBackend::WasmGC).unwrap();
+    let summary: Vec<_> = timings
+        .iter()
+        .map(|t| {
+            (
+                t.package.as_str(),
+                t.kind.as_str(),
+                t.start_ms,
+                t.duration_ms,
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            ("user/mod/lib", "build-package", 0.0, 2.0),
+            ("user/mod/main", "link-core", 2.4, 0.5)
+        ]
+    );
+    assert_eq!(timings[0].backend, "wasm-gc");
+}
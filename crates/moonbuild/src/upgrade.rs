@@ -36,6 +36,11 @@ pub struct UpgradeSubcommand {
     #[clap(long)]
     pub dev: bool,
 
+    /// Install the given toolchain version instead of the latest, e.g.
+    /// `0.6.25`, or a channel like `nightly`
+    #[clap(long, value_name = "VERSION", conflicts_with = "dev")]
+    pub version: Option<String>,
+
     #[clap(long, hide = true)]
     pub non_interactive: bool,
 
@@ -110,7 +115,17 @@ fn test_extract_date() {
     assert!(date1 > date2);
 }
 
-fn should_upgrade(latest_version_info: &VersionItems) -> Option<bool> {
+/// A toolchain component that an upgrade would change.
+#[derive(Debug)]
+pub struct VersionChange {
+    pub name: String,
+    pub current: String,
+    pub new: String,
+}
+
+/// Returns the components newer than the installed ones, or `None` if the
+/// versions can't be compared.
+fn version_changes(latest_version_info: &VersionItems) -> Option<Vec<VersionChange>> {
     let moon_version = get_moon_version();
     let moonrun_version = get_moonrun_version().ok()?;
     let moonc_version = get_moonc_version().ok()?;
@@ -119,25 +134,95 @@ fn should_upgrade(latest_version_info: &VersionItems) -> Option<bool> {
     let moon_date = extract_date(&moon_version)?;
     let moonrun_date = extract_date(&moonrun_version)?;
     let moonc_date = extract_date(&moonc_version)?;
-    let mut should_upgrade = false;
+    let mut changes = vec![];
     for item in &latest_version_info.items {
         let latest_date = extract_date(&item.version)?;
 
-        if ((item.name == "moon") && latest_date > moon_date)
-            || (item.name == "moonrun" && latest_date > moonrun_date)
-            || (item.name == "moonc" && latest_date > moonc_date)
-        {
-            should_upgrade = true;
+        let (current, current_date) = match item.name.as_str() {
+            "moon" => (&moon_version, &moon_date),
+            "moonrun" => (&moonrun_version, &moonrun_date),
+            "moonc" => (&moonc_version, &moonc_date),
+            _ => continue,
+        };
+        if latest_date > *current_date {
+            changes.push(VersionChange {
+                name: item.name.clone(),
+                current: current.trim().to_string(),
+                new: item.version.clone(),
+            });
         }
     }
 
-    Some(should_upgrade)
+    Some(changes)
 }
 
-pub fn upgrade(cmd: UpgradeSubcommand) -> Result<i32> {
-    ctrlc::set_handler(upgrade_dialoguer_ctrlc_handler)?;
-    let h = moon_dir::home();
+/// Release channels accepted by `--version` besides exact versions.
+const CHANNELS: &[&str] = &["latest", "nightly"];
+
+/// The URL of the toolchain archive for `version` on this platform, which the
+/// install scripts download.
+fn archive_url(root: &str, version: &str) -> Option<String> {
+    let (platform, ext) = match (std::env::consts::OS, std::env::consts::ARCH) {
+        ("linux", "x86_64") => ("linux-x86_64", "tar.gz"),
+        ("macos", "x86_64") => ("darwin-x86_64", "tar.gz"),
+        ("macos", "aarch64") => ("darwin-aarch64", "tar.gz"),
+        ("windows", "x86_64") => ("windows-x86_64", "zip"),
+        _ => return None,
+    };
+    Some(format!(
+        "{root}/binaries/{version}/moonbit-{platform}.{ext}"
+    ))
+}
+
+/// Check that `version` can be downloaded from `root`, and whether it's the
+/// one already installed.
+fn resolve_pinned_version(root: &str, version: &str) -> Result<Vec<VersionChange>> {
+    // It ends up in the install command line
+    if !version
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+'))
+    {
+        bail!("invalid toolchain version `{version}`");
+    }
+    let url = archive_url(root, version).context("no prebuilt toolchain for this platform")?;
+    let resp = reqwest::blocking::Client::new()
+        .head(&url)
+        .send()
+        .with_context(|| format!("failed to check whether version `{version}` exists"))?;
+    if !resp.status().is_success() {
+        bail!("toolchain version `{version}` does not exist (checked {url})");
+    }
+
+    // Channels move, so they're never considered installed
+    let current = get_moonc_version().unwrap_or_default();
+    let current = current.trim();
+    let installed = !CHANNELS.contains(&version)
+        && current
+            .trim_start_matches('v')
+            .strip_prefix(version.trim_start_matches('v'))
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(['+', ' ']));
+    if installed {
+        return Ok(vec![]);
+    }
+    Ok(vec![VersionChange {
+        name: "moonc".to_string(),
+        current: current.to_string(),
+        new: version.to_string(),
+    }])
+}
+
+/// What an upgrade would do, resolved without touching anything on disk.
+pub struct UpgradePlan {
+    /// The server to install from
+    pub root: String,
+    pub download_page: &'static str,
+    /// The components that would change. `None` if that can't be determined,
+    /// e.g. for `--dev` or when the server is unreachable, in which case the
+    /// upgrade is done anyway.
+    pub changes: Option<Vec<VersionChange>>,
+}
 
+pub fn resolve_upgrade(cmd: &UpgradeSubcommand) -> Result<UpgradePlan> {
     println!("Checking network ...");
     let root = if cmd.base_url.is_none() {
         check_connectivity()?.to_string()
@@ -152,19 +237,87 @@ pub fn upgrade(cmd: UpgradeSubcommand) -> Result<i32> {
         "https://www.moonbitlang.com/download"
     };
 
-    println!("Checking latest toolchain version ...");
-    let version_url = format!("{root}/version.json");
-    if !cmd.force && !cmd.dev {
+    let mut changes = None;
+    if let Some(version) = &cmd.version {
+        println!("Checking toolchain version {version} ...");
+        changes = Some(resolve_pinned_version(&root, version)?);
+    } else if !cmd.dev {
+        println!("Checking latest toolchain version ...");
+        let version_url = format!("{root}/version.json");
         // if any step(network request, serde json...) fail, just do upgrade
         if let Ok(data) = reqwest::blocking::get(version_url) {
             if let Ok(latest_version_info) = data.json::<VersionItems>() {
-                if let Some(false) = should_upgrade(&latest_version_info) {
-                    println!("Your toolchain is up to date. You can use `moon upgrade --force` to force upgrade.");
-                    return Ok(0);
-                }
+                changes = version_changes(&latest_version_info);
+            }
+        }
+    }
+
+    Ok(UpgradePlan {
+        root,
+        download_page,
+        changes,
+    })
+}
+
+/// Print what `moon upgrade` would change, without applying it.
+pub fn upgrade_dry_run(cmd: UpgradeSubcommand) -> Result<i32> {
+    let plan = resolve_upgrade(&cmd)?;
+    match &plan.changes {
+        _ if cmd.dev => println!(
+            "Would install the latest development version from {}",
+            plan.root
+        ),
+        None => println!(
+            "Unable to determine the latest toolchain version, would reinstall from {}",
+            plan.root
+        ),
+        Some(changes) if changes.is_empty() => {
+            let msg = match &cmd.version {
+                Some(version) => format!("Version {version} is already installed"),
+                None => "Your toolchain is up to date".to_string(),
+            };
+            if cmd.force {
+                println!("{msg}, would reinstall it because of `--force`.");
+            } else {
+                println!("{msg}.");
+            }
+        }
+        Some(changes) => {
+            let name_width = changes.iter().map(|c| c.name.len()).max().unwrap_or(0);
+            let current_width = changes.iter().map(|c| c.current.len()).max().unwrap_or(0);
+            for c in changes {
+                println!(
+                    "  {:name_width$}  {:current_width$} -> {}",
+                    c.name,
+                    c.current,
+                    c.new.green()
+                );
             }
         }
     }
+    Ok(0)
+}
+
+pub fn upgrade(cmd: UpgradeSubcommand) -> Result<i32> {
+    ctrlc::set_handler(upgrade_dialoguer_ctrlc_handler)?;
+    let h = moon_dir::home();
+
+    let UpgradePlan {
+        root,
+        download_page,
+        changes,
+    } = resolve_upgrade(&cmd)?;
+    if !cmd.force && changes.is_some_and(|c| c.is_empty()) {
+        match &cmd.version {
+            Some(version) => println!(
+                "Version {version} is already installed. You can use `moon upgrade --force` to reinstall it."
+            ),
+            None => println!(
+                "Your toolchain is up to date. You can use `moon upgrade --force` to force upgrade."
+            ),
+        }
+        return Ok(0);
+    }
 
     println!("{}", "Warning: moon upgrade is highly experimental.".bold());
     let msg = format!("If you encounter any problems, please reinstall by visit {download_page}");
@@ -202,13 +355,17 @@ pub fn do_upgrade_unix(cmd: &UpgradeSubcommand, root: &str) -> Result<i32> {
     let args = [
         "-c".to_string(),
         format!(
-            "curl -fsSL {}/install/unix.sh | {}bash",
+            "curl -fsSL {}/install/unix.sh | {}{}bash",
             root,
             if cmd.dev {
                 "MOONBIT_INSTALL_DEV=1 "
             } else {
                 ""
             },
+            match &cmd.version {
+                Some(version) => format!("MOONBIT_INSTALL_VERSION={version} "),
+                None => String::new(),
+            },
         ),
     ];
     let hint = format!("{} {} '{}'", exe, args[0], args[1..].join(" "));
@@ -273,10 +430,16 @@ mod windows {
         if cmd.dev {
             script.push_str("$env:MOONBIT_INSTALL_DEV=1\n");
         }
+        if let Some(version) = &cmd.version {
+            script.push_str(&format!("$env:MOONBIT_INSTALL_VERSION=\"{version}\"\n"));
+        }
         script.push_str("Set-ExecutionPolicy RemoteSigned -Scope CurrentUser\n");
         script.push_str(&format!("irm {}/install/powershell.ps1 | iex\n", root));
         if cmd.dev {
-            script.push_str("Remove-Item Env:\\MOONBIT_INSTALL_DEV")
+            script.push_str("Remove-Item Env:\\MOONBIT_INSTALL_DEV\n")
+        }
+        if cmd.version.is_some() {
+            script.push_str("Remove-Item Env:\\MOONBIT_INSTALL_VERSION\n")
         }
         let script_path = tmp_dir.join("script.ps1");
         std::fs::write(&script_path, &script).context("failed to write install script")?;
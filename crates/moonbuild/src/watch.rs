@@ -27,8 +27,154 @@ use notify::{Config, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
 use moonutil::common::{
     MoonbuildOpt, MooncOpt, RunMode, DOT_MBT_DOT_MD, MOON_MOD_JSON, MOON_PKG_JSON, WATCH_MODE_DIR,
 };
+use regex::Regex;
+use serde::Serialize;
 use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+use crate::WatchKind;
+
+/// Options of the watch mode
+#[derive(Debug, Clone)]
+pub struct WatchOpt {
+    /// Write the events as newline-delimited JSON to stdout, instead of
+    /// human-readable messages. Output of the build itself is still printed
+    /// as-is, so consumers should skip lines that aren't JSON objects.
+    pub events_json: bool,
+    /// Wait until no file changes for this long before rebuilding, so that a
+    /// burst of changes results in one rebuild. Zero rebuilds immediately.
+    pub debounce: Duration,
+    /// The paths whose changes don't trigger rebuilds
+    pub ignore: WatchIgnore,
+}
+
+/// The file at the root of the source directory listing the globs of
+/// [`WatchIgnore`], one per line.
+pub const MOON_IGNORE: &str = ".moonignore";
+
+/// Globs ignored even without any configuration.
+const DEFAULT_IGNORE: &[&str] = &[".git"];
+
+/// Globs of the paths whose changes don't trigger rebuilds, relative to the
+/// source directory and matched like in `.gitignore`: `*` and `?` don't match
+/// `/`, `**` matches any number of directories, and a glob without `/` matches
+/// any component of the path. Everything under a matched directory is
+/// ignored too.
+///
+/// The target directory is always ignored by the watchers, since the build
+/// writing to it would trigger the next build otherwise.
+#[derive(Debug, Clone, Default)]
+pub struct WatchIgnore {
+    root: std::path::PathBuf,
+    globs: Vec<Regex>,
+}
+
+impl WatchIgnore {
+    /// The default globs, the ones in [`MOON_IGNORE`] under `source_dir` if
+    /// it exists, and `extra`, e.g. from `--watch-ignore`.
+    pub fn new(source_dir: &Path, extra: &[String]) -> anyhow::Result<Self> {
+        let path = source_dir.join(MOON_IGNORE);
+        let file = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read `{}`", path.display()))
+            }
+        };
+        let from_file = file
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'));
+        let globs = DEFAULT_IGNORE
+            .iter()
+            .copied()
+            .chain(from_file)
+            .chain(extra.iter().map(String::as_str))
+            .map(|glob| {
+                glob_to_regex(glob).with_context(|| format!("invalid watch ignore glob `{glob}`"))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            root: source_dir.to_path_buf(),
+            globs,
+        })
+    }
+
+    /// Whether changes of `path` are ignored. Paths outside the source
+    /// directory never are.
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        let relative = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        self.globs.iter().any(|re| re.is_match(&relative))
+    }
+}
+
+/// The regex of a glob of [`WatchIgnore`], matching the path of the glob and
+/// everything under it.
+fn glob_to_regex(glob: &str) -> Result<Regex, regex::Error> {
+    let glob = glob.trim_end_matches('/');
+    let anchored = glob.contains('/');
+    let mut re = String::from(if anchored { "^" } else { "(^|/)" });
+    let mut chars = glob.trim_start_matches('/').chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                // `**/` may match no directory at all
+                if chars.next_if_eq(&'/').is_some() {
+                    re.push_str("(.*/)?");
+                } else {
+                    re.push_str(".*");
+                }
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            c => re.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    re.push_str("(/|$)");
+    Regex::new(&re)
+}
+
+impl WatchOpt {
+    /// Whether changes of `path` may trigger a rebuild, i.e. it's neither
+    /// under `ignore_dir` nor ignored by [`WatchOpt::ignore`].
+    fn is_watched(&self, path: &Path, ignore_dir: &Path) -> bool {
+        !path.starts_with(ignore_dir) && !self.ignore.is_ignored(path)
+    }
+
+    /// Whether the event modifies some watched file, see [`Self::is_watched`].
+    fn triggers_rebuild(&self, event: &notify::Event, ignore_dir: &Path) -> bool {
+        is_modification(event) && event.paths.iter().any(|p| self.is_watched(p, ignore_dir))
+    }
+
+    fn emit(&self, event: WatchEvent) {
+        if self.events_json {
+            println!(
+                "{}",
+                serde_json::to_string(&event).expect("serializing watch event")
+            );
+        }
+    }
+}
+
+/// An event of the watch mode, emitted with [`WatchOpt::events_json`]. Changes
+/// of many files at once result in one `rebuild_start`/`rebuild_done` pair.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum WatchEvent<'a> {
+    FileChanged { path: &'a Path },
+    RebuildStart,
+    RebuildDone { success: bool, duration_ms: u128 },
+}
 
 pub fn watching(
     moonc_opt: &MooncOpt,
@@ -36,104 +182,248 @@ pub fn watching(
     registry_config: &RegistryConfig,
     module: &ModuleDB,
     original_target_dir: &Path,
+    watch_opt: &WatchOpt,
 ) -> anyhow::Result<i32> {
-    run_and_print(moonc_opt, moonbuild_opt, module)?;
+    let target_dir = &moonbuild_opt.target_dir;
+    let kind = match moonbuild_opt.run_mode {
+        RunMode::Check => WatchKind::Check,
+        _ => WatchKind::Build,
+    };
+    claim_pid_file(target_dir, kind)?;
+    run_and_print(moonc_opt, moonbuild_opt, watch_opt, module)?;
 
     let (tx, rx) = std::sync::mpsc::channel();
     let mut watcher = RecommendedWatcher::new(tx, Config::default())?;
+    set_ctrlc_handler();
+    let ignore_dir = legacy_ignore_dir(moonbuild_opt, original_target_dir);
 
     {
-        // make sure the handler is only set once when --watch --target all
-        static HANDLER_SET: AtomicBool = AtomicBool::new(false);
+        // main thread
+        watcher.watch(&moonbuild_opt.source_dir, RecursiveMode::Recursive)?;
+
+        // in watch mode, moon is a long-running process that should handle errors as much as possible rather than throwing them up and then exiting.
+        // coalesce the events received during the previous rebuild into one
+        // rebuild
+        while let Some(events) = recv_debounced(&rx, watch_opt.debounce, |e| {
+            watch_opt.triggers_rebuild(e, ignore_dir)
+        }) {
+            let _ = handle_file_change(
+                moonc_opt,
+                moonbuild_opt,
+                watch_opt,
+                registry_config,
+                module,
+                ignore_dir,
+                &events,
+            );
+        }
+    }
+    Ok(0)
+}
+
+/// Watch `source_dir` like [`watching`], but call `run` for each rebuild
+/// instead of running the legacy build. Changes inside `ignore_dir`, usually
+/// the target directory, are not reported.
+///
+/// `run` is called again for changes of `moon.mod.json` and `moon.pkg.json`
+/// too, so it should load the module afresh each time.
+pub fn watching_with(
+    source_dir: &Path,
+    target_dir: &Path,
+    ignore_dir: &Path,
+    kind: WatchKind,
+    watch_opt: &WatchOpt,
+    mut run: impl FnMut() -> anyhow::Result<i32>,
+) -> anyhow::Result<i32> {
+    claim_pid_file(target_dir, kind)?;
+    print_run(watch_opt, &mut run);
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut watcher = RecommendedWatcher::new(tx, Config::default())?;
+    set_ctrlc_handler();
+    watcher.watch(source_dir, RecursiveMode::Recursive)?;
+
+    while let Some(events) = recv_debounced(&rx, watch_opt.debounce, |e| {
+        watch_opt.triggers_rebuild(e, ignore_dir)
+    }) {
+        let changed_paths = events
+            .iter()
+            .flat_map(|e| &e.paths)
+            .filter(|p| watch_opt.is_watched(p, ignore_dir))
+            .collect::<Vec<_>>();
+        if changed_paths.is_empty() {
+            continue;
+        }
+        for path in &changed_paths {
+            watch_opt.emit(WatchEvent::FileChanged { path });
+        }
+        // prevent the case that the whole target_dir was deleted
+        if let Err(e) = std::fs::create_dir_all(target_dir) {
+            println!(
+                "Failed to create target directory '{}': {e}",
+                target_dir.display()
+            );
+            continue;
+        }
+        print_run(watch_opt, &mut run);
+    }
+    Ok(0)
+}
+
+/// Watch like [`watching_with`], and after each rebuild, call `after_build`
+/// with its outcome: what `build` made on success, or `None` if the build
+/// failed, with `Err(code)`, or errored. This is for actions like running the
+/// built program, which should still happen in the same watcher.
+///
+/// An error of `after_build` is reported like a failed rebuild.
+pub fn watching_then<T>(
+    source_dir: &Path,
+    target_dir: &Path,
+    ignore_dir: &Path,
+    kind: WatchKind,
+    watch_opt: &WatchOpt,
+    mut build: impl FnMut() -> anyhow::Result<Result<T, i32>>,
+    mut after_build: impl FnMut(Option<T>) -> anyhow::Result<()>,
+) -> anyhow::Result<i32> {
+    watching_with(
+        source_dir,
+        target_dir,
+        ignore_dir,
+        kind,
+        watch_opt,
+        || match build() {
+            Ok(Ok(built)) => after_build(Some(built)).map(|()| 0),
+            Ok(Err(code)) => after_build(None).map(|()| code),
+            Err(e) => after_build(None).and(Err(e)),
+        },
+    )
+}
 
-        if HANDLER_SET
-            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
-            .is_ok()
-        {
-            ctrlc::set_handler(moonutil::common::dialoguer_ctrlc_handler)
-                .expect("Error setting Ctrl-C handler");
+/// Fail if another watcher of the same kind runs on `target_dir`, otherwise
+/// write the PID file of this one.
+fn claim_pid_file(target_dir: &Path, kind: WatchKind) -> anyhow::Result<()> {
+    crate::remove_legacy_pid_file(target_dir)?;
+    let pid_path = kind.pid_path(target_dir);
+    match crate::watcher_is_running(target_dir, kind) {
+        Ok(crate::WatcherStatus::Running { pid }) => {
+            anyhow::bail!("another {kind:?} watcher (PID {pid}) is already running");
         }
+        Ok(crate::WatcherStatus::Stale { pid }) => {
+            eprintln!("removing the stale PID file of watcher (PID {pid})");
+            std::fs::remove_file(&pid_path)
+                .with_context(|| format!("failed to remove `{}`", pid_path.display()))?;
+        }
+        // An unreadable PID file gets overwritten below anyway
+        Ok(crate::WatcherStatus::NotStarted) | Err(_) => {}
     }
+    crate::write_watcher_pid(target_dir, kind)
+}
 
+fn set_ctrlc_handler() {
+    // make sure the handler is only set once when --watch --target all
+    static HANDLER_SET: AtomicBool = AtomicBool::new(false);
+
+    if HANDLER_SET
+        .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+        .is_ok()
     {
-        // main thread
-        watcher.watch(&moonbuild_opt.source_dir, RecursiveMode::Recursive)?;
+        ctrlc::set_handler(moonutil::common::dialoguer_ctrlc_handler)
+            .expect("Error setting Ctrl-C handler");
+    }
+}
 
-        // in watch mode, moon is a long-running process that should handle errors as much as possible rather than throwing them up and then exiting.
-        for res in rx {
-            match res {
-                Ok(event) => {
-                    match event.kind {
-                        // when a file was modified, multiple events may be received, we only care about data those modified data
-                        #[cfg(unix)]
-                        EventKind::Modify(notify::event::ModifyKind::Data(_)) => {
-                            if let Ok(None) = handle_file_change(
-                                moonc_opt,
-                                moonbuild_opt,
-                                registry_config,
-                                module,
-                                original_target_dir,
-                                &event,
-                            ) {
-                                continue;
-                            }
-                        }
-                        // windows has different file event kind
-                        #[cfg(windows)]
-                        EventKind::Modify(_) => {
-                            if let Ok(None) = handle_file_change(
-                                moonc_opt,
-                                moonbuild_opt,
-                                registry_config,
-                                module,
-                                original_target_dir,
-                                &event,
-                            ) {
-                                continue;
-                            }
-                        }
-                        _ => {
-                            continue;
-                        }
-                    }
-                }
-                Err(e) => {
-                    println!("failed: {e:?}");
-                    continue;
-                }
+/// Block until some events matching `filter` arrive, then coalesce those
+/// arriving at the same time into one batch. Each new matching event restarts
+/// the `debounce` window, while the others are dropped without extending it.
+/// Returns `None` once the watcher is dropped.
+pub(crate) fn recv_debounced(
+    rx: &Receiver<notify::Result<notify::Event>>,
+    debounce: Duration,
+    filter: impl Fn(&notify::Event) -> bool,
+) -> Option<Vec<notify::Event>> {
+    let mut events = Vec::new();
+    // Whether the event was kept
+    let mut collect = |res: notify::Result<notify::Event>| match res {
+        Ok(event) if filter(&event) => {
+            events.push(event);
+            true
+        }
+        Ok(_) => false,
+        Err(e) => {
+            println!("failed: {e:?}");
+            false
+        }
+    };
+    while !collect(rx.recv().ok()?) {}
+    rx.try_iter().for_each(|res| {
+        collect(res);
+    });
+    if !debounce.is_zero() {
+        let mut deadline = Instant::now() + debounce;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let Ok(res) = rx.recv_timeout(timeout) else {
+                break;
+            };
+            if collect(res) {
+                deadline = Instant::now() + debounce;
             }
         }
     }
-    Ok(0)
+    Some(events)
 }
 
-fn handle_file_change(
-    moonc_opt: &MooncOpt,
-    moonbuild_opt: &MoonbuildOpt,
-    registry_config: &RegistryConfig,
-    module: &ModuleDB,
-    original_target_dir: &Path,
-    event: &notify::Event,
-) -> anyhow::Result<Option<()>> {
+/// Whether the event modifies a file. When a file was modified, multiple events
+/// may be received, we only care about those modified data.
+fn is_modification(event: &notify::Event) -> bool {
+    match event.kind {
+        EventKind::Modify(notify::event::ModifyKind::Data(_)) => true,
+        // windows has different file event kind
+        EventKind::Modify(_) => cfg!(windows),
+        _ => false,
+    }
+}
+
+/// The directory whose changes the legacy watcher ignores.
+fn legacy_ignore_dir<'a>(
+    moonbuild_opt: &'a MoonbuildOpt,
+    original_target_dir: &'a Path,
+) -> &'a Path {
     // check --watch will own a subdir named `watch` in target_dir but build --watch still use the original target_dir
-    let (source_dir, target_dir) = (&moonbuild_opt.source_dir, &moonbuild_opt.target_dir);
-    let original_target_dir = match moonbuild_opt.run_mode {
-        RunMode::Check => target_dir
+    match moonbuild_opt.run_mode {
+        RunMode::Check => moonbuild_opt
+            .target_dir
             .ancestors()
             .find(|p| p.ends_with(WATCH_MODE_DIR))
             .unwrap()
             .parent()
             .unwrap(),
         _ => original_target_dir,
-    };
-    if event.paths.iter().all(|p| {
-        p.starts_with(
-            // can't be `target_dir` since the real target dir for watch mode is `target_dir/watch`
-            original_target_dir,
-        )
-    }) {
+    }
+}
+
+fn handle_file_change(
+    moonc_opt: &MooncOpt,
+    moonbuild_opt: &MoonbuildOpt,
+    watch_opt: &WatchOpt,
+    registry_config: &RegistryConfig,
+    module: &ModuleDB,
+    ignore_dir: &Path,
+    events: &[notify::Event],
+) -> anyhow::Result<Option<()>> {
+    let (source_dir, target_dir) = (&moonbuild_opt.source_dir, &moonbuild_opt.target_dir);
+    // can't be `target_dir` since the real target dir for watch mode is `target_dir/watch`
+    let changed_paths = events
+        .iter()
+        .flat_map(|e| &e.paths)
+        .filter(|p| watch_opt.is_watched(p, ignore_dir))
+        .collect::<Vec<_>>();
+    if changed_paths.is_empty() {
         return Ok(None);
     }
+    for path in &changed_paths {
+        watch_opt.emit(WatchEvent::FileChanged { path });
+    }
 
     // prevent the case that the whole target_dir was deleted
     if !target_dir.exists() {
@@ -145,7 +435,7 @@ fn handle_file_change(
 
     let mut need_new_module = false;
     let mut cur_mbt_md_path = String::new();
-    for p in &event.paths {
+    for p in changed_paths {
         if p.display().to_string().ends_with(DOT_MBT_DOT_MD) {
             cur_mbt_md_path = p.display().to_string();
         }
@@ -159,7 +449,10 @@ fn handle_file_change(
     if need_new_module {
         let (resolved_env, dir_sync_result) = match auto_sync(
             source_dir,
-            &AutoSyncFlags { frozen: false },
+            &AutoSyncFlags {
+                frozen: false,
+                locked: false,
+            },
             registry_config,
             false,
         ) {
@@ -183,7 +476,7 @@ fn handle_file_change(
                 return Ok(None);
             }
         };
-        run_and_print(moonc_opt, moonbuild_opt, &module)?;
+        run_and_print(moonc_opt, moonbuild_opt, watch_opt, &module)?;
     } else {
         if cur_mbt_md_path.ends_with(DOT_MBT_DOT_MD) {
             for (_, pkg) in module.get_all_packages() {
@@ -194,7 +487,7 @@ fn handle_file_change(
                 }
             }
         }
-        run_and_print(moonc_opt, moonbuild_opt, module)?;
+        run_and_print(moonc_opt, moonbuild_opt, watch_opt, module)?;
     }
     Ok(Some(()))
 }
@@ -202,16 +495,40 @@ fn handle_file_change(
 fn run_and_print(
     moonc_opt: &MooncOpt,
     moonbuild_opt: &MoonbuildOpt,
+    watch_opt: &WatchOpt,
     module: &ModuleDB,
 ) -> anyhow::Result<()> {
-    print!("{esc}[2J{esc}[1;1H", esc = 27 as char);
-    let result = match moonbuild_opt.run_mode {
-        RunMode::Check => crate::entry::run_check(moonc_opt, moonbuild_opt, module),
-        RunMode::Build => crate::entry::run_build(moonc_opt, moonbuild_opt, module),
+    let run = match moonbuild_opt.run_mode {
+        RunMode::Check => crate::entry::run_check,
+        RunMode::Build => crate::entry::run_build,
         _ => {
             anyhow::bail!("watch mode only support check and build");
         }
     };
+    print_run(watch_opt, || run(moonc_opt, moonbuild_opt, module));
+    Ok(())
+}
+
+/// Clear the screen (or emit `rebuild_start`), run the rebuild and report how
+/// it went.
+fn print_run(watch_opt: &WatchOpt, run: impl FnOnce() -> anyhow::Result<i32>) {
+    if watch_opt.events_json {
+        watch_opt.emit(WatchEvent::RebuildStart);
+    } else {
+        print!("{esc}[2J{esc}[1;1H", esc = 27 as char);
+    }
+    let start = Instant::now();
+    let result = run();
+    if watch_opt.events_json {
+        if let Err(e) = &result {
+            eprintln!("{e:?}");
+        }
+        watch_opt.emit(WatchEvent::RebuildDone {
+            success: matches!(result, Ok(0)),
+            duration_ms: start.elapsed().as_millis(),
+        });
+        return;
+    }
     match result {
         Ok(0) => {
             println!(
@@ -233,5 +550,63 @@ fn run_and_print(
             );
         }
     }
-    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watch_ignore() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join(MOON_IGNORE),
+            "# editor files\n*.swp\n\n/gen/**/*.mbt\n",
+        )
+        .unwrap();
+        let ignore = WatchIgnore::new(dir.path(), &["out/".to_string()]).unwrap();
+        let ignored = |p: &str| ignore.is_ignored(&dir.path().join(p));
+        assert!(ignored(".git/index"));
+        assert!(ignored("src/.main.mbt.swp"));
+        assert!(ignored("gen/x.mbt"));
+        assert!(ignored("gen/a/b/x.mbt"));
+        assert!(!ignored("src/gen/x.mbt"));
+        assert!(ignored("lib/out/x.mbt"));
+        assert!(!ignored("lib/output.mbt"));
+        assert!(!ignored("src/main.mbt"));
+        assert!(!ignore.is_ignored(Path::new("/elsewhere/.git")));
+    }
+
+    #[test]
+    fn test_recv_debounced_ignored_events_dont_delay() {
+        let modify = |path: &str| {
+            Ok(
+                notify::Event::new(EventKind::Modify(notify::event::ModifyKind::Data(
+                    notify::event::DataChange::Any,
+                )))
+                .add_path(path.into()),
+            )
+        };
+        let (tx, rx) = std::sync::mpsc::channel();
+        tx.send(modify("src/main.mbt")).unwrap();
+        // A busy target directory keeps writing during the debounce window
+        let writer = std::thread::spawn(move || {
+            for _ in 0..100 {
+                if tx.send(modify("target/out.wasm")).is_err() {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        });
+        let start = Instant::now();
+        let events = recv_debounced(&rx, Duration::from_millis(50), |e| {
+            !e.paths.iter().any(|p| p.starts_with("target"))
+        })
+        .unwrap();
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].paths, [Path::new("src/main.mbt")]);
+        drop(rx);
+        writer.join().unwrap();
+    }
 }
@@ -252,6 +252,83 @@ pub fn sync_deps(
     Ok(())
 }
 
+/// Check that the dependencies directory is in sync with the target package
+/// list without modifying it, failing with the differences otherwise.
+pub fn check_deps(dep_dir: &DepDir, pkg_list: &ResolvedEnv) -> anyhow::Result<()> {
+    let target_dep_dir = pkg_list_to_dep_dir_state(pkg_list.all_modules());
+    let current_dep_dir = if dep_dir.path().exists() {
+        dep_dir.get_current_state()?
+    } else {
+        DepDirState::new()
+    };
+
+    let diff = diff_dep_dir_state(&current_dep_dir, &target_dep_dir);
+    let changes = describe_dep_dir_diff(&current_dep_dir, &diff);
+    if !changes.is_empty() {
+        anyhow::bail!(
+            "the dependencies in `{}` need to be updated, but `--locked` was passed:\n{}",
+            dep_dir.path().display(),
+            changes.join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// List the changes of a diff, one line per package, sorted by name.
+fn describe_dep_dir_diff(current: &DepDirState, diff: &DepDirStateDiff) -> Vec<String> {
+    let version_of = |user: &ArcStr, pkg: &ArcStr| {
+        let version = current
+            .get(user)
+            .and_then(|p| p.get(pkg))
+            .cloned()
+            .flatten();
+        version.map_or_else(|| "(unknown version)".to_string(), |v| v.to_string())
+    };
+    let mut lines = vec![];
+    for (user, pkgs) in &diff.add_pkg {
+        let removed = diff.remove_pkg.get(user);
+        for (pkg, source) in pkgs {
+            if removed.is_some_and(|r| r.contains(pkg)) {
+                lines.push((
+                    format!("{user}/{pkg}"),
+                    format!(
+                        "  ~ {user}/{pkg} {} -> {}",
+                        version_of(user, pkg),
+                        source.version()
+                    ),
+                ));
+            } else {
+                lines.push((
+                    format!("{user}/{pkg}"),
+                    format!("  + {user}/{pkg} {}", source.version()),
+                ));
+            }
+        }
+    }
+    let removed_users = diff
+        .remove_user
+        .iter()
+        .flat_map(|user| current[user].keys().map(move |pkg| (user, pkg)));
+    let removed_pkgs = diff
+        .remove_pkg
+        .iter()
+        .flat_map(|(user, pkgs)| pkgs.iter().map(move |pkg| (user, pkg)))
+        .filter(|(user, pkg)| {
+            !diff
+                .add_pkg
+                .get(*user)
+                .is_some_and(|a| a.contains_key(*pkg))
+        });
+    for (user, pkg) in removed_users.chain(removed_pkgs) {
+        lines.push((
+            format!("{user}/{pkg}"),
+            format!("  - {user}/{pkg} {}", version_of(user, pkg)),
+        ));
+    }
+    lines.sort();
+    lines.into_iter().map(|(_, line)| line).collect()
+}
+
 fn pkg_to_dir(dep_dir: &DepDir, username: &str, pkgname: &str) -> PathBuf {
     // Special case: core library locates in ~/.moon
     if format!("{username}/{pkgname}") == MOONBITLANG_CORE {
@@ -448,4 +525,30 @@ mod test {
             "remove pkg"
         );
     }
+
+    #[test]
+    fn test_describe_diff() {
+        let original = to_state(
+            r"
+        user1:foo@1.0.0,bar@1.2.0
+        user2:baz@2.0.1
+        ",
+        );
+        let target = to_new_state(
+            r"
+        user1:foo@1.2.0
+        user3:qux@3.0.0
+        ",
+        );
+        let diff = super::diff_dep_dir_state(&original, &target);
+        assert_eq!(
+            super::describe_dep_dir_diff(&original, &diff),
+            vec![
+                "  - user1/bar 1.2.0",
+                "  ~ user1/foo 1.0.0 -> 1.2.0",
+                "  - user2/baz 2.0.1",
+                "  + user3/qux 3.0.0",
+            ]
+        );
+    }
 }
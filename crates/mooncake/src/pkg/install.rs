@@ -41,7 +41,7 @@ pub fn install(
 ) -> anyhow::Result<i32> {
     let m = read_module_desc_file_in_dir(source_dir)?;
     let m = Arc::new(m);
-    install_impl(source_dir, m, quiet, verbose, false).map(|_| 0)
+    install_impl(source_dir, m, quiet, verbose, false, false).map(|_| 0)
 }
 
 pub(crate) fn install_impl(
@@ -50,12 +50,15 @@ pub(crate) fn install_impl(
     quiet: bool,
     verbose: bool,
     dont_sync: bool,
+    locked: bool,
 ) -> anyhow::Result<(ResolvedEnv, DepDir)> {
     let registry = crate::registry::RegistryList::with_default_registry();
     let ms = ModuleSource::from_local_module(&m, source_dir).expect("Malformed module manifest");
     let res = resolve_single_root_with_defaults(&registry, ms, Arc::clone(&m))?;
     let dep_dir = crate::dep_dir::DepDir::of_source(source_dir);
-    if !dont_sync {
+    if locked {
+        crate::dep_dir::check_deps(&dep_dir, &res)?;
+    } else if !dont_sync {
         crate::dep_dir::sync_deps(&dep_dir, &registry, &res, quiet)
             .context("When installing packages")?;
     }
@@ -185,6 +188,8 @@ fn get_module_db(
         use_tcc_run: false,
         dynamic_stub_libs: None,
         render_no_loc: DiagnosticLevel::default(),
+        no_progress: false,
+        content_hash: false,
     };
     let module_db = scan(
         false,
@@ -189,6 +189,7 @@ impl OnlineRegistry {
             let data = std::fs::read(cache_file)?;
             return Ok(bytes::Bytes::from(data));
         }
+        moonutil::common::ensure_online(&format!("download {name}@{version}"))?;
         if !quiet {
             println!("Downloading {name}");
         }
@@ -79,7 +79,10 @@ pub struct Paths {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BuildScriptOutput {
-    /// Rerun conditions. **DOES NOT WORK NOW**
+    /// Rerun conditions. The output of the script is cached, and the script
+    /// is rerun only if one of these inputs, the script itself, the moon
+    /// version or the target backend changes. If empty, any change to the
+    /// environment also reruns the script.
     #[serde(default)]
     pub rerun_if: Vec<RerunIfKind>,
     // TODO: How much of these vars are useful? We don't fetch link flags from
@@ -28,6 +28,35 @@ use crate::dirs::SourceTargetDirs;
 //     no_std: bool,
 // }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+pub enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Apply the choice to this process, and to the child processes through
+    /// `NO_COLOR` and `CLICOLOR_FORCE`.
+    pub fn apply(self) {
+        match self {
+            // `colored` already checks the terminal and the variables
+            ColorChoice::Auto => {}
+            ColorChoice::Always => {
+                colored::control::set_override(true);
+                std::env::remove_var("NO_COLOR");
+                std::env::set_var("CLICOLOR_FORCE", "1");
+            }
+            ColorChoice::Never => {
+                colored::control::set_override(false);
+                std::env::remove_var("CLICOLOR_FORCE");
+                std::env::set_var("NO_COLOR", "1");
+            }
+        }
+    }
+}
+
 #[derive(Debug, clap::Parser, Serialize, Deserialize, Clone)]
 #[clap(next_help_heading = "Common Options")]
 pub struct UniversalFlags {
@@ -56,6 +85,18 @@ pub struct UniversalFlags {
     #[clap(long, global = true, conflicts_with = "dry_run")]
     pub build_graph: bool,
 
+    /// Allow the `pre-build` commands of packages to run, other than `:embed`. They can run arbitrary code, and running them without this flag is deprecated
+    #[clap(long, global = true, env = crate::common::MOON_ALLOW_PRE_BUILD_SCRIPTS, value_parser = clap::builder::FalseyValueParser::new())]
+    pub allow_pre_build_scripts: bool,
+
+    /// Never access the network. Commands that need it fail right away, and dependencies must already be cached
+    #[clap(long, global = true, env = crate::common::MOON_OFFLINE, value_parser = clap::builder::FalseyValueParser::new())]
+    pub offline: bool,
+
+    /// When to color the output. `auto` colors it if stdout is a terminal and `NO_COLOR` is not set
+    #[clap(long, global = true, value_name = "WHEN", default_value = "auto")]
+    pub color: ColorChoice,
+
     /// Unstable flags to MoonBuild.
     #[clap(long, short = 'Z', default_value = "", env = "MOON_UNSTABLE")]
     pub unstable_feature: Box<crate::features::FeatureGate>,
@@ -16,6 +16,7 @@
 //
 // For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
 
+use crate::compiler_flags::OptLevel as CCOptLevel;
 use crate::cond_expr::{CompileCondition, OptLevel};
 pub use crate::dirs::check_moon_mod_exists;
 use crate::module::{MoonMod, MoonModJSON};
@@ -46,6 +47,9 @@ pub const MOONBITLANG_ABORT: &str = "moonbitlang/core/abort";
 
 pub const MOON_TEST_DELIMITER_BEGIN: &str = "----- BEGIN MOON TEST RESULT -----";
 pub const MOON_TEST_DELIMITER_END: &str = "----- END MOON TEST RESULT -----";
+/// Printed to both `stdout` and `stderr` by the JS test driver before the first
+/// test, to tell the output of the tests from that of the driver.
+pub const MOON_TEST_OUTPUT_START: &str = "----- START MOON TEST OUTPUT -----";
 
 pub const MOON_COVERAGE_DELIMITER_BEGIN: &str = "----- BEGIN MOONBIT COVERAGE -----";
 pub const MOON_COVERAGE_DELIMITER_END: &str = "----- END MOONBIT COVERAGE -----";
@@ -72,6 +76,50 @@ pub const MOONCAKE_BIN: &str = "$mooncake_bin";
 pub const MOD_DIR: &str = "$mod_dir";
 pub const PKG_DIR: &str = "$pkg_dir";
 
+/// Set to allow `pre-build` commands other than `:embed`, which run arbitrary
+/// code, without the deprecation warning. `--allow-pre-build-scripts` sets it
+/// too, for the nested commands.
+pub const MOON_ALLOW_PRE_BUILD_SCRIPTS: &str = "MOON_ALLOW_PRE_BUILD_SCRIPTS";
+
+/// Whether the `pre-build` command is allowed to run. `:embed` is built in, so
+/// always allowed, while other commands should be allowed explicitly. They
+/// still run otherwise, with a warning, until this becomes an error.
+pub fn pre_build_command_allowed(command: &str) -> bool {
+    command.starts_with(":embed") || env_flag_set(MOON_ALLOW_PRE_BUILD_SCRIPTS)
+}
+
+/// Set to never access the network. `--offline` sets it too, for the nested
+/// commands.
+pub const MOON_OFFLINE: &str = "MOON_OFFLINE";
+
+/// Fail right away if `moon` runs offline, instead of waiting on the network
+/// to `action`.
+pub fn ensure_online(action: &str) -> anyhow::Result<()> {
+    if env_flag_set(MOON_OFFLINE) {
+        anyhow::bail!(
+            "cannot {action} in offline mode, remove `--offline` to allow network access"
+        );
+    }
+    Ok(())
+}
+
+/// Whether colors are disabled by `NO_COLOR`, which `--color never` sets.
+/// Escapes in the output of tests and tools are stripped then.
+pub fn no_color() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty())
+}
+
+/// Whether a boolean environment variable is set, with the same falsey values
+/// as clap's `FalseyValueParser`.
+fn env_flag_set(name: &str) -> bool {
+    std::env::var(name).is_ok_and(|v| {
+        !matches!(
+            v.to_ascii_lowercase().as_str(),
+            "" | "0" | "n" | "no" | "f" | "false" | "off"
+        )
+    })
+}
+
 pub const SINGLE_FILE_TEST_PACKAGE: &str = "moon/test/single";
 pub const SINGLE_FILE_TEST_MODULE: &str = "moon/test";
 
@@ -254,6 +302,7 @@ pub enum SurfaceTarget {
     Js,
     Native,
     LLVM,
+    Wasi,
     All,
 }
 
@@ -280,6 +329,9 @@ pub fn lower_surface_targets(st: &[SurfaceTarget]) -> Vec<TargetBackend> {
             SurfaceTarget::LLVM => {
                 result.insert(TargetBackend::LLVM);
             }
+            SurfaceTarget::Wasi => {
+                result.insert(TargetBackend::Wasi);
+            }
             SurfaceTarget::All => {
                 result.insert(TargetBackend::Wasm);
                 result.insert(TargetBackend::WasmGC);
@@ -288,6 +340,7 @@ pub fn lower_surface_targets(st: &[SurfaceTarget]) -> Vec<TargetBackend> {
                 result.insert(TargetBackend::Js);
                 // todo: enable native backend
                 // result.insert(TargetBackend::Native);
+                // not wasi either, which is experimental behind `-Z wasi`
             }
         }
     }
@@ -296,6 +349,71 @@ pub fn lower_surface_targets(st: &[SurfaceTarget]) -> Vec<TargetBackend> {
     result
 }
 
+/// The backends `build` and `test` go through for `--target all` in the
+/// module at `source_dir`: the ones declared in the `supported-targets` of
+/// its packages, or the backends of [`SurfaceTarget::All`] if no package
+/// declares any. MoonGRES is only included when a package declares it.
+pub fn all_targets_of_module(source_dir: &Path) -> anyhow::Result<Vec<TargetBackend>> {
+    let mod_desc = read_module_desc_file_in_dir(source_dir)?;
+    let root = source_dir.join(mod_desc.source.as_deref().unwrap_or_default());
+    let mut declared = std::collections::BTreeSet::new();
+    let mut walker = walkdir::WalkDir::new(&root).into_iter().filter_entry(|e| {
+        e.file_type().is_dir() && !IGNORE_DIRS.contains(&e.file_name().to_string_lossy().as_ref())
+    });
+    while let Some(entry) = walker.next() {
+        let dir = entry.context("failed to read entry")?.into_path();
+        if dir != root && dir.join(MOON_MOD_JSON).exists() {
+            // A module of its own, declaring its own backends
+            walker.skip_current_dir();
+            continue;
+        }
+        let pkg_json = dir.join(MOON_PKG_JSON);
+        if !pkg_json.exists() {
+            continue;
+        }
+        // Read the JSON as-is, since the package loaded by
+        // `read_package_desc_file_in_dir` supports all backends when it
+        // declares none
+        let j: MoonPkgJSON =
+            serde_json_lenient::from_reader(BufReader::new(File::open(&pkg_json)?))
+                .context(format!("Failed to parse {pkg_json:?}"))?;
+        for target in j.supported_targets.iter().flatten() {
+            declared.insert(TargetBackend::str_to_backend(target)?);
+        }
+    }
+    if !declared.is_empty() {
+        return Ok(declared.into_iter().collect());
+    }
+    #[allow(unused_mut)]
+    let mut res = lower_surface_targets(&[SurfaceTarget::All]);
+    #[cfg(feature = "moongres")]
+    res.retain(|it| *it != TargetBackend::MoonGRES);
+    Ok(res)
+}
+
+#[test]
+fn test_all_targets_of_module() {
+    use TargetBackend::*;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    std::fs::create_dir_all(dir.join("src/a")).unwrap();
+    std::fs::create_dir_all(dir.join("src/b")).unwrap();
+    std::fs::write(
+        dir.join(MOON_MOD_JSON),
+        r#"{"name": "u/m", "source": "src"}"#,
+    )
+    .unwrap();
+    std::fs::write(dir.join("src/a").join(MOON_PKG_JSON), "{}").unwrap();
+    assert_eq!(all_targets_of_module(dir).unwrap(), [Wasm, WasmGC, Js]);
+    std::fs::write(
+        dir.join("src/b").join(MOON_PKG_JSON),
+        r#"{"supported-targets": ["native", "js"]}"#,
+    )
+    .unwrap();
+    assert_eq!(all_targets_of_module(dir).unwrap(), [Js, Native]);
+}
+
 #[rustfmt::skip]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Serialize, Deserialize, Default, Hash)]
 #[repr(u8)]
@@ -307,7 +425,8 @@ pub enum TargetBackend {
     MoonGRES,
     Js,
     Native,
-    LLVM
+    LLVM,
+    Wasi
 }
 
 impl std::fmt::Display for TargetBackend {
@@ -326,6 +445,7 @@ impl TargetBackend {
             Self::Js => "js",
             Self::Native => "native",
             Self::LLVM => "llvm",
+            Self::Wasi => "wasm", // WASI modules are compiled as plain Wasm
         }
     }
 
@@ -338,6 +458,7 @@ impl TargetBackend {
             Self::Js => "js",
             Self::Native => "exe",
             Self::LLVM => "exe",
+            Self::Wasi => "wasm",
         }
     }
 
@@ -350,6 +471,7 @@ impl TargetBackend {
             Self::Js => "js",
             Self::Native => "c",
             Self::LLVM => O_EXT,
+            Self::Wasi => "wasm",
         }
     }
 
@@ -362,6 +484,7 @@ impl TargetBackend {
             Self::Js => "js",
             Self::Native => "native",
             Self::LLVM => "llvm",
+            Self::Wasi => "wasi",
         }
     }
 
@@ -374,6 +497,7 @@ impl TargetBackend {
             Self::Js => "js",
             Self::Native => "native",
             Self::LLVM => "llvm",
+            Self::Wasi => "wasi",
         }
     }
 
@@ -386,8 +510,9 @@ impl TargetBackend {
             "js" => Ok(Self::Js),
             "native" => Ok(Self::Native),
             "llvm" => Ok(Self::LLVM),
+            "wasi" => Ok(Self::Wasi),
             _ => bail!(
-                "invalid backend: {}, only support wasm, wasm-gc, js, native, llvm",
+                "invalid backend: {}, only support wasm, wasm-gc, js, native, llvm, wasi",
                 s
             ),
         }
@@ -405,7 +530,7 @@ impl TargetBackend {
     pub fn is_native(self) -> bool {
         match self {
             Self::Native | Self::LLVM => true,
-            Self::Wasm | Self::WasmGC | Self::Js => false,
+            Self::Wasm | Self::WasmGC | Self::Js | Self::Wasi => false,
             #[cfg(feature = "moongres")]
             Self::MoonGRES => false,
         }
@@ -420,6 +545,7 @@ impl TargetBackend {
             Self::Js,
             Self::Native,
             Self::LLVM,
+            Self::Wasi,
         ]
     }
 
@@ -428,22 +554,31 @@ impl TargetBackend {
             Self::WasmGC | Self::Js => true,
             #[cfg(feature = "moongres")]
             Self::MoonGRES => true,
-            Self::Wasm | Self::Native | Self::LLVM => false,
+            Self::Wasm | Self::Native | Self::LLVM | Self::Wasi => false,
         }
     }
 
     pub fn is_wasm(&self) -> bool {
         match self {
-            Self::Wasm | Self::WasmGC => true,
+            Self::Wasm | Self::WasmGC | Self::Wasi => true,
             #[cfg(feature = "moongres")]
             Self::MoonGRES => true,
             Self::Js | Self::Native | Self::LLVM => false,
         }
     }
 
+    /// The backend to match against in conditional compilation. WASI shares
+    /// the same output as plain Wasm, so it's treated as Wasm there.
+    pub fn to_cond_target(self) -> Self {
+        match self {
+            Self::Wasi => Self::Wasm,
+            _ => self,
+        }
+    }
+
     pub fn allowed_as_project_target(&self) -> bool {
         match self {
-            Self::Wasm | Self::WasmGC | Self::Js | Self::Native => true,
+            Self::Wasm | Self::WasmGC | Self::Js | Self::Native | Self::Wasi => true,
             #[cfg(feature = "moongres")]
             Self::MoonGRES => true,
             Self::LLVM => false,
@@ -522,6 +657,11 @@ pub struct MoonbuildOpt {
     pub use_tcc_run: bool,
     pub dynamic_stub_libs: Option<Vec<String>>,
     pub render_no_loc: DiagnosticLevel,
+    /// Do not show the build progress, even on terminals
+    pub no_progress: bool,
+    /// Check whether source files changed by their content, see
+    /// `moonbuild::content_hash`
+    pub content_hash: bool,
 }
 
 impl MoonbuildOpt {
@@ -535,6 +675,9 @@ pub struct BuildOpt {
     pub install_path: Option<PathBuf>,
 
     pub filter_package: Option<String>,
+
+    /// Keep building the packages not depending on failed ones
+    pub keep_going: bool,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -543,6 +686,11 @@ pub struct CheckOpt {
     pub patch_file: Option<PathBuf>,
     pub no_mi: bool,
     pub explain: bool,
+    /// Print diagnostics as LSP JSON, see [`crate::render::LspDiagnostic`]
+    pub diagnostics_json: bool,
+    /// Write the diagnostics as a SARIF report to this file, see
+    /// [`crate::sarif`]
+    pub sarif: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -590,8 +738,12 @@ impl BlockStyle {
 #[derive(Debug, Clone, Default)]
 pub struct FmtOpt {
     pub check: bool,
+    /// Print the differences when checking
+    pub diff: bool,
     pub block_style: BlockStyle,
     pub extra_args: Vec<String>,
+    /// Only format these files, as canonicalized paths
+    pub only_files: Option<HashSet<PathBuf>>,
 }
 
 #[derive(Debug, Clone)]
@@ -623,6 +775,103 @@ impl MooncOpt {
             single_file: false,
         }
     }
+
+    /// Resolve the compiler flags of building the module in `src_dir` with
+    /// `opts`, taking the extra flags and the preferred target from its
+    /// `moon.mod.json`.
+    pub fn for_module(src_dir: &Path, opts: &CompilerFlagsOptions) -> anyhow::Result<Self> {
+        if opts.native_opt_level.is_some() {
+            bail!("the C optimization level is only supported with Rupes Recta");
+        }
+        if !check_moon_mod_exists(src_dir) {
+            bail!("could not find `{}`", MOON_MOD_JSON);
+        }
+        let moon_mod = read_module_desc_file_in_dir(src_dir)?;
+        let extra_build_opt = moon_mod.compile_flags.unwrap_or_default();
+        let extra_link_opt = moon_mod.link_flags.unwrap_or_default();
+
+        let output_format = if opts.output_wat {
+            OutputFormat::Wat
+        } else {
+            OutputFormat::Wasm
+        };
+
+        let target_backend = opts
+            .target_backend
+            .or(moon_mod.preferred_target) // if we have specified in module config
+            .unwrap_or_default();
+
+        if target_backend == TargetBackend::Js && output_format == OutputFormat::Wat {
+            bail!("--output-wat is not supported for --target js");
+        }
+
+        let output_format = match target_backend {
+            TargetBackend::Js => OutputFormat::Js,
+            TargetBackend::Native => OutputFormat::Native,
+            TargetBackend::LLVM => OutputFormat::LLVM,
+            _ => output_format,
+        };
+
+        let debug_flag = opts.debug;
+        let source_map = debug_flag && target_backend.supports_source_map();
+
+        let build_opt = BuildPackageFlags {
+            debug_flag,
+            strip_flag: opts.strip.unwrap_or(!debug_flag),
+            source_map,
+            enable_coverage: opts.enable_coverage,
+            deny_warn: false,
+            target_backend,
+            warn_list: opts.warn_list.clone(),
+            alert_list: opts.alert_list.clone(),
+            enable_value_tracing: opts.enable_value_tracing,
+        };
+
+        let link_opt = LinkCoreFlags {
+            debug_flag,
+            source_map,
+            output_format,
+            target_backend,
+        };
+
+        let nostd = opts.no_std || moon_mod.name == MOONBITLANG_CORE;
+        let render = !opts.no_render || std::env::var("MOON_NO_RENDER").unwrap_or_default() == "1";
+
+        Ok(Self {
+            build_opt,
+            link_opt,
+            extra_build_opt,
+            extra_link_opt,
+            nostd,
+            render,
+            single_file: false,
+        })
+    }
+}
+
+/// The options that [`MooncOpt::for_module`] resolves the compiler flags from,
+/// the defaults being those of a release build for the preferred target.
+#[derive(Debug, Clone, Default)]
+pub struct CompilerFlagsOptions {
+    /// `None` to use the preferred target of the module, or the default one
+    pub target_backend: Option<TargetBackend>,
+    /// Build in the debug profile instead of the release one
+    pub debug: bool,
+    /// Whether to strip the debug info; `None` to strip it in release builds
+    pub strip: Option<bool>,
+    /// Output WAT instead of WASM
+    pub output_wat: bool,
+    pub enable_coverage: bool,
+    pub enable_value_tracing: bool,
+    pub warn_list: Option<String>,
+    pub alert_list: Option<String>,
+    /// Don't link the standard library
+    pub no_std: bool,
+    /// Don't render the diagnostics of `moonc`
+    pub no_render: bool,
+    /// The C optimization level of the native backends, which only the
+    /// Rupes Recta build supports
+    pub native_opt_level: Option<CCOptLevel>,
 }
 
 pub const DEP_PATH: &str = ".mooncakes";
@@ -1200,6 +1449,7 @@ pub fn gen_moonbitlang_abort_pkg(moonc_opt: &MooncOpt) -> Package {
         alert_list: None,
         targets: None,
         pre_build: None,
+        post_build: vec![],
         patch_file: None,
         no_mi: false,
         install_path: None,
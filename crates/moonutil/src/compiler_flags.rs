@@ -270,12 +270,16 @@ pub enum OutputType {
     Executable, // .exe or no extension
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum OptLevel {
     Speed,
     Size,
     Debug,
     None,
+    // -O1, -O3 and -Oz, only selected explicitly by `--opt-level`
+    Basic,
+    Aggressive,
+    MinSize,
 }
 
 #[derive(Clone, PartialEq, Eq, Builder)]
@@ -749,6 +753,28 @@ fn add_cc_optimization_flags(
                     buf.push("-O0".to_string());
                 }
             }
+            // MSVC has no finer levels of optimizing for speed than /O2
+            OptLevel::Basic => {
+                if cc.is_msvc() {
+                    buf.push("/O2".to_string());
+                } else if cc.is_full_featured_gcc_like() {
+                    buf.push("-O1".to_string());
+                }
+            }
+            OptLevel::Aggressive => {
+                if cc.is_msvc() {
+                    buf.push("/O2".to_string());
+                } else if cc.is_full_featured_gcc_like() {
+                    buf.push("-O3".to_string());
+                }
+            }
+            OptLevel::MinSize => {
+                if cc.is_msvc() {
+                    buf.push("/O1".to_string());
+                } else if cc.is_full_featured_gcc_like() {
+                    buf.push("-Oz".to_string());
+                }
+            }
         }
     }
 }
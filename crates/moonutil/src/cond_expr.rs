@@ -66,7 +66,7 @@ impl CondExpr {
         match self {
             CondExpr::Atom(atom) => match atom {
                 Atom::OptLevel(level) => level == &opt_level,
-                Atom::Target(backend) => backend == &target_backend,
+                Atom::Target(backend) => *backend == target_backend.to_cond_target(),
             },
             CondExpr::Condition(op, exprs) => match op {
                 LogicOp::And => exprs.iter().all(|x| x.eval(opt_level, target_backend)),
@@ -122,7 +122,8 @@ pub struct CompileCondition {
 
 impl CompileCondition {
     pub fn eval(&self, opt_level: OptLevel, target_backend: TargetBackend) -> bool {
-        self.optlevel.contains(&opt_level) && self.backend.contains(&target_backend)
+        self.optlevel.contains(&opt_level)
+            && self.backend.contains(&target_backend.to_cond_target())
     }
 }
 
@@ -355,7 +356,10 @@ impl From<CondExpr> for StringOrArray {
                 Atom::OptLevel(OptLevel::Release) => StringOrArray::String("release".to_string()),
                 Atom::OptLevel(OptLevel::Debug) => StringOrArray::String("debug".to_string()),
                 Atom::Target(tb) => match tb {
-                    TargetBackend::Wasm => StringOrArray::String("wasm".to_string()),
+                    // WASI is never parsed from conditional expressions
+                    TargetBackend::Wasm | TargetBackend::Wasi => {
+                        StringOrArray::String("wasm".to_string())
+                    }
                     TargetBackend::WasmGC => StringOrArray::String("wasm-gc".to_string()),
                     #[cfg(feature = "moongres")]
                     TargetBackend::MoonGRES => StringOrArray::String("moongres".to_string()),
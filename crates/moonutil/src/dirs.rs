@@ -49,7 +49,7 @@ pub struct SourceTargetDirs {
     #[arg(long = "directory", global = true, alias = "source-dir", short = 'C')]
     pub source_dir: Option<PathBuf>,
 
-    /// The target directory. Defaults to `source_dir/target`.
+    /// The target directory. Defaults to `$MOON_TARGET_DIR`, or `source_dir/target` if unset.
     #[clap(long, global = true)]
     pub target_dir: Option<PathBuf>,
 }
@@ -60,6 +60,9 @@ impl SourceTargetDirs {
     }
 }
 
+/// The environment variable overriding the default target directory.
+pub const MOON_TARGET_DIR: &str = "MOON_TARGET_DIR";
+
 pub struct PackageDirs {
     pub source_dir: PathBuf,
     pub target_dir: PathBuf,
@@ -80,6 +83,35 @@ fn find_ancestor_with_mod(source_dir: &Path) -> Option<PathBuf> {
         .map(|p| p.to_path_buf())
 }
 
+/// The directories of the modules in the workspace containing `dir`, with the
+/// root of the workspace first.
+///
+/// The root is the outermost ancestor of `dir` with a `moon.mod.json`, not the
+/// nearest one, so the workspace is the same from any directory in it,
+/// including those of nested modules. The packages of a module never include
+/// the modules nested in it, so those are found by walking down from the root,
+/// skipping [`IGNORE_DIRS`] as the package discovery does.
+pub fn workspace_modules(dir: &Path) -> Option<Vec<PathBuf>> {
+    let root = dir
+        .ancestors()
+        .filter(|d| check_moon_mod_exists(d))
+        .last()?;
+    let walker = walkdir::WalkDir::new(root)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| {
+            e.file_type().is_dir()
+                && !IGNORE_DIRS.contains(&e.file_name().to_str().unwrap_or_default())
+        });
+    Some(
+        walker
+            .flatten()
+            .filter(|e| check_moon_mod_exists(e.path()))
+            .map(|e| e.into_path())
+            .collect(),
+    )
+}
+
 fn get_src_dst_dir(matches: &SourceTargetDirs) -> Result<PackageDirs, PackageDirsError> {
     let source_dir = match matches.source_dir.clone() {
         Some(v) => v,
@@ -93,10 +125,20 @@ fn get_src_dst_dir(matches: &SourceTargetDirs) -> Result<PackageDirs, PackageDir
     let project_root = find_ancestor_with_mod(&source_dir)
         .ok_or_else(|| PackageDirsError::NotInProject(source_dir.clone()))?;
 
-    let target_dir = matches
-        .target_dir
-        .clone()
-        .unwrap_or_else(|| project_root.join("target"));
+    let target_dir = match (&matches.target_dir, std::env::var_os(MOON_TARGET_DIR)) {
+        (Some(v), _) => v.clone(),
+        // Relative paths are ambiguous for an environment variable shared by
+        // different checkouts
+        (None, Some(v)) if !Path::new(&v).is_absolute() => {
+            return Err(anyhow::anyhow!(
+                "`{MOON_TARGET_DIR}` must be an absolute path, got `{}`",
+                Path::new(&v).display()
+            )
+            .into());
+        }
+        (None, Some(v)) => PathBuf::from(v),
+        (None, None) => project_root.join("target"),
+    };
     if !target_dir.exists() {
         std::fs::create_dir_all(&target_dir)
             .context("failed to create target directory")
@@ -234,9 +276,15 @@ pub fn clean_dir_in_target(target_dir: &Path) -> anyhow::Result<()> {
         for entry in std::fs::read_dir(d)? {
             let entry = entry?;
             let path = entry.path();
-            if path.is_file()
-                && path.file_name().and_then(|name| name.to_str()) != Some(MOON_PID_NAME)
-            {
+            // Keep the PID files of running watchers, i.e. `.moon.pid` and
+            // `.moon.<kind>.pid`
+            let is_pid_file = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| {
+                    name == MOON_PID_NAME || (name.starts_with(".moon.") && name.ends_with(".pid"))
+                });
+            if path.is_file() && !is_pid_file {
                 fs::remove_file(path)?;
             } else if path.is_dir() {
                 fs::remove_dir_all(path)?;
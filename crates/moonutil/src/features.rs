@@ -134,6 +134,9 @@ features! {
     (unstable, rr_export_package_graph, "Export the package dependency graph (only with Rupes Recta)"),
     (unstable, rr_export_build_plan, "Export the build plan graph (only with Rupes Recta)"),
     (unstable, rr_n2_explain, "Ask n2 to explain rerun reasons (only with Rupes Recta)"),
+    (unstable, rr_tcc_run, "Run debug Native tests with `tcc -run` (only with Rupes Recta)"),
+    (unstable, content_hash, "Don't rebuild for source files touched without changing their content"),
+    (unstable, wasi, "Enable the experimental `wasi` target run with `wasmtime` (only with Rupes Recta)"),
 }
 
 impl FromStr for Box<FeatureGate> {
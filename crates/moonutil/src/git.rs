@@ -16,7 +16,7 @@
 //
 // For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, thiserror::Error)]
 #[error("git command failed: `{cmd}`")]
@@ -143,3 +143,78 @@ fn test_bad_git_command() {
     assert!(err_msg.contains("git command failed: `git pull`"));
     assert!(err_msg.contains("Caused by:"));
 }
+
+/// Clone the latest commit of `url` into `dest`.
+pub fn git_clone_shallow(url: &str, dest: &Path) -> Result<(), GitCommandError> {
+    let args = ["clone", "--depth", "1", url, dest.to_str().unwrap()];
+    let mut git_clone = git_command(&args, Stdios::inherit())?;
+    let status = git_clone.wait().map_err(|e| GitCommandError {
+        cmd: format!("git {}", args.join(" ")),
+        source: GitCommandErrorKind::IO(e),
+    })?;
+    if !status.success() {
+        return Err(GitCommandError {
+            cmd: format!("git {}", args.join(" ")),
+            source: match status.code() {
+                Some(code) => GitCommandErrorKind::ExitStatus(code),
+                None => GitCommandErrorKind::UnknownExitCode,
+            },
+        });
+    }
+    Ok(())
+}
+
+/// Run git in `path` and return its stdout. Errors of git are printed to
+/// stderr, as they explain the failure better than the exit code.
+fn git_output(path: &Path, args: &[&str]) -> Result<String, GitCommandError> {
+    let args = [&["-C", path.to_str().unwrap()][..], args].concat();
+    let cmd = || format!("git {}", args.join(" "));
+    let stdios = Stdios {
+        stdin: std::process::Stdio::null(),
+        stdout: std::process::Stdio::piped(),
+        stderr: std::process::Stdio::inherit(),
+    };
+    let output = git_command(&args, stdios)?
+        .wait_with_output()
+        .map_err(|e| GitCommandError {
+            cmd: cmd(),
+            source: GitCommandErrorKind::IO(e),
+        })?;
+    if !output.status.success() {
+        return Err(GitCommandError {
+            cmd: cmd(),
+            source: match output.status.code() {
+                Some(code) => GitCommandErrorKind::ExitStatus(code),
+                None => GitCommandErrorKind::UnknownExitCode,
+            },
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The files under `path` which differ from `git_ref` in the working tree,
+/// including untracked ones but not deleted ones.
+pub fn changed_files_since(path: &Path, git_ref: &str) -> Result<Vec<PathBuf>, GitCommandError> {
+    let changed = git_output(
+        path,
+        &[
+            "diff",
+            "--name-only",
+            "--relative",
+            "--diff-filter=d",
+            "-z",
+            git_ref,
+            "--",
+        ],
+    )?;
+    let untracked = git_output(path, &["ls-files", "--others", "--exclude-standard", "-z"])?;
+    let mut files: Vec<PathBuf> = changed
+        .split('\0')
+        .chain(untracked.split('\0'))
+        .filter(|f| !f.is_empty())
+        .map(|f| path.join(f))
+        .collect();
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
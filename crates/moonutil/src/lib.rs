@@ -36,7 +36,9 @@ pub mod moon_dir;
 pub mod mooncake_bin;
 pub mod mooncakes;
 pub mod package;
+pub mod parallelism;
 pub mod path;
 pub mod render;
+pub mod sarif;
 pub mod scan;
 pub mod version;
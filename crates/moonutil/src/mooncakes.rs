@@ -457,6 +457,12 @@ pub mod sync {
         /// Do not sync dependencies, assuming local dependencies are up-to-date
         #[clap(long)]
         pub frozen: bool,
+
+        /// Fail instead of syncing if the dependencies in `.mooncakes` don't
+        /// match the ones resolved from the manifest
+        #[clap(long, conflicts_with = "frozen")]
+        #[serde(default)]
+        pub locked: bool,
     }
 
     impl AutoSyncFlags {
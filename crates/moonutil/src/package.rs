@@ -30,7 +30,7 @@ use serde::{Deserialize, Serialize};
 use crate::{
     common::{
         FileName, GeneratedTestDriver, TargetBackend, TargetBackend::Js, TargetBackend::Native,
-        TargetBackend::Wasm, TargetBackend::WasmGC, TargetBackend::LLVM,
+        TargetBackend::Wasi, TargetBackend::Wasm, TargetBackend::WasmGC, TargetBackend::LLVM,
     },
     cond_expr::{CompileCondition, CondExpr, CondExprs},
     path::{ImportComponent, PathComponent},
@@ -87,6 +87,7 @@ pub struct Package {
 
     pub targets: Option<IndexMap<FileName, CondExpr>>,
     pub pre_build: Option<Vec<MoonPkgGenerate>>,
+    pub post_build: Vec<PostBuildCopy>,
 
     // for ide moon check
     pub patch_file: Option<PathBuf>,
@@ -301,6 +302,12 @@ pub struct MoonPkgJSON {
     #[schemars(rename = "pre-build")]
     pub pre_build: Option<Vec<MoonPkgGenerate>>,
 
+    /// Files to copy after a successful build
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(alias = "post-build")]
+    #[schemars(rename = "post-build")]
+    pub post_build: Option<Vec<PostBuildCopy>>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(alias = "bin-name")]
     #[schemars(rename = "bin-name")]
@@ -398,7 +405,7 @@ impl LinkDepItem {
 
     pub fn exports(&self, b: TargetBackend) -> Option<&[String]> {
         match b {
-            Wasm => self.wasm_exports(),
+            Wasm | Wasi => self.wasm_exports(),
             WasmGC => self.wasm_gc_exports(),
             #[cfg(feature = "moongres")]
             TargetBackend::MoonGRES => None,
@@ -410,7 +417,7 @@ impl LinkDepItem {
 
     pub fn export_memory_name(&self, b: TargetBackend) -> Option<&str> {
         match b {
-            Wasm => self.wasm_export_memory_name(),
+            Wasm | Wasi => self.wasm_export_memory_name(),
             WasmGC => self.wasm_gc_export_memory_name(),
             #[cfg(feature = "moongres")]
             TargetBackend::MoonGRES => None,
@@ -422,7 +429,7 @@ impl LinkDepItem {
 
     pub fn heap_start_address(&self, b: TargetBackend) -> Option<u32> {
         match b {
-            Wasm => self.wasm_heap_start_address(),
+            Wasm | Wasi => self.wasm_heap_start_address(),
             WasmGC => None,
             #[cfg(feature = "moongres")]
             TargetBackend::MoonGRES => None,
@@ -434,7 +441,7 @@ impl LinkDepItem {
 
     pub fn import_memory(&self, b: TargetBackend) -> Option<&ImportMemory> {
         match b {
-            Wasm => self.wasm_import_memory(),
+            Wasm | Wasi => self.wasm_import_memory(),
             WasmGC => self.wasm_gc_import_memory(),
             #[cfg(feature = "moongres")]
             TargetBackend::MoonGRES => None,
@@ -446,7 +453,7 @@ impl LinkDepItem {
 
     pub fn memory_limits(&self, b:TargetBackend) -> Option<&MemoryLimits> {
         match b {
-            Wasm => self.wasm_memory_limits(),
+            Wasm | Wasi => self.wasm_memory_limits(),
             WasmGC => self.wasm_gc_memory_limits(),
             #[cfg(feature = "moongres")]
             TargetBackend::MoonGRES => self.moongres_memory_limits(),
@@ -458,7 +465,7 @@ impl LinkDepItem {
 
     pub fn shared_memory(&self, b: TargetBackend) -> Option<bool> {
         match b {
-            Wasm => self.wasm_shared_memory(),
+            Wasm | Wasi => self.wasm_shared_memory(),
             WasmGC => self.wasm_gc_shared_memory(),
             #[cfg(feature = "moongres")]
             TargetBackend::MoonGRES => self.moongres_shared_memory(),
@@ -470,7 +477,7 @@ impl LinkDepItem {
 
     pub fn link_flags(&self, b: TargetBackend) -> Option<&[String]> {
         match b {
-            Wasm => self.wasm_link_flags(),
+            Wasm | Wasi => self.wasm_link_flags(),
             WasmGC => self.wasm_gc_link_flags(),
             #[cfg(feature = "moongres")]
             TargetBackend::MoonGRES => self.moongres_link_flags(),
@@ -700,7 +707,7 @@ pub struct Link {
 impl Link {
     pub fn need_link(&self, target: TargetBackend) -> bool {
         match target {
-            Wasm | WasmGC | Js => true,
+            Wasm | WasmGC | Js | Wasi => true,
             #[cfg(feature = "moongres")]
             TargetBackend::MoonGRES => true,
             Native | LLVM => self.native.as_ref().is_some_and(|n| {
@@ -720,6 +727,24 @@ pub struct MoonPkgGenerate {
     pub command: String,
 }
 
+/// Copy an artifact of the package to `to` after a successful build.
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct PostBuildCopy {
+    /// The artifact to copy
+    pub from: ArtifactSelector,
+    /// The destination, relative to the package directory. If it ends with
+    /// `/`, the artifact is copied into it keeping its file name
+    pub to: String,
+}
+
+/// An artifact of a package. `executable` is the linked output to run for the
+/// target backend, i.e. the `.wasm`, `.js` or native executable
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArtifactSelector {
+    Executable,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(untagged)]
 pub enum StringOrArray {
@@ -776,6 +801,7 @@ pub struct MoonPkg {
     pub targets: Option<CondExprs>,
 
     pub pre_build: Option<Vec<MoonPkgGenerate>>,
+    pub post_build: Vec<PostBuildCopy>,
 
     pub bin_name: Option<String>,
     pub bin_target: TargetBackend,
@@ -927,6 +953,7 @@ pub fn convert_pkg_json_to_package(j: MoonPkgJSON) -> anyhow::Result<MoonPkg> {
         alert_list: j.alert_list,
         targets: j.targets,
         pre_build: j.pre_build,
+        post_build: j.post_build.unwrap_or_default(),
         bin_name: j.bin_name,
         bin_target,
         supported_targets: supported_backends,
@@ -0,0 +1,157 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! The default number of parallel jobs of builds and tests.
+
+use std::num::NonZeroUsize;
+
+/// The number of jobs to run in parallel when `--jobs` is not given: the
+/// available parallelism of the machine, clamped to the CPU quota of the
+/// cgroups of `moon` on Linux. Containers in CI often have much fewer CPUs
+/// allotted than the host has, and running a job per host CPU thrashes them.
+pub fn default_parallelism() -> std::io::Result<NonZeroUsize> {
+    let available = std::thread::available_parallelism()?;
+    Ok(match cgroup_cpu_limit() {
+        Some(limit) => available.min(limit),
+        None => available,
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn cgroup_cpu_limit() -> Option<NonZeroUsize> {
+    None
+}
+
+/// The smallest CPU quota of the cgroups `moon` is in, including their
+/// ancestors, reading `cpu.max` of cgroup v2 and the CFS quota of cgroup v1.
+#[cfg(target_os = "linux")]
+fn cgroup_cpu_limit() -> Option<NonZeroUsize> {
+    use std::path::Path;
+
+    let cgroups = std::fs::read_to_string("/proc/self/cgroup").ok()?;
+    let mut limit = None;
+    for line in cgroups.lines() {
+        // `<id>:<controllers>:<path>`, with no controllers for cgroup v2
+        let mut parts = line.splitn(3, ':');
+        let (Some(_), Some(controllers), Some(path)) = (parts.next(), parts.next(), parts.next())
+        else {
+            continue;
+        };
+        let found = if controllers.is_empty() {
+            min_in_hierarchy(Path::new("/sys/fs/cgroup"), path, |dir| {
+                parse_cpu_max(&std::fs::read_to_string(dir.join("cpu.max")).ok()?)
+            })
+        } else if controllers.split(',').any(|it| it == "cpu") {
+            ["/sys/fs/cgroup/cpu", "/sys/fs/cgroup/cpu,cpuacct"]
+                .iter()
+                .find_map(|root| {
+                    min_in_hierarchy(Path::new(root), path, |dir| {
+                        let read = |name| std::fs::read_to_string(dir.join(name)).ok();
+                        parse_cfs_quota(&read("cpu.cfs_quota_us")?, &read("cpu.cfs_period_us")?)
+                    })
+                })
+        } else {
+            None
+        };
+        limit = limit.into_iter().chain(found).min();
+    }
+    limit
+}
+
+/// The smallest limit read by `read` from the cgroup at `path` under the
+/// mount point `root` and its ancestors.
+///
+/// Without a cgroup namespace, `path` is relative to the root of the host,
+/// while a container only mounts its own cgroup at `root`. The directories
+/// that don't exist are skipped, so the limit of the container is still found
+/// at `root` then.
+#[cfg(target_os = "linux")]
+fn min_in_hierarchy(
+    root: &std::path::Path,
+    path: &str,
+    read: impl Fn(&std::path::Path) -> Option<NonZeroUsize>,
+) -> Option<NonZeroUsize> {
+    if !root.is_dir() {
+        return None;
+    }
+    let dir = root.join(path.trim_start_matches('/'));
+    dir.ancestors()
+        .take_while(|it| it.starts_with(root))
+        .filter_map(read)
+        .min()
+}
+
+/// Parse the `cpu.max` of cgroup v2, `<quota> <period>` or `max <period>`.
+#[cfg(target_os = "linux")]
+fn parse_cpu_max(content: &str) -> Option<NonZeroUsize> {
+    let mut parts = content.split_whitespace();
+    let quota = parts.next()?.parse().ok()?;
+    let period = parts.next()?.parse().ok()?;
+    quota_to_cpus(quota, period)
+}
+
+/// Parse the `cpu.cfs_quota_us` and `cpu.cfs_period_us` of cgroup v1, where a
+/// quota of `-1` means no limit.
+#[cfg(target_os = "linux")]
+fn parse_cfs_quota(quota: &str, period: &str) -> Option<NonZeroUsize> {
+    quota_to_cpus(quota.trim().parse().ok()?, period.trim().parse().ok()?)
+}
+
+/// The number of whole CPUs of a quota, at least one, so that a quota of 1.5
+/// CPUs doesn't oversubscribe with 2 jobs.
+#[cfg(target_os = "linux")]
+fn quota_to_cpus(quota: u64, period: u64) -> Option<NonZeroUsize> {
+    if period == 0 {
+        return None;
+    }
+    Some(NonZeroUsize::new((quota / period) as usize).unwrap_or(NonZeroUsize::MIN))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cgroup_quota() {
+        assert_eq!(parse_cpu_max("max 100000\n"), None);
+        assert_eq!(parse_cpu_max("400000 100000\n"), NonZeroUsize::new(4));
+        assert_eq!(parse_cpu_max("150000 100000\n"), NonZeroUsize::new(1));
+        assert_eq!(parse_cpu_max("50000 100000\n"), NonZeroUsize::new(1));
+        assert_eq!(parse_cfs_quota("-1\n", "100000\n"), None);
+        assert_eq!(
+            parse_cfs_quota("200000\n", "100000\n"),
+            NonZeroUsize::new(2)
+        );
+    }
+
+    #[test]
+    fn test_min_in_hierarchy() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let inner = root.join("a/b");
+        std::fs::create_dir_all(&inner).unwrap();
+        std::fs::write(root.join("a/cpu.max"), "200000 100000").unwrap();
+        std::fs::write(inner.join("cpu.max"), "max 100000").unwrap();
+        let read = |dir: &std::path::Path| {
+            parse_cpu_max(&std::fs::read_to_string(dir.join("cpu.max")).ok()?)
+        };
+        assert_eq!(min_in_hierarchy(root, "/a/b", read), NonZeroUsize::new(2));
+        // The cgroup of the host, not visible in the container
+        assert_eq!(min_in_hierarchy(root, "/docker/123", read), None);
+    }
+}
@@ -66,6 +66,53 @@ impl Position {
     }
 }
 
+/// A diagnostic in the shape of an LSP `Diagnostic`, plus the `path` of the
+/// file it belongs to. moonc doesn't report related information, so there is
+/// no `relatedInformation`.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct LspDiagnostic {
+    pub path: String,
+    pub range: LspRange,
+    /// 1 for errors, 2 for warnings and 3 for the rest
+    pub severity: u8,
+    pub code: String,
+    pub source: &'static str,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct LspRange {
+    pub start: LspPosition,
+    pub end: LspPosition,
+}
+
+/// A 0-based position, with `character` counted in UTF-16 code units as LSP
+/// does by default.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct LspPosition {
+    pub line: u32,
+    pub character: u32,
+}
+
+impl Position {
+    /// moonc counts lines and Unicode scalar values from 1. Without the
+    /// source, the column can't be converted and is assumed to be ASCII.
+    fn to_lsp(&self, line_index: Option<&line_index::LineIndex>) -> LspPosition {
+        let line = (self.line as u32).saturating_sub(1);
+        let col = (self.col as u32).saturating_sub(1);
+        let character = line_index
+            .and_then(|index| {
+                let utf8 = index.to_utf8(
+                    line_index::WideEncoding::Utf32,
+                    line_index::WideLineCol { line, col },
+                )?;
+                index.to_wide(line_index::WideEncoding::Utf16, utf8)
+            })
+            .map_or(col, |wide| wide.col);
+        LspPosition { line, character }
+    }
+}
+
 #[derive(Deserialize)]
 struct SourceMap {
     mappings: Vec<SourceMapping>,
@@ -161,6 +208,15 @@ impl MooncDiagnostic {
         }
 
         let (kind, color) = diagnostic.get_level_and_color();
+        // `--color` is applied to `colored`
+        let use_color = colored::control::SHOULD_COLORIZE.should_colorize();
+        let paint = |s: String| {
+            if use_color {
+                s.fg(color).to_string()
+            } else {
+                s
+            }
+        };
 
         // for no-location diagnostic, like Missing main function in the main package(4067)
         if diagnostic.location.path.is_empty() {
@@ -170,13 +226,12 @@ impl MooncDiagnostic {
             {
                 eprintln!(
                     "{}",
-                    format!(
+                    paint(format!(
                         "{}: [{}] {}",
                         kind,
                         diagnostic.formatted_error_code(),
                         diagnostic.message
-                    )
-                    .fg(color)
+                    ))
                 );
             }
             return Some(kind);
@@ -258,14 +313,14 @@ impl MooncDiagnostic {
         let mut report_builder =
             ariadne::Report::build(kind, (&display_filename, start_offset..end_offset)).with_label(
                 ariadne::Label::new((&display_filename, start_offset..end_offset))
-                    .with_message((&diagnostic.message).fg(color))
+                    .with_message(paint(diagnostic.message.clone()))
                     .with_color(color),
             );
 
         if explain {
             let error_code_doc = get_error_code_doc(&diagnostic.formatted_error_code());
             if let Some(doc) = error_code_doc {
-                report_builder = report_builder.with_help(doc.fg(color));
+                report_builder = report_builder.with_help(paint(doc.to_string()));
             } else {
                 warn!(
                     "Failed to get doc for error code: {}",
@@ -274,10 +329,10 @@ impl MooncDiagnostic {
             }
         } else {
             report_builder = report_builder
-                .with_message(format!("[{}]", diagnostic.formatted_error_code()).fg(color));
+                .with_message(paint(format!("[{}]", diagnostic.formatted_error_code())));
         }
 
-        if !use_fancy {
+        if !use_fancy || !use_color {
             report_builder =
                 report_builder.with_config(ariadne::Config::default().with_color(false));
         }
@@ -314,7 +369,7 @@ impl MooncDiagnostic {
             .map(|it| (it.content.clone(), it.name.clone()))
     }
 
-    fn get_level_and_color(&self) -> (ariadne::ReportKind<'static>, ariadne::Color) {
+    pub fn get_level_and_color(&self) -> (ariadne::ReportKind<'static>, ariadne::Color) {
         if self.level == "error" {
             (ariadne::ReportKind::Error, ariadne::Color::Red)
         } else if self.level == "warning" {
@@ -324,7 +379,80 @@ impl MooncDiagnostic {
         }
     }
 
+    /// Convert to an [`LspDiagnostic`], reading the file to convert the
+    /// columns.
+    pub fn to_lsp(&self) -> LspDiagnostic {
+        let content = std::fs::read_to_string(&self.location.path).ok();
+        let line_index = content.as_deref().map(line_index::LineIndex::new);
+        LspDiagnostic {
+            path: self.location.path.clone(),
+            range: LspRange {
+                start: self.location.start.to_lsp(line_index.as_ref()),
+                end: self.location.end.to_lsp(line_index.as_ref()),
+            },
+            severity: match self.level.as_str() {
+                "error" => 1,
+                "warning" => 2,
+                _ => 3,
+            },
+            code: self.formatted_error_code(),
+            source: "moonc",
+            message: self.message.clone(),
+        }
+    }
+
+    /// Print the diagnostic in `content` as one line of [`LspDiagnostic`]
+    /// JSON to stdout, returning its kind like [`MooncDiagnostic::render`].
+    /// Output that isn't a diagnostic goes to stderr as-is.
+    pub fn print_lsp_json(content: &str) -> Option<ReportKind<'static>> {
+        let Ok(diagnostic) = serde_json_lenient::from_str::<MooncDiagnostic>(content) else {
+            eprintln!("{content}");
+            return None;
+        };
+        println!(
+            "{}",
+            serde_json_lenient::to_string(&diagnostic.to_lsp()).expect("serializing diagnostic")
+        );
+        Some(diagnostic.get_level_and_color().0)
+    }
+
     pub fn formatted_error_code(&self) -> String {
         format!("{:04}", self.error_code)
     }
 }
+
+#[test]
+fn test_position_to_lsp() {
+    // `😀` is 1 scalar value but 2 UTF-16 code units
+    let content = "let a = \"😀\" + b\nlet c = 1\n";
+    let index = line_index::LineIndex::new(content);
+    let pos = |line, col| Position { line, col }.to_lsp(Some(&index));
+    assert_eq!(
+        pos(1, 1),
+        LspPosition {
+            line: 0,
+            character: 0
+        }
+    );
+    assert_eq!(
+        pos(1, 15),
+        LspPosition {
+            line: 0,
+            character: 15
+        }
+    );
+    assert_eq!(
+        pos(2, 5),
+        LspPosition {
+            line: 1,
+            character: 4
+        }
+    );
+    assert_eq!(
+        Position { line: 1, col: 15 }.to_lsp(None),
+        LspPosition {
+            line: 0,
+            character: 14
+        }
+    );
+}
@@ -0,0 +1,304 @@
+// moon: The build system and package manager for MoonBit.
+// Copyright (C) 2024 International Digital Economy Academy
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// For inquiries, you can contact us via e-mail at jichuruanjian@idea.edu.cn.
+
+//! SARIF 2.1.0 reports of the diagnostics of moonc, for code scanning
+//! dashboards like the one of GitHub.
+//!
+//! The locations are relative to the root of the repository, given as the
+//! `SRCROOT` base in `originalUriBaseIds`, so that the dashboards can resolve
+//! them against their checkout. Files outside of the root, like the ones of
+//! the standard library, keep absolute `file:` URIs.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::render::MooncDiagnostic;
+
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+const SRCROOT: &str = "SRCROOT";
+
+#[derive(Debug, Serialize)]
+pub struct SarifLog {
+    #[serde(rename = "$schema")]
+    pub schema: &'static str,
+    pub version: &'static str,
+    pub runs: Vec<Run>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Run {
+    pub tool: Tool,
+    pub original_uri_base_ids: BTreeMap<&'static str, ArtifactLocation>,
+    /// moonc counts columns in Unicode scalar values, like SARIF does by
+    /// default, so no conversion is needed
+    pub column_kind: &'static str,
+    pub results: Vec<SarifResult>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Tool {
+    pub driver: Driver,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Driver {
+    pub name: &'static str,
+    pub information_uri: &'static str,
+    pub rules: Vec<Rule>,
+}
+
+/// A rule is an error code of moonc.
+#[derive(Debug, Serialize)]
+pub struct Rule {
+    pub id: String,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SarifResult {
+    pub rule_id: String,
+    /// `error`, `warning` or `note`
+    pub level: &'static str,
+    pub message: Message,
+    pub locations: Vec<SarifLocation>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct Message {
+    pub text: String,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SarifLocation {
+    pub physical_location: PhysicalLocation,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PhysicalLocation {
+    pub artifact_location: ArtifactLocation,
+    pub region: Region,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ArtifactLocation {
+    pub uri: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uri_base_id: Option<&'static str>,
+}
+
+/// A 1-based region, with the end column exclusive.
+#[derive(Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Region {
+    pub start_line: usize,
+    pub start_column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+/// The root of the repository containing `source_dir`, i.e. the nearest
+/// ancestor with a `.git`, or `source_dir` itself outside of repositories.
+pub fn repo_root(source_dir: &Path) -> PathBuf {
+    source_dir
+        .ancestors()
+        .find(|it| it.join(".git").exists())
+        .unwrap_or(source_dir)
+        .to_path_buf()
+}
+
+/// Build the SARIF log of the diagnostics in `outputs`, the output lines of
+/// build tasks. Relative diagnostic paths are resolved against `root`.
+///
+/// The same diagnostic is reported once, even though moonc reports it again
+/// for each target checking the file, e.g. the whitebox tests.
+pub fn sarif_log(outputs: &[String], root: &Path) -> SarifLog {
+    let mut results: Vec<SarifResult> = vec![];
+    let mut rules = BTreeSet::new();
+    for diagnostic in outputs
+        .iter()
+        .filter_map(|it| serde_json_lenient::from_str::<MooncDiagnostic>(it).ok())
+    {
+        let result = to_result(&diagnostic, root);
+        if results.contains(&result) {
+            continue;
+        }
+        rules.insert(result.rule_id.clone());
+        results.push(result);
+    }
+    SarifLog {
+        schema: SCHEMA,
+        version: "2.1.0",
+        runs: vec![Run {
+            tool: Tool {
+                driver: Driver {
+                    name: "moonc",
+                    information_uri: "https://www.moonbitlang.com",
+                    rules: rules.into_iter().map(|id| Rule { id }).collect(),
+                },
+            },
+            original_uri_base_ids: BTreeMap::from([(
+                SRCROOT,
+                ArtifactLocation {
+                    uri: file_uri(root, true),
+                    uri_base_id: None,
+                },
+            )]),
+            column_kind: "unicodeCodePoints",
+            results,
+        }],
+    }
+}
+
+/// Write the SARIF log of the diagnostics in `outputs` to `path`, with the
+/// locations relative to the repository containing `source_dir`.
+pub fn write_sarif(path: &Path, outputs: &[String], source_dir: &Path) -> anyhow::Result<()> {
+    let log = sarif_log(outputs, &repo_root(source_dir));
+    let content = serde_json_lenient::to_string_pretty(&log)?;
+    std::fs::write(path, content)
+        .with_context(|| format!("failed to write the SARIF report to {}", path.display()))
+}
+
+fn to_result(diagnostic: &MooncDiagnostic, root: &Path) -> SarifResult {
+    let loc = &diagnostic.location;
+    let path = root.join(&loc.path);
+    let artifact_location = match path.strip_prefix(root) {
+        Ok(relative) => ArtifactLocation {
+            uri: encode_path(relative),
+            uri_base_id: Some(SRCROOT),
+        },
+        Err(_) => ArtifactLocation {
+            uri: file_uri(&path, false),
+            uri_base_id: None,
+        },
+    };
+    SarifResult {
+        rule_id: diagnostic.formatted_error_code(),
+        level: match diagnostic.level.as_str() {
+            "error" => "error",
+            "warning" => "warning",
+            _ => "note",
+        },
+        message: Message {
+            text: diagnostic.message.clone(),
+        },
+        locations: vec![SarifLocation {
+            physical_location: PhysicalLocation {
+                artifact_location,
+                region: Region {
+                    start_line: loc.start.line,
+                    start_column: loc.start.col,
+                    end_line: loc.end.line,
+                    end_column: loc.end.col,
+                },
+            },
+        }],
+    }
+}
+
+/// The `file:` URI of the absolute `path`, ending with a `/` if `is_dir`, as
+/// SARIF requires of base URIs.
+fn file_uri(path: &Path, is_dir: bool) -> String {
+    let mut uri = encode_path(path);
+    if !uri.starts_with('/') {
+        // `C:/foo` on Windows
+        uri.insert(0, '/');
+    }
+    if is_dir && !uri.ends_with('/') {
+        uri.push('/');
+    }
+    format!("file://{uri}")
+}
+
+/// Percent-encode `path` for a URI, with `/` as the separator on all
+/// platforms.
+fn encode_path(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut res = String::with_capacity(path.len());
+    for b in path.bytes() {
+        if b.is_ascii_alphanumeric() || b"/-._~:".contains(&b) {
+            res.push(b as char);
+        } else {
+            res.push_str(&format!("%{b:02X}"));
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sarif_log() {
+        let root = Path::new("/repo");
+        let diagnostic = |level: &str, path: &str, code: u32| {
+            format!(
+                r#"{{"level":"{level}","loc":{{"path":"{path}","start":{{"line":3,"col":5}},"end":{{"line":3,"col":8}}}},"message":"oops","error_code":{code}}}"#
+            )
+        };
+        let outputs = [
+            diagnostic("error", "/repo/src/my lib.mbt", 4014),
+            String::from("not a diagnostic"),
+            diagnostic("warning", "/elsewhere/core.mbt", 2),
+            diagnostic("error", "/repo/src/my lib.mbt", 4014),
+        ];
+        let log = sarif_log(&outputs, root);
+        let run = &log.runs[0];
+        assert_eq!(
+            run.tool
+                .driver
+                .rules
+                .iter()
+                .map(|it| it.id.as_str())
+                .collect::<Vec<_>>(),
+            ["0002", "4014"]
+        );
+        assert_eq!(run.original_uri_base_ids[SRCROOT].uri, "file:///repo/");
+        assert_eq!(run.results.len(), 2);
+        let location = |i: usize| &run.results[i].locations[0].physical_location;
+        assert_eq!(
+            location(0).artifact_location,
+            ArtifactLocation {
+                uri: String::from("src/my%20lib.mbt"),
+                uri_base_id: Some(SRCROOT),
+            }
+        );
+        assert_eq!(
+            location(0).region,
+            Region {
+                start_line: 3,
+                start_column: 5,
+                end_line: 3,
+                end_column: 8,
+            }
+        );
+        assert_eq!(run.results[1].level, "warning");
+        assert_eq!(
+            location(1).artifact_location.uri,
+            "file:///elsewhere/core.mbt"
+        );
+    }
+}
@@ -277,6 +277,7 @@ fn scan_module_packages(
                     no_mi: false,
                     install_path: None,
                     bin_name: None,
+                    post_build: vec![],
 
                     ..cur_pkg.clone()
                 };
@@ -554,6 +555,7 @@ fn scan_one_package(
         } else {
             Some(prebuild)
         },
+        post_build: pkg.post_build,
         patch_file: None,
         no_mi: false,
         install_path: moonbuild_opt